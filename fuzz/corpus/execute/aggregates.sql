@@ -0,0 +1,4 @@
+SELECT count(*), sum(score), avg(score), min(name), max(id) FROM t
+SELECT active, count(*) FROM t GROUP BY active HAVING count(*) > 1
+SELECT t_id, count(note) FROM u GROUP BY t_id ORDER BY t_id
+SELECT sum(id) FROM t WHERE false
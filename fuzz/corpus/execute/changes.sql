@@ -0,0 +1,7 @@
+INSERT INTO t VALUES (4, 'd', 4.5, false) RETURNING id, score * 2
+INSERT INTO t (id, name) VALUES (5, 'eeeeeeeeeeeeeeeeeeeeeeee')
+UPDATE t SET score = score + 1, name = upper(name) WHERE active
+UPDATE t SET id = 7 WHERE id = 1
+DELETE FROM u WHERE t_id = 3 RETURNING id, note
+TRUNCATE u
+SELECT * FROM t
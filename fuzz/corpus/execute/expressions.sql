@@ -0,0 +1,5 @@
+SELECT id * 2 + 1, -score, score / 0, id % 2 FROM t
+SELECT CASE WHEN score > 1 THEN 'high' WHEN score = 0 THEN 'zero' ELSE 'low' END FROM t
+SELECT CAST(score AS INT), CAST(id AS VARCHAR), CAST('12' AS DOUBLE) FROM t
+SELECT upper(name), lower(name), length(name), coalesce(NULL, name) FROM t
+SELECT 2147483647 + 1, 1 / 0, NULL = NULL, NOT active FROM t
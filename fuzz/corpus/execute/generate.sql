@@ -0,0 +1,4 @@
+SELECT * FROM generate_series(1, 10)
+SELECT * FROM generate_series(1, 1000000000)
+SET statement_timeout = 1
+SELECT * FROM generate_series(10, 1, -1)
@@ -0,0 +1,6 @@
+SELECT t.name, u.note FROM t JOIN u ON t.id = u.t_id
+SELECT t.name, u.note FROM t LEFT JOIN u ON t.id = u.t_id WHERE u.note IS NULL
+SELECT * FROM t, u WHERE t.id < u.t_id
+SELECT name FROM t WHERE id IN (SELECT t_id FROM u)
+SELECT name, (SELECT count(*) FROM u WHERE u.t_id = t.id) FROM t
+SELECT name FROM t WHERE EXISTS (SELECT 1 FROM u WHERE u.t_id = t.id)
@@ -0,0 +1,7 @@
+CREATE TABLE v (id INT PRIMARY KEY, x INT NOT NULL, y VARCHAR(4) DEFAULT 'none')
+INSERT INTO v (id, x) VALUES (1, 1), (2, NULL)
+ALTER TABLE v ADD COLUMN z DOUBLE
+CREATE INDEX v_x ON v (x)
+DROP TABLE v
+UNDROP TABLE v
+SELECT * FROM v
@@ -0,0 +1,5 @@
+SELECT * FROM t
+SELECT id, name FROM t WHERE score > 1 AND active ORDER BY name DESC LIMIT 2 OFFSET 1
+SELECT DISTINCT active FROM t
+SELECT * FROM t WHERE name LIKE 'a%' OR id IN (2, 3)
+SELECT * FROM t WHERE score BETWEEN 0 AND 2 AND name IS NOT NULL
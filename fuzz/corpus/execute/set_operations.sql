@@ -0,0 +1,5 @@
+SELECT id FROM t UNION SELECT t_id FROM u
+SELECT id FROM t UNION ALL SELECT t_id FROM u ORDER BY id
+SELECT id FROM t INTERSECT SELECT t_id FROM u
+SELECT id FROM t EXCEPT SELECT t_id FROM u
+SELECT score FROM t UNION SELECT id FROM u
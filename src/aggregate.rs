@@ -0,0 +1,498 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::{
+    evaluator::{Evaluator, OutColumn},
+    join::HashKey,
+    parser::{
+        expression::{Expression, Ident, Literal},
+        select::SelectItem,
+    },
+    rowset::RowSet,
+    table::{ColumnData, RowId, Table},
+    Error, Result,
+};
+
+/// A function of the select list that sums up the rows of a group into a
+/// single value, leaving out nulls. By what they take, they give:
+///
+/// - `count`: an int, of values of any type, or of rows with `count(*)`
+/// - `sum`: of ints, an int when every group's sum fits one and otherwise a
+///   double for all of them, added up as an i64 so that nothing overflows on
+///   the way; of floats or doubles, their type, added up as a double
+/// - `avg`, `stddev`, `variance`, `median` and `percentile_cont`: a double,
+///   of numbers
+/// - `min` and `max`: the type they take, of any values
+/// - `string_agg`: a string, of values of any type
+///
+/// Groups without any values give null, other than to `count`, which gives 0.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Aggregate {
+    Count,
+    Sum,
+    Min,
+    Max,
+    Avg,
+    // `string_agg(expr, separator)`, or `group_concat(expr[, separator])`
+    // which separates with commas by default
+    StringAgg,
+    StddevSamp,
+    StddevPop,
+    VarSamp,
+    VarPop,
+    Median,
+    // `percentile_cont(expr, fraction)`, interpolating between the values
+    // closest to the fraction
+    PercentileCont,
+}
+
+impl Aggregate {
+    pub fn name(self) -> &'static str {
+        match self {
+            Aggregate::Count => "count",
+            Aggregate::Sum => "sum",
+            Aggregate::Min => "min",
+            Aggregate::Max => "max",
+            Aggregate::Avg => "avg",
+            Aggregate::StringAgg => "string_agg",
+            Aggregate::StddevSamp => "stddev_samp",
+            Aggregate::StddevPop => "stddev_pop",
+            Aggregate::VarSamp => "var_samp",
+            Aggregate::VarPop => "var_pop",
+            Aggregate::Median => "median",
+            Aggregate::PercentileCont => "percentile_cont",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_lowercase().as_str() {
+            "count" => Some(Aggregate::Count),
+            "sum" => Some(Aggregate::Sum),
+            "min" => Some(Aggregate::Min),
+            "max" => Some(Aggregate::Max),
+            "avg" => Some(Aggregate::Avg),
+            "string_agg" | "group_concat" => Some(Aggregate::StringAgg),
+            "stddev" | "stddev_samp" => Some(Aggregate::StddevSamp),
+            "stddev_pop" => Some(Aggregate::StddevPop),
+            "variance" | "var_samp" => Some(Aggregate::VarSamp),
+            "var_pop" => Some(Aggregate::VarPop),
+            "median" => Some(Aggregate::Median),
+            "percentile_cont" => Some(Aggregate::PercentileCont),
+            _ => None,
+        }
+    }
+}
+
+/// Whether `expr` calls an aggregate anywhere in it.
+pub fn contains_aggregate(expr: &Expression) -> bool {
+    match expr {
+        Expression::Function { name, args, .. } => {
+            Aggregate::from_name(name).is_some() || args.iter().any(contains_aggregate)
+        }
+        Expression::Unary { expression, .. } => contains_aggregate(expression),
+        Expression::Binary { left, right, .. } => {
+            contains_aggregate(left) || contains_aggregate(right)
+        }
+        Expression::IsFalse(e)
+        | Expression::IsTrue(e)
+        | Expression::IsNull(e)
+        | Expression::IsNotNull(e)
+        | Expression::InSubquery { expr: e, .. }
+        | Expression::MatchAgainst { column: e, .. } => contains_aggregate(e),
+        Expression::Tuple(items) => items.iter().any(contains_aggregate),
+        Expression::InList { expr, list, .. } => {
+            contains_aggregate(expr) || list.iter().any(contains_aggregate)
+        }
+        Expression::Case {
+            branches,
+            otherwise,
+        } => {
+            branches
+                .iter()
+                .any(|(c, r)| contains_aggregate(c) || contains_aggregate(r))
+                || otherwise.as_deref().is_some_and(contains_aggregate)
+        }
+        Expression::Values(_)
+        | Expression::Literal(_)
+        | Expression::Ident(_)
+        | Expression::Subquery(_)
+        | Expression::None => false,
+    }
+}
+
+// an aggregate as called, with the constant argument some of them take after
+// the values
+struct Call<'a> {
+    aggregate: Aggregate,
+    distinct: bool,
+    separator: &'a str,
+    fraction: f64,
+}
+
+// an aggregate over the values of a group seen so far
+struct Accumulator<'a> {
+    call: &'a Call<'a>,
+    // of values that were seen already, when only distinct ones count
+    seen: Option<HashSet<HashKey>>,
+    count: usize,
+    // `None` once past what an i64 holds
+    int_sum: Option<i64>,
+    sum: f64,
+    // the running mean, and sum of squared differences from it, of the
+    // values so far, for variances
+    mean: f64,
+    m2: f64,
+    // the first value, whose type a sum has
+    first: Option<Literal>,
+    // the smallest or largest value
+    best: Option<Literal>,
+    text: String,
+    // every value, for percentiles
+    values: Vec<f64>,
+}
+
+impl<'a> Accumulator<'a> {
+    fn new(call: &'a Call<'a>) -> Self {
+        Self {
+            call,
+            seen: call.distinct.then(HashSet::new),
+            count: 0,
+            int_sum: Some(0),
+            sum: 0.0,
+            mean: 0.0,
+            m2: 0.0,
+            first: None,
+            best: None,
+            text: String::new(),
+            values: Vec::new(),
+        }
+    }
+
+    fn add(&mut self, value: Literal) -> Result<()> {
+        if let Some(seen) = &mut self.seen {
+            // nan isn't equal to anything, itself included
+            if HashKey::new(value.clone()).is_some_and(|key| !seen.insert(key)) {
+                return Ok(());
+            }
+        }
+
+        let aggregate = self.call.aggregate;
+        match aggregate {
+            Aggregate::Count => {}
+            Aggregate::Sum | Aggregate::Avg => {
+                if let Literal::Int(i) = value {
+                    self.int_sum = self.int_sum.and_then(|s| s.checked_add(i as i64));
+                }
+                self.sum += number(aggregate, &value)?;
+            }
+            Aggregate::Min if self.best.as_ref().map_or(true, |b| value < *b) => {
+                self.best = Some(value.clone())
+            }
+            Aggregate::Max if self.best.as_ref().map_or(true, |b| value > *b) => {
+                self.best = Some(value.clone())
+            }
+            Aggregate::Min | Aggregate::Max => {}
+            Aggregate::StringAgg => {
+                if self.count > 0 {
+                    self.text += self.call.separator;
+                }
+                match &value {
+                    Literal::Str(s) => self.text += s,
+                    value => self.text += &value.to_string(),
+                }
+            }
+            // welford's, which doesn't lose precision to large sums
+            Aggregate::StddevSamp
+            | Aggregate::StddevPop
+            | Aggregate::VarSamp
+            | Aggregate::VarPop => {
+                let x = number(aggregate, &value)?;
+                let delta = x - self.mean;
+                self.mean += delta / (self.count + 1) as f64;
+                self.m2 += delta * (x - self.mean);
+            }
+            Aggregate::Median | Aggregate::PercentileCont => {
+                self.values.push(number(aggregate, &value)?)
+            }
+        }
+
+        self.first.get_or_insert(value);
+        self.count += 1;
+        Ok(())
+    }
+
+    fn finish(mut self) -> Result<Literal> {
+        let aggregate = self.call.aggregate;
+        let out_of_range =
+            || Error::InvalidOperation(format!("{} out of range of an int", aggregate.name()));
+        let n = self.count as f64;
+        Ok(match (aggregate, &self.first) {
+            (Aggregate::Count, _) => {
+                Literal::Int(i32::try_from(self.count).map_err(|_| out_of_range())?)
+            }
+            (_, None) => Literal::Null,
+            (Aggregate::Sum, Some(Literal::Int(_))) => match self.int_sum {
+                Some(sum) => i32::try_from(sum).map_or(Literal::Double(sum as f64), Literal::Int),
+                None => Literal::Double(self.sum),
+            },
+            (Aggregate::Sum, Some(Literal::Float(_))) => Literal::Float(self.sum as f32),
+            (Aggregate::Sum, Some(_)) => Literal::Double(self.sum),
+            (Aggregate::Avg, Some(_)) => Literal::Double(self.sum / n),
+            (Aggregate::Min | Aggregate::Max, Some(_)) => self.best.unwrap_or(Literal::Null),
+            (Aggregate::StringAgg, Some(_)) => Literal::Str(self.text),
+            // of a sample, which takes more than one value
+            (Aggregate::StddevSamp | Aggregate::VarSamp, _) if self.count < 2 => Literal::Null,
+            (Aggregate::StddevSamp, _) => Literal::Double((self.m2 / (n - 1.0)).sqrt()),
+            (Aggregate::VarSamp, _) => Literal::Double(self.m2 / (n - 1.0)),
+            (Aggregate::StddevPop, _) => Literal::Double((self.m2 / n).sqrt()),
+            (Aggregate::VarPop, _) => Literal::Double(self.m2 / n),
+            (Aggregate::Median, _) => Literal::Double(percentile(&mut self.values, 0.5)),
+            (Aggregate::PercentileCont, _) => {
+                Literal::Double(percentile(&mut self.values, self.call.fraction))
+            }
+        })
+    }
+}
+
+// `value` of a numeric aggregate, which takes nothing else
+fn number(aggregate: Aggregate, value: &Literal) -> Result<f64> {
+    match value {
+        Literal::Int(i) => Ok(*i as f64),
+        Literal::Float(f) => Ok(*f as f64),
+        Literal::Double(d) => Ok(*d),
+        value => Err(Error::InvalidOperation(format!(
+            "{} of {value}",
+            aggregate.name()
+        ))),
+    }
+}
+
+// the fraction a percentile is at, from 0 to 1. Floats are widened through
+// their text, so that 0.9 isn't 0.8999999761581421.
+fn fraction_of(literal: &Literal) -> Option<f64> {
+    let fraction = match literal {
+        Literal::Int(i) => *i as f64,
+        Literal::Float(f) => f.to_string().parse().ok()?,
+        Literal::Double(d) => *d,
+        _ => return None,
+    };
+    (0.0..=1.0).contains(&fraction).then_some(fraction)
+}
+
+// the value at `fraction` of the way through `values` were they sorted,
+// interpolated between the two closest ones. Only those are put in place
+// rather than sorting every value.
+fn percentile(values: &mut [f64], fraction: f64) -> f64 {
+    let position = fraction * (values.len() - 1) as f64;
+    let lower = position.floor() as usize;
+    let (_, &mut low, above) = values.select_nth_unstable_by(lower, f64::total_cmp);
+    match above.iter().copied().min_by(f64::total_cmp) {
+        Some(high) if position > lower as f64 => low + (high - low) * (position - lower as f64),
+        _ => low,
+    }
+}
+
+// a column evaluated over the rows of a table, where a constant has its
+// single value for every row
+struct Evaluated {
+    column: Option<OutColumn>,
+    constant: bool,
+}
+
+impl Evaluated {
+    fn new(evaluator: &Evaluator, table: Option<&Table>, expr: &Expression) -> Result<Self> {
+        let mut out = evaluator.eval(table, expr.clone())?;
+        if out.len() > 1 {
+            return Err(Error::InvalidQuery(format!(
+                "{expr}, which has more than one column, in a grouped select"
+            )));
+        }
+
+        Ok(Self {
+            column: out.pop(),
+            constant: matches!(expr, Expression::Literal(_)),
+        })
+    }
+
+    fn get(&self, id: RowId) -> Option<Literal> {
+        let id = if self.constant { 0 } else { id };
+        self.column.as_ref()?.data.get_literal(id)
+    }
+}
+
+/// The rows of a grouped select: one per group of `rows` with the same values
+/// of `group_by`, in the order of their first rows, or one of all `rows` when
+/// there's nothing to group by. Every expression of `projection` is either an
+/// aggregate or one of `group_by`.
+pub fn group(
+    evaluator: &Evaluator,
+    table: Option<&Table>,
+    rows: &RowSet,
+    group_by: &[Expression],
+    projection: &[SelectItem],
+) -> Result<Vec<OutColumn>> {
+    let keys = group_by
+        .iter()
+        .map(|expr| Evaluated::new(evaluator, table, expr))
+        .collect::<Result<Vec<_>>>()?;
+
+    // the rows of each group, with nulls grouped together
+    let mut groups: Vec<Vec<RowId>> = Vec::new();
+    if group_by.is_empty() {
+        groups.push(rows.iter().collect());
+    } else {
+        let mut index: HashMap<Vec<Option<HashKey>>, usize> = HashMap::new();
+        for id in rows.iter() {
+            let key = keys
+                .iter()
+                .map(|k| k.get(id).and_then(HashKey::new))
+                .collect();
+            let group = *index.entry(key).or_insert_with(|| {
+                groups.push(Vec::new());
+                groups.len() - 1
+            });
+            groups[group].push(id);
+        }
+    }
+    evaluator.deadline().check()?;
+
+    let mut out = Vec::with_capacity(projection.len());
+    for item in projection {
+        let (name, values, precision) = match &item.expr {
+            Expression::Ident(Ident::Wildcard) => {
+                return Err(Error::InvalidQuery("* in a grouped select".to_owned()))
+            }
+            Expression::Function {
+                name,
+                args,
+                distinct,
+            } if Aggregate::from_name(name).is_some() => {
+                let aggregate = Aggregate::from_name(name).expect("checked to be an aggregate");
+                let mut call = Call {
+                    aggregate,
+                    distinct: *distinct,
+                    separator: "",
+                    fraction: 0.0,
+                };
+                let arg = match (aggregate, &args[..]) {
+                    (Aggregate::Count, [Expression::Ident(Ident::Wildcard)]) if !distinct => None,
+                    (Aggregate::StringAgg, [arg, Expression::Literal(Literal::Str(separator))]) => {
+                        call.separator = separator;
+                        Some(arg)
+                    }
+                    (Aggregate::StringAgg, [arg]) if name.eq_ignore_ascii_case("group_concat") => {
+                        call.separator = ",";
+                        Some(arg)
+                    }
+                    (Aggregate::StringAgg, _) => {
+                        return Err(Error::InvalidQuery(format!(
+                            "{}, where an expression and a separator are expected",
+                            item.expr
+                        )))
+                    }
+                    (Aggregate::PercentileCont, [arg, Expression::Literal(fraction)])
+                        if fraction_of(fraction).is_some() =>
+                    {
+                        call.fraction = fraction_of(fraction).unwrap_or_default();
+                        Some(arg)
+                    }
+                    (Aggregate::PercentileCont, _) => {
+                        return Err(Error::InvalidQuery(format!(
+                            "{}, where an expression and a fraction from 0 to 1 are expected",
+                            item.expr
+                        )))
+                    }
+                    (_, [arg]) => Some(arg),
+                    _ => {
+                        return Err(Error::InvalidQuery(format!(
+                            "{}, where a single expression is expected",
+                            item.expr
+                        )))
+                    }
+                };
+                if arg.is_some_and(contains_aggregate) {
+                    return Err(Error::InvalidQuery(format!(
+                        "{}, an aggregate inside an aggregate",
+                        item.expr
+                    )));
+                }
+                let arg = arg
+                    .map(|arg| Evaluated::new(evaluator, table, arg))
+                    .transpose()?;
+
+                let mut values = Vec::with_capacity(groups.len());
+                for (group, ids) in groups.iter().enumerate() {
+                    let mut acc = Accumulator::new(&call);
+                    for &id in ids {
+                        match &arg {
+                            None => acc.add(Literal::Bool(true))?,
+                            Some(arg) => {
+                                if let Some(value) = arg.get(id) {
+                                    acc.add(value)?;
+                                }
+                            }
+                        }
+                    }
+                    values.push((group, acc.finish()?));
+                }
+                // sums of ints that don't all fit one are all doubles
+                if values.iter().any(|(_, v)| matches!(v, Literal::Double(_))) {
+                    for (_, value) in values.iter_mut() {
+                        if let Literal::Int(i) = value {
+                            *value = Literal::Double(*i as f64);
+                        }
+                    }
+                }
+
+                let precision = match aggregate {
+                    Aggregate::Sum | Aggregate::Min | Aggregate::Max => {
+                        arg.and_then(|a| a.column).and_then(|c| c.precision)
+                    }
+                    _ => None,
+                };
+                (name.clone(), values, precision)
+            }
+            expr if contains_aggregate(expr) => {
+                return Err(Error::Unsupported(format!(
+                    "{expr}, an aggregate inside an expression"
+                )))
+            }
+            expr => {
+                let Some(k) = group_by.iter().position(|g| same(g, expr)) else {
+                    return Err(Error::InvalidQuery(format!(
+                        "{expr}, which is neither grouped by nor aggregated"
+                    )));
+                };
+                let values = groups
+                    .iter()
+                    .enumerate()
+                    .map(|(group, ids)| {
+                        let value = ids.first().and_then(|&id| keys[k].get(id));
+                        (group, value.unwrap_or(Literal::Null))
+                    })
+                    .collect();
+                let column = keys[k].column.as_ref();
+                let name = column.map_or_else(|| expr.to_string(), |c| c.name.clone());
+                (name, values, column.and_then(|c| c.precision))
+            }
+        };
+
+        out.push(OutColumn {
+            name: item.alias.clone().unwrap_or(name),
+            data: ColumnData::from_literals(values)?.unwrap_or(ColumnData::Int(Default::default())),
+            precision,
+        });
+    }
+
+    Ok(out)
+}
+
+// whether `a` and `b` are the same expression, with names compared ignoring
+// case like they're looked up
+fn same(a: &Expression, b: &Expression) -> bool {
+    match (a, b) {
+        (Expression::Ident(Ident::Named(a)), Expression::Ident(Ident::Named(b))) => {
+            a.eq_ignore_ascii_case(b)
+        }
+        (a, b) => a == b,
+    }
+}
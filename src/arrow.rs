@@ -0,0 +1,214 @@
+use std::{
+    fmt::Display,
+    io::{Cursor, Write},
+    path::Path,
+    sync::Arc,
+};
+
+use arrow_array::{
+    cast::AsArray,
+    types::{
+        ArrowPrimitiveType, Float32Type, Float64Type, Int16Type, Int32Type, Int64Type, Int8Type,
+        UInt16Type, UInt32Type, UInt64Type, UInt8Type,
+    },
+    Array, ArrayRef, BooleanArray, Float32Array, Float64Array, Int32Array, RecordBatch,
+    StringArray,
+};
+use arrow_ipc::{
+    reader::{FileReader, StreamReader},
+    writer::{FileWriter, StreamWriter},
+};
+use arrow_schema::{ArrowError, DataType as ArrowType, Field, Schema};
+
+use crate::{
+    database::RowIter,
+    parser::expression::Literal,
+    table::{ColumnData, RowId},
+    Error, Result,
+};
+
+// rows per record batch written
+const BATCH_ROWS: usize = 64 * 1024;
+
+// what every Arrow IPC file starts with, unlike streams
+const FILE_MAGIC: &[u8] = b"ARROW1";
+
+/// Which of the Arrow IPC formats: files, which can be read from in any order,
+/// or streams, which can be read from as they arrive.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IpcFormat {
+    File,
+    Stream,
+}
+
+impl IpcFormat {
+    /// Streams for paths ending in `.arrows`, files otherwise.
+    pub fn from_path(path: &Path) -> Self {
+        match path.extension() {
+            Some(ext) if ext == "arrows" => IpcFormat::Stream,
+            _ => IpcFormat::File,
+        }
+    }
+
+    pub fn content_type(self) -> &'static str {
+        match self {
+            IpcFormat::File => "application/vnd.apache.arrow.file",
+            IpcFormat::Stream => "application/vnd.apache.arrow.stream",
+        }
+    }
+}
+
+/// Writes `rows` to `writer`, with every column nullable and of the Arrow type
+/// closest to its own.
+pub fn write_rows(rows: RowIter, writer: impl Write, format: IpcFormat) -> Result<()> {
+    let (names, columns, ids) = rows.into_parts();
+    let fields: Vec<Field> = names
+        .iter()
+        .zip(&columns)
+        .map(|(name, data)| Field::new(name, arrow_type(data), true))
+        .collect();
+    let schema = Arc::new(Schema::new(fields));
+
+    let batches = ids.chunks(BATCH_ROWS).map(|ids| {
+        let arrays = columns.iter().map(|data| array(data, ids)).collect();
+        RecordBatch::try_new(schema.clone(), arrays)
+    });
+    match format {
+        IpcFormat::File => {
+            let mut writer = FileWriter::try_new(writer, &schema).map_err(arrow_error)?;
+            for batch in batches {
+                writer
+                    .write(&batch.map_err(arrow_error)?)
+                    .map_err(arrow_error)?;
+            }
+            writer.finish().map_err(arrow_error)
+        }
+        IpcFormat::Stream => {
+            let mut writer = StreamWriter::try_new(writer, &schema).map_err(arrow_error)?;
+            for batch in batches {
+                writer
+                    .write(&batch.map_err(arrow_error)?)
+                    .map_err(arrow_error)?;
+            }
+            writer.finish().map_err(arrow_error)
+        }
+    }
+}
+
+/// The column names and rows of the Arrow IPC file or stream in `data`, with
+/// nulls as `Literal::Null`.
+pub fn read_rows(data: &[u8]) -> Result<(Vec<String>, Vec<Vec<Literal>>)> {
+    let (schema, batches) = if data.starts_with(FILE_MAGIC) {
+        let reader = FileReader::try_new(Cursor::new(data), None).map_err(arrow_error)?;
+        (reader.schema(), reader.collect::<Vec<_>>())
+    } else {
+        let reader = StreamReader::try_new(data, None).map_err(arrow_error)?;
+        (reader.schema(), reader.collect::<Vec<_>>())
+    };
+    let names: Vec<String> = schema.fields().iter().map(|f| f.name().clone()).collect();
+
+    let mut rows = Vec::new();
+    for batch in batches {
+        let batch = batch.map_err(arrow_error)?;
+        let mut columns = names
+            .iter()
+            .zip(batch.columns())
+            .map(|(name, array)| literals(name, array).map(Vec::into_iter))
+            .collect::<Result<Vec<_>>>()?;
+        for _ in 0..batch.num_rows() {
+            rows.push(columns.iter_mut().filter_map(Iterator::next).collect());
+        }
+    }
+
+    Ok((names, rows))
+}
+
+fn arrow_type(data: &ColumnData) -> ArrowType {
+    match data {
+        ColumnData::Int(_) => ArrowType::Int32,
+        ColumnData::Str(_) => ArrowType::Utf8,
+        ColumnData::Float(_) => ArrowType::Float32,
+        ColumnData::Double(_) => ArrowType::Float64,
+        ColumnData::Bool(_) => ArrowType::Boolean,
+    }
+}
+
+// the values of the rows `ids`, in that order
+fn array(data: &ColumnData, ids: &[RowId]) -> ArrayRef {
+    match data {
+        ColumnData::Int(map) => Arc::new(Int32Array::from_iter(
+            ids.iter().map(|id| map.get(id).copied()),
+        )),
+        ColumnData::Str(map) => Arc::new(StringArray::from_iter(
+            ids.iter().map(|id| map.get(id).map(|s| &**s)),
+        )),
+        ColumnData::Float(map) => Arc::new(Float32Array::from_iter(
+            ids.iter().map(|id| map.get(id).copied()),
+        )),
+        ColumnData::Double(map) => Arc::new(Float64Array::from_iter(
+            ids.iter().map(|id| map.get(id).copied()),
+        )),
+        ColumnData::Bool(map) => Arc::new(BooleanArray::from_iter(
+            ids.iter().map(|id| map.get(id).copied()),
+        )),
+    }
+}
+
+// the values of the column `name`, where integers have to fit an int
+fn literals(name: &str, array: &dyn Array) -> Result<Vec<Literal>> {
+    fn primitives<T: ArrowPrimitiveType>(
+        array: &dyn Array,
+        literal: impl Fn(T::Native) -> Result<Literal>,
+    ) -> Result<Vec<Literal>> {
+        array
+            .as_primitive::<T>()
+            .iter()
+            .map(|v| v.map_or(Ok(Literal::Null), &literal))
+            .collect()
+    }
+    let int = |v: i64| {
+        i32::try_from(v)
+            .map(Literal::Int)
+            .map_err(|_| out_of_range(name, v))
+    };
+    let text = |v: Option<&str>| v.map_or(Literal::Null, |s| Literal::Str(s.to_owned()));
+
+    match array.data_type() {
+        ArrowType::Int8 => primitives::<Int8Type>(array, |v| Ok(Literal::Int(v.into()))),
+        ArrowType::Int16 => primitives::<Int16Type>(array, |v| Ok(Literal::Int(v.into()))),
+        ArrowType::Int32 => primitives::<Int32Type>(array, |v| Ok(Literal::Int(v))),
+        ArrowType::Int64 => primitives::<Int64Type>(array, int),
+        ArrowType::UInt8 => primitives::<UInt8Type>(array, |v| Ok(Literal::Int(v.into()))),
+        ArrowType::UInt16 => primitives::<UInt16Type>(array, |v| Ok(Literal::Int(v.into()))),
+        ArrowType::UInt32 => primitives::<UInt32Type>(array, |v| int(v.into())),
+        ArrowType::UInt64 => primitives::<UInt64Type>(array, |v| {
+            i64::try_from(v)
+                .map_err(|_| out_of_range(name, v))
+                .and_then(int)
+        }),
+        ArrowType::Float32 => primitives::<Float32Type>(array, |v| Ok(Literal::Float(v))),
+        ArrowType::Float64 => primitives::<Float64Type>(array, |v| Ok(Literal::Double(v))),
+        ArrowType::Boolean => Ok(array
+            .as_boolean()
+            .iter()
+            .map(|v| v.map_or(Literal::Null, Literal::Bool))
+            .collect()),
+        ArrowType::Utf8 => Ok(array.as_string::<i32>().iter().map(text).collect()),
+        ArrowType::LargeUtf8 => Ok(array.as_string::<i64>().iter().map(text).collect()),
+        ArrowType::Utf8View => Ok(array.as_string_view().iter().map(text).collect()),
+        ArrowType::Null => Ok(vec![Literal::Null; array.len()]),
+        other => Err(Error::Unsupported(format!(
+            "arrow column {name} of type {other}"
+        ))),
+    }
+}
+
+fn out_of_range(name: &str, value: impl Display) -> Error {
+    Error::InvalidOperation(format!(
+        "{value} in column {name}, which is out of range for an int"
+    ))
+}
+
+fn arrow_error(e: ArrowError) -> Error {
+    Error::Arrow(e.to_string())
+}
@@ -0,0 +1,173 @@
+use std::{
+    collections::VecDeque,
+    fmt::Display,
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use crate::{
+    parser::expression::{Expression, Literal},
+    table::DataType,
+    virtual_table::VirtualTable,
+    Result,
+};
+
+/// Failed logins, refused addresses, permission denials and admin actions,
+/// most recent last. It's a virtual table, with the columns `at` (in seconds
+/// since the unix epoch), `kind`, `username`, `address` and `action`, and is
+/// kept in memory only.
+#[derive(Debug, Clone, Default)]
+pub struct AuditLog(Arc<Mutex<Events>>);
+
+#[derive(Debug, Default)]
+struct Events {
+    events: VecDeque<AuditEvent>,
+    retention: AuditRetention,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct AuditEvent {
+    pub at: f64,
+    pub kind: AuditKind,
+    pub username: Option<String>,
+    pub address: Option<String>,
+    pub action: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AuditKind {
+    // credentials, a token or a certificate that didn't check out
+    LoginFailed,
+    // a connection from an address the acl refuses
+    AddressRefused,
+    PermissionDenied,
+    Admin,
+}
+
+impl Display for AuditKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            AuditKind::LoginFailed => "login failed",
+            AuditKind::AddressRefused => "address refused",
+            AuditKind::PermissionDenied => "permission denied",
+            AuditKind::Admin => "admin",
+        })
+    }
+}
+
+/// How many events are kept in the audit log, and for how long.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AuditRetention {
+    pub max_events: usize,
+    pub max_age: Duration,
+}
+
+impl Default for AuditRetention {
+    fn default() -> Self {
+        Self {
+            max_events: 10_000,
+            max_age: Duration::from_secs(7 * 24 * 60 * 60),
+        }
+    }
+}
+
+impl AuditLog {
+    pub fn new(retention: AuditRetention) -> Self {
+        Self(Arc::new(Mutex::new(Events {
+            events: VecDeque::new(),
+            retention,
+        })))
+    }
+
+    pub fn set_retention(&self, retention: AuditRetention) {
+        let mut events = self.0.lock().unwrap();
+        events.retention = retention;
+        events.expire();
+    }
+
+    pub fn retention(&self) -> AuditRetention {
+        self.0.lock().unwrap().retention
+    }
+
+    pub fn record(
+        &self,
+        kind: AuditKind,
+        username: Option<&str>,
+        address: Option<&str>,
+        action: impl Into<String>,
+    ) {
+        let event = AuditEvent {
+            at: now(),
+            kind,
+            username: username.map(str::to_owned),
+            address: address.map(str::to_owned),
+            action: action.into(),
+        };
+        log::info!("audit: {event:?}");
+
+        let mut events = self.0.lock().unwrap();
+        events.events.push_back(event);
+        events.expire();
+    }
+
+    /// The events still kept, oldest first.
+    pub fn events(&self) -> Vec<AuditEvent> {
+        let mut events = self.0.lock().unwrap();
+        events.expire();
+        events.events.iter().cloned().collect()
+    }
+}
+
+impl Events {
+    fn expire(&mut self) {
+        let AuditRetention {
+            max_events,
+            max_age,
+        } = self.retention;
+        let oldest = now() - max_age.as_secs_f64();
+
+        while self
+            .events
+            .front()
+            .is_some_and(|e| e.at < oldest || self.events.len() > max_events)
+        {
+            self.events.pop_front();
+        }
+    }
+}
+
+impl VirtualTable for AuditLog {
+    fn schema(&self) -> Vec<(String, DataType)> {
+        vec![
+            ("at".to_owned(), DataType::Double),
+            ("kind".to_owned(), DataType::Str),
+            ("username".to_owned(), DataType::Str),
+            ("address".to_owned(), DataType::Str),
+            ("action".to_owned(), DataType::Str),
+        ]
+    }
+
+    fn scan(&self, _filters: &[Expression]) -> Result<Vec<Vec<Literal>>> {
+        let text = |s: Option<String>| s.map_or(Literal::Null, Literal::Str);
+        Ok(self
+            .events()
+            .into_iter()
+            .map(|e| {
+                vec![
+                    Literal::Double(e.at),
+                    Literal::Str(e.kind.to_string()),
+                    text(e.username),
+                    text(e.address),
+                    Literal::Str(e.action),
+                ]
+            })
+            .collect())
+    }
+}
+
+fn now() -> f64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs_f64())
+        .unwrap_or_default()
+}
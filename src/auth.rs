@@ -0,0 +1,133 @@
+use std::{
+    collections::HashMap,
+    sync::Mutex,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use hmac::{Hmac, Mac};
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+
+use crate::{Error, Result};
+
+type Signature = Hmac<Sha256>;
+
+/// Issues and checks the short lived tokens users log in for. A token is its
+/// claims, signed with HMAC-SHA256, so every server with the same signing key
+/// accepts it without having issued it.
+pub struct Tokens {
+    key: Vec<u8>,
+    ttl: Duration,
+    // by id, along with when they expire, after which they're refused anyway
+    revoked: Mutex<HashMap<String, u64>>,
+}
+
+/// A token, as handed out to a user that logged in.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Token {
+    pub token: String,
+    // in seconds since the unix epoch
+    pub expires_at: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Claims {
+    sub: String,
+    exp: u64,
+    jti: String,
+}
+
+impl Tokens {
+    /// Tokens signed with `key`, valid for `ttl` after they're issued.
+    pub fn new(key: impl Into<Vec<u8>>, ttl: Duration) -> Self {
+        Self {
+            key: key.into(),
+            ttl,
+            revoked: Default::default(),
+        }
+    }
+
+    /// Like `new`, with a random key, so that tokens don't outlive the process.
+    pub fn with_random_key(ttl: Duration) -> Self {
+        let mut key = vec![0; 32];
+        rand::thread_rng().fill_bytes(&mut key);
+        Self::new(key, ttl)
+    }
+
+    pub fn issue(&self, user: &str) -> Token {
+        let mut id = [0; 16];
+        rand::thread_rng().fill_bytes(&mut id);
+        let claims = Claims {
+            sub: user.to_owned(),
+            exp: now() + self.ttl.as_secs(),
+            jti: URL_SAFE_NO_PAD.encode(id),
+        };
+
+        let claims_json = serde_json::to_vec(&claims).expect("claims serialize");
+        let payload = URL_SAFE_NO_PAD.encode(claims_json);
+        let signature = URL_SAFE_NO_PAD.encode(self.sign(&payload).finalize().into_bytes());
+
+        Token {
+            token: format!("{payload}.{signature}"),
+            expires_at: claims.exp,
+        }
+    }
+
+    /// The user `token` was issued to, unless it wasn't signed with this key,
+    /// has expired or was revoked.
+    pub fn verify(&self, token: &str) -> Result<String> {
+        let claims = self.claims(token)?;
+        if self.revoked.lock().unwrap().contains_key(&claims.jti) {
+            return Err(Error::InvalidToken("revoked".to_owned()));
+        }
+
+        Ok(claims.sub)
+    }
+
+    /// Refuses `token` from now on, e.g. once its user logs out.
+    pub fn revoke(&self, token: &str) -> Result<()> {
+        let claims = self.claims(token)?;
+        let now = now();
+        let mut revoked = self.revoked.lock().unwrap();
+        revoked.retain(|_, exp| *exp >= now);
+        revoked.insert(claims.jti, claims.exp);
+
+        Ok(())
+    }
+
+    // the claims of a token signed with this key that hasn't expired yet
+    fn claims(&self, token: &str) -> Result<Claims> {
+        let malformed = || Error::InvalidToken("malformed".to_owned());
+        let (payload, signature) = token.split_once('.').ok_or_else(malformed)?;
+        let signature = URL_SAFE_NO_PAD.decode(signature).map_err(|_| malformed())?;
+        self.sign(payload)
+            .verify_slice(&signature)
+            .map_err(|_| Error::InvalidToken("bad signature".to_owned()))?;
+
+        let claims: Claims = URL_SAFE_NO_PAD
+            .decode(payload)
+            .ok()
+            .and_then(|json| serde_json::from_slice(&json).ok())
+            .ok_or_else(malformed)?;
+        if claims.exp < now() {
+            return Err(Error::InvalidToken("expired".to_owned()));
+        }
+
+        Ok(claims)
+    }
+
+    fn sign(&self, payload: &str) -> Signature {
+        let mut mac = Signature::new_from_slice(&self.key).expect("hmac takes keys of any size");
+        mac.update(payload.as_bytes());
+        mac
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
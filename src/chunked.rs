@@ -0,0 +1,162 @@
+use std::{collections::BTreeMap, fmt::Debug, sync::Arc};
+
+use serde::{ser::SerializeMap, Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::table::RowId;
+
+const CHUNK_ROWS: RowId = 1024;
+
+/// A map from RowId to a value, with the same interface as the `BTreeMap` it
+/// replaces. The rows are split into chunks of consecutive ids that are shared
+/// between clones, so that copying a table is cheap and a change afterwards
+/// only copies the chunk it touches.
+#[derive(Clone)]
+pub struct ChunkedMap<T> {
+    chunks: BTreeMap<RowId, Arc<BTreeMap<RowId, T>>>,
+    len: usize,
+}
+
+impl<T> Default for ChunkedMap<T> {
+    fn default() -> Self {
+        Self {
+            chunks: BTreeMap::new(),
+            len: 0,
+        }
+    }
+}
+
+impl<T> ChunkedMap<T> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn get(&self, id: &RowId) -> Option<&T> {
+        self.chunks.get(&(id / CHUNK_ROWS))?.get(id)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&RowId, &T)> {
+        self.chunks.values().flat_map(|c| c.iter())
+    }
+
+    pub fn keys(&self) -> impl Iterator<Item = &RowId> {
+        self.iter().map(|(k, _)| k)
+    }
+
+    pub fn values(&self) -> impl Iterator<Item = &T> {
+        self.iter().map(|(_, v)| v)
+    }
+
+    pub fn clear(&mut self) {
+        self.chunks.clear();
+        self.len = 0;
+    }
+}
+
+impl<T: Clone> ChunkedMap<T> {
+    pub fn insert(&mut self, id: RowId, value: T) -> Option<T> {
+        let chunk = self.chunks.entry(id / CHUNK_ROWS).or_default();
+        let old = Arc::make_mut(chunk).insert(id, value);
+        if old.is_none() {
+            self.len += 1;
+        }
+        old
+    }
+
+    pub fn remove(&mut self, id: &RowId) -> Option<T> {
+        let key = id / CHUNK_ROWS;
+        let chunk = self.chunks.get_mut(&key)?;
+        if !chunk.contains_key(id) {
+            return None;
+        }
+
+        let old = Arc::make_mut(chunk).remove(id);
+        if chunk.is_empty() {
+            self.chunks.remove(&key);
+        }
+        self.len -= 1;
+        old
+    }
+
+    pub fn values_mut(&mut self) -> impl Iterator<Item = &mut T> {
+        self.chunks
+            .values_mut()
+            .flat_map(|c| Arc::make_mut(c).values_mut())
+    }
+
+    /// Keeps only the rows `f` returns true for. Chunks that keep all of their
+    /// rows are left shared.
+    pub fn retain(&mut self, mut f: impl FnMut(&RowId, &T) -> bool) {
+        for chunk in self.chunks.values_mut() {
+            if chunk.iter().all(|(k, v)| f(k, v)) {
+                continue;
+            }
+            Arc::make_mut(chunk).retain(|k, v| f(k, v));
+        }
+
+        self.chunks.retain(|_, c| !c.is_empty());
+        self.len = self.chunks.values().map(|c| c.len()).sum();
+    }
+}
+
+impl<T: Clone> FromIterator<(RowId, T)> for ChunkedMap<T> {
+    fn from_iter<I: IntoIterator<Item = (RowId, T)>>(iter: I) -> Self {
+        let mut map = ChunkedMap::new();
+        for (id, value) in iter {
+            map.insert(id, value);
+        }
+        map
+    }
+}
+
+impl<T: Clone + 'static> IntoIterator for ChunkedMap<T> {
+    type Item = (RowId, T);
+    type IntoIter = Box<dyn Iterator<Item = (RowId, T)>>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        // chunks still shared with a clone have to be copied out
+        Box::new(
+            self.chunks
+                .into_values()
+                .flat_map(|c| Arc::unwrap_or_clone(c).into_iter()),
+        )
+    }
+}
+
+impl<T: PartialEq> PartialEq for ChunkedMap<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.len == other.len && self.iter().eq(other.iter())
+    }
+}
+
+impl<T: Debug> Debug for ChunkedMap<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_map().entries(self.iter()).finish()
+    }
+}
+
+// the same as a `BTreeMap`, so that persisted databases stay readable
+impl<T: Serialize> Serialize for ChunkedMap<T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(Some(self.len))?;
+        for (k, v) in self.iter() {
+            map.serialize_entry(k, v)?;
+        }
+        map.end()
+    }
+}
+
+impl<'de, T: Deserialize<'de> + Clone> Deserialize<'de> for ChunkedMap<T> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        Ok(BTreeMap::<RowId, T>::deserialize(deserializer)?
+            .into_iter()
+            .collect())
+    }
+}
@@ -0,0 +1,381 @@
+use std::time::Duration;
+
+use actix_codec::Framed;
+use awc::{ws, BoxedSocket};
+use futures_util::{stream, SinkExt, Stream, StreamExt};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    database::{ChangeEvent, View},
+    source::{Source, SourceRecord},
+    Error, Result,
+};
+
+const MAX_RECONNECTS: u32 = 8;
+const MAX_RESPONSE_SIZE: usize = 64 * 1024 * 1024;
+
+// the part of `Error::to_json` that errors from the database are rebuilt from
+#[derive(Deserialize)]
+struct RemoteError {
+    code: String,
+    message: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct Credentials {
+    pub username: String,
+    pub password: String,
+}
+
+impl Credentials {
+    pub fn new(username: impl Into<String>, password: impl Into<String>) -> Self {
+        Self {
+            username: username.into(),
+            password: password.into(),
+        }
+    }
+}
+
+/// A connection to a remote socketdb server, e.g. `http://127.0.0.1:8080`.
+pub struct Client {
+    http: awc::Client,
+    url: String,
+    creds: Credentials,
+}
+
+impl Client {
+    pub async fn connect(url: impl Into<String>, creds: Credentials) -> Result<Self> {
+        let client = Self {
+            http: awc::Client::new(),
+            url: url.into().trim_end_matches('/').to_owned(),
+            creds,
+        };
+
+        // an empty batch does nothing on the server, but still checks that it
+        // is reachable and accepts our credentials
+        client.execute("").await?;
+
+        Ok(client)
+    }
+
+    /// Runs `sql`, returning the views it produced. Those over the limits on
+    /// responses the server has are `truncated`, with the rest of their rows
+    /// fetched by `fetch_cursor` with their `cursor`.
+    pub async fn execute(&self, sql: &str) -> Result<Vec<View>> {
+        let request = self.http.post(format!("{}/query", self.url));
+        self.views(request, sql.to_owned()).await
+    }
+
+    /// Like `execute`, with at most `page_size` rows in each view, the rest
+    /// being fetched through the cursor of the view with `fetch_page`.
+    pub async fn execute_paged(&self, sql: &str, page_size: usize) -> Result<Vec<View>> {
+        let request = self
+            .http
+            .post(format!("{}/query?page_size={page_size}", self.url));
+        self.views(request, sql.to_owned()).await
+    }
+
+    /// The rows left out of a truncated view, which may be truncated in turn.
+    pub async fn fetch_cursor(&self, cursor: &str) -> Result<View> {
+        self.cursor(cursor, None).await
+    }
+
+    /// The next `page_size` rows of a view, with a cursor to those after
+    /// unless it's the last page.
+    pub async fn fetch_page(&self, cursor: &str, page_size: usize) -> Result<View> {
+        self.cursor(cursor, Some(page_size)).await
+    }
+
+    async fn cursor(&self, cursor: &str, page_size: Option<usize>) -> Result<View> {
+        let mut url = format!("{}/query/cursors/{cursor}", self.url);
+        if let Some(page_size) = page_size {
+            url.push_str(&format!("?page_size={page_size}"));
+        }
+        let mut views = self.views(self.http.get(url), String::new()).await?;
+        views
+            .pop()
+            .ok_or_else(|| Error::ClientError(format!("no rows for cursor {cursor}")))
+    }
+
+    // the views the server responded to `request` with
+    async fn views(&self, request: awc::ClientRequest, body: String) -> Result<Vec<View>> {
+        let mut resp = request
+            .insert_header(("ws-username", self.creds.username.as_str()))
+            .insert_header(("ws-password", self.creds.password.as_str()))
+            .send_body(body)
+            .await
+            .map_err(|e| Error::ClientError(e.to_string()))?;
+
+        if !resp.status().is_success() {
+            let body = resp
+                .body()
+                .await
+                .map_err(|e| Error::ClientError(e.to_string()))?;
+            return Err(match serde_json::from_slice::<RemoteError>(&body) {
+                Ok(RemoteError { code, message }) => Error::Remote { code, message },
+                Err(_) => Error::ClientError(format!(
+                    "{}: {}",
+                    resp.status(),
+                    String::from_utf8_lossy(&body)
+                )),
+            });
+        }
+
+        resp.json::<Vec<View>>()
+            .limit(MAX_RESPONSE_SIZE)
+            .await
+            .map_err(|e| Error::ClientError(e.to_string()))
+    }
+
+    pub fn subscribe(&self, table: &str) -> impl Stream<Item = Result<ChangeEvent>> {
+        self.subscribe_from(table, None)
+    }
+
+    /// Like [`Client::subscribe`], but first replays the events after
+    /// `resume_from` (the `seq` of the last event seen) that the server still
+    /// has buffered.
+    ///
+    /// The stream reconnects on its own when the socket drops, resuming after
+    /// the last event it yielded.
+    pub fn subscribe_from(
+        &self,
+        table: &str,
+        resume_from: Option<u64>,
+    ) -> impl Stream<Item = Result<ChangeEvent>> {
+        self.subscriber(table, resume_from, None)
+    }
+
+    /// Like [`Client::subscribe`], but the server sends at most `credits`
+    /// events ahead of the ones taken from the stream, rather than however
+    /// many changes there are.
+    pub fn subscribe_with_credits(
+        &self,
+        table: &str,
+        credits: usize,
+    ) -> impl Stream<Item = Result<ChangeEvent>> {
+        self.subscriber(table, None, Some(credits))
+    }
+
+    fn subscriber(
+        &self,
+        table: &str,
+        resume_from: Option<u64>,
+        credits: Option<usize>,
+    ) -> impl Stream<Item = Result<ChangeEvent>> {
+        let state = Subscriber {
+            http: self.http.clone(),
+            url: self.url.clone(),
+            creds: self.creds.clone(),
+            table: table.to_owned(),
+            last_seq: resume_from,
+            credits,
+            unacked: 0,
+            conn: None,
+            reconnects: 0,
+            failed: false,
+        };
+
+        stream::unfold(state, |mut sub| async move {
+            let item = sub.next_event().await?;
+            Some((item, sub))
+        })
+    }
+}
+
+// the query of the url subscriptions are opened with
+#[derive(Serialize)]
+struct WsQuery<'a> {
+    table: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    resume: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    credits: Option<usize>,
+}
+
+struct Subscriber {
+    http: awc::Client,
+    url: String,
+    creds: Credentials,
+    table: String,
+    last_seq: Option<u64>,
+    credits: Option<usize>,
+    // events yielded since the last acknowledgement
+    unacked: usize,
+    conn: Option<Framed<BoxedSocket, ws::Codec>>,
+    reconnects: u32,
+    failed: bool,
+}
+
+impl Subscriber {
+    async fn open(&self) -> Result<Framed<BoxedSocket, ws::Codec>> {
+        let query = serde_urlencoded::to_string(WsQuery {
+            table: &self.table,
+            resume: self.last_seq,
+            credits: self.credits,
+        })
+        .map_err(|e| Error::ClientError(e.to_string()))?;
+        let url = format!("{}/ws?{query}", self.url);
+
+        let (_, conn) = self
+            .http
+            .ws(url)
+            .header("ws-username", self.creds.username.as_str())
+            .header("ws-password", self.creds.password.as_str())
+            .connect()
+            .await
+            .map_err(|e| Error::ClientError(e.to_string()))?;
+
+        Ok(conn)
+    }
+
+    async fn next_event(&mut self) -> Option<Result<ChangeEvent>> {
+        if self.failed {
+            return None;
+        }
+
+        // being asked for the next event means the ones before were handled
+        if let Some(conn) = self.conn.as_mut().filter(|_| self.unacked > 0) {
+            let ack = format!("ack {}", self.unacked);
+            self.unacked = 0;
+            if conn.send(ws::Message::Text(ack.into())).await.is_err() {
+                self.conn = None;
+            }
+        }
+
+        loop {
+            let Some(conn) = self.conn.as_mut() else {
+                match self.open().await {
+                    Ok(conn) => {
+                        self.conn = Some(conn);
+                        self.reconnects = 0;
+                    }
+                    Err(e) if self.reconnects >= MAX_RECONNECTS => {
+                        self.failed = true;
+                        return Some(Err(e));
+                    }
+                    Err(e) => {
+                        log::warn!("reconnecting to {}: {e}", self.url);
+                        self.reconnects += 1;
+                        actix::clock::sleep(Duration::from_millis(100 << self.reconnects)).await;
+                    }
+                }
+                continue;
+            };
+
+            match conn.next().await {
+                Some(Ok(ws::Frame::Text(text))) => {
+                    if let Ok(RemoteError { code, message }) = serde_json::from_slice(&text) {
+                        // the subscription was refused, which reconnecting won't change
+                        self.failed = true;
+                        return Some(Err(Error::Remote { code, message }));
+                    }
+
+                    return Some(match serde_json::from_slice::<ChangeEvent>(&text) {
+                        Ok(event) => {
+                            self.last_seq = Some(event.seq);
+                            self.unacked += 1;
+                            Ok(event)
+                        }
+                        Err(e) => Err(Error::ClientError(e.to_string())),
+                    });
+                }
+                Some(Ok(ws::Frame::Ping(msg))) => {
+                    _ = conn.send(ws::Message::Pong(msg)).await;
+                }
+                Some(Ok(ws::Frame::Close(_)) | Err(_)) | None => {
+                    self.conn = None;
+                    self.unacked = 0;
+                }
+                Some(Ok(_)) => {}
+            }
+        }
+    }
+}
+
+/// A source of json records sent as the text messages of a websocket, like
+/// `ws://upstream:9000/feed`, see `Database::attach_source`. It reconnects on
+/// its own when the socket drops, until it fails to `MAX_RECONNECTS` times in a
+/// row.
+pub struct JsonWebSocket {
+    records: flume::Receiver<Result<SourceRecord>>,
+}
+
+impl JsonWebSocket {
+    pub fn connect(url: impl Into<String>) -> Self {
+        let url = url.into();
+        let (tx, rx) = flume::bounded(1024);
+        std::thread::spawn(move || actix::System::new().block_on(feed(url, tx)));
+        Self { records: rx }
+    }
+}
+
+impl Source for JsonWebSocket {
+    fn next_record(&mut self) -> Option<Result<SourceRecord>> {
+        self.records.recv().ok()
+    }
+}
+
+async fn feed(url: String, records: flume::Sender<Result<SourceRecord>>) {
+    let http = awc::Client::new();
+    let mut reconnects = 0;
+    loop {
+        let mut conn = match http.ws(url.as_str()).connect().await {
+            Ok((_, conn)) => {
+                reconnects = 0;
+                conn
+            }
+            Err(e) if reconnects >= MAX_RECONNECTS => {
+                _ = records
+                    .send_async(Err(Error::ClientError(e.to_string())))
+                    .await;
+                return;
+            }
+            Err(e) => {
+                log::warn!("reconnecting to {url}: {e}");
+                reconnects += 1;
+                actix::clock::sleep(Duration::from_millis(100 << reconnects)).await;
+                continue;
+            }
+        };
+
+        while let Some(frame) = conn.next().await {
+            let record = match frame {
+                Ok(ws::Frame::Text(text)) => std::str::from_utf8(&text)
+                    .map_err(|e| Error::ClientError(e.to_string()))
+                    .and_then(SourceRecord::from_json),
+                Ok(ws::Frame::Ping(msg)) => {
+                    _ = conn.send(ws::Message::Pong(msg)).await;
+                    continue;
+                }
+                Ok(ws::Frame::Close(_)) | Err(_) => break,
+                Ok(_) => continue,
+            };
+            // once the source is detached, nobody is left to apply them
+            if records.send_async(record).await.is_err() {
+                return;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    #[test]
+    fn ws_queries_encode_table_names() {
+        let query = serde_urlencoded::to_string(WsQuery {
+            table: "a&b=c d#e",
+            resume: Some(3),
+            credits: None,
+        })
+        .unwrap();
+
+        let decoded: HashMap<String, String> = serde_urlencoded::from_str(&query).unwrap();
+        assert_eq!(decoded.len(), 2);
+        assert_eq!(decoded["table"], "a&b=c d#e");
+        assert_eq!(decoded["resume"], "3");
+    }
+}
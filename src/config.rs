@@ -0,0 +1,383 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use serde::Deserialize;
+
+use crate::{
+    acl::{Acl, Cidr},
+    audit::AuditRetention,
+    parser::{cache, parser::Limits},
+    Error, Result,
+};
+
+/// The settings of a server, as read from `socketdb.toml`, like:
+///
+/// ```toml
+/// listen = "0.0.0.0:8080"
+/// data_dir = "/var/lib/socketdb"
+/// replay_log = "replay.jsonl"
+///
+/// [tls]
+/// cert = "server.pem"
+/// key = "server.key"
+///
+/// [limits]
+/// statement_timeout_ms = 5000
+/// cursor_idle_secs = 60
+///
+/// [limits.response]
+/// max_rows = 10000
+///
+/// [limits.roles.analyst]
+/// max_rows = 1000000
+/// ```
+///
+/// Anything left out keeps its default. The `SOCKETDB_*` environment variables
+/// take precedence over the file, see `apply_env`.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub listen: String,
+    pub data_dir: PathBuf,
+    pub tls: Option<TlsConfig>,
+    pub auth: AuthConfig,
+    pub autosave: Autosave,
+    pub limits: LimitsConfig,
+    pub log: LogConfig,
+    pub acl: Acl,
+    pub audit: AuditConfig,
+    pub sources: Vec<SourceConfig>,
+    // every statement executed is appended to, to run again with `--replay`
+    pub replay_log: Option<PathBuf>,
+}
+
+/// Serves over TLS with the certificate chain and key in the pem files at
+/// `cert` and `key`, requiring clients to present a certificate signed by
+/// `client_ca`, if set.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TlsConfig {
+    pub cert: PathBuf,
+    pub key: PathBuf,
+    pub client_ca: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AuthConfig {
+    pub backend: AuthBackend,
+    pub token_ttl_secs: u64,
+    // without one, tokens are signed with a key that changes every restart
+    pub signing_key: Option<String>,
+    // of the `admin` superuser created while there are no users
+    pub admin_password: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AuthBackend {
+    /// The users created with `CREATE USER`.
+    #[default]
+    Users,
+    /// No authentication at all, every statement is executed without a user,
+    /// like the ones typed into the repl.
+    None,
+}
+
+/// When databases are written to `data_dir`: after every statement with an
+/// `interval_secs` of 0, or else at most once per interval.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Autosave {
+    pub enabled: bool,
+    pub interval_secs: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LimitsConfig {
+    pub max_sql_size: usize,
+    pub max_depth: usize,
+    pub statement_timeout_ms: Option<u64>,
+    // how many selects each database keeps parsed, 0 for none
+    pub statement_cache_size: usize,
+    // of the views sent back by the server, see `response_limits`
+    pub response: ResponseLimits,
+    // over `response` for the users named, by name
+    pub roles: HashMap<String, ResponseLimits>,
+    // how long the rest of a truncated view is kept for its cursor to be
+    // fetched, since the view or the page before was sent
+    pub cursor_idle_secs: u64,
+    // websocket subscriptions are closed once the client hasn't answered a
+    // ping or sent anything for this long, 0 for never
+    pub session_idle_secs: u64,
+    // and once they've been open for this long, 0 for never
+    pub session_max_secs: u64,
+}
+
+/// How much of each view a query responds with at most, so that a careless
+/// `SELECT *` of a large table can't tie up the connection. What's left out
+/// is kept behind a cursor to fetch next. Without either, views are sent
+/// whole.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ResponseLimits {
+    pub max_rows: Option<usize>,
+    // roughly, counting the formatted values of the rows
+    pub max_bytes: Option<usize>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LogConfig {
+    // in the syntax of `RUST_LOG`, like `info,sqlparser=error`
+    pub level: String,
+}
+
+/// An upstream feed whose records are applied to `table`, of the default
+/// database unless `database` names another: json lines read from a
+/// `tcp://host:port` connection, or the json messages of a `ws://` websocket.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SourceConfig {
+    pub table: String,
+    pub database: Option<String>,
+    pub url: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AuditConfig {
+    pub max_events: usize,
+    pub max_age_secs: u64,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            listen: "127.0.0.1:8080".to_owned(),
+            data_dir: "data".into(),
+            tls: None,
+            auth: AuthConfig::default(),
+            autosave: Autosave::default(),
+            limits: LimitsConfig::default(),
+            log: LogConfig::default(),
+            acl: Acl::default(),
+            audit: AuditConfig::default(),
+            sources: Vec::new(),
+            replay_log: None,
+        }
+    }
+}
+
+impl Default for AuthConfig {
+    fn default() -> Self {
+        Self {
+            backend: AuthBackend::Users,
+            token_ttl_secs: 15 * 60,
+            signing_key: None,
+            admin_password: None,
+        }
+    }
+}
+
+impl Default for Autosave {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            interval_secs: 0,
+        }
+    }
+}
+
+impl Default for LimitsConfig {
+    fn default() -> Self {
+        let limits = Limits::default();
+        Self {
+            max_sql_size: limits.max_size,
+            max_depth: limits.max_depth,
+            statement_timeout_ms: None,
+            statement_cache_size: cache::DEFAULT_CAPACITY,
+            response: ResponseLimits::default(),
+            roles: HashMap::new(),
+            cursor_idle_secs: 5 * 60,
+            session_idle_secs: 60,
+            session_max_secs: 60 * 60,
+        }
+    }
+}
+
+impl LimitsConfig {
+    /// The limits on the responses to `user`: those of its role, where they
+    /// are set, or else the ones for everybody.
+    pub fn response_limits(&self, user: Option<&str>) -> ResponseLimits {
+        let role = user.and_then(|user| {
+            self.roles
+                .iter()
+                .find(|(role, _)| role.eq_ignore_ascii_case(user))
+                .map(|(_, limits)| limits)
+        });
+
+        match role {
+            Some(role) => ResponseLimits {
+                max_rows: role.max_rows.or(self.response.max_rows),
+                max_bytes: role.max_bytes.or(self.response.max_bytes),
+            },
+            None => self.response,
+        }
+    }
+
+    pub fn cursor_idle(&self) -> Duration {
+        Duration::from_secs(self.cursor_idle_secs)
+    }
+
+    pub fn session_idle(&self) -> Option<Duration> {
+        (self.session_idle_secs > 0).then(|| Duration::from_secs(self.session_idle_secs))
+    }
+
+    pub fn session_max(&self) -> Option<Duration> {
+        (self.session_max_secs > 0).then(|| Duration::from_secs(self.session_max_secs))
+    }
+}
+
+impl Default for LogConfig {
+    fn default() -> Self {
+        Self {
+            level: "error,rustyline=error,sqlparser=error".to_owned(),
+        }
+    }
+}
+
+impl Default for AuditConfig {
+    fn default() -> Self {
+        let retention = AuditRetention::default();
+        Self {
+            max_events: retention.max_events,
+            max_age_secs: retention.max_age.as_secs(),
+        }
+    }
+}
+
+impl Config {
+    /// Reads the config at `path`, with the environment applied over it.
+    pub fn load(path: &Path) -> Result<Self> {
+        let toml = std::fs::read_to_string(path)
+            .map_err(|e| Error::Config(format!("{}: {e}", path.display())))?;
+        let mut config: Config =
+            toml::from_str(&toml).map_err(|e| Error::Config(format!("{}: {e}", path.display())))?;
+        config.apply_env()?;
+        Ok(config)
+    }
+
+    /// Overrides the settings that the `SOCKETDB_*` environment variables
+    /// are set for.
+    pub fn apply_env(&mut self) -> Result<()> {
+        fn var(name: &str) -> Option<String> {
+            std::env::var(name).ok()
+        }
+        fn number<T: std::str::FromStr>(name: &str) -> Result<Option<T>> {
+            var(name)
+                .map(|v| {
+                    v.trim()
+                        .parse()
+                        .map_err(|_| Error::Config(format!("{name} isn't a number: {v:?}")))
+                })
+                .transpose()
+        }
+        // comma separated address blocks
+        fn blocks(name: &str) -> Result<Option<Vec<Cidr>>> {
+            var(name)
+                .map(|list| {
+                    list.split(',')
+                        .filter(|b| !b.trim().is_empty())
+                        .map(str::parse)
+                        .collect()
+                })
+                .transpose()
+        }
+
+        if let Some(listen) = var("SOCKETDB_LISTEN") {
+            self.listen = listen;
+        }
+        if let Some(dir) = var("SOCKETDB_DATA_DIR") {
+            self.data_dir = dir.into();
+        }
+        if let (Some(cert), Some(key)) = (var("SOCKETDB_TLS_CERT"), var("SOCKETDB_TLS_KEY")) {
+            self.tls = Some(TlsConfig {
+                cert: cert.into(),
+                key: key.into(),
+                client_ca: var("SOCKETDB_TLS_CLIENT_CA").map(Into::into),
+            });
+        }
+        if let Some(ttl) = number("SOCKETDB_TOKEN_TTL")? {
+            self.auth.token_ttl_secs = ttl;
+        }
+        if let Some(key) = var("SOCKETDB_SIGNING_KEY") {
+            self.auth.signing_key = Some(key);
+        }
+        if let Some(password) = var("SOCKETDB_ADMIN_PASSWORD") {
+            self.auth.admin_password = Some(password);
+        }
+        if let Some(allow) = blocks("SOCKETDB_ALLOW")? {
+            self.acl.allow = allow;
+        }
+        if let Some(deny) = blocks("SOCKETDB_DENY")? {
+            self.acl.deny = deny;
+        }
+        if let Some(max) = number("SOCKETDB_MAX_RESPONSE_ROWS")? {
+            self.limits.response.max_rows = Some(max);
+        }
+        if let Some(max) = number("SOCKETDB_MAX_RESPONSE_BYTES")? {
+            self.limits.response.max_bytes = Some(max);
+        }
+        if let Some(secs) = number("SOCKETDB_CURSOR_IDLE_TIMEOUT")? {
+            self.limits.cursor_idle_secs = secs;
+        }
+        if let Some(secs) = number("SOCKETDB_SESSION_IDLE_TIMEOUT")? {
+            self.limits.session_idle_secs = secs;
+        }
+        if let Some(max) = number("SOCKETDB_AUDIT_MAX_EVENTS")? {
+            self.audit.max_events = max;
+        }
+        if let Some(secs) = number("SOCKETDB_AUDIT_MAX_AGE")? {
+            self.audit.max_age_secs = secs;
+        }
+        if let Some(path) = var("SOCKETDB_REPLAY_LOG") {
+            self.replay_log = Some(path.into());
+        }
+        if let Some(level) = var("SOCKET_DB_LOG_LEVEL") {
+            self.log.level = level;
+        }
+
+        Ok(())
+    }
+
+    pub fn token_ttl(&self) -> Duration {
+        Duration::from_secs(self.auth.token_ttl_secs)
+    }
+
+    pub fn limits(&self) -> Limits {
+        Limits {
+            max_size: self.limits.max_sql_size,
+            max_depth: self.limits.max_depth,
+        }
+    }
+
+    pub fn statement_timeout(&self) -> Option<Duration> {
+        self.limits.statement_timeout_ms.map(Duration::from_millis)
+    }
+
+    pub fn statement_cache_size(&self) -> usize {
+        self.limits.statement_cache_size
+    }
+
+    pub fn audit_retention(&self) -> AuditRetention {
+        AuditRetention {
+            max_events: self.audit.max_events,
+            max_age: Duration::from_secs(self.audit.max_age_secs),
+        }
+    }
+}
@@ -1,23 +1,72 @@
 use crate::{
+    aggregate,
+    audit::{AuditKind, AuditLog},
+    deadline::{CancelToken, Deadline},
     evaluator::{Evaluator, OutColumn},
+    format, fulltext,
+    functions::{Functions, Session},
+    generate::{self, GenerateSeries},
+    join::{self, HashKey, Relation},
+    kernels,
+    load::{self, LoadRecord, LoadSummary},
     metacommands::MetaCommand,
-    parser::parser::{self, Query},
-    table::Table,
+    migrations::{self, Migration, MIGRATIONS_TABLE},
+    optimizer,
+    parser::{
+        cache::StatementCache,
+        expression::{Binary, Expression, Ident, Literal, Subquery},
+        parser::{self, Limits, Query, SqlDialect},
+        policy::PolicyCommand,
+        select::{Join, OrderBy, Select, SelectItem, SetOperator},
+        trigger::{RowImage, Trigger, TriggerTiming},
+    },
+    plan::Plan,
+    planner::{self, AccessPath},
+    regexp::Patterns,
+    replay::ReplayLog,
+    rowset::RowSet,
+    sample::Sample,
+    schema::{Schema, TableSchema},
+    sequence::{Sequence, Sequences},
+    sort::{self, SortKey},
+    source::{Source, SourceOp, SourceRecord},
+    sql,
+    system::{Started, System},
+    table::{Assigned, Column, ColumnData, ColumnHeader, RowId, Strictness, Table},
+    users::Users,
+    virtual_table::{self, VirtualTable, VirtualTables},
     Error, Result,
 };
 use serde::{Deserialize, Serialize};
 use std::{
-    collections::HashMap,
+    borrow::Cow,
+    collections::{hash_map::Entry, HashMap, HashSet, VecDeque},
     fmt::Display,
     fs::File,
     io::{BufReader, BufWriter, Read, Write},
     path::Path,
     str::FromStr,
+    sync::{Arc, RwLock},
+    time::{Duration, Instant},
 };
 
 use flume::{Receiver, Sender};
 
-#[derive(Debug, Default, Serialize, Deserialize)]
+// how many change events are kept around for subscribers resuming after a
+// reconnect
+const HISTORY_LEN: usize = 1024;
+
+// how deep triggers may fire other triggers before we give up
+const MAX_TRIGGER_DEPTH: usize = 16;
+
+// records of each attached source applied at once, so that a busy feed
+// doesn't hold up statements
+const SOURCE_BATCH: usize = 1024;
+
+// rows `.generate` inserts at once, each insert sending its own event
+const GENERATE_CHUNK: usize = 1000;
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
 pub(crate) struct Row {
     items: Vec<String>,
 }
@@ -31,40 +80,525 @@ impl Row {
 #[derive(Debug, Default, Serialize, Deserialize)]
 pub struct Database {
     tables: Vec<Table>,
+    // who can log in to a server holding the database, see `users`
+    #[serde(default)]
+    users: Arc<RwLock<Users>>,
+    // see `CREATE SEQUENCE`
+    #[serde(default)]
+    sequences: Sequences,
+    #[serde(skip)]
+    receiver: Option<Receiver<Subscription>>,
+    #[serde(skip)]
+    ws_map: HashMap<String, Vec<Subscriber>>,
+    // by lowercase channel name, see `listen`
+    #[serde(skip)]
+    listeners: HashMap<String, Vec<Sender<String>>>,
+    #[serde(skip)]
+    seq: u64,
+    #[serde(skip)]
+    history: VecDeque<ChangeEvent>,
+    #[serde(skip)]
+    hooks: ChangeHooks,
+    #[serde(skip)]
+    functions: Functions,
+    // of the statement being executed, see `start_deadline`
+    #[serde(skip)]
+    patterns: Patterns,
+    #[serde(skip)]
+    virtual_tables: VirtualTables,
+    #[serde(skip)]
+    trigger_depth: usize,
+    #[serde(skip)]
+    strictness: Strictness,
+    #[serde(skip)]
+    statement_timeout: Option<Duration>,
+    #[serde(skip)]
+    limits: Limits,
+    // of the selects `query` was given, see `set_statement_cache_size`
+    #[serde(skip)]
+    statement_cache: StatementCache,
+    #[serde(skip)]
+    dialect: SqlDialect,
+    #[serde(skip)]
+    display_precision: Option<usize>,
+    #[serde(skip)]
+    output_mode: OutputMode,
+    // see `set_deterministic_order`
+    #[serde(skip)]
+    deterministic_order: bool,
+    // most recently dropped last, see `UNDROP TABLE`
+    #[serde(skip)]
+    dropped: VecDeque<(Table, Instant)>,
+    #[serde(skip)]
+    drop_retention: DropRetention,
+    // in place of `statement_timeout`, see `run_with_timeout`
+    #[serde(skip)]
+    request_timeout: Option<Duration>,
+    // see `run_cancellable`
+    #[serde(skip)]
+    cancel: Option<CancelToken>,
+    // whom the statements are executed for, see `run_as`
+    #[serde(skip)]
+    session: Session,
+    // where permission denials and changes to users are recorded, if anywhere
+    #[serde(skip)]
+    audit: Option<AuditLog>,
+    // where every statement executed is recorded, if anywhere
+    #[serde(skip)]
+    replay: Option<ReplayLog>,
+    // see `attach_source`
+    #[serde(skip)]
+    sources: Vec<AttachedSource>,
+    // of the statement being executed, along with any it fires triggers for
+    #[serde(skip)]
+    deadline: Deadline,
+    // see `uptime()`
     #[serde(skip)]
-    receiver: Option<Receiver<(String, Sender<String>)>>,
+    started: Started,
+    // the notifications and replay records of the migration being applied,
+    // sent and written once it commits, see `migrate`
     #[serde(skip)]
-    ws_map: HashMap<String, Vec<Sender<String>>>,
+    held: Option<Vec<Held>>,
+}
+
+#[derive(Debug)]
+struct AttachedSource {
+    table: String,
+    records: Receiver<Result<SourceRecord>>,
+}
+
+#[derive(Debug)]
+enum Held {
+    // with the rows its view is of, see `notify_rows`
+    Change {
+        table: String,
+        kind: ChangeKind,
+        view: Option<View>,
+        rows: Option<Table>,
+    },
+    Notification(Notification),
+    // a statement for the replay log, see `record`
+    Record {
+        user: Option<String>,
+        sql: String,
+    },
+}
+
+/// How many dropped tables are kept around to be brought back with
+/// `UNDROP TABLE`, and for how long.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DropRetention {
+    pub max_tables: usize,
+    pub max_age: Duration,
+}
+
+impl Default for DropRetention {
+    fn default() -> Self {
+        Self {
+            max_tables: 16,
+            max_age: Duration::from_secs(60 * 60),
+        }
+    }
+}
+
+/// The key a table is looked up and subscribed to by, which is the same for
+/// every spelling of its name: `Orders`, `ORDERS` and `"orders"` alike.
+pub fn table_key(name: &str) -> String {
+    name.trim().trim_matches('"').to_lowercase()
+}
+
+type ChangeHook = Box<dyn Fn(&ChangeEvent) + Send + Sync>;
+
+// in-process listeners, keyed by lowercase table name (`*` for every table)
+#[derive(Default)]
+struct ChangeHooks(HashMap<String, Vec<ChangeHook>>);
+
+impl std::fmt::Debug for ChangeHooks {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_map()
+            .entries(self.0.iter().map(|(k, v)| (k, v.len())))
+            .finish()
+    }
+}
+
+// the receiving end of a subscription
+#[derive(Debug)]
+struct Subscriber {
+    sender: Sender<String>,
+    span: tracing::Span,
+    user: Option<String>,
+    columns: Option<Vec<String>>,
+    throttle: Option<Duration>,
+    sample_every: Option<u64>,
+    changes: u64,
+    last_sent: Option<Instant>,
+    // the latest event held back by `throttle`, see `flush`
+    pending: Option<String>,
+}
+
+impl Subscriber {
+    // sends `json` unless it isn't sampled, or holds on to it in place of
+    // the one before while throttled
+    fn offer(&mut self, json: &str, now: Instant) {
+        self.changes += 1;
+        if self.sample_every.is_some_and(|n| self.changes % n != 0) {
+            return;
+        }
+
+        if self.throttled(now) {
+            self.pending = Some(json.to_owned());
+        } else {
+            self.send(json.to_owned(), now);
+        }
+    }
+
+    fn flush(&mut self, now: Instant) {
+        if self.throttled(now) {
+            return;
+        }
+
+        if let Some(json) = self.pending.take() {
+            self.send(json, now);
+        }
+    }
+
+    fn throttled(&self, now: Instant) -> bool {
+        match (self.throttle, self.last_sent) {
+            (Some(interval), Some(at)) => now.duration_since(at) < interval,
+            _ => false,
+        }
+    }
+
+    fn send(&mut self, json: String, now: Instant) {
+        self.pending = None;
+        self.last_sent = Some(now);
+        _ = self.sender.send(json);
+    }
+}
+
+/// What listeners of a channel receive (serialized as json) for each `NOTIFY`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Notification {
+    pub channel: String,
+    pub payload: String,
+}
+
+/// A request to receive the change events of `table` through `sender`.
+///
+/// When `resume_from` is set, buffered events newer than that sequence number
+/// are replayed first. Subscriptions to tables that don't exist are refused
+/// with an error, and closed, unless `wait_for_table` is set, in which case
+/// events start once the table is created. With `columns`, the views in
+/// events hold only those columns.
+///
+/// `throttle` sends at most one event per interval, the latest, and
+/// `sample_every` only every nth change. Drops are always sent.
+///
+/// Sending each event is traced in `span`, e.g. of the request subscribing.
+/// The views in events only hold the rows the policies on the table let
+/// `user` see.
+#[derive(Debug, Clone)]
+pub struct Subscription {
+    pub table: String,
+    pub sender: Sender<String>,
+    pub span: tracing::Span,
+    pub user: Option<String>,
+    pub resume_from: Option<u64>,
+    pub wait_for_table: bool,
+    pub columns: Option<Vec<String>>,
+    pub throttle: Option<Duration>,
+    pub sample_every: Option<u64>,
 }
 
-#[derive(Debug, Default)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ChangeKind {
+    Insert,
+    Update,
+    Delete,
+    Truncate,
+    Drop,
+    Undrop,
+}
+
+/// What subscribers of a table receive (serialized as json) after it changes.
+/// The view is of the table as it is after the change, but for `Delete`,
+/// where it's of the rows deleted, as they were.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChangeEvent {
+    pub seq: u64,
+    pub table: String,
+    pub kind: ChangeKind,
+    pub view: Option<View>,
+}
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
 pub struct View {
     columns: Vec<String>,
     rows: Vec<Row>,
+    // whether rows were left out to keep the response within its limits,
+    // which the cursor, if there is one, fetches
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    truncated: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    cursor: Option<String>,
+}
+
+impl ChangeEvent {
+    // as sent to subscribers, with only `columns` of the view when given
+    fn to_json(&self, columns: Option<&[String]>) -> Option<String> {
+        let json = match (columns, &self.view) {
+            (Some(columns), Some(view)) => serde_json::to_string(&ChangeEvent {
+                seq: self.seq,
+                table: self.table.clone(),
+                kind: self.kind,
+                view: Some(view.project(columns)),
+            }),
+            _ => serde_json::to_string(self),
+        };
+
+        json.map_err(|e| log::error!("failed to serialize change event: {e}"))
+            .ok()
+    }
 }
 
 impl View {
     pub fn new(cols: Vec<OutColumn>) -> Self {
-        let columns = cols.iter().map(|c| c.name.clone()).collect();
+        RowIter::new(cols, None).into()
+    }
+
+    pub fn columns(&self) -> &[String] {
+        &self.columns
+    }
+
+    pub fn rows(&self) -> impl Iterator<Item = &[String]> {
+        self.rows.iter().map(|r| r.items.as_slice())
+    }
+
+    pub fn truncated(&self) -> bool {
+        self.truncated
+    }
+
+    /// What fetches the rows left out of the view, see `truncate`.
+    pub fn cursor(&self) -> Option<&str> {
+        self.cursor.as_deref()
+    }
+
+    pub fn set_cursor(&mut self, cursor: String) {
+        self.cursor = Some(cursor);
+    }
+
+    /// Leaves out the rows past the first `max_rows`, or past those that add
+    /// up to more than `max_bytes` (counting their values as they're sent in
+    /// json), returning them as a view of the same columns. The first row is
+    /// always kept, so that paging through the rest gets somewhere.
+    pub fn truncate(&mut self, max_rows: Option<usize>, max_bytes: Option<usize>) -> Option<View> {
+        let mut bytes = 0;
+        let kept = self
+            .rows
+            .iter()
+            .take(max_rows.unwrap_or(usize::MAX))
+            .take_while(|r| {
+                bytes += json_size(&r.items);
+                max_bytes.map_or(true, |max| bytes <= max)
+            })
+            .count()
+            .max(1);
+        if kept >= self.rows.len() {
+            return None;
+        }
+
+        self.truncated = true;
+        Some(View {
+            columns: self.columns.clone(),
+            rows: self.rows.split_off(kept),
+            ..Default::default()
+        })
+    }
 
-        let max_rows = cols.iter().map(|c| c.data.len()).max().unwrap_or(0);
+    /// Only `columns`, in the order given, matched ignoring case. Those the
+    /// view doesn't have are left out.
+    pub fn project(&self, columns: &[String]) -> View {
+        let idx: Vec<usize> = columns
+            .iter()
+            .filter_map(|c| self.columns.iter().position(|n| n.eq_ignore_ascii_case(c)))
+            .collect();
 
-        let mut rows = Vec::new();
-        for i in 0..=max_rows {
-            let mut row = Vec::new();
-            for col in &cols {
-                row.push(col.data.get_as_string(i).unwrap_or_default());
+        View {
+            columns: idx.iter().map(|&i| self.columns[i].clone()).collect(),
+            rows: self
+                .rows
+                .iter()
+                .map(|r| Row {
+                    items: idx
+                        .iter()
+                        .filter_map(|&i| r.items.get(i).cloned())
+                        .collect(),
+                })
+                .collect(),
+            ..Default::default()
+        }
+    }
+}
+
+/// The rows of a query result, formatted as they are iterated over.
+#[derive(Debug)]
+pub struct RowIter {
+    columns: Vec<String>,
+    data: Vec<(ColumnData, Option<usize>)>,
+    order: Order,
+    remaining: Option<usize>,
+}
+
+#[derive(Debug)]
+enum Order {
+    // every id in the set, ascending, from `next` on
+    Ids { ids: RowSet, next: RowId },
+    Sorted(std::vec::IntoIter<RowId>),
+}
+
+impl RowIter {
+    pub fn new(cols: Vec<OutColumn>, limit: Option<usize>) -> Self {
+        let ids = Self::row_ids(&cols);
+        Self::with_order(cols, Order::Ids { ids, next: 0 }, limit)
+    }
+
+    /// Gives the rows in the order of `ids`.
+    pub fn sorted(cols: Vec<OutColumn>, ids: Vec<RowId>) -> Self {
+        Self::with_order(cols, Order::Sorted(ids.into_iter()), None)
+    }
+
+    pub fn columns(&self) -> &[String] {
+        &self.columns
+    }
+
+    // every row that has a value in any of the columns
+    fn row_ids(cols: &[OutColumn]) -> RowSet {
+        let mut ids = RowSet::new();
+        for col in cols {
+            ids.union_with(&col.data.keys());
+        }
+        ids
+    }
+
+    /// The column names and data, along with the ids of the rows left, in
+    /// order.
+    pub(crate) fn into_parts(mut self) -> (Vec<String>, Vec<ColumnData>, Vec<RowId>) {
+        let ids = std::iter::from_fn(|| self.next_id()).collect();
+        let data = self.data.into_iter().map(|(data, _)| data).collect();
+        (self.columns, data, ids)
+    }
+
+    /// Leaves out the first `n` rows, which don't count towards the limit.
+    pub fn offset(mut self, n: usize) -> Self {
+        let remaining = self.remaining.take();
+        for _ in 0..n {
+            if self.next_id().is_none() {
+                break;
             }
+        }
+        self.remaining = remaining;
+        self
+    }
 
-            // don't show empty rows
-            if row.iter().all(|x| x.is_empty()) {
-                continue;
+    fn next_id(&mut self) -> Option<RowId> {
+        if self.remaining == Some(0) {
+            return None;
+        }
+
+        let id = match &mut self.order {
+            Order::Ids { ids, next } => {
+                let id = ids.next_from(*next)?;
+                *next = id + 1;
+                id
+            }
+            Order::Sorted(ids) => ids.next()?,
+        };
+        if let Some(remaining) = self.remaining.as_mut() {
+            *remaining -= 1;
+        }
+        Some(id)
+    }
+
+    /// Like `View::truncate`, leaves out the rows past the first `max_rows`,
+    /// or past those that add up to more than `max_bytes`, returning them as
+    /// a view.
+    pub fn truncate(&mut self, max_rows: Option<usize>, max_bytes: Option<usize>) -> Option<View> {
+        if max_rows.is_none() && max_bytes.is_none() {
+            return None;
+        }
+
+        let mut kept = Vec::new();
+        let mut rest = Vec::new();
+        let mut bytes = 0;
+        while kept.len() < max_rows.unwrap_or(usize::MAX).max(1) {
+            let Some(id) = self.next_id() else {
+                break;
+            };
+            if let Some(max) = max_bytes {
+                bytes += json_size(&self.format(id));
+                if bytes > max && !kept.is_empty() {
+                    rest.push(id);
+                    break;
+                }
             }
+            kept.push(id);
+        }
+        rest.extend(std::iter::from_fn(|| self.next_id()));
+        self.order = Order::Sorted(kept.into_iter());
+        self.remaining = None;
+
+        if rest.is_empty() {
+            return None;
+        }
+        Some(View {
+            columns: self.columns.clone(),
+            rows: rest
+                .into_iter()
+                .map(|id| Row {
+                    items: self.format(id),
+                })
+                .collect(),
+            ..Default::default()
+        })
+    }
+
+    fn format(&self, id: RowId) -> Vec<String> {
+        self.data
+            .iter()
+            .map(|(d, precision)| d.get_as_string(id, *precision).unwrap_or_default())
+            .collect()
+    }
 
-            rows.push(Row { items: row });
+    fn with_order(cols: Vec<OutColumn>, order: Order, limit: Option<usize>) -> Self {
+        let (columns, data) = cols
+            .into_iter()
+            .map(|c| (c.name, (c.data, c.precision)))
+            .unzip();
+        Self {
+            columns,
+            data,
+            order,
+            remaining: limit,
         }
+    }
+}
+
+impl Iterator for RowIter {
+    type Item = Vec<String>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let id = self.next_id()?;
+        Some(self.format(id))
+    }
+}
 
-        Self { columns, rows }
+impl From<RowIter> for View {
+    fn from(rows: RowIter) -> Self {
+        Self {
+            columns: rows.columns.clone(),
+            rows: rows.map(|items| Row { items }).collect(),
+            ..Default::default()
+        }
     }
 }
 
@@ -111,12 +645,316 @@ impl Display for View {
     }
 }
 
+impl View {
+    /// A GitHub flavored markdown table, with `|` escaped and line breaks as
+    /// `<br>`.
+    pub fn to_markdown(&self) -> String {
+        fn line<'a>(cells: impl Iterator<Item = &'a String>) -> String {
+            let cells: Vec<String> = cells
+                .map(|c| c.replace('|', "\\|").replace('\n', "<br>"))
+                .collect();
+            format!("| {} |\n", cells.join(" | "))
+        }
+
+        let mut md = line(self.columns.iter());
+        md.push_str(&format!("|{}\n", " --- |".repeat(self.columns.len())));
+        for row in self.rows.iter().filter(|r| !r.is_empty()) {
+            md.push_str(&line(row.items.iter()));
+        }
+        md
+    }
+
+    /// An html `<table>`, with the column names in its `<thead>` and every
+    /// value escaped.
+    pub fn to_html(&self) -> String {
+        fn line<'a>(cells: impl Iterator<Item = &'a String>, tag: &str) -> String {
+            let cells: String = cells
+                .map(|c| format!("<{tag}>{}</{tag}>", escape_html(c)))
+                .collect();
+            format!("<tr>{cells}</tr>\n")
+        }
+
+        let mut html = String::from("<table>\n<thead>\n");
+        html.push_str(&line(self.columns.iter(), "th"));
+        html.push_str("</thead>\n<tbody>\n");
+        for row in self.rows.iter().filter(|r| !r.is_empty()) {
+            html.push_str(&line(row.items.iter(), "td"));
+        }
+        html.push_str("</tbody>\n</table>\n");
+        html
+    }
+
+    /// Comma separated values, with a header of the column names, quoting
+    /// values with commas, quotes or line breaks in them.
+    pub fn to_csv(&self) -> String {
+        fn line<'a>(cells: impl Iterator<Item = &'a String>) -> String {
+            let cells: Vec<String> = cells
+                .map(|c| {
+                    if c.contains([',', '"', '\n', '\r']) {
+                        format!("\"{}\"", c.replace('"', "\"\""))
+                    } else {
+                        c.clone()
+                    }
+                })
+                .collect();
+            format!("{}\n", cells.join(","))
+        }
+
+        let mut csv = line(self.columns.iter());
+        for row in self.rows.iter().filter(|r| !r.is_empty()) {
+            csv.push_str(&line(row.items.iter()));
+        }
+        csv
+    }
+
+    /// As the repl prints it in `mode`.
+    pub fn render(&self, mode: OutputMode) -> String {
+        match mode {
+            OutputMode::Table => self.to_string(),
+            OutputMode::Markdown => self.to_markdown(),
+            OutputMode::Html => self.to_html(),
+            OutputMode::Csv => self.to_csv(),
+            OutputMode::Json => serde_json::to_string(self).expect("views serialize"),
+        }
+    }
+}
+
+// about how many bytes a row takes up in json, each value being quoted and
+// followed by a comma
+fn json_size(items: &[String]) -> usize {
+    items.iter().map(|i| i.len() + 3).sum()
+}
+
+fn escape_html(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// How the repl prints views, set with `.mode`, and what the server sends
+/// them as for the media type of the same name, see `from_media_type`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum OutputMode {
+    #[default]
+    Table,
+    Markdown,
+    Html,
+    Csv,
+    // as the server sends views by default
+    Json,
+}
+
+impl FromStr for OutputMode {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_lowercase().as_str() {
+            "table" => Ok(OutputMode::Table),
+            "markdown" => Ok(OutputMode::Markdown),
+            "html" => Ok(OutputMode::Html),
+            "csv" => Ok(OutputMode::Csv),
+            "json" => Ok(OutputMode::Json),
+            _ => Err(Error::InvalidMetaCommand(format!(
+                "unknown mode {s}, expected table, markdown, html, csv or json"
+            ))),
+        }
+    }
+}
+
+impl OutputMode {
+    /// The mode of a media type without its parameters, like `text/csv`.
+    pub fn from_media_type(media_type: &str) -> Option<Self> {
+        match media_type {
+            "text/plain" => Some(OutputMode::Table),
+            "text/markdown" => Some(OutputMode::Markdown),
+            "text/html" => Some(OutputMode::Html),
+            "text/csv" => Some(OutputMode::Csv),
+            "application/json" => Some(OutputMode::Json),
+            _ => None,
+        }
+    }
+
+    pub fn media_type(self) -> &'static str {
+        match self {
+            OutputMode::Table => "text/plain; charset=utf-8",
+            OutputMode::Markdown => "text/markdown; charset=utf-8",
+            OutputMode::Html => "text/html; charset=utf-8",
+            OutputMode::Csv => "text/csv; charset=utf-8",
+            OutputMode::Json => "application/json",
+        }
+    }
+}
+
 impl Database {
     pub fn new() -> Self {
         log::debug!("creating a new database");
         Self::default()
     }
 
+    /// A new database that handles values which don't fit their column as
+    /// `strictness` says, until changed with `SET sql_strictness`.
+    pub fn with_strictness(strictness: Strictness) -> Self {
+        Self {
+            strictness,
+            ..Self::default()
+        }
+    }
+
+    pub fn strictness(&self) -> Strictness {
+        self.strictness
+    }
+
+    /// The flavor of sql that statements are read as, unless a session
+    /// changes it for itself with `SET dialect`.
+    pub fn set_dialect(&mut self, dialect: SqlDialect) {
+        self.dialect = dialect;
+    }
+
+    pub fn dialect(&self) -> SqlDialect {
+        self.dialect
+    }
+
+    /// How long a statement may run for before it is abandoned with
+    /// `Error::Timeout`, or `None` for as long as it takes. Sessions can make
+    /// it shorter for themselves with `SET statement_timeout`, in
+    /// milliseconds, but not longer.
+    pub fn set_statement_timeout(&mut self, timeout: Option<Duration>) {
+        self.statement_timeout = timeout;
+    }
+
+    pub fn statement_timeout(&self) -> Option<Duration> {
+        self.statement_timeout
+    }
+
+    /// How many digits after the decimal point floating point values are
+    /// shown with, where their column doesn't say, or `None` for as many as
+    /// it takes to tell them apart. Sessions can set their own with
+    /// `SET display_precision`, which the change events sent to subscribers
+    /// don't follow.
+    pub fn set_display_precision(&mut self, precision: Option<usize>) {
+        self.display_precision = precision;
+    }
+
+    pub fn display_precision(&self) -> Option<usize> {
+        self.display_precision
+    }
+
+    // the one the views given to the session are shown with
+    fn session_precision(&self) -> Option<usize> {
+        self.session.display_precision.or(self.display_precision)
+    }
+
+    /// Whether selects without `ORDER BY` are put in order of the primary
+    /// keys of their tables, so that the same rows always come out the same
+    /// way, e.g. for comparing results. Can also be set with
+    /// `SET deterministic_order`.
+    pub fn set_deterministic_order(&mut self, on: bool) {
+        self.deterministic_order = on;
+    }
+
+    pub fn deterministic_order(&self) -> bool {
+        self.deterministic_order
+    }
+
+    /// Dropped tables beyond these are gone for good, rather than waiting to
+    /// be brought back with `UNDROP TABLE`. Zero tables turns it off.
+    pub fn set_drop_retention(&mut self, retention: DropRetention) {
+        self.drop_retention = retention;
+        self.expire_dropped();
+    }
+
+    pub fn drop_retention(&self) -> DropRetention {
+        self.drop_retention
+    }
+
+    /// Bounds on the size of the sql given to `query`, and how deeply its
+    /// expressions may nest.
+    pub fn set_limits(&mut self, limits: Limits) {
+        self.limits = limits;
+    }
+
+    pub fn limits(&self) -> Limits {
+        self.limits
+    }
+
+    /// How many selects are kept parsed, by their sql with the values in them
+    /// taken out, so that running them again skips parsing. Zero turns it off.
+    pub fn set_statement_cache_size(&mut self, size: usize) {
+        self.statement_cache.set_capacity(size);
+    }
+
+    pub fn statement_cache_size(&self) -> usize {
+        self.statement_cache.capacity()
+    }
+
+    /// Calls `f` with `timeout`, when given, applying to the statements it
+    /// executes along with the statement timeout, e.g. for a single request.
+    /// The shorter of the two is the one that ends them.
+    pub fn run_with_timeout<T>(
+        &mut self,
+        timeout: Option<Duration>,
+        f: impl FnOnce(&mut Self) -> T,
+    ) -> T {
+        self.request_timeout = timeout;
+        let out = f(self);
+        self.request_timeout = None;
+        out
+    }
+
+    /// Calls `f` with the statements it executes running in `session`, whose
+    /// user is restricted by the policies on tables. Without a user, as for
+    /// the repl, policies don't apply.
+    pub fn run_as<T>(&mut self, session: Session, f: impl FnOnce(&mut Self) -> T) -> T {
+        self.session = session;
+        let out = f(self);
+        self.session = Session::default();
+        out
+    }
+
+    /// The users that can log in, see `CREATE USER`, shared so that a server
+    /// can check credentials without waiting on the statements being executed.
+    pub fn users(&self) -> Arc<RwLock<Users>> {
+        self.users.clone()
+    }
+
+    /// Makes the users of this database `users`, those of another one, e.g.
+    /// so that the named databases of a server know whoever can log in to it.
+    pub fn set_users(&mut self, users: Arc<RwLock<Users>>) {
+        self.users = users;
+    }
+
+    /// Records statements refused for lack of permission, and changes to
+    /// users, in `audit`, along with who made them.
+    pub fn set_audit_log(&mut self, audit: AuditLog) {
+        self.audit = Some(audit);
+    }
+
+    /// Records every statement executed through `query` and `query_rows` in
+    /// `replay`, to be executed again with `replay::replay`, along with the
+    /// sql equivalent to the writes of loads, imports, sources, `generate` and
+    /// `expire_rows`.
+    pub fn set_replay_log(&mut self, replay: ReplayLog) {
+        self.replay = Some(replay);
+    }
+
+    /// Calls `f` with the statements it executes stopping with
+    /// `Error::Cancelled` once `token` is cancelled.
+    pub fn run_cancellable<T>(&mut self, token: CancelToken, f: impl FnOnce(&mut Self) -> T) -> T {
+        self.cancel = Some(token);
+        let out = f(self);
+        self.cancel = None;
+        out
+    }
+
     pub fn open(path: impl AsRef<Path>) -> Result<Self> {
         let path = path.as_ref();
 
@@ -130,246 +968,2822 @@ impl Database {
         file.read_to_end(&mut buf)?;
 
         log::debug!("deserializing from bincode");
-        let db = bincode::deserialize(&buf)?;
+        let db: Database = bincode::deserialize(&buf)?;
+        for table in &db.tables {
+            table.validate()?;
+        }
 
         log::info!("opened database: `{}`", path.display());
 
         Ok(db)
     }
 
+    /// A copy of the tables, detached from any subscribers and hooks, so that it
+    /// can be changed freely without affecting this database. Column data is
+    /// shared until either side changes it. Registered functions, virtual
+    /// tables and settings are carried over, but not the audit and replay
+    /// logs, which are for the owner of the copy to set.
+    pub fn snapshot(&self) -> Database {
+        Database {
+            tables: self.tables.clone(),
+            users: Arc::new(RwLock::new(self.users.read().unwrap().clone())),
+            sequences: self.sequences.clone(),
+            functions: self.functions.clone(),
+            virtual_tables: self.virtual_tables.clone(),
+            strictness: self.strictness,
+            statement_timeout: self.statement_timeout,
+            limits: self.limits,
+            statement_cache: StatementCache::new(self.statement_cache.capacity()),
+            dialect: self.dialect,
+            display_precision: self.display_precision,
+            output_mode: self.output_mode,
+            deterministic_order: self.deterministic_order,
+            drop_retention: self.drop_retention,
+            started: self.started,
+            ..Default::default()
+        }
+    }
+
+    pub fn table(&self, name: &str) -> Option<&Table> {
+        self.tables
+            .iter()
+            .find(|t| table_key(&t.name) == table_key(name))
+    }
+
     pub fn recv_senders(&mut self) -> Result<()> {
         let Some(ref rx) = self.receiver else {
             return Ok(());
         };
 
-        if let Ok((tbl_name, sender)) = rx.try_recv() {
-            log::info!("subscribed to table: {tbl_name}");
-            self.ws_map
-                .entry(tbl_name)
-                .and_modify(|v| v.push(sender.clone()))
-                .or_insert(vec![sender]);
+        while let Ok(sub) = rx.try_recv() {
+            let key = table_key(&sub.table);
+            let refusal = match self.table(&key) {
+                None if sub.wait_for_table => None,
+                // virtual tables never change, so there's nothing to subscribe to
+                None => Some(self.table_not_found(&sub.table)),
+                Some(table) => sub
+                    .columns
+                    .iter()
+                    .flatten()
+                    .find(|c| table.col_from_name(c).is_none())
+                    .map(|c| table.column_not_found(c)),
+            };
+            if let Some(error) = refusal {
+                log::info!("rejected subscription to table {}: {error}", sub.table);
+                _ = sub.sender.send(error.to_json().to_string());
+                continue;
+            }
+            log::info!("subscribed to table: {}", sub.table);
+
+            if let Some(resume_from) = sub.resume_from {
+                // which rows the user could see back then isn't known, so
+                // they only learn that something changed
+                let restricted =
+                    sub.user.is_some() && self.table(&key).is_some_and(|t| !t.policies.is_empty());
+                for event in self
+                    .history
+                    .iter()
+                    .filter(|e| e.seq > resume_from && table_key(&e.table) == key)
+                {
+                    let json = match restricted {
+                        true => ChangeEvent {
+                            view: None,
+                            ..event.clone()
+                        }
+                        .to_json(None),
+                        false => event.to_json(sub.columns.as_deref()),
+                    };
+                    if let Some(json) = json {
+                        _ = sub.sender.send(json);
+                    }
+                }
+            }
+
+            self.ws_map.entry(key).or_default().push(Subscriber {
+                sender: sub.sender,
+                span: sub.span,
+                user: sub.user,
+                columns: sub.columns,
+                throttle: sub.throttle,
+                sample_every: sub.sample_every.filter(|&n| n > 1),
+                changes: 0,
+                last_sent: None,
+                pending: None,
+            });
         }
 
         Ok(())
     }
 
-    pub fn set_receiver(&mut self, receiver: Receiver<(String, Sender<String>)>) {
-        self.receiver = Some(receiver);
+    /// Sends the events that throttled subscriptions held back, once their
+    /// interval is up. Called regularly, e.g. along with `recv_senders`.
+    pub fn flush_subscriptions(&mut self) {
+        let now = Instant::now();
+        for sub in self.ws_map.values_mut().flatten() {
+            sub.flush(now);
+        }
     }
 
-    pub fn execute(&mut self, query: Query) -> Result<Option<View>> {
-        self.recv_senders()?;
+    /// Sends what is given to `NOTIFY channel` through `sender`, as a json
+    /// `Notification`, until it is unlistened or its receiver goes away.
+    pub fn listen(&mut self, channel: &str, sender: Sender<String>) {
+        let senders = self.listeners.entry(channel.to_lowercase()).or_default();
+        if !senders.iter().any(|s| s.same_channel(&sender)) {
+            senders.push(sender);
+        }
+    }
+
+    /// Stops sending notifications of `channel`, or of every channel for `*`,
+    /// through `sender`.
+    pub fn unlisten(&mut self, channel: &str, sender: &Sender<String>) {
+        for (name, senders) in self.listeners.iter_mut() {
+            if channel == "*" || *name == channel.to_lowercase() {
+                senders.retain(|s| !s.same_channel(sender));
+            }
+        }
+        self.listeners.retain(|_, senders| !senders.is_empty());
+    }
+
+    pub fn set_receiver(&mut self, receiver: Receiver<Subscription>) {
+        self.receiver = Some(receiver);
+    }
+
+    /// Writes the tables to `path`, zstd compressed, for `restore`.
+    pub fn persist(&self, path: &Path) -> Result<()> {
+        let json = serde_json::to_string(&self).map_err(|e| Error::IOError(e.to_string()))?;
+
+        let file = File::create(path)?;
+        let buf = BufWriter::new(file);
+
+        let mut encoder = zstd::Encoder::new(buf, 3)?;
+        encoder.write_all(json.as_bytes())?;
+        encoder.finish()?;
+
+        Ok(())
+    }
+
+    /// Reads back a database written by `persist`.
+    pub fn restore(path: &Path) -> Result<Database> {
+        let file = File::open(path)?;
+        let buf = BufReader::new(file);
+
+        let decoded = zstd::decode_all(buf)?;
+        let mut db: Database = serde_json::from_slice(&decoded)
+            .map_err(|e| Error::DeserializingError(e.to_string()))?;
+        for table in &db.tables {
+            table.validate()?;
+        }
+
+        for table in db.tables.iter_mut() {
+            table.rebuild_pk_map();
+            table.rebuild_dictionaries();
+            table.rebuild_fulltext();
+        }
+
+        Ok(db)
+    }
+
+    /// Calls `hook` with every change made to `table`, or to any table when
+    /// `table` is `*`, from within the thread executing the statement.
+    pub fn on_change(&mut self, table: &str, hook: impl Fn(&ChangeEvent) + Send + Sync + 'static) {
+        self.hooks
+            .0
+            .entry(table_key(table))
+            .or_default()
+            .push(Box::new(hook));
+    }
+
+    /// Makes `function` callable from sql as `name(args...)`, replacing any
+    /// function previously registered under the same (case insensitive) name.
+    pub fn register_function(
+        &mut self,
+        name: &str,
+        function: impl Fn(&[Literal]) -> Result<Literal> + Send + Sync + 'static,
+    ) {
+        self.functions.register(name, function);
+    }
+
+    /// Deletes the rows of `table` matching `predicate`, going through the same
+    /// triggers and notifications as a `DELETE`, and returns how many there were.
+    /// Combined with the `__inserted_at` column this expires old rows, e.g.
+    /// `db.expire_rows("cache", col(INSERTED_AT).lt(cutoff))`.
+    pub fn expire_rows(&mut self, table: &str, predicate: impl Into<Expression>) -> Result<usize> {
+        let before = self.tables[self.table_index(table)?].row_ids().len();
+
+        self.execute_recorded(Query::Delete {
+            table: table.to_owned(),
+            selection: Some(predicate.into()),
+            returning: Vec::new(),
+        })?;
+
+        let after = self.tables[self.table_index(table)?].row_ids().len();
+        Ok(before.saturating_sub(after))
+    }
+
+    /// Makes `table` selectable from as `name`. Virtual tables are read only,
+    /// and are scanned again by every select that reads from them.
+    pub fn register_virtual_table(
+        &mut self,
+        name: &str,
+        table: impl VirtualTable + 'static,
+    ) -> Result<()> {
+        if self.table(name).is_some() {
+            return Err(Error::TableAlreadyExists(name.to_owned()));
+        }
+
+        self.virtual_tables.register(name, table);
+        Ok(())
+    }
+
+    /// Applies the records of `source` to `table` as they arrive, like the
+    /// statements inserting, updating and deleting them would, so that
+    /// subscribers see them as changes. It's read on a thread of its own,
+    /// while its records are applied by `apply_sources`.
+    pub fn attach_source(&mut self, table: &str, mut source: impl Source) -> Result<()> {
+        let name = self.tables[self.table_index(table)?].name.clone();
+        let (tx, rx) = flume::bounded(SOURCE_BATCH);
+        std::thread::spawn(move || {
+            while let Some(record) = source.next_record() {
+                if tx.send(record).is_err() {
+                    break;
+                }
+            }
+        });
+
+        self.sources.push(AttachedSource {
+            table: name,
+            records: rx,
+        });
+        Ok(())
+    }
+
+    /// Applies the records that arrived from attached sources since it was
+    /// last called, returning how many were. Records that can't be applied are
+    /// skipped, and sources whose feed ended are detached.
+    pub fn apply_sources(&mut self) -> usize {
+        let mut applied = 0;
+        let sources = std::mem::take(&mut self.sources);
+        for source in &sources {
+            for record in source.records.try_iter().take(SOURCE_BATCH) {
+                match record.and_then(|r| self.apply_record(&source.table, r)) {
+                    Ok(()) => applied += 1,
+                    Err(e) => log::error!("skipped a record for {}: {e}", source.table),
+                }
+            }
+        }
+
+        self.sources = sources
+            .into_iter()
+            .filter(|s| {
+                let ended = s.records.is_disconnected() && s.records.is_empty();
+                if ended {
+                    log::info!("the source of {} ended", s.table);
+                }
+                !ended
+            })
+            .collect();
+        applied
+    }
+
+    fn apply_record(&mut self, table: &str, record: SourceRecord) -> Result<()> {
+        let SourceRecord { op, row } = record;
+        if op == SourceOp::Insert {
+            let (columns, values) = row.into_iter().unzip();
+            self.execute_recorded(Query::Insert {
+                table: table.to_owned(),
+                columns,
+                sources: vec![values],
+                returning: Vec::new(),
+            })?;
+            return Ok(());
+        }
+
+        let pk = self
+            .table(table)
+            .and_then(|t| t.pk_column())
+            .map(|c| c.header.name.clone())
+            .ok_or_else(|| self.table_not_found(table))?;
+        let mut assignments: HashMap<String, Expression> = row
+            .into_iter()
+            .map(|(k, v)| (k, Expression::Literal(v)))
+            .collect();
+        let key = assignments
+            .keys()
+            .find(|c| c.eq_ignore_ascii_case(&pk))
+            .cloned();
+        let Some(Expression::Literal(value)) = key.and_then(|k| assignments.remove(&k)) else {
+            return Err(Error::InvalidOperation(format!(
+                "update or delete of a record without its primary key {pk}"
+            )));
+        };
+
+        let selection = Some(Expression::Binary {
+            operator: Binary::Eq,
+            left: Box::new(Expression::Ident(Ident::Named(pk))),
+            right: Box::new(Expression::Literal(value)),
+        });
+        let table = table.to_owned();
+        match op {
+            SourceOp::Update => self.execute_recorded(Query::Update {
+                table,
+                assignments,
+                selection,
+            })?,
+            _ => self.execute_recorded(Query::Delete {
+                table,
+                selection,
+                returning: Vec::new(),
+            })?,
+        };
+        Ok(())
+    }
+
+    fn notify(&mut self, table: &str, kind: ChangeKind, view: Option<View>) {
+        self.notify_rows(table, kind, view, None);
+    }
+
+    // like `notify`, with `view` being of `rows` rather than of the table as
+    // it is, which the policies of subscribers are then applied to instead
+    fn notify_rows(
+        &mut self,
+        table: &str,
+        kind: ChangeKind,
+        view: Option<View>,
+        rows: Option<&Table>,
+    ) {
+        if let Some(held) = &mut self.held {
+            held.push(Held::Change {
+                table: table.to_owned(),
+                kind,
+                view,
+                rows: rows.cloned(),
+            });
+            return;
+        }
+
+        let span = tracing::debug_span!("notify", table, seq = self.seq + 1);
+        let _notify = span.enter();
+        self.seq += 1;
+        let event = ChangeEvent {
+            seq: self.seq,
+            table: table.to_owned(),
+            kind,
+            view,
+        };
+
+        let restricted = self.restricted_views(table, rows, &event);
+        if let Some(subs) = self.ws_map.get_mut(&table_key(table)) {
+            let now = Instant::now();
+            // serialized once for each set of columns and user subscribed with
+            let mut serialized = Vec::<(_, Option<usize>, Option<String>)>::new();
+            for sub in subs {
+                // in the trace of the subscription, as a result of the change
+                let send = tracing::debug_span!(parent: &sub.span, "send", seq = self.seq);
+                send.follows_from(&span);
+                let _send = send.entered();
+                // which of the restricted views the subscriber gets, if any
+                let view = sub
+                    .user
+                    .as_deref()
+                    .and_then(|u| restricted.iter().position(|(r, _)| r == u));
+                let json = match serialized
+                    .iter()
+                    .find(|(c, v, _)| *c == sub.columns && *v == view)
+                {
+                    Some((_, _, json)) => json,
+                    None => {
+                        let json = match view {
+                            Some(v) => ChangeEvent {
+                                view: Some(restricted[v].1.clone()),
+                                ..event.clone()
+                            }
+                            .to_json(sub.columns.as_deref()),
+                            None => event.to_json(sub.columns.as_deref()),
+                        };
+                        serialized.push((sub.columns.clone(), view, json));
+                        &serialized[serialized.len() - 1].2
+                    }
+                };
+
+                match json {
+                    // nothing comes after it, so it can't wait
+                    Some(json) if kind == ChangeKind::Drop => sub.send(json.clone(), now),
+                    Some(json) => sub.offer(json, now),
+                    None => {}
+                }
+            }
+        }
+
+        for key in [table_key(table), "*".to_owned()] {
+            for hook in self.hooks.0.get(&key).into_iter().flatten() {
+                hook(&event);
+            }
+        }
+
+        if self.history.len() == HISTORY_LEN {
+            self.history.pop_front();
+        }
+        self.history.push_back(event);
+    }
+
+    fn send_notification(&mut self, notification: Notification) -> Result<()> {
+        let Some(senders) = self.listeners.get_mut(&notification.channel.to_lowercase()) else {
+            return Ok(());
+        };
+
+        let json = serde_json::to_string(&notification)
+            .map_err(|e| Error::InvalidOperation(e.to_string()))?;
+        // listeners that went away are forgotten
+        senders.retain(|s| s.send(json.clone()).is_ok());
+        Ok(())
+    }
+
+    // sends and records what was held back while a migration was applied,
+    // in order, ending the subscriptions to the tables it dropped once
+    // they've seen the drop
+    fn release(&mut self, held: Vec<Held>) -> Result<()> {
+        for held in held {
+            match held {
+                Held::Change {
+                    table,
+                    kind,
+                    view,
+                    rows,
+                } => {
+                    self.notify_rows(&table, kind, view, rows.as_ref());
+                    if kind == ChangeKind::Drop {
+                        self.ws_map.remove(&table_key(&table));
+                    }
+                }
+                Held::Notification(notification) => self.send_notification(notification)?,
+                Held::Record { user, sql } => {
+                    if let Some(replay) = &self.replay {
+                        replay.record(user.as_deref(), &sql);
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub fn execute(&mut self, query: Query) -> Result<Option<View>> {
+        let _span = tracing::debug_span!("execute", statement = query.name()).entered();
+        self.recv_senders()?;
+
+        // statements fired by triggers count towards the one firing them
+        if self.trigger_depth == 0 {
+            self.start_deadline();
+        }
+
+        // the tables the cached selects were parsed against are changing
+        if matches!(
+            query,
+            Query::CreateTable { .. } | Query::Drop(_) | Query::Undrop(_)
+        ) {
+            self.statement_cache.clear();
+        }
 
         match query {
             parser::Query::CreateTable { name, columns } => {
                 if self
                     .tables
                     .iter()
-                    .any(|t| t.name.to_lowercase() == name.to_lowercase())
+                    .any(|t| table_key(&t.name) == table_key(&name))
+                    || self.virtual_tables.get(&name).is_some()
                 {
                     log::error!("table {name} already exists");
                     return Err(Error::TableAlreadyExists(name));
                 } else {
-                    let table = Table::new(name.to_string().to_uppercase(), columns);
+                    let table = Table::new(name.to_string().to_uppercase(), columns)?;
                     self.tables.push(table);
                     log::debug!("created table: {name}");
                 }
             }
             parser::Query::Truncate(tbl_name) => {
-                match self
+                let idx = self.table_index(&tbl_name)?;
+                let name = self.tables[idx].name.clone();
+                if self.session.user.is_some() && !self.tables[idx].policies.is_empty() {
+                    return Err(self.permission_denied(format!(
+                        "truncate of {name}, which has policies restricting which rows can be deleted"
+                    )));
+                }
+
+                self.fire_triggers(&name, TriggerTiming::Before, ChangeKind::Truncate, &[])?;
+                self.tables[idx].truncate();
+                self.notify(&name, ChangeKind::Truncate, None);
+                self.fire_triggers(&name, TriggerTiming::After, ChangeKind::Truncate, &[])?;
+            }
+            parser::Query::Select(select) => {
+                return Ok(Some(self.select(select)?.into()));
+            }
+            Query::Insert {
+                table,
+                columns,
+                sources,
+                returning,
+            } => {
+                let idx = self.table_index(&table)?;
+                let (columns, sources) = self.with_defaults(idx, columns, sources)?;
+                let tbl = &self.tables[idx];
+                let name = tbl.name.clone();
+
+                let images = if has_row_triggers(tbl, ChangeKind::Insert) {
+                    insert_images(tbl, &columns, &sources)
+                } else {
+                    Vec::new()
+                };
+
+                self.fire_triggers(&name, TriggerTiming::Before, ChangeKind::Insert, &images)?;
+
+                let first = self.tables[idx].next_row_id();
+                let inserted = first..first + sources.len();
+                self.tables[idx].insert(columns, sources, self.strictness)?;
+                // as the rows were inserted, before the triggers after
+                let returned = match returning.is_empty() {
+                    true => None,
+                    false => Some(self.returning(&self.tables[idx], inserted, &returning)?),
+                };
+
+                let view = self.table_view(idx);
+                self.notify(&name, ChangeKind::Insert, Some(view));
+                log::info!("sent insert updates");
+
+                self.fire_triggers(&name, TriggerTiming::After, ChangeKind::Insert, &images)?;
+                if returned.is_some() {
+                    return Ok(returned);
+                }
+            }
+            Query::Drop(table) => {
+                let Some(idx) = self
+                    .tables
+                    .iter()
+                    .position(|t| table_key(&t.name) == table_key(&table))
+                else {
+                    return Ok(None);
+                };
+
+                let table = self.tables.remove(idx);
+                let name = table.name.clone();
+                self.dropped.push_back((table, Instant::now()));
+                self.expire_dropped();
+                self.notify(&name, ChangeKind::Drop, None);
+                // which ends their subscriptions, once they have seen the drop,
+                // or when it's released if it's held back, see `release`
+                if self.held.is_none() {
+                    self.ws_map.remove(&table_key(&name));
+                }
+                log::debug!("dropped table: {name}");
+            }
+            Query::Undrop(table) => {
+                self.expire_dropped();
+                let Some(idx) = self
+                    .dropped
+                    .iter()
+                    .rposition(|(t, _)| table_key(&t.name) == table_key(&table))
+                else {
+                    let dropped = self.dropped.iter().map(|(t, _)| t.name.as_str());
+                    return Err(Error::table_not_found(&table, dropped));
+                };
+
+                if self
                     .tables
-                    .iter_mut()
-                    .find(|t| t.name.to_lowercase() == tbl_name.to_lowercase())
+                    .iter()
+                    .any(|t| table_key(&t.name) == table_key(&table))
+                    || self.virtual_tables.get(&table).is_some()
                 {
-                    Some(tbl) => {
-                        tbl.truncate();
-                        if let Some(txs) = self.ws_map.get(&tbl.name.to_lowercase()) {
-                            for tx in txs {
-                                _ = tx.send(format!("table: {tbl_name} truncated"));
-                            }
+                    return Err(Error::TableAlreadyExists(table));
+                }
+
+                if let Some((table, _)) = self.dropped.remove(idx) {
+                    let name = table.name.clone();
+                    self.tables.push(table);
+                    let view = self.table_view(self.tables.len() - 1);
+                    self.notify(&name, ChangeKind::Undrop, Some(view));
+                    log::debug!("undropped table: {name}");
+                }
+            }
+            Query::Update {
+                table,
+                assignments,
+                selection,
+            } => {
+                let idx = self.table_index(&table)?;
+                let table = &self.tables[idx];
+                let name = table.name.clone();
+
+                let selection = selection.ok_or(Error::Unsupported(
+                    "update without selection (where)".to_string(),
+                ))?;
+
+                let mut selected = self.select_rows(Some(table), selection)?;
+                if let Some(allowed) = self.allowed_rows(table, PolicyCommand::Update)? {
+                    selected.intersect_with(&allowed);
+                }
+
+                // evaluated once, so that `nextval()` advances once
+                let assigned =
+                    table.assigned(&assignments, &selected, &self.evaluator(), self.strictness)?;
+                let images = if has_row_triggers(table, ChangeKind::Update) {
+                    update_images(table, &assigned, &selected)
+                } else {
+                    Vec::new()
+                };
+
+                self.fire_triggers(&name, TriggerTiming::Before, ChangeKind::Update, &images)?;
+
+                self.tables[idx].update(assigned, &selected)?;
+                // the rows as written, which triggers before may have changed
+                let images: Vec<RowImage> = images
+                    .into_iter()
+                    .zip(selected.iter())
+                    .map(|(image, id)| RowImage {
+                        new: Some(self.tables[idx].row_values(id)),
+                        ..image
+                    })
+                    .collect();
+
+                let view = self.table_view(idx);
+                self.notify(&name, ChangeKind::Update, Some(view));
+
+                self.fire_triggers(&name, TriggerTiming::After, ChangeKind::Update, &images)?;
+            }
+            Query::Delete {
+                table,
+                selection,
+                returning,
+            } => {
+                let idx = self.table_index(&table)?;
+                let table = &self.tables[idx];
+                let name = table.name.clone();
+
+                let mut selected = if let Some(selection) = &selection {
+                    self.select_rows(Some(table), selection.clone())?
+                } else {
+                    table.row_ids()
+                };
+                let allowed = self.allowed_rows(table, PolicyCommand::Delete)?;
+                if let Some(allowed) = &allowed {
+                    selected.intersect_with(allowed);
+                }
+
+                let images: Vec<RowImage> = if has_row_triggers(table, ChangeKind::Delete) {
+                    selected
+                        .iter()
+                        .map(|id| RowImage {
+                            old: Some(table.row_values(id)),
+                            new: None,
+                        })
+                        .collect()
+                } else {
+                    Vec::new()
+                };
+
+                self.fire_triggers(&name, TriggerTiming::Before, ChangeKind::Delete, &images)?;
+
+                // as the rows were, before they're gone
+                let deleted = self.tables[idx].restricted(&selected);
+                let returned = match returning.is_empty() {
+                    true => None,
+                    false => Some(self.returning(&deleted, selected.iter(), &returning)?),
+                };
+
+                if selection.is_some() || allowed.is_some() {
+                    self.tables[idx].delete(&selected)?;
+                } else {
+                    self.tables[idx].truncate();
+                }
+
+                let view = self.view_of(&deleted);
+                self.notify_rows(&name, ChangeKind::Delete, Some(view), Some(&deleted));
+
+                self.fire_triggers(&name, TriggerTiming::After, ChangeKind::Delete, &images)?;
+                if returned.is_some() {
+                    return Ok(returned);
+                }
+            }
+            Query::CreateTrigger { table, trigger } => {
+                self.check_superuser("managing triggers")?;
+                let idx = self.table_index(&table)?;
+                let table = &mut self.tables[idx];
+
+                if table
+                    .triggers
+                    .iter()
+                    .any(|t| t.name.to_lowercase() == trigger.name.to_lowercase())
+                {
+                    return Err(Error::TriggerAlreadyExists(trigger.name));
+                }
+
+                log::debug!("created trigger {} on {}", trigger.name, table.name);
+                table.triggers.push(trigger);
+            }
+            Query::DropTrigger {
+                table,
+                name,
+                if_exists,
+            } => {
+                self.check_superuser("managing triggers")?;
+                let idx = self.table_index(&table)?;
+                let table = &mut self.tables[idx];
+
+                let before = table.triggers.len();
+                table
+                    .triggers
+                    .retain(|t| t.name.to_lowercase() != name.to_lowercase());
+
+                if table.triggers.len() == before && !if_exists {
+                    return Err(Error::TriggerNotFound(name));
+                }
+            }
+            Query::CreatePolicy { table, policy } => {
+                self.check_superuser("managing policies")?;
+                let idx = self.table_index(&table)?;
+                let table = &self.tables[idx];
+
+                if table
+                    .policies
+                    .iter()
+                    .any(|p| p.name.eq_ignore_ascii_case(&policy.name))
+                {
+                    return Err(Error::PolicyAlreadyExists(policy.name));
+                }
+                // so that a predicate that can't be evaluated is refused now,
+                // rather than failing every statement on the table
+                self.select_rows(Some(table), policy.predicate(&Session::default())?)?;
+
+                log::debug!("created policy {} on {}", policy.name, table.name);
+                self.tables[idx].policies.push(policy);
+            }
+            Query::DropPolicy {
+                table,
+                name,
+                if_exists,
+            } => {
+                self.check_superuser("managing policies")?;
+                let idx = self.table_index(&table)?;
+                let table = &mut self.tables[idx];
+
+                let before = table.policies.len();
+                table
+                    .policies
+                    .retain(|p| !p.name.eq_ignore_ascii_case(&name));
+
+                if table.policies.len() == before && !if_exists {
+                    return Err(Error::PolicyNotFound(name));
+                }
+            }
+            Query::CreateSequence {
+                name,
+                options,
+                if_not_exists,
+            } => {
+                let sequence = Sequence::new(&name, &options)?;
+                match self.sequences.create(&name, sequence) {
+                    Err(Error::SequenceAlreadyExists(_)) if if_not_exists => {}
+                    res => res?,
+                }
+                log::debug!("created sequence {name}");
+            }
+            Query::DropSequence { name, if_exists } => {
+                if !self.sequences.drop(&name) && !if_exists {
+                    return Err(Error::SequenceNotFound(name));
+                }
+            }
+            Query::CreateFullTextIndex {
+                table,
+                name,
+                column,
+            } => {
+                let idx = self.table_index(&table)?;
+                let table = &mut self.tables[idx];
+                log::debug!("creating full text index {name} on {}", table.name);
+                table.create_fulltext_index(name, &column)?;
+            }
+            Query::DropFullTextIndex {
+                table,
+                name,
+                if_exists,
+            } => {
+                let idx = self.table_index(&table)?;
+                if !self.tables[idx].drop_fulltext_index(&name) && !if_exists {
+                    return Err(Error::IndexNotFound(name));
+                }
+            }
+            Query::CreateUser {
+                name,
+                password,
+                superuser,
+            } => {
+                self.check_superuser("managing users")?;
+                self.users
+                    .write()
+                    .unwrap()
+                    .create(&name, &password, superuser)?;
+                self.audit(AuditKind::Admin, format!("create user {name}"));
+            }
+            Query::AlterUser { name, options } => {
+                // anyone may change their own password
+                let own_password = options.superuser.is_none()
+                    && self
+                        .session
+                        .user
+                        .as_ref()
+                        .is_some_and(|u| u.eq_ignore_ascii_case(&name));
+                if !own_password {
+                    self.check_superuser("managing users")?;
+                }
+                self.users.write().unwrap().alter(
+                    &name,
+                    options.password.as_deref(),
+                    options.superuser,
+                )?;
+                self.audit(AuditKind::Admin, format!("alter user {name}"));
+            }
+            Query::DropUser { name, if_exists } => {
+                self.check_superuser("managing users")?;
+                self.users.write().unwrap().remove(&name, if_exists)?;
+                self.audit(AuditKind::Admin, format!("drop user {name}"));
+            }
+            Query::Notify { channel, payload } => {
+                let notification = Notification { channel, payload };
+                match &mut self.held {
+                    Some(held) => held.push(Held::Notification(notification)),
+                    None => self.send_notification(notification)?,
+                }
+            }
+            Query::Explain { analyze, query } => match *query {
+                Query::Select(select) => return Ok(Some(self.explain(select, analyze)?)),
+                query => return Err(Error::Unsupported(format!("EXPLAIN of {}", query.name()))),
+            },
+            Query::Kill(_) => {
+                return Err(Error::Unsupported(
+                    "KILL outside of the server, which keeps track of connections".to_owned(),
+                ))
+            }
+            Query::Listen(_) | Query::Unlisten(_) => {
+                return Err(Error::Unsupported(
+                    "listening outside of a websocket subscription, where it is sent as a message"
+                        .to_owned(),
+                ))
+            }
+            Query::Set { variable, value } => match (variable.to_lowercase().as_str(), value) {
+                ("sql_strictness", Literal::Str(value)) => {
+                    self.strictness = value.parse()?;
+                    log::debug!("strictness set to {:?}", self.strictness);
+                }
+                // for the statements after the one setting it in the session,
+                // which `parser::parse_all` reads in it already
+                ("dialect", Literal::Str(value)) => {
+                    let dialect = value.parse()?;
+                    self.session.dialect = Some(dialect);
+                    log::debug!("session dialect set to {dialect:?}");
+                }
+                // in milliseconds, with 0 for none like postgres
+                // for the session, within the statement timeout of the database
+                ("statement_timeout", Literal::Int(ms)) if ms >= 0 => {
+                    let timeout = (ms > 0).then(|| Duration::from_millis(ms as u64));
+                    self.session.statement_timeout = timeout;
+                    log::debug!("session statement timeout set to {timeout:?}");
+                }
+                ("deterministic_order", Literal::Bool(on)) => {
+                    self.deterministic_order = on;
+                    log::debug!("deterministic order set to {on}");
+                }
+                ("deterministic_order", Literal::Str(value))
+                    if value.eq_ignore_ascii_case("on") || value.eq_ignore_ascii_case("off") =>
+                {
+                    self.deterministic_order = value.eq_ignore_ascii_case("on");
+                    log::debug!("deterministic order set to {value}");
+                }
+                // for the session, `default` going back to the database's
+                ("display_precision", Literal::Int(digits)) if digits >= 0 => {
+                    self.session.display_precision = Some(digits as usize);
+                    log::debug!("session display precision set to {digits}");
+                }
+                ("display_precision", Literal::Str(value))
+                    if value.eq_ignore_ascii_case("default") =>
+                {
+                    self.session.display_precision = None;
+                    log::debug!("session display precision reset");
+                }
+                (_, value) => {
+                    return Err(Error::Unsupported(format!(
+                        "setting {variable} to {value:?}"
+                    )))
+                }
+            },
+        }
+
+        Ok(None)
+    }
+
+    // forgets the dropped tables that are past `drop_retention`
+    fn expire_dropped(&mut self) {
+        let DropRetention {
+            max_tables,
+            max_age,
+        } = self.drop_retention;
+        while self.dropped.len() > max_tables
+            || self
+                .dropped
+                .front()
+                .is_some_and(|(_, at)| at.elapsed() > max_age)
+        {
+            self.dropped.pop_front();
+        }
+    }
+
+    // the whole of a table, as sent to its subscribers
+    fn table_view(&self, idx: usize) -> View {
+        self.view_of(&self.tables[idx])
+    }
+
+    // what `SELECT *` gives of `table`, for its change events, which are
+    // shown with the display precision of the database rather than that of
+    // the session making the change, as they go to every subscriber
+    fn view_of(&self, table: &Table) -> View {
+        let cols = table
+            .columns
+            .iter()
+            .filter(|c| !c.header.hidden)
+            .map(|c| OutColumn {
+                precision: c.header.precision.or(self.display_precision),
+                ..c.into()
+            })
+            .collect();
+
+        View::new(cols)
+    }
+
+    fn start_deadline(&mut self) {
+        // whichever ends first, so that neither a request nor a session can
+        // outlast the statement timeout of the database
+        let timeout = [
+            self.statement_timeout,
+            self.request_timeout,
+            self.session.statement_timeout,
+        ]
+        .into_iter()
+        .flatten()
+        .min();
+        self.deadline = Deadline::after(timeout).cancelled_by(self.cancel.clone());
+        self.patterns.clear();
+    }
+
+    // `columns` and `sources` of an insert into the table at `idx`, along
+    // with the values of the columns they leave out that have a default,
+    // evaluated for every row, so that e.g. each gets its own `nextval`
+    fn with_defaults(
+        &self,
+        idx: usize,
+        mut columns: Vec<String>,
+        mut sources: Vec<Vec<Literal>>,
+    ) -> Result<(Vec<String>, Vec<Vec<Literal>>)> {
+        // without a list of columns, all of them are given
+        if columns.is_empty() {
+            return Ok((columns, sources));
+        }
+
+        let evaluator = self.evaluator();
+        let mut added = Vec::new();
+        for col in self.tables[idx].columns.iter().filter(|c| !c.header.hidden) {
+            let name = &col.header.name;
+            if columns.iter().any(|c| c.eq_ignore_ascii_case(name)) {
+                continue;
+            }
+            let Some(default) = col.header.default_value()? else {
+                continue;
+            };
+
+            let default = optimizer::simplify(default);
+            for row in sources.iter_mut() {
+                let value = evaluator
+                    .eval(None, default.clone())?
+                    .first()
+                    .and_then(|c| c.data.get_literal(0))
+                    .unwrap_or(Literal::Null);
+                row.push(value);
+            }
+            added.push(name.clone());
+        }
+        columns.extend(added);
+
+        Ok((columns, sources))
+    }
+
+    fn evaluator(&self) -> Evaluator<'_> {
+        Evaluator::new(
+            &self.functions,
+            &self.patterns,
+            &self.sequences,
+            System {
+                tables: &self.tables,
+                started: self.started,
+            },
+            self.strictness,
+            self.deadline.clone(),
+        )
+    }
+
+    fn select(&self, select: Select) -> Result<RowIter> {
+        self.run(&self.plan(select), &mut None)
+    }
+
+    // the plan `select` is executed by
+    fn plan(&self, select: Select) -> Plan {
+        let mut plan =
+            optimizer::push_down_filters(Plan::new(select), &|name| self.column_names(name));
+        if self.deterministic_order {
+            plan = optimizer::order_deterministically(plan, &|name| {
+                let pk = self.table(name)?.pk_column()?;
+                Some(pk.header.name.clone())
+            });
+        }
+        optimizer::prune_columns(plan)
+    }
+
+    // the names of the columns of the table or virtual table `name`
+    fn column_names(&self, name: &str) -> Option<Vec<String>> {
+        if let Some(table) = self.table(name) {
+            let names = table.columns.iter().map(|c| c.header.name.clone());
+            return Some(names.collect());
+        }
+        let vt = self.virtual_tables.get(name)?;
+        Some(vt.schema().into_iter().map(|(name, _)| name).collect())
+    }
+
+    // the rows `plan` gives, recording what each operator did in `stages`
+    // when given
+    fn run(&self, plan: &Plan, stages: &mut Option<Vec<Stage>>) -> Result<RowIter> {
+        match plan {
+            // only as many rows as the limit reaches are sorted
+            Plan::Limit {
+                input,
+                limit,
+                offset,
+            } => match input.as_ref() {
+                Plan::Sort { input, keys } => self.sorted(input, keys, *limit, *offset, stages),
+                input => {
+                    let result = self.projection(input, stages)?.result;
+                    if let Some(detail) = limit_detail(*limit, *offset) {
+                        let limited = || {
+                            let count = RowIter::row_ids(&result).len().saturating_sub(*offset);
+                            limit.map_or(count, |limit| count.min(limit))
+                        };
+                        record(stages, Stage::new("limit", detail), Instant::now(), limited);
+                    }
+                    Ok(RowIter::new(result, *limit).offset(*offset))
+                }
+            },
+            Plan::Sort { input, keys } => self.sorted(input, keys, None, 0, stages),
+            plan => Ok(RowIter::new(self.projection(plan, stages)?.result, None)),
+        }
+    }
+
+    // the rows of the table `plan` scans or joins that it selects
+    fn rows(&self, plan: &Plan, stages: &mut Option<Vec<Stage>>) -> Result<Rows<'_>> {
+        match plan {
+            Plan::NoTable => Ok(Rows {
+                table: None,
+                selected: None,
+            }),
+            Plan::Scan {
+                table,
+                args,
+                columns,
+                sample,
+                ..
+            } => self.scan(
+                table,
+                args.as_deref(),
+                columns.as_deref(),
+                sample.as_ref(),
+                &[],
+                stages,
+            ),
+            Plan::Join { left, right, join } => {
+                let left_rows = self.rows(left, stages)?;
+                let right_rows = self.rows(right, stages)?;
+
+                let _span = tracing::debug_span!("join", table = %join.table).entered();
+                let started = Instant::now();
+                let evaluator = self.evaluator();
+                let joined = join::join(
+                    self.relation(left, &left_rows)?,
+                    self.relation(right, &right_rows)?,
+                    join,
+                    &evaluator,
+                )?;
+                record(
+                    stages,
+                    Stage::new("join", join_detail(join)),
+                    started,
+                    || joined.row_ids().len(),
+                );
+
+                Ok(Rows {
+                    table: Some(Cow::Owned(joined)),
+                    selected: None,
+                })
+            }
+            Plan::Filter { .. } => {
+                let (input, predicates) = plan.filters();
+                // filters right on top of a scan are pushed into a virtual
+                // table
+                let mut rows = match input {
+                    Plan::Scan {
+                        table,
+                        args,
+                        columns,
+                        sample,
+                        ..
+                    } => self.scan(
+                        table,
+                        args.as_deref(),
+                        columns.as_deref(),
+                        sample.as_ref(),
+                        &predicates,
+                        stages,
+                    )?,
+                    input => self.rows(input, stages)?,
+                };
+
+                let _span = tracing::debug_span!("selection").entered();
+                for predicate in predicates {
+                    let started = Instant::now();
+                    let detail = stages
+                        .as_ref()
+                        .map(|_| predicate.to_string())
+                        .unwrap_or_default();
+                    let (selected, path) =
+                        self.select_rows_indexed(rows.table.as_deref(), predicate)?;
+                    let stage = Stage::new("filter", detail).access(path.as_ref());
+                    record(stages, stage, started, || selected.len());
+                    match rows.selected.as_mut() {
+                        Some(rows) => rows.intersect_with(&selected),
+                        None => rows.selected = Some(selected),
+                    }
+                }
+
+                Ok(rows)
+            }
+            plan => Err(Error::InvalidOperation(format!(
+                "{} in place of the rows of a table",
+                plan.name()
+            ))),
+        }
+    }
+
+    // a side of a join: the rows `plan` selects, with only the columns the
+    // rest of the plan needs when it scans a table
+    fn relation<'a>(&self, plan: &Plan, rows: &'a Rows<'_>) -> Result<Relation<'a>> {
+        let Some(table) = rows.table.as_deref() else {
+            return Err(Error::InvalidOperation("join without tables".to_owned()));
+        };
+
+        let mut relation = match plan.filters().0 {
+            Plan::Scan {
+                table: name,
+                args,
+                alias,
+                columns,
+                ..
+            } => {
+                let mut relation = Relation::base(table, alias.as_deref());
+                // only the rows of a table are all kept by its hidden columns
+                if let (Some(columns), None, Some(_)) = (columns, args, self.table(name)) {
+                    relation.prune(columns);
+                }
+                relation
+            }
+            _ => Relation::joined(table),
+        };
+        if let Some(selected) = &rows.selected {
+            relation.restrict(selected);
+        }
+
+        Ok(relation)
+    }
+
+    // the table `name` as scanned for a plan, along with the filters on it
+    // for a virtual table to use, and only the rows of `sample` if given
+    fn scan(
+        &self,
+        name: &str,
+        args: Option<&[Expression]>,
+        columns: Option<&[String]>,
+        sample: Option<&Sample>,
+        pushdown: &[Expression],
+        stages: &mut Option<Vec<Stage>>,
+    ) -> Result<Rows<'_>> {
+        let started = Instant::now();
+        let mut table = self.source(name, args, columns, pushdown)?;
+        if let Some(sample) = sample {
+            let rows = sample.rows(&table.row_ids());
+            table = Cow::Owned(table.restricted(&rows));
+        }
+        let stage = Stage::new("scan", self.scan_detail(name, args, columns, sample));
+        record(stages, stage, started, || table.row_ids().len());
+
+        Ok(Rows {
+            table: Some(table),
+            selected: None,
+        })
+    }
+
+    // the select list of `plan`, a projection or an aggregation, evaluated
+    // for the rows it's built on
+    fn projection<'p>(
+        &self,
+        plan: &'p Plan,
+        stages: &mut Option<Vec<Stage>>,
+    ) -> Result<Projection<'_, 'p>> {
+        if let Plan::Combine {
+            left,
+            right,
+            operator,
+            all,
+        } = plan
+        {
+            let result = self.combine(left, right, *operator, *all, stages)?;
+            // the columns of a wildcard of the first select can't be told
+            // apart from the select list
+            let items = plan.items();
+            let columns = match items
+                .iter()
+                .any(|i| i.expr == Expression::Ident(Ident::Wildcard))
+            {
+                true => Vec::new(),
+                false => (0..items.len()).map(Some).collect(),
+            };
+            return Ok(Projection {
+                table: None,
+                items: if columns.is_empty() { &[] } else { items },
+                grouped: true,
+                result,
+                columns,
+            });
+        }
+        let (input, items, group_by) = match plan {
+            Plan::Project { input, items } => (input, items, None),
+            Plan::Aggregate {
+                input,
+                group_by,
+                items,
+            } => (input, items, Some(group_by.as_slice())),
+            plan => {
+                return Err(Error::InvalidOperation(format!(
+                    "{} in place of a select list",
+                    plan.name()
+                )))
+            }
+        };
+        let Rows {
+            table,
+            mut selected,
+        } = self.rows(input, stages)?;
+
+        let evaluator = self.evaluator();
+        let project = tracing::debug_span!("project").entered();
+        let started = Instant::now();
+        let detail = stages
+            .as_ref()
+            .map(|_| projection_detail(items, group_by.unwrap_or_default()))
+            .unwrap_or_default();
+        let mut projected = Vec::new();
+        // the column of each item of the select list, when it has one
+        let mut columns = Vec::new();
+        match group_by {
+            Some(group_by) => {
+                let rows = match selected.take() {
+                    Some(rows) => rows,
+                    None => table.as_deref().map(Table::row_ids).unwrap_or_default(),
+                };
+                projected = aggregate::group(&evaluator, table.as_deref(), &rows, group_by, items)?;
+                columns = (0..projected.len()).map(Some).collect();
+            }
+            None => {
+                for p in items {
+                    let mut out = match &p.expr {
+                        Expression::Subquery(subquery) => {
+                            vec![self.subquery(table.as_deref(), selected.as_ref(), subquery)?]
+                        }
+                        expr => {
+                            let expr = self.in_subqueries(table.as_deref(), expr.clone())?;
+                            evaluator.eval(table.as_deref(), optimizer::simplify(expr))?
                         }
+                    };
+                    if let (Some(alias), [column]) = (&p.alias, &mut out[..]) {
+                        column.name = alias.clone();
                     }
-                    None => Err(Error::TableNotFound(tbl_name))?,
+                    columns.push((out.len() == 1).then_some(projected.len()));
+                    projected.extend(out);
                 }
             }
-            parser::Query::Select(select) => {
-                let table = select.from.and_then(|name| {
-                    self.tables
+        }
+        project.exit();
+
+        log::debug!("selected: {selected:?}");
+        log::debug!("projected: {projected:?}");
+
+        for p in projected.iter_mut() {
+            p.precision = p.precision.or(self.session_precision());
+        }
+
+        let result = match selected {
+            Some(keys) => projected
+                .into_iter()
+                .map(|mut p| {
+                    p.data.retain_keys(&keys);
+                    p
+                })
+                .collect(),
+            // everything is selected
+            None => projected,
+        };
+        record(stages, Stage::new(plan.name(), detail), started, || {
+            RowIter::row_ids(&result).len()
+        });
+
+        log::debug!("result: {result:?}");
+
+        Ok(Projection {
+            table,
+            items,
+            grouped: group_by.is_some(),
+            result,
+            columns,
+        })
+    }
+
+    // the rows of the projection `input` ordered by `keys`, leaving out the
+    // first `offset` and any past `limit` of the rest
+    fn sorted(
+        &self,
+        input: &Plan,
+        keys: &[OrderBy],
+        limit: Option<usize>,
+        offset: usize,
+        stages: &mut Option<Vec<Stage>>,
+    ) -> Result<RowIter> {
+        let Projection {
+            table,
+            items,
+            grouped,
+            result,
+            columns,
+        } = self.projection(input, stages)?;
+        let table = table.as_deref();
+
+        let evaluator = self.evaluator();
+        let detail = sort_detail(keys, limit, offset);
+        let mut data = Vec::new();
+        for o in keys {
+            // the rows of a grouped select are only in its result, and
+            // subqueries are only run for it
+            let item = items.iter().position(|p| p.stands_for(&o.expr));
+            let from_result =
+                grouped || item.is_some_and(|i| matches!(items[i].expr, Expression::Subquery(_)));
+            if from_result {
+                // or the column of that name, for a key that's in none of
+                // the items, like one of a wildcard
+                let column = item.and_then(|i| columns[i]).or_else(|| match &o.expr {
+                    Expression::Ident(Ident::Named(name)) if item.is_none() => result
                         .iter()
-                        .find(|t| name.to_lowercase() == t.name.to_lowercase())
+                        .position(|c| c.name.eq_ignore_ascii_case(name)),
+                    _ => None,
+                });
+                match column.and_then(|i| result.get(i)) {
+                    Some(column) => data.push(column.data.clone()),
+                    None => {
+                        return Err(Error::InvalidQuery(format!(
+                            "order by {}, which isn't in the select list of a grouped select",
+                            o.expr
+                        )))
+                    }
+                }
+                continue;
+            }
+
+            let mut key = evaluator.eval(table, optimizer::simplify(o.expr.clone()))?;
+            match (key.pop(), key.is_empty()) {
+                (Some(key), true) => data.push(key.data),
+                _ => {
+                    return Err(Error::InvalidQuery(format!(
+                        "order by {}, which isn't a single value",
+                        o.expr
+                    )))
+                }
+            }
+        }
+        let sort_keys: Vec<SortKey> = data
+            .iter()
+            .zip(keys)
+            .map(|(data, o)| SortKey {
+                data,
+                desc: o.desc,
+                nulls_first: o.nulls_first,
+            })
+            .collect();
+        self.deadline.check()?;
+        let _span = tracing::debug_span!("sort").entered();
+        let started = Instant::now();
+        let top = limit.map(|limit| limit.saturating_add(offset));
+        let mut ids = sort::sort(&RowIter::row_ids(&result), &sort_keys, top);
+        ids.drain(..offset.min(ids.len()));
+        record(stages, Stage::new("sort", detail), started, || ids.len());
+
+        Ok(RowIter::sorted(result, ids))
+    }
+
+    // the rows of the selects `left` and `right` combined by `operator`,
+    // matched up by position and named after the columns of `left`. Rows are
+    // the same when all of their values are, nulls included, and only `all`
+    // keeps more than one of the same row.
+    fn combine(
+        &self,
+        left: &Plan,
+        right: &Plan,
+        operator: SetOperator,
+        all: bool,
+        stages: &mut Option<Vec<Stage>>,
+    ) -> Result<Vec<OutColumn>> {
+        let (names, left_data, left_ids) = self.run(left, stages)?.into_parts();
+        let (_, right_data, right_ids) = self.run(right, stages)?.into_parts();
+        if left_data.len() != right_data.len() {
+            return Err(Error::InvalidQuery(format!(
+                "{} of a select of {} columns with one of {}",
+                operator.name(),
+                left_data.len(),
+                right_data.len()
+            )));
+        }
+
+        self.deadline.check()?;
+        let _span = tracing::debug_span!("combine", operator = operator.name()).entered();
+        let started = Instant::now();
+        let row = |data: &[ColumnData], id: RowId| -> Vec<Literal> {
+            let values = data.iter().map(|d| d.get_literal(id));
+            values.map(|v| v.unwrap_or(Literal::Null)).collect()
+        };
+        let mut rows: Vec<Vec<Literal>> = left_ids.iter().map(|&id| row(&left_data, id)).collect();
+        let mut right_rows: Vec<Vec<Literal>> =
+            right_ids.iter().map(|&id| row(&right_data, id)).collect();
+
+        // both sides are brought to the type of each column first, so that
+        // an int and a double of the same value are the same
+        let mut datatypes = Vec::with_capacity(names.len());
+        for (i, name) in names.iter().enumerate() {
+            let values = rows.iter().chain(&right_rows).map(|r| &r[i]);
+            let datatype = kernels::common_type(values).map_err(|(a, b)| {
+                Error::InvalidQuery(format!(
+                    "{} of column {name} with values of both {a:?} and {b:?}",
+                    operator.name()
+                ))
+            })?;
+            datatypes.push(datatype);
+        }
+        for r in rows.iter_mut().chain(right_rows.iter_mut()) {
+            for (value, datatype) in r.iter_mut().zip(&datatypes) {
+                if let Some(datatype) = *datatype {
+                    let v = std::mem::replace(value, Literal::Null);
+                    *value = v
+                        .cast(datatype, Strictness::Lenient)
+                        .unwrap_or(Literal::Null);
+                }
+            }
+        }
+
+        if !all {
+            rows = distinct(rows);
+        }
+        match operator {
+            SetOperator::Union => {
+                rows.extend(right_rows);
+                if !all {
+                    rows = distinct(rows);
+                }
+            }
+            SetOperator::Intersect | SetOperator::Except => {
+                // each row of `right` matches one of `left` at most
+                let mut counts: HashMap<Vec<Option<HashKey>>, usize> = HashMap::new();
+                for r in &right_rows {
+                    *counts.entry(row_key(r)).or_default() += 1;
+                }
+                let keep = operator == SetOperator::Intersect;
+                rows.retain(|r| {
+                    let matched = match counts.get_mut(&row_key(r)) {
+                        Some(count) if *count > 0 => {
+                            *count -= 1;
+                            true
+                        }
+                        _ => false,
+                    };
+                    matched == keep
+                });
+            }
+        }
+
+        let mut columns: Vec<Vec<(RowId, Literal)>> = vec![Vec::new(); names.len()];
+        let count = rows.len();
+        for (id, r) in rows.into_iter().enumerate() {
+            for (column, value) in columns.iter_mut().zip(r) {
+                column.push((id, value));
+            }
+        }
+        let mut result = Vec::with_capacity(names.len());
+        for (name, values) in names.into_iter().zip(columns) {
+            let data = ColumnData::from_literals(values)?
+                .unwrap_or_else(|| ColumnData::Int(Default::default()));
+            result.push(OutColumn {
+                name,
+                data,
+                precision: self.session_precision(),
+            });
+        }
+        let detail = if all { "all" } else { "" };
+        record(stages, Stage::new(operator.name(), detail), started, || {
+            count
+        });
+
+        Ok(result)
+    }
+
+    // the values of `items` for each of the `rows` of `table`, in order, as
+    // `RETURNING` gives them for the rows a statement changed
+    fn returning(
+        &self,
+        table: &Table,
+        rows: impl IntoIterator<Item = RowId>,
+        items: &[SelectItem],
+    ) -> Result<View> {
+        let rows: Vec<RowId> = rows.into_iter().collect();
+        let table = table.restricted(&rows.iter().copied().collect());
+        let evaluator = self.evaluator();
+        let mut columns = Vec::new();
+        for item in items {
+            let mut out = evaluator.eval(Some(&table), optimizer::simplify(item.expr.clone()))?;
+            if let (Some(alias), [column]) = (&item.alias, &mut out[..]) {
+                column.name = alias.clone();
+            }
+            columns.extend(out);
+        }
+        for column in columns.iter_mut() {
+            column.precision = column.precision.or(self.session_precision());
+        }
+
+        Ok(RowIter::sorted(columns, rows).into())
+    }
+
+    // the column of a scalar subquery of the select list, run for each of
+    // `rows` of `table` with the columns of it that the subquery refers to
+    // bound to their values. Rows with the same values share a run, so that
+    // one referring to none runs once.
+    fn subquery(
+        &self,
+        table: Option<&Table>,
+        rows: Option<&RowSet>,
+        subquery: &Subquery,
+    ) -> Result<OutColumn> {
+        let template = &subquery.select;
+        let outer = match table {
+            Some(table) => self.outer_references(table, template)?,
+            None => Vec::new(),
+        };
+        let rows = match (rows, table) {
+            (Some(rows), _) => rows.clone(),
+            (None, Some(table)) => table.row_ids(),
+            (None, None) => [0].into_iter().collect(),
+        };
+
+        let mut name = None;
+        let mut runs: HashMap<Vec<Option<HashKey>>, Literal> = HashMap::new();
+        let mut values = Vec::with_capacity(rows.len());
+        for id in rows.iter() {
+            let bound: Vec<Literal> = outer
+                .iter()
+                .map(|(_, c)| c.data.get_literal(id).unwrap_or(Literal::Null))
+                .collect();
+            let key = bound.iter().cloned().map(HashKey::new).collect();
+            let value = match runs.entry(key) {
+                Entry::Occupied(run) => run.get().clone(),
+                Entry::Vacant(run) => {
+                    let (column, value) = self.scalar(template, &outer, &bound)?;
+                    name.get_or_insert(column);
+                    run.insert(value).clone()
+                }
+            };
+            values.push((id, value));
+        }
+
+        // named like the column of the subquery, even when it never ran
+        let name = match name {
+            Some(name) => name,
+            None => self.scalar(template, &outer, &[])?.0,
+        };
+        Ok(OutColumn {
+            name,
+            data: ColumnData::from_literals(values)?.unwrap_or(ColumnData::Int(Default::default())),
+            precision: None,
+        })
+    }
+
+    // `expr` with each `<value> [NOT] IN (<select>)` in it as the `IN` of the
+    // list of values the select gives, which is run once for it. Selects
+    // referring to the columns of `table`, which would have to run for each
+    // of its rows, aren't supported.
+    fn in_subqueries(&self, table: Option<&Table>, mut expr: Expression) -> Result<Expression> {
+        expr.rewrite(&mut |e| {
+            let Expression::InSubquery {
+                expr,
+                subquery,
+                negated,
+            } = e
+            else {
+                return Ok(());
+            };
+            if let Some(table) = table {
+                if let Some((name, _)) = self.outer_references(table, &subquery.select)?.first() {
+                    return Err(Error::Unsupported(format!(
+                        "IN ({}), which refers to {name} of the select it's in",
+                        subquery.sql
+                    )));
+                }
+            }
+
+            let (names, data, ids) = self.select((*subquery.select).clone())?.into_parts();
+            if names.len() != 1 {
+                return Err(Error::InvalidOperation(format!(
+                    "IN of a subquery of {} columns",
+                    names.len()
+                )));
+            }
+            let list = ids
+                .into_iter()
+                .map(|id| Expression::Literal(data[0].get_literal(id).unwrap_or(Literal::Null)))
+                .collect();
+            *e = Expression::InList {
+                expr: Box::new(std::mem::replace(&mut **expr, Expression::None)),
+                list,
+                negated: *negated,
+            };
+            Ok(())
+        })?;
+        Ok(expr)
+    }
+
+    // the columns of `table` that `select`, a subquery of a select from it,
+    // refers to: names that aren't columns of its own tables, and names
+    // qualified with a table that isn't one of them
+    fn outer_references<'t>(
+        &self,
+        table: &'t Table,
+        select: &Select,
+    ) -> Result<Vec<(String, &'t Column)>> {
+        let inner = match &select.from {
+            Some(name) => {
+                let from = self.source(name, select.from_args.as_deref(), None, &[])?;
+                if select.joins.is_empty() {
+                    Some(from)
+                } else {
+                    let alias = select.alias.as_deref();
+                    Some(Cow::Owned(self.join_all(&from, alias, &select.joins)?))
+                }
+            }
+            None => None,
+        };
+        let mut qualifiers: Vec<&str> = [&select.from, &select.alias]
+            .into_iter()
+            .flatten()
+            .map(String::as_str)
+            .collect();
+        for j in &select.joins {
+            qualifiers.push(&j.table);
+            qualifiers.extend(j.alias.as_deref());
+        }
+
+        let mut outer: Vec<(String, &Column)> = Vec::new();
+        for expr in select.clone().expressions_mut() {
+            for name in expr.idents() {
+                let inner_column = match name.rsplit_once('.') {
+                    Some((qualifier, _)) => {
+                        qualifiers.iter().any(|q| q.eq_ignore_ascii_case(qualifier))
+                    }
+                    None => inner
+                        .as_ref()
+                        .is_some_and(|t| t.col_from_name(name).is_some()),
+                };
+                if inner_column || outer.iter().any(|(n, _)| n == name) {
+                    continue;
+                }
+                if let Some(column) = table.col_from_name(name) {
+                    outer.push((name.to_owned(), column));
+                }
+            }
+        }
+
+        Ok(outer)
+    }
+
+    // the single value `select` gives with the `outer` columns bound to
+    // `values`, or null when it gives no rows, along with its column name
+    fn scalar(
+        &self,
+        select: &Select,
+        outer: &[(String, &Column)],
+        values: &[Literal],
+    ) -> Result<(String, Literal)> {
+        let mut select = select.clone();
+        for expr in select.expressions_mut() {
+            expr.bind(&|name| {
+                let i = outer.iter().position(|(n, _)| n == name)?;
+                Some(values.get(i).cloned().unwrap_or(Literal::Null))
+            });
+        }
+
+        let (mut names, data, ids) = self.select(select)?.into_parts();
+        if names.len() != 1 {
+            return Err(Error::InvalidOperation(format!(
+                "subquery of {} columns as a single value",
+                names.len()
+            )));
+        }
+        let value = match ids[..] {
+            [] => Literal::Null,
+            [id] => data[0].get_literal(id).unwrap_or(Literal::Null),
+            _ => {
+                return Err(Error::EvaluationError(
+                    "more than one row from a subquery used as a value".to_owned(),
+                ))
+            }
+        };
+        Ok((names.remove(0), value))
+    }
+
+    // a table, or a virtual one scanned into a table, to select from. Only
+    // `columns` of a table are copied when its rows are restricted, if given.
+    fn source(
+        &self,
+        name: &str,
+        args: Option<&[Expression]>,
+        columns: Option<&[String]>,
+        pushdown: &[Expression],
+    ) -> Result<Cow<'_, Table>> {
+        if let Some(args) = args {
+            return self.table_function(name, args, pushdown).map(Cow::Owned);
+        }
+
+        if let Some(table) = self.table(name) {
+            let Some(allowed) = self.allowed_rows(table, PolicyCommand::Select)? else {
+                return Ok(Cow::Borrowed(table));
+            };
+            let mut table = match columns {
+                Some(columns) => table.pruned(columns),
+                None => table.clone(),
+            };
+            table.retain_rows(&allowed);
+            return Ok(Cow::Owned(table));
+        }
+
+        match self.virtual_tables.get(name) {
+            Some(vt) => Ok(Cow::Owned(virtual_table::materialize(
+                name,
+                vt.as_ref(),
+                pushdown,
+            )?)),
+            None => Err(self.table_not_found(name)),
+        }
+    }
+
+    // the rows of the table function `name`, called with constant `args`
+    fn table_function(
+        &self,
+        name: &str,
+        args: &[Expression],
+        pushdown: &[Expression],
+    ) -> Result<Table> {
+        let args = args
+            .iter()
+            .map(|arg| match optimizer::simplify(arg.clone()) {
+                Expression::Literal(lit) => Ok(lit),
+                arg => Err(Error::InvalidQuery(format!(
+                    "arguments of {name} have to be constants, not {arg}"
+                ))),
+            })
+            .collect::<Result<Vec<Literal>>>()?;
+
+        match name.to_lowercase().as_str() {
+            "generate_series" => {
+                virtual_table::materialize(name, &GenerateSeries::new(&args)?, pushdown)
+            }
+            _ => Err(Error::Unsupported(format!("table function: {name}"))),
+        }
+    }
+
+    // the rows of `table` the policies on it let the user executing the
+    // statement `command` on, or `None` when they aren't restricted
+    fn allowed_rows(&self, table: &Table, command: PolicyCommand) -> Result<Option<RowSet>> {
+        self.allowed_rows_for(table, &self.session, command)
+    }
+
+    fn allowed_rows_for(
+        &self,
+        table: &Table,
+        session: &Session,
+        command: PolicyCommand,
+    ) -> Result<Option<RowSet>> {
+        let Some(user) = session.user.as_deref() else {
+            return Ok(None);
+        };
+        if table.policies.is_empty() {
+            return Ok(None);
+        }
+
+        // any of the policies that apply can allow a row, and none applying
+        // allows none
+        let mut allowed = RowSet::new();
+        for policy in table
+            .policies
+            .iter()
+            .filter(|p| p.applies_to(user, command))
+        {
+            allowed.union_with(&self.select_rows(Some(table), policy.predicate(session)?)?);
+        }
+
+        Ok(Some(allowed))
+    }
+
+    // the views of `event` for the users subscribed to its table that are
+    // restricted by policies on it, applied to `rows` in place of the table
+    // when given
+    fn restricted_views(
+        &self,
+        table: &str,
+        rows: Option<&Table>,
+        event: &ChangeEvent,
+    ) -> Vec<(String, View)> {
+        let (Some(subs), Some(table), Some(_)) = (
+            self.ws_map.get(&table_key(table)),
+            rows.or_else(|| self.table(table)),
+            event.view.as_ref(),
+        ) else {
+            return Vec::new();
+        };
+
+        let mut views: Vec<(String, View)> = Vec::new();
+        for user in subs.iter().filter_map(|s| s.user.as_deref()) {
+            if views.iter().any(|(u, _)| u == user) {
+                continue;
+            }
+
+            let session = Session {
+                user: Some(user.to_owned()),
+                ..Default::default()
+            };
+            let allowed = self
+                .allowed_rows_for(table, &session, PolicyCommand::Select)
+                .unwrap_or_else(|e| {
+                    log::error!("failed to apply the policies on {}: {e}", table.name);
+                    Some(RowSet::new())
                 });
+            if let Some(allowed) = allowed {
+                views.push((user.to_owned(), self.view_of(&table.restricted(&allowed))));
+            }
+        }
+
+        views
+    }
+
+    fn table_not_found(&self, name: &str) -> Error {
+        let existing = self
+            .tables
+            .iter()
+            .map(|t| t.name.as_str())
+            .chain(self.virtual_tables.iter().map(|(name, _)| name.as_str()));
+        Error::table_not_found(name, existing)
+    }
+
+    fn join_all(&self, from: &Table, alias: Option<&str>, joins: &[Join]) -> Result<Table> {
+        let _span = tracing::debug_span!("join", tables = joins.len() + 1).entered();
+        let evaluator = self.evaluator();
+        let mut joined: Option<Table> = None;
+
+        for j in joins {
+            let right = self.source(&j.table, j.args.as_deref(), None, &[])?;
+
+            let left = match &joined {
+                Some(table) => Relation::joined(table),
+                None => Relation::base(from, alias),
+            };
+            let right = Relation::base(&right, j.alias.as_deref());
+
+            joined = Some(join::join(left, right, j, &evaluator)?);
+        }
+
+        joined.ok_or(Error::InvalidOperation("join without tables".to_owned()))
+    }
+
+    // the rows of `table` for which `selection` holds, from an index when possible
+    fn select_rows(&self, table: Option<&Table>, selection: Expression) -> Result<RowSet> {
+        self.select_rows_indexed(table, selection)
+            .map(|(rows, _)| rows)
+    }
+
+    // `select_rows`, and the access path the planner chose for it when the
+    // index could answer it
+    fn select_rows_indexed(
+        &self,
+        table: Option<&Table>,
+        selection: Expression,
+    ) -> Result<(RowSet, Option<AccessPath>)> {
+        let selection = self.in_subqueries(table, fulltext::as_predicate(selection))?;
+        let selection = optimizer::simplify(selection);
+        match selection {
+            Expression::Literal(Literal::Bool(true)) => {
+                let rows = table.map_or_else(|| [0].into_iter().collect(), Table::row_ids);
+                return Ok((rows, None));
+            }
+            Expression::Literal(Literal::Bool(false) | Literal::Null) => {
+                return Ok((RowSet::new(), None))
+            }
+            _ => {}
+        }
+
+        let path = tracing::debug_span!("plan")
+            .in_scope(|| table.and_then(|t| planner::access_path(t, &selection)));
+        if let (Some(table), Some(range)) = (table, path.as_ref().and_then(|p| p.range.clone())) {
+            log::debug!("using the primary key index of {}", table.name);
+            return Ok((table.pk_range(range), path));
+        }
+
+        let evaluator = self.evaluator();
+        let mut selected = evaluator.eval(table, selection)?;
+        let rows = match selected.len() {
+            // null
+            0 => RowSet::new(),
+            1 => selected.remove(0).data.keys_where_true()?,
+            _ => {
+                return Err(Error::InvalidOperation(
+                    "more than one column found in selection".to_owned(),
+                ))
+            }
+        };
+
+        Ok((rows, path))
+    }
+
+    /// The steps executing `select` goes through, as a view of `stage`,
+    /// `detail`, `index` (whether a filter uses the primary key index) and
+    /// `estimate` (how many rows the planner expects a filter the index
+    /// could answer to give, which it chooses by). With `analyze`, it is
+    /// executed, adding how many `rows` each step gave and how long it took
+    /// in `time_ms`.
+    fn explain(&self, select: Select, analyze: bool) -> Result<View> {
+        let plan = self.plan(select);
+        if !analyze {
+            let mut stages = Vec::new();
+            self.plan_stages(&plan, &mut stages);
+            return Ok(Stage::view(stages, false));
+        }
+
+        let mut stages = Some(Vec::new());
+        let rows = self.run(&plan, &mut stages)?;
+
+        // formatting the rows is part of executing a select too
+        let started = Instant::now();
+        let mut count = 0;
+        for _ in rows {
+            count += 1;
+        }
+        record(&mut stages, Stage::new("output", ""), started, || count);
+
+        Ok(Stage::view(stages.unwrap_or_default(), true))
+    }
+
+    // the steps of `plan` and the operators it's built on, without executing
+    // them, in the order they would be executed in
+    fn plan_stages(&self, plan: &Plan, stages: &mut Vec<Stage>) {
+        match plan {
+            Plan::NoTable => {}
+            Plan::Scan {
+                table,
+                args,
+                columns,
+                sample,
+                ..
+            } => {
+                let detail =
+                    self.scan_detail(table, args.as_deref(), columns.as_deref(), sample.as_ref());
+                stages.push(Stage::new("scan", detail));
+            }
+            Plan::Join { left, right, join } => {
+                self.plan_stages(left, stages);
+                self.plan_stages(right, stages);
+                stages.push(Stage::new("join", join_detail(join)));
+            }
+            Plan::Filter { input, predicate } => {
+                self.plan_stages(input, stages);
+                // joined tables and table functions have no index
+                let table = match plan.filters().0 {
+                    Plan::Scan {
+                        table, args: None, ..
+                    } => self.table(table),
+                    _ => None,
+                };
+                let path = table
+                    .and_then(|t| planner::access_path(t, &optimizer::simplify(predicate.clone())));
+                stages.push(Stage::new("filter", predicate.to_string()).access(path.as_ref()));
+            }
+            Plan::Project { input, items } => {
+                self.plan_stages(input, stages);
+                stages.push(Stage::new("project", projection_detail(items, &[])));
+            }
+            Plan::Aggregate {
+                input,
+                group_by,
+                items,
+            } => {
+                self.plan_stages(input, stages);
+                stages.push(Stage::new("aggregate", projection_detail(items, group_by)));
+            }
+            Plan::Combine {
+                left,
+                right,
+                operator,
+                all,
+            } => {
+                self.plan_stages(left, stages);
+                self.plan_stages(right, stages);
+                stages.push(Stage::new(operator.name(), if *all { "all" } else { "" }));
+            }
+            Plan::Sort { input, keys } => {
+                self.plan_stages(input, stages);
+                stages.push(Stage::new("sort", sort_detail(keys, None, 0)));
+            }
+            // a limit on sorted rows is part of sorting them
+            Plan::Limit {
+                input,
+                limit,
+                offset,
+            } => match input.as_ref() {
+                Plan::Sort { input, keys } => {
+                    self.plan_stages(input, stages);
+                    stages.push(Stage::new("sort", sort_detail(keys, *limit, *offset)));
+                }
+                input => {
+                    self.plan_stages(input, stages);
+                    if let Some(detail) = limit_detail(*limit, *offset) {
+                        stages.push(Stage::new("limit", detail));
+                    }
+                }
+            },
+        }
+    }
+
+    fn scan_detail(
+        &self,
+        name: &str,
+        args: Option<&[Expression]>,
+        columns: Option<&[String]>,
+        sample: Option<&Sample>,
+    ) -> String {
+        let detail = match args {
+            Some(args) => {
+                let args: Vec<String> = args.iter().map(|a| a.to_string()).collect();
+                format!("function {name}({})", args.join(", "))
+            }
+            // only tables are narrowed down to the columns needed
+            None => match (self.table(name), columns) {
+                (Some(table), Some(columns)) => {
+                    format!("table {}, columns {}", table.name, columns.join(", "))
+                }
+                (Some(table), None) => format!("table {}", table.name),
+                (None, _) => format!("virtual table {name}"),
+            },
+        };
+        match sample {
+            Some(sample) => format!("{detail}, {sample}"),
+            None => detail,
+        }
+    }
+
+    // only superusers manage users, policies and triggers, besides the repl
+    // and embedders, which run statements without a user. Triggers fire in
+    // the sessions of whoever changes their table, so they could copy out
+    // rows their creator isn't allowed to see. Refused as `action`.
+    fn check_superuser(&self, action: &str) -> Result<()> {
+        let Some(user) = &self.session.user else {
+            return Ok(());
+        };
 
-                // dear god this is dogshit
-                // but I need to get this done by tomorrow
+        match self.users.read().unwrap().get(user) {
+            Some(u) if u.superuser => Ok(()),
+            _ => Err(self.permission_denied(format!("{action} as {user}, not a superuser"))),
+        }
+    }
 
-                let mut selected = Vec::new();
-                let mut projected = Vec::new();
+    fn permission_denied(&self, action: String) -> Error {
+        self.audit(AuditKind::PermissionDenied, action.clone());
+        Error::PermissionDenied(action)
+    }
 
-                for s in select.selection {
-                    if matches!(s, crate::parser::expression::Expression::None) {
-                        continue;
-                    }
+    fn audit(&self, kind: AuditKind, action: String) {
+        if let Some(audit) = &self.audit {
+            let Session {
+                user, client_addr, ..
+            } = &self.session;
+            audit.record(kind, user.as_deref(), client_addr.as_deref(), action);
+        }
+    }
 
-                    selected.extend(Evaluator::eval(table, s)?);
-                }
+    fn table_index(&self, name: &str) -> Result<usize> {
+        let idx = self
+            .tables
+            .iter()
+            .position(|t| table_key(&t.name) == table_key(name));
+
+        match idx {
+            Some(idx) => Ok(idx),
+            None if self.virtual_tables.get(name).is_some() => Err(Error::InvalidOperation(
+                format!("virtual table {name} is read only"),
+            )),
+            None => Err(self.table_not_found(name)),
+        }
+    }
+
+    // the triggers of `table` for `event` at `timing`. Those after leave the
+    // change that fired them in place when they fail, see `TriggerTiming`.
+    fn fire_triggers(
+        &mut self,
+        table: &str,
+        timing: TriggerTiming,
+        event: ChangeKind,
+        rows: &[RowImage],
+    ) -> Result<()> {
+        let triggers: Vec<Trigger> = self
+            .tables
+            .iter()
+            .find(|t| t.name == table)
+            .map(|t| {
+                t.triggers
+                    .iter()
+                    .filter(|t| t.timing == timing && t.event == event)
+                    .cloned()
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        if triggers.is_empty() {
+            return Ok(());
+        }
+
+        if self.trigger_depth >= MAX_TRIGGER_DEPTH {
+            return Err(Error::InvalidOperation(format!(
+                "triggers nested more than {MAX_TRIGGER_DEPTH} levels deep"
+            )));
+        }
+
+        self.trigger_depth += 1;
+        let res = self.run_triggers(&triggers, rows);
+        self.trigger_depth -= 1;
+
+        res
+    }
 
-                for p in select.projection {
-                    projected.extend(Evaluator::eval(table, p)?);
+    fn run_triggers(&mut self, triggers: &[Trigger], rows: &[RowImage]) -> Result<()> {
+        for trigger in triggers {
+            log::debug!("firing trigger {}", trigger.name);
+
+            if trigger.for_each_row {
+                for row in rows {
+                    self.deadline.check()?;
+                    let mut stmt = trigger.bind(Some(row))?;
+                    self.session.bind(&mut stmt)?;
+                    self.execute(parser::parse(stmt)?)?;
                 }
+            } else {
+                let mut stmt = trigger.bind(None)?;
+                self.session.bind(&mut stmt)?;
+                self.execute(parser::parse(stmt)?)?;
+            }
+        }
 
-                log::debug!("selected: {selected:?}");
-                log::debug!("projected: {projected:?}");
+        Ok(())
+    }
 
-                let result = if selected.is_empty() {
-                    // everything is selected
-                    projected
-                } else {
-                    let mut res = Vec::new();
-                    for p in projected {
-                        for s in &selected {
-                            let name = p.name.clone();
-                            let keys: Vec<usize> = match &s.data {
-                                crate::table::ColumnData::Bool(b) => {
-                                    b.iter().filter(|(_, v)| **v).map(|(k, _)| *k).collect()
-                                }
-                                _ => panic!("not possible"),
-                            };
+    /// Executes every statement in `query`, returning the views they produced.
+    pub fn query(&mut self, query: &str) -> Result<Vec<View>> {
+        self.record(query);
+        let queries = parser::parse_all_cached(
+            query,
+            self.limits,
+            self.session.dialect.unwrap_or(self.dialect),
+            &self.session,
+            &mut self.statement_cache,
+        )?;
 
-                            log::debug!("selected keys: {keys:?}");
+        let mut views = Vec::new();
+        for query in queries {
+            if let Some(view) = self.execute(query)? {
+                views.push(view);
+            }
+        }
 
-                            let mut data = p.data.clone();
-                            data.retain_keys(&keys);
+        Ok(views)
+    }
 
-                            let col = OutColumn { name, data };
+    // held back while a migration is applied, so that one rolled back
+    // leaves nothing to replay
+    fn record(&mut self, query: &str) {
+        let Some(replay) = &self.replay else {
+            return;
+        };
+        match &mut self.held {
+            Some(held) => held.push(Held::Record {
+                user: self.session.user.clone(),
+                sql: query.to_owned(),
+            }),
+            None => replay.record(self.session.user.as_deref(), query),
+        }
+    }
 
-                            res.push(col);
-                        }
-                    }
-                    res
-                };
+    // like `execute`, for the writes made through the api rather than from
+    // sql, recording the statement they're equivalent to so that replaying
+    // the log leaves the tables as they were
+    fn execute_recorded(&mut self, query: Query) -> Result<Option<View>> {
+        if let Some(sql) = self.replay.as_ref().and_then(|_| query.write_sql()) {
+            self.record(&sql);
+        }
+        self.execute(query)
+    }
 
-                log::debug!("result: {result:?}");
+    /// Whether every statement of `query` only reads the database, see
+    /// `Query::is_read_only`, so that it can be run on a snapshot of it.
+    pub fn is_read_only(&mut self, query: &str) -> Result<bool> {
+        let queries = parser::parse_all_cached(
+            query,
+            self.limits,
+            self.session.dialect.unwrap_or(self.dialect),
+            &self.session,
+            &mut self.statement_cache,
+        )?;
+        Ok(queries.iter().all(Query::is_read_only))
+    }
 
-                return Ok(Some(View::new(result)));
+    /// Runs a single `SELECT`, giving back its rows one at a time rather than
+    /// formatting all of them up front like `query` does.
+    pub fn query_rows(&mut self, query: &str) -> Result<RowIter> {
+        self.record(query);
+        let mut queries = parser::parse_all_cached(
+            query,
+            self.limits,
+            self.session.dialect.unwrap_or(self.dialect),
+            &self.session,
+            &mut self.statement_cache,
+        )?;
+        match (queries.pop(), queries.is_empty()) {
+            (Some(Query::Select(select)), true) => {
+                self.start_deadline();
+                self.select(select)
             }
-            Query::Insert {
-                table,
+            _ => Err(Error::InvalidQuery(
+                "expected a single select statement".to_owned(),
+            )),
+        }
+    }
+
+    /// Writes the rows of `table` to `writer` as Arrow IPC, as `SELECT *`
+    /// would give them.
+    #[cfg(feature = "arrow")]
+    pub fn export_arrow(
+        &mut self,
+        table: &str,
+        writer: impl std::io::Write,
+        format: crate::arrow::IpcFormat,
+    ) -> Result<()> {
+        let name = self.table(table).map(|t| t.name.clone());
+        let name = name.ok_or_else(|| self.table_not_found(table))?;
+        let rows = self.query_rows(&format!("SELECT * FROM {}", sql::ident(&name)))?;
+        crate::arrow::write_rows(rows, writer, format)
+    }
+
+    /// Writes the rows of the select in `query` to `writer` as an Excel
+    /// spreadsheet.
+    #[cfg(feature = "xlsx")]
+    pub fn export_xlsx(&mut self, query: &str, writer: impl std::io::Write + Send) -> Result<()> {
+        let rows = self.query_rows(query)?;
+        crate::xlsx::write_rows(rows, writer)
+    }
+
+    /// Inserts the rows of the Arrow IPC file or stream in `data` into
+    /// `table`, matching columns by name, like an `INSERT` of them would.
+    /// Returns how many rows there were.
+    #[cfg(feature = "arrow")]
+    pub fn import_arrow(&mut self, table: &str, data: &[u8]) -> Result<usize> {
+        self.table_index(table)?;
+        let (columns, sources) = crate::arrow::read_rows(data)?;
+        let count = sources.len();
+        if count > 0 {
+            self.execute_recorded(Query::Insert {
+                table: table.to_owned(),
                 columns,
                 sources,
-            } => {
-                match self
-                    .tables
-                    .iter_mut()
-                    .find(|t| t.name.to_lowercase() == table.to_lowercase())
-                {
-                    Some(tbl) => {
-                        tbl.insert(columns.clone(), sources.clone())?;
-
-                        let outcols: Vec<OutColumn> =
-                            tbl.columns.iter().map(OutColumn::from).collect();
-                        let view = View::new(outcols);
-                        if let Some(txs) = self.ws_map.get(&tbl.name.to_lowercase()) {
-                            for tx in txs {
-                                _ = tx.send(format!("table: {} updated\n {view}", tbl.name));
-                                log::info!("sent insert updates");
-                            }
-                        }
+                returning: Vec::new(),
+            })?;
+        }
+
+        Ok(count)
+    }
+
+    /// The tables, their columns, constraints and indexes, as json, see
+    /// `Schema`.
+    pub fn schema_to_json(&self) -> String {
+        self.schema().to_json()
+    }
+
+    pub fn schema(&self) -> Schema {
+        Schema {
+            tables: self.tables.iter().map(TableSchema::from).collect(),
+        }
+    }
+
+    /// The sql recreating one table, or every one: its `CREATE TABLE`
+    /// statement followed by an `INSERT` for each of its rows, one statement
+    /// per line.
+    pub fn dump(&self, table: Option<&str>) -> Result<String> {
+        let tables: Vec<&Table> = match table {
+            Some(name) => vec![self.table(name).ok_or_else(|| self.table_not_found(name))?],
+            None => self.tables.iter().collect(),
+        };
+
+        let mut out = String::new();
+        for table in tables {
+            out += &TableSchema::from(table).to_sql();
+            out.push('\n');
+
+            let columns: Vec<&Column> = table.columns.iter().filter(|c| !c.header.hidden).collect();
+            let names: Vec<_> = columns.iter().map(|c| sql::ident(&c.header.name)).collect();
+            for id in table.row_ids().iter() {
+                let values: Vec<String> = columns
+                    .iter()
+                    .map(|c| c.data.get_literal(id).unwrap_or(Literal::Null).to_string())
+                    .collect();
+                out += &format!(
+                    "INSERT INTO {} ({}) VALUES ({});\n",
+                    sql::ident(&table.name),
+                    names.join(", "),
+                    values.join(", ")
+                );
+            }
+        }
+        Ok(out)
+    }
+
+    /// Creates the tables of the json `schema` that don't exist yet, after
+    /// checking that those which do are as described. Returns the names of
+    /// the tables created.
+    pub fn apply_schema(&mut self, schema: &str) -> Result<Vec<String>> {
+        let schema = Schema::from_json(schema)?;
+
+        let mut missing = Vec::new();
+        for table in &schema.tables {
+            if let Some(index) = table.unsupported_indexes().next() {
+                return Err(Error::Unsupported(format!(
+                    "index {} of {}, other than the one of the primary key",
+                    index.name, table.name
+                )));
+            }
+            match self.table(&table.name) {
+                Some(existing) => {
+                    let differences = table.differences(&TableSchema::from(existing));
+                    if !differences.is_empty() {
+                        return Err(Error::InvalidSchema(format!(
+                            "table {} differs from the schema: {}",
+                            table.name,
+                            differences.join(", ")
+                        )));
                     }
-                    None => Err(Error::TableNotFound(table))?,
                 }
+                None => missing.push(table),
             }
-            Query::Drop(table) => self
-                .tables
-                .retain(|t| t.name.to_lowercase() != table.to_lowercase()),
-            Query::Update {
-                table,
-                assignments,
-                selection,
-            } => {
-                let table = self
-                    .tables
-                    .iter_mut()
-                    .find(|t| table.to_lowercase() == t.name.to_lowercase())
-                    .ok_or(Error::TableNotFound(table))?;
+        }
 
-                let selection = selection.ok_or(Error::Unsupported(
-                    "update without selection (where)".to_string(),
-                ))?;
+        let mut created = Vec::new();
+        for table in missing {
+            self.query(&table.to_sql())?;
+            created.push(table.name.to_uppercase());
+        }
 
-                let selected = Evaluator::eval(Some(table), selection)?;
-                if selected.len() != 1 {
-                    return Err(Error::InvalidOperation(
-                        "more than one column found in selection".to_owned(),
-                    ));
-                }
-                let selected = selected[0].data.keys_where_true()?;
+        Ok(created)
+    }
+
+    /// Applies the migrations in `dir` not recorded in `__migrations` yet, in
+    /// order of version. One that fails is rolled back, putting tables,
+    /// users, sequences and settings back as they were before it, and stops
+    /// the rest. Subscribers and listeners are only notified of the changes of
+    /// a migration, and the replay log only records it, once it's applied
+    /// whole. Returns those applied.
+    pub fn migrate(&mut self, dir: &Path) -> Result<Vec<Migration>> {
+        let migrations = migrations::read_dir(dir)?;
+        if self.table(MIGRATIONS_TABLE).is_none() {
+            self.query(&format!(
+                "CREATE TABLE {MIGRATIONS_TABLE} (version int PRIMARY KEY, name varchar)"
+            ))?;
+        }
+        let recorded: Vec<i32> = self
+            .query_rows(&format!("SELECT version FROM {MIGRATIONS_TABLE}"))?
+            .filter_map(|row| row.first()?.parse().ok())
+            .collect();
 
-                table.update(assignments, selected)?;
+        let mut applied = Vec::new();
+        for migration in migrations {
+            if recorded.contains(&migration.version) {
+                continue;
+            }
+            let sql = std::fs::read_to_string(&migration.path)?;
 
-                let outcols: Vec<OutColumn> = table.columns.iter().map(OutColumn::from).collect();
-                let view = View::new(outcols);
-                if let Some(txs) = self.ws_map.get(&table.name.to_lowercase()) {
-                    for tx in txs {
-                        _ = tx.send(format!("table: {} updated\n {view}", table.name));
-                    }
+            let tables = self.tables.clone();
+            let dropped = self.dropped.clone();
+            let users = self.users.read().unwrap().clone();
+            let sequences = self.sequences.clone();
+            let settings = (
+                self.strictness,
+                self.session.dialect,
+                self.session.statement_timeout,
+                self.deterministic_order,
+                self.session.display_precision,
+            );
+            let record = Query::Insert {
+                table: MIGRATIONS_TABLE.to_owned(),
+                columns: Vec::new(),
+                sources: vec![vec![
+                    Literal::Int(migration.version),
+                    Literal::Str(migration.name.clone()),
+                ]],
+                returning: Vec::new(),
+            };
+            self.held = Some(Vec::new());
+            let res = self.query(&sql).and_then(|_| self.execute_recorded(record));
+            let held = self.held.take().unwrap_or_default();
+            if let Err(e) = res {
+                self.tables = tables;
+                self.dropped = dropped;
+                *self.users.write().unwrap() = users;
+                self.sequences = sequences;
+                (
+                    self.strictness,
+                    self.session.dialect,
+                    self.session.statement_timeout,
+                    self.deterministic_order,
+                    self.session.display_precision,
+                ) = settings;
+                return Err(Error::Migration(format!(
+                    "{} was rolled back: {e}",
+                    migration.path.display()
+                )));
+            }
+            self.release(held)?;
+
+            log::info!("applied migration {}", migration.path.display());
+            applied.push(migration);
+        }
+
+        Ok(applied)
+    }
+
+    /// Inserts a chunk of the rows of a bulk load into `table`, all at once,
+    /// or else one at a time, rejecting those that don't go in. Errors that
+    /// would reject every row, like a denied permission, end the load instead.
+    pub fn load(&mut self, table: &str, records: Vec<LoadRecord>) -> Result<LoadSummary> {
+        let idx = self.table_index(table)?;
+        let headers: Vec<ColumnHeader> = self.tables[idx]
+            .columns
+            .iter()
+            .map(|c| c.header.clone())
+            .filter(|h| !h.hidden)
+            .collect();
+
+        let mut summary = LoadSummary {
+            chunks: 1,
+            ..Default::default()
+        };
+        let mut lines = Vec::new();
+        let mut sources = Vec::new();
+        for LoadRecord { line, row } in records {
+            match row.and_then(|row| load::table_row(&headers, row, self.strictness)) {
+                Ok(row) => {
+                    lines.push(line);
+                    sources.push(row);
                 }
+                Err(message) => summary.reject(line, message),
             }
-            Query::Delete { table, selection } => {
-                let table = self
-                    .tables
-                    .iter_mut()
-                    .find(|t| table.to_lowercase() == t.name.to_lowercase())
-                    .ok_or(Error::TableNotFound(table))?;
-
-                if let Some(selection) = selection {
-                    let selected = Evaluator::eval(Some(table), selection)?;
-                    if selected.len() != 1 {
-                        return Err(Error::InvalidOperation(
-                            "more than one column found in selection".to_owned(),
-                        ));
-                    }
-                    let selected = selected[0].data.keys_where_true()?;
-                    table.delete(selected)?;
-
-                    let outcols: Vec<OutColumn> =
-                        table.columns.iter().map(OutColumn::from).collect();
-                    let view = View::new(outcols);
-                    if let Some(txs) = self.ws_map.get(&table.name.to_lowercase()) {
-                        for tx in txs {
-                            _ = tx
-                                .send(format!("data deleted from table: {}\n {view}", table.name));
-                        }
+        }
+        if sources.is_empty() {
+            return Ok(summary);
+        }
+
+        let insert = |sources| Query::Insert {
+            table: table.to_owned(),
+            columns: headers.iter().map(|h| h.name.clone()).collect(),
+            sources,
+            returning: Vec::new(),
+        };
+        match self.execute_recorded(insert(sources.clone())) {
+            Ok(_) => summary.rows += sources.len(),
+            Err(e) if load::is_fatal(&e) => return Err(e),
+            Err(_) => {
+                for (line, row) in lines.into_iter().zip(sources) {
+                    match self.execute_recorded(insert(vec![row])) {
+                        Ok(_) => summary.rows += 1,
+                        Err(e) if load::is_fatal(&e) => return Err(e),
+                        Err(e) => summary.reject(line, e.to_string()),
                     }
-                } else {
-                    table.truncate();
                 }
+                summary.errors.sort_by_key(|e| e.line);
             }
         }
 
-        Ok(None)
+        Ok(summary)
     }
 
-    pub fn execute_all(&mut self, query: &str) -> Result<()> {
-        if let Ok(meta) = MetaCommand::from_str(query) {
-            self.metacommand_handler(meta);
-            return Ok(());
+    /// Inserts `rows` rows of made up values into `table`, see
+    /// `generate::rows`, a chunk at a time so that subscribers see them
+    /// arrive like they would from a busy client.
+    pub fn generate(&mut self, table: &str, rows: usize) -> Result<usize> {
+        let mut generated = 0;
+        while generated < rows {
+            let idx = self.table_index(table)?;
+            let columns = self.tables[idx]
+                .columns
+                .iter()
+                .filter(|c| !c.header.hidden)
+                .map(|c| c.header.name.clone())
+                .collect();
+            let sources =
+                generate::rows(&self.tables[idx], (rows - generated).min(GENERATE_CHUNK))?;
+            generated += sources.len();
+
+            self.execute_recorded(Query::Insert {
+                table: table.to_owned(),
+                columns,
+                sources,
+                returning: Vec::new(),
+            })?;
         }
 
-        let queries = parser::parse_all(query)?;
+        Ok(generated)
+    }
 
-        for query in queries {
-            if let Some(view) = self.execute(query)? {
-                println!("{view}");
-            }
+    pub fn execute_all(&mut self, query: &str) -> Result<()> {
+        if query.starts_with('.') {
+            return self.metacommand_handler(MetaCommand::from_str(query)?);
+        }
+
+        for view in self.query(query)? {
+            println!("{}", view.render(self.output_mode));
         }
 
         Ok(())
     }
 }
 
+// a step of executing a select, as `EXPLAIN` shows it
+struct Stage {
+    name: &'static str,
+    detail: String,
+    // for filters, along with the rows the planner expects them to give
+    // when the index could answer them
+    indexed: Option<bool>,
+    estimate: Option<usize>,
+    // with `ANALYZE`
+    rows: Option<usize>,
+    time: Option<Duration>,
+}
+
+impl Stage {
+    fn new(name: &'static str, detail: impl Into<String>) -> Self {
+        Self {
+            name,
+            detail: detail.into(),
+            indexed: None,
+            estimate: None,
+            rows: None,
+            time: None,
+        }
+    }
+
+    // of a filter, found through `path`
+    fn access(self, path: Option<&AccessPath>) -> Self {
+        Self {
+            indexed: Some(path.is_some_and(|p| p.range.is_some())),
+            estimate: path.map(|p| p.estimate),
+            ..self
+        }
+    }
+
+    fn view(stages: Vec<Stage>, analyze: bool) -> View {
+        let mut columns = vec!["stage", "detail", "index", "estimate"];
+        if analyze {
+            columns.extend(["rows", "time_ms"]);
+        }
+
+        let rows = stages
+            .into_iter()
+            .map(|s| {
+                let mut items = vec![
+                    s.name.to_owned(),
+                    s.detail,
+                    s.indexed.map(|i| i.to_string()).unwrap_or_default(),
+                    s.estimate.map(|e| e.to_string()).unwrap_or_default(),
+                ];
+                if analyze {
+                    items.push(s.rows.map(|r| r.to_string()).unwrap_or_default());
+                    items.push(
+                        s.time
+                            .map(|t| format::double(t.as_secs_f64() * 1000.0, Some(3)))
+                            .unwrap_or_default(),
+                    );
+                }
+                Row { items }
+            })
+            .collect();
+
+        View {
+            columns: columns.into_iter().map(str::to_owned).collect(),
+            rows,
+            ..Default::default()
+        }
+    }
+}
+
+// the rows of the table a plan scans or joins that it selects, `None` for
+// all of them
+struct Rows<'a> {
+    table: Option<Cow<'a, Table>>,
+    selected: Option<RowSet>,
+}
+
+// the select list of a plan, evaluated for the rows of `table` it selects
+struct Projection<'a, 'p> {
+    table: Option<Cow<'a, Table>>,
+    items: &'p [SelectItem],
+    grouped: bool,
+    result: Vec<OutColumn>,
+    // the column of `result` of each of `items`, when it has one
+    columns: Vec<Option<usize>>,
+}
+
+// with `EXPLAIN ANALYZE`, how many rows the step that started at `started`
+// gave, and how long it took
+fn record(
+    stages: &mut Option<Vec<Stage>>,
+    mut stage: Stage,
+    started: Instant,
+    rows: impl FnOnce() -> usize,
+) {
+    if let Some(stages) = stages {
+        stage.time = Some(started.elapsed());
+        stage.rows = Some(rows());
+        stages.push(stage);
+    }
+}
+
+// the first of each of the same rows, in order
+fn distinct(rows: Vec<Vec<Literal>>) -> Vec<Vec<Literal>> {
+    let mut seen = HashSet::new();
+    rows.into_iter()
+        .filter(|r| seen.insert(row_key(r)))
+        .collect()
+}
+
+// what a row of values is told apart from others by, which nulls are the
+// same in
+fn row_key(row: &[Literal]) -> Vec<Option<HashKey>> {
+    row.iter().cloned().map(HashKey::new).collect()
+}
+
+fn join_detail(join: &Join) -> String {
+    let mut detail = format!("{:?} join {}", join.kind, join.table).to_lowercase();
+    if join.natural {
+        detail = format!("natural {detail}");
+    }
+    if let Some(alias) = &join.alias {
+        detail += &format!(" as {alias}");
+    }
+    if let Some(on) = &join.on {
+        detail += &format!(" on {on}");
+    }
+    if !join.using.is_empty() {
+        detail += &format!(" using ({})", join.using.join(", "));
+    }
+    detail
+}
+
+fn projection_detail(projection: &[SelectItem], group_by: &[Expression]) -> String {
+    let projection: Vec<String> = projection
+        .iter()
+        .map(|p| match &p.alias {
+            Some(alias) => format!("{} as {alias}", p.expr),
+            None => p.expr.to_string(),
+        })
+        .collect();
+    let mut detail = projection.join(", ");
+    if !group_by.is_empty() {
+        let group_by: Vec<String> = group_by.iter().map(Expression::to_string).collect();
+        detail += &format!(" by {}", group_by.join(", "));
+    }
+    detail
+}
+
+fn limit_detail(limit: Option<usize>, offset: usize) -> Option<String> {
+    match (limit, offset) {
+        (None, 0) => None,
+        (Some(limit), 0) => Some(limit.to_string()),
+        (Some(limit), offset) => Some(format!("{limit} offset {offset}")),
+        (None, offset) => Some(format!("offset {offset}")),
+    }
+}
+
+fn sort_detail(order_by: &[OrderBy], limit: Option<usize>, offset: usize) -> String {
+    let keys: Vec<String> = order_by
+        .iter()
+        .map(|o| {
+            let mut key = o.expr.to_string();
+            if o.desc {
+                key += " desc";
+            }
+            // only when they don't go where the direction puts them anyway
+            if o.nulls_first != o.desc {
+                key += if o.nulls_first {
+                    " nulls first"
+                } else {
+                    " nulls last"
+                };
+            }
+            key
+        })
+        .collect();
+
+    let mut detail = format!("by {}", keys.join(", "));
+    if let Some(limit) = limit {
+        detail += &format!(", top {limit}");
+    }
+    if offset > 0 {
+        detail += &format!(", offset {offset}");
+    }
+    detail
+}
+
+fn has_row_triggers(table: &Table, event: ChangeKind) -> bool {
+    table
+        .triggers
+        .iter()
+        .any(|t| t.for_each_row && t.event == event)
+}
+
+fn insert_images(table: &Table, columns: &[String], sources: &[Vec<Literal>]) -> Vec<RowImage> {
+    let names: Vec<String> = if columns.is_empty() {
+        table
+            .columns
+            .iter()
+            .filter(|c| !c.header.hidden)
+            .map(|c| c.header.name.to_lowercase())
+            .collect()
+    } else {
+        columns.iter().map(|c| c.to_lowercase()).collect()
+    };
+
+    sources
+        .iter()
+        .map(|values| {
+            let mut new: HashMap<String, Literal> = table
+                .columns
+                .iter()
+                .map(|c| (c.header.name.to_lowercase(), Literal::Null))
+                .collect();
+            new.extend(names.iter().cloned().zip(values.iter().cloned()));
+
+            RowImage {
+                old: None,
+                new: Some(new),
+            }
+        })
+        .collect()
+}
+
+fn update_images(table: &Table, assigned: &Assigned, selected: &RowSet) -> Vec<RowImage> {
+    selected
+        .iter()
+        .map(|id| RowImage {
+            old: Some(table.row_values(id)),
+            new: Some(table.assigned_row_values(assigned, id)),
+        })
+        .collect()
+}
+
 // Meta Commands
 impl Database {
-    fn metacommand_handler(&mut self, cmd: MetaCommand) {
+    fn metacommand_handler(&mut self, cmd: MetaCommand) -> Result<()> {
         match cmd {
             MetaCommand::ListTables => {
                 let mut tbl = prettytable::Table::new();
@@ -389,33 +3803,395 @@ impl Database {
                         tbl.add_row(r);
                     });
 
+                for (name, vt) in self.virtual_tables.iter() {
+                    let col_names = vt
+                        .schema()
+                        .into_iter()
+                        .fold("".to_string(), |acc, (i, _)| format!("{acc}{i}\n"));
+                    tbl.add_row(prettytable::row![
+                        format!("{} (virtual)", name.to_uppercase()),
+                        col_names
+                    ]);
+                }
+
                 println!("{tbl}");
             }
 
-            MetaCommand::Exit => std::process::exit(0),
-            MetaCommand::Persist(path) => {
-                let json = serde_json::to_string(&self).expect("failed to serialize the database");
+            MetaCommand::Check(table) => {
+                let tables: Vec<&Table> = match table {
+                    Some(name) => vec![self
+                        .table(&name)
+                        .ok_or_else(|| self.table_not_found(&name))?],
+                    None => self.tables.iter().collect(),
+                };
 
-                let file = File::create(path).expect("failed to create file");
-                let buf = BufWriter::new(file);
+                let mut tbl = prettytable::Table::new();
+                tbl.add_row(prettytable::row!["name", "problems"]);
+                for t in tables {
+                    let problems = t.verify();
+                    let problems = if problems.is_empty() {
+                        "none".to_owned()
+                    } else {
+                        problems.join("\n")
+                    };
+                    tbl.add_row(prettytable::row![t.name, problems]);
+                }
 
-                let mut encoder =
-                    zstd::Encoder::new(buf, 3).expect("failed to create zstd compression ecoder");
-                encoder
-                    .write_all(json.as_bytes())
-                    .expect("failed to write to file");
-                encoder.finish().expect("failed to finish writing to file");
+                println!("{tbl}");
             }
-            MetaCommand::Restore(path) => {
-                let file = File::open(path).expect("failed to open file");
-                let buf = BufReader::new(file);
 
-                let decoded = zstd::decode_all(buf).expect("failed to decode db from disk");
-                let db: Database =
-                    serde_json::from_slice(&decoded).expect("db deserialization error");
+            MetaCommand::Mode(mode) => self.output_mode = mode,
+            MetaCommand::Generate { table, rows } => {
+                let generated = self.generate(&table, rows)?;
+                println!("generated {generated} rows into {table}");
+            }
+            MetaCommand::Migrate(dir) => {
+                let applied = self.migrate(&dir)?;
+                if applied.is_empty() {
+                    println!("no migrations to apply");
+                }
+                for migration in applied {
+                    println!("applied {}", migration.path.display());
+                }
+            }
+            MetaCommand::Schema { table, json } => {
+                let mut schema = self.schema();
+                if let Some(name) = table {
+                    let name = self
+                        .table(&name)
+                        .map(|t| t.name.clone())
+                        .ok_or_else(|| self.table_not_found(&name))?;
+                    schema.tables.retain(|t| t.name == name);
+                }
 
-                self.tables = db.tables;
+                if json {
+                    println!("{}", schema.to_json());
+                } else {
+                    for table in &schema.tables {
+                        println!("{}", table.to_sql());
+                    }
+                }
+            }
+            MetaCommand::Dump(table) => print!("{}", self.dump(table.as_deref())?),
+            MetaCommand::Exit => std::process::exit(0),
+            MetaCommand::Persist(path) => self.persist(&path)?,
+            MetaCommand::Restore(path) => self.tables = Database::restore(&path)?.tables,
+            #[cfg(feature = "arrow")]
+            MetaCommand::Export { table, path } => {
+                let format = crate::arrow::IpcFormat::from_path(&path);
+                let file = BufWriter::new(File::create(&path)?);
+                self.export_arrow(&table, file, format)?;
+            }
+            #[cfg(feature = "arrow")]
+            MetaCommand::Import { table, path } => {
+                let count = self.import_arrow(&table, &std::fs::read(&path)?)?;
+                println!("imported {count} rows into {table}");
+            }
+            #[cfg(not(feature = "arrow"))]
+            MetaCommand::Export { .. } | MetaCommand::Import { .. } => {
+                return Err(Error::Unsupported(
+                    "arrow ipc, without the arrow feature".to_owned(),
+                ))
+            }
+            #[cfg(feature = "xlsx")]
+            MetaCommand::ExportXlsx { path, query } => {
+                let file = BufWriter::new(File::create(&path)?);
+                self.export_xlsx(&query, file)?;
+            }
+            #[cfg(not(feature = "xlsx"))]
+            MetaCommand::ExportXlsx { .. } => {
+                return Err(Error::Unsupported(
+                    "xlsx, without the xlsx feature".to_owned(),
+                ))
             }
         }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::builder::{col, lit};
+
+    // the last view `sql` gives, as csv
+    fn csv(db: &mut Database, sql: &str) -> String {
+        let mut views = db.query(sql).unwrap();
+        views.pop().expect("a view").to_csv()
+    }
+
+    // the events of a subscription to `table` of `db`, without a user
+    fn subscribe(db: &mut Database, table: &str) -> Receiver<String> {
+        let (subscriptions, receiver) = flume::unbounded();
+        db.set_receiver(receiver);
+        let (sender, events) = flume::unbounded();
+        subscriptions
+            .send(Subscription {
+                table: table.to_owned(),
+                sender,
+                span: tracing::Span::none(),
+                user: None,
+                resume_from: None,
+                wait_for_table: false,
+                columns: None,
+                throttle: None,
+                sample_every: None,
+            })
+            .unwrap();
+        db.recv_senders().unwrap();
+        events
+    }
+
+    fn next_event(events: &Receiver<String>) -> ChangeEvent {
+        serde_json::from_str(&events.try_recv().expect("an event")).unwrap()
+    }
+
+    #[test]
+    fn update_sets_constant_expressions_on_every_row() {
+        let mut db = Database::new();
+        db.query("CREATE TABLE t (id INT PRIMARY KEY, n INT)")
+            .unwrap();
+        db.query("INSERT INTO t VALUES (1, 0), (2, 0), (3, 0)")
+            .unwrap();
+        db.query("CREATE SEQUENCE s").unwrap();
+
+        db.query("UPDATE t SET n = nextval('s') WHERE id >= 2")
+            .unwrap();
+        db.query("UPDATE t SET n = n + 10 WHERE id <= 2").unwrap();
+
+        assert_eq!(
+            csv(&mut db, "SELECT id, n FROM t ORDER BY id"),
+            "id,n\n1,10\n2,11\n3,1\n"
+        );
+    }
+
+    #[test]
+    fn update_evaluates_once_with_row_triggers() {
+        let mut db = Database::new();
+        db.query("CREATE TABLE t (id INT PRIMARY KEY, n INT)")
+            .unwrap();
+        db.query("CREATE TABLE changes (id INT PRIMARY KEY, n INT)")
+            .unwrap();
+        db.query("INSERT INTO t VALUES (1, 0)").unwrap();
+        db.query("CREATE SEQUENCE s").unwrap();
+        db.query(
+            "CREATE TRIGGER logged AFTER UPDATE ON t FOR EACH ROW \
+             INSERT INTO changes VALUES (NEW.n, NEW.n)",
+        )
+        .unwrap();
+
+        db.query("UPDATE t SET n = nextval('s') WHERE id = 1")
+            .unwrap();
+        db.query("UPDATE t SET n = nextval('s') WHERE id = 1")
+            .unwrap();
+
+        assert_eq!(csv(&mut db, "SELECT n FROM t"), "n\n2\n");
+        assert_eq!(
+            csv(&mut db, "SELECT id, n FROM changes ORDER BY id"),
+            "id,n\n1,1\n2,2\n"
+        );
+    }
+
+    #[test]
+    fn delete_events_carry_the_deleted_rows() {
+        let mut db = Database::new();
+        db.query("CREATE TABLE t (id INT PRIMARY KEY, name VARCHAR)")
+            .unwrap();
+        db.query("INSERT INTO t VALUES (1, 'a'), (2, 'b'), (3, 'c')")
+            .unwrap();
+        let events = subscribe(&mut db, "t");
+
+        let returned = csv(&mut db, "DELETE FROM t WHERE id >= 2 RETURNING name");
+
+        let event = next_event(&events);
+        assert_eq!(event.kind, ChangeKind::Delete);
+        assert_eq!(event.view.unwrap().to_csv(), "id,name\n2,b\n3,c\n");
+        assert_eq!(returned, "name\nb\nc\n");
+        assert_eq!(csv(&mut db, "SELECT id FROM t"), "id\n1\n");
+    }
+
+    #[test]
+    fn subscribers_see_truncates_and_drops_of_mixed_case_tables() {
+        let mut db = Database::new();
+        db.query("CREATE TABLE Orders (id INT PRIMARY KEY)")
+            .unwrap();
+        db.query("INSERT INTO Orders VALUES (1)").unwrap();
+        let events = subscribe(&mut db, "ORDERS");
+
+        db.query("TRUNCATE orders").unwrap();
+        db.query("DROP TABLE oRdErS").unwrap();
+
+        let truncate = next_event(&events);
+        assert_eq!(truncate.kind, ChangeKind::Truncate);
+        assert_eq!(table_key(&truncate.table), "orders");
+        assert_eq!(next_event(&events).kind, ChangeKind::Drop);
+        assert!(!db.ws_map.contains_key("orders"));
+    }
+
+    #[test]
+    fn select_list_aliases_name_their_columns() {
+        let mut db = Database::new();
+        db.query("CREATE TABLE items (id INT PRIMARY KEY, price INT)")
+            .unwrap();
+        db.query("INSERT INTO items VALUES (1, 3)").unwrap();
+
+        assert_eq!(
+            csv(&mut db, "SELECT price * 2 AS doubled FROM items"),
+            "doubled\n6\n"
+        );
+    }
+
+    #[test]
+    fn replay_reproduces_writes_made_through_the_api() {
+        let path = std::env::temp_dir().join(format!("socketdb-replay-{}", std::process::id()));
+        let mut db = Database::new();
+        db.set_replay_log(ReplayLog::open(&path).unwrap());
+        db.query("CREATE TABLE items (id INT PRIMARY KEY, \"Name\" VARCHAR, n INT)")
+            .unwrap();
+        db.generate("items", 20).unwrap();
+        db.expire_rows("items", col("id").gt(lit(15))).unwrap();
+
+        let mut replayed = Database::new();
+        for entry in crate::replay::read(&path).unwrap() {
+            replayed.query(&entry.sql).unwrap();
+        }
+        std::fs::remove_file(&path).unwrap();
+
+        let select = "SELECT * FROM items ORDER BY id";
+        assert_eq!(csv(&mut replayed, select), csv(&mut db, select));
+        assert_eq!(csv(&mut db, select).lines().count(), 16);
+    }
+
+    #[test]
+    fn replay_leaves_out_rolled_back_migrations() {
+        let id = std::process::id();
+        let dir = std::env::temp_dir().join(format!("socketdb-replayed-migrations-{id}"));
+        let path = std::env::temp_dir().join(format!("socketdb-replay-migrations-{id}"));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(
+            dir.join("0001_create.sql"),
+            "CREATE TABLE t (id INT PRIMARY KEY); INSERT INTO t VALUES (1)",
+        )
+        .unwrap();
+        std::fs::write(
+            dir.join("0002_fail.sql"),
+            "INSERT INTO t VALUES (2); SELECT * FROM missing",
+        )
+        .unwrap();
+        let mut db = Database::new();
+        db.set_replay_log(ReplayLog::open(&path).unwrap());
+
+        let res = db.migrate(&dir);
+        let entries = crate::replay::read(&path).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert!(matches!(res, Err(Error::Migration(_))));
+        assert!(entries.iter().all(|e| !e.sql.contains("missing")));
+        let mut replayed = Database::new();
+        for entry in entries {
+            replayed.query(&entry.sql).unwrap();
+        }
+        for select in ["SELECT * FROM t ORDER BY id", "SELECT * FROM __migrations"] {
+            assert_eq!(csv(&mut replayed, select), csv(&mut db, select));
+        }
+        assert_eq!(csv(&mut db, "SELECT * FROM t"), "id\n1\n");
+    }
+
+    #[test]
+    fn failed_migrations_are_rolled_back_unnotified() {
+        let dir = std::env::temp_dir().join(format!("socketdb-migrations-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("0001_insert.sql"), "INSERT INTO t VALUES (0)").unwrap();
+        // everything but the last statement goes through
+        std::fs::write(
+            dir.join("0002_fail.sql"),
+            "INSERT INTO t VALUES (nextval('s')); SET dialect = 'mysql'; NOTIFY c, 'x'; \
+             DROP TABLE t; SELECT * FROM missing",
+        )
+        .unwrap();
+        let mut db = Database::new();
+        db.query("CREATE TABLE t (id INT PRIMARY KEY)").unwrap();
+        db.query("CREATE SEQUENCE s").unwrap();
+        let events = subscribe(&mut db, "t");
+        let (listener, notifications) = flume::unbounded();
+        db.listen("c", listener);
+
+        let res = db.migrate(&dir);
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert!(matches!(
+            res,
+            Err(Error::Migration(e)) if e.to_lowercase().contains("`missing` not found")
+        ));
+        let event = next_event(&events);
+        assert_eq!(event.kind, ChangeKind::Insert);
+        assert_eq!(event.view.unwrap().to_csv(), "id\n0\n");
+        assert!(events.try_recv().is_err());
+        assert!(notifications.try_recv().is_err());
+        assert!(db.ws_map.contains_key(&table_key("t")));
+        assert_eq!(db.session.dialect, None);
+        assert_eq!(csv(&mut db, "SELECT nextval('s') AS n"), "n\n1\n");
+        assert_eq!(csv(&mut db, "SELECT id FROM t"), "id\n0\n");
+    }
+
+    #[test]
+    fn sessions_and_requests_can_only_shorten_the_statement_timeout() {
+        let slow = "SELECT count(*) FROM generate_series(1, 1000000000)";
+        let mut db = Database::new();
+        db.set_statement_timeout(Some(Duration::from_millis(50)));
+
+        let res = db.run_as(Session::default(), |db| {
+            db.query("SET statement_timeout = 0")?;
+            db.query("SET statement_timeout = 3600000")?;
+            db.query(slow)
+        });
+        assert!(matches!(res, Err(Error::Timeout(_))));
+        assert_eq!(db.session.statement_timeout, None);
+
+        let res = db.run_with_timeout(Some(Duration::from_secs(3600)), |db| db.query(slow));
+        assert!(matches!(res, Err(Error::Timeout(_))));
+    }
+
+    #[test]
+    fn set_dialect_lasts_for_the_session() {
+        let mut db = Database::new();
+        db.query("CREATE TABLE t (id INT PRIMARY KEY)").unwrap();
+        db.query("INSERT INTO t VALUES (1), (2), (3)").unwrap();
+
+        let views = db
+            .run_as(Session::default(), |db| {
+                db.query("SET dialect = 'mysql'; SELECT `id` FROM t ORDER BY id LIMIT 1, 1")
+            })
+            .unwrap();
+
+        assert_eq!(views[0].to_csv(), "id\n2\n");
+        assert_eq!(db.dialect, SqlDialect::Postgres);
+        let other = db.run_as(Session::default(), |db| db.query("SELECT `id` FROM t"));
+        assert!(other.is_err());
+    }
+
+    #[test]
+    fn set_display_precision_lasts_for_the_session() {
+        let mut db = Database::new();
+        db.query("CREATE TABLE t (id INT PRIMARY KEY, score DOUBLE)")
+            .unwrap();
+        let events = subscribe(&mut db, "t");
+
+        let views = db
+            .run_as(Session::default(), |db| {
+                db.query("SET display_precision = 1; INSERT INTO t VALUES (1, 1.25); SELECT score FROM t")
+            })
+            .unwrap();
+
+        assert_eq!(views.last().unwrap().to_csv(), "score\n1.3\n");
+        assert_eq!(db.display_precision(), None);
+        assert_eq!(
+            next_event(&events).view.unwrap().to_csv(),
+            "id,score\n1,1.25\n"
+        );
+        let other = db.run_as(Session::default(), |db| csv(db, "SELECT score FROM t"));
+        assert_eq!(other, "score\n1.25\n");
     }
 }
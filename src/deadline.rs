@@ -0,0 +1,66 @@
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+use crate::{Error, Result};
+
+/// When the statement being executed has to be done by. It is checked between
+/// the steps of executing it, each of which goes through a batch of rows at
+/// once, so a statement can run over by as long as its slowest step takes.
+#[derive(Debug, Clone, Default)]
+pub struct Deadline {
+    at: Option<(Instant, Duration)>,
+    cancel: Option<CancelToken>,
+}
+
+impl Deadline {
+    /// `timeout` from now, or never for `None`.
+    pub fn after(timeout: Option<Duration>) -> Self {
+        Self {
+            at: timeout.and_then(|t| Some((Instant::now().checked_add(t)?, t))),
+            cancel: None,
+        }
+    }
+
+    /// Also stops the statement once `token` is cancelled.
+    pub fn cancelled_by(self, token: Option<CancelToken>) -> Self {
+        Self {
+            cancel: token,
+            ..self
+        }
+    }
+
+    pub fn check(&self) -> Result<()> {
+        if self.cancel.as_ref().is_some_and(CancelToken::is_cancelled) {
+            return Err(Error::Cancelled);
+        }
+
+        match self.at {
+            Some((at, timeout)) if Instant::now() >= at => Err(Error::Timeout(timeout)),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Cancels the statements it is given to from another thread, e.g. for
+/// `KILL`, at the next check of their deadline.
+#[derive(Debug, Clone, Default)]
+pub struct CancelToken(Arc<AtomicBool>);
+
+impl CancelToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
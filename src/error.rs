@@ -1,4 +1,4 @@
-use sqlparser::parser::ParserError;
+use sqlparser::{parser::ParserError, tokenizer::Location};
 
 pub type Result<T> = std::result::Result<T, Error>;
 
@@ -14,23 +14,307 @@ pub enum Error {
     ParsingError(ParserError),
     #[error("invalid query: `{0}` not supported")]
     InvalidQuery(String),
-    #[error("invalid query: Column `{col}` not found in table `{table}`")]
+    #[error("invalid query: Column `{col}` not found in table `{table}`{}", did_you_mean(.suggestion))]
     ColumnNotFound {
         col: String,
         table: String,
+        suggestion: Option<String>,
     },
     #[error("invalid operation: `{0}` not allowed")]
     InvalidOperation(String),
-    #[error("invalid query: table `{0}` not found")]
-    TableNotFound(String),
+    #[error("invalid query: table `{table}` not found{}", did_you_mean(.suggestion))]
+    TableNotFound {
+        table: String,
+        suggestion: Option<String>,
+    },
     #[error("invalid query: table `{0}` already exists")]
     TableAlreadyExists(String),
+    #[error("invalid query: trigger `{0}` already exists")]
+    TriggerAlreadyExists(String),
+    #[error("invalid query: trigger `{0}` not found")]
+    TriggerNotFound(String),
     #[error("unsupported feature: `{0}`")]
     Unsupported(String),
     #[error("evaluation error: `{0}`")]
     EvaluationError(String),
+    #[error("client error: `{0}`")]
+    ClientError(String),
+    #[error("invalid schema: `{0}`")]
+    InvalidSchema(String),
+    #[error("statement timed out after {0:?}")]
+    Timeout(std::time::Duration),
+    #[error("limit exceeded: `{0}`")]
+    LimitExceeded(String),
+    #[error("statement cancelled")]
+    Cancelled,
+    #[error("connection {0} not found")]
+    ConnectionNotFound(u64),
+    #[error("invalid query: policy `{0}` already exists")]
+    PolicyAlreadyExists(String),
+    #[error("invalid query: policy `{0}` not found")]
+    PolicyNotFound(String),
+    #[error("database `{0}` not found")]
+    DatabaseNotFound(String),
+    #[error("database `{0}` already exists")]
+    DatabaseAlreadyExists(String),
+    #[error("invalid token: {0}")]
+    InvalidToken(String),
+    #[error("user `{0}` already exists")]
+    UserAlreadyExists(String),
+    #[error("user `{0}` not found")]
+    UserNotFound(String),
+    #[error("tls error: `{0}`")]
+    Tls(String),
+    #[error("permission denied: {0}")]
+    PermissionDenied(String),
+    #[error("config error: `{0}`")]
+    Config(String),
+    #[error("arrow error: `{0}`")]
+    Arrow(String),
+    #[error("xlsx error: `{0}`")]
+    Xlsx(String),
+    #[error("migration error: `{0}`")]
+    Migration(String),
+    #[error("invalid query: index `{0}` already exists")]
+    IndexAlreadyExists(String),
+    #[error("invalid query: index `{0}` not found")]
+    IndexNotFound(String),
+    #[error("invalid query: sequence `{0}` already exists")]
+    SequenceAlreadyExists(String),
+    #[error("invalid query: sequence `{0}` not found")]
+    SequenceNotFound(String),
+    #[error("cursor `{0}` not found")]
+    CursorNotFound(String),
+    // an error the server sent back, see `Error::to_json`
+    #[error("{code}: {message}")]
+    Remote { code: String, message: String },
     #[error("unknown error")]
     Unknown,
+    // an error in parsing the sql text, along with where in the text it is
+    #[error(
+        "{source} at {span}{}",
+        .statement.map(|s| format!(" of statement {}", s + 1)).unwrap_or_default()
+    )]
+    Located {
+        statement: Option<usize>,
+        span: Span,
+        source: Box<Error>,
+    },
+}
+
+/// A position in sql text, counted from 1 like an editor does.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Span {
+    pub line: u64,
+    pub column: u64,
+}
+
+impl std::fmt::Display for Span {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "line {}, column {}", self.line, self.column)
+    }
+}
+
+impl Span {
+    /// The line of `input` the span is on, with a `^` under its column.
+    pub fn underline(&self, input: &str) -> Option<String> {
+        let line = input.lines().nth(self.line.checked_sub(1)? as usize)?;
+        let pad = " ".repeat(self.column.saturating_sub(1) as usize);
+        Some(format!("{line}\n{pad}^"))
+    }
+}
+
+impl Error {
+    /// `table` doesn't exist, suggesting whichever of the `existing` ones is
+    /// spelled most like it.
+    pub fn table_not_found<'a>(table: &str, existing: impl IntoIterator<Item = &'a str>) -> Self {
+        Error::TableNotFound {
+            table: table.to_owned(),
+            suggestion: closest(table, existing),
+        }
+    }
+
+    /// Like `table_not_found`, for a column of `table`.
+    pub fn column_not_found<'a>(
+        col: &str,
+        table: &str,
+        existing: impl IntoIterator<Item = &'a str>,
+    ) -> Self {
+        Error::ColumnNotFound {
+            col: col.to_owned(),
+            table: table.to_owned(),
+            suggestion: closest(col, existing),
+        }
+    }
+
+    /// Attaches where in the sql text the error comes from. Errors from
+    /// sqlparser say where they are themselves, and `fallback` is used for
+    /// anything else.
+    pub fn at(self, statement: Option<usize>, fallback: Location) -> Self {
+        let (source, location) = match self {
+            Error::ParsingError(ParserError::ParserError(msg)) => {
+                let (msg, location) = split_location(msg);
+                (Error::ParsingError(ParserError::ParserError(msg)), location)
+            }
+            Error::ParsingError(ParserError::TokenizerError(msg)) => {
+                let (msg, location) = split_location(msg);
+                (
+                    Error::ParsingError(ParserError::TokenizerError(msg)),
+                    location,
+                )
+            }
+            e @ Error::Located { .. } => return e,
+            e => (e, None),
+        };
+
+        let location = location.unwrap_or(fallback);
+        if location.line == 0 {
+            return source;
+        }
+
+        Error::Located {
+            statement,
+            span: Span {
+                line: location.line,
+                column: location.column,
+            },
+            source: Box::new(source),
+        }
+    }
+
+    /// A code for the kind of error that stays the same between releases, for
+    /// programs to tell errors apart by instead of their message.
+    pub fn code(&self) -> &str {
+        match self {
+            Error::Unknown => "SDB-0000",
+            Error::InvalidMetaCommand(_) => "SDB-0001",
+            Error::IOError(_) => "SDB-0002",
+            Error::DeserializingError(_) => "SDB-0003",
+            Error::ParsingError(_) => "SDB-0004",
+            Error::InvalidQuery(_) => "SDB-0005",
+            Error::ColumnNotFound { .. } => "SDB-0006",
+            Error::InvalidOperation(_) => "SDB-0007",
+            Error::TableNotFound { .. } => "SDB-0008",
+            Error::TableAlreadyExists(_) => "SDB-0009",
+            Error::TriggerAlreadyExists(_) => "SDB-0010",
+            Error::TriggerNotFound(_) => "SDB-0011",
+            Error::Unsupported(_) => "SDB-0012",
+            Error::EvaluationError(_) => "SDB-0013",
+            Error::ClientError(_) => "SDB-0014",
+            Error::InvalidSchema(_) => "SDB-0015",
+            Error::Timeout(_) => "SDB-0016",
+            Error::LimitExceeded(_) => "SDB-0017",
+            Error::DatabaseNotFound(_) => "SDB-0018",
+            Error::DatabaseAlreadyExists(_) => "SDB-0019",
+            Error::Cancelled => "SDB-0020",
+            Error::ConnectionNotFound(_) => "SDB-0021",
+            Error::PolicyAlreadyExists(_) => "SDB-0022",
+            Error::PolicyNotFound(_) => "SDB-0023",
+            Error::InvalidToken(_) => "SDB-0024",
+            Error::UserAlreadyExists(_) => "SDB-0025",
+            Error::UserNotFound(_) => "SDB-0026",
+            Error::Tls(_) => "SDB-0027",
+            Error::PermissionDenied(_) => "SDB-0028",
+            Error::Config(_) => "SDB-0029",
+            Error::Arrow(_) => "SDB-0030",
+            Error::Xlsx(_) => "SDB-0031",
+            Error::Migration(_) => "SDB-0032",
+            Error::IndexAlreadyExists(_) => "SDB-0033",
+            Error::IndexNotFound(_) => "SDB-0034",
+            Error::SequenceAlreadyExists(_) => "SDB-0035",
+            Error::SequenceNotFound(_) => "SDB-0036",
+            Error::CursorNotFound(_) => "SDB-0037",
+            Error::Remote { code, .. } => code,
+            Error::Located { source, .. } => source.code(),
+        }
+    }
+
+    /// `{"code": ..., "message": ..., "statement": ..., "span": ...}`, where the
+    /// statement (counted from 0) and span are null unless the error came
+    /// from parsing.
+    pub fn to_json(&self) -> serde_json::Value {
+        let (statement, span, message) = match self {
+            Error::Located {
+                statement,
+                span,
+                source,
+            } => (
+                *statement,
+                Some(serde_json::json!({ "line": span.line, "column": span.column })),
+                source.to_string(),
+            ),
+            Error::Remote { message, .. } => (None, None, message.clone()),
+            e => (None, None, e.to_string()),
+        };
+
+        serde_json::json!({
+            "code": self.code(),
+            "message": message,
+            "statement": statement,
+            "span": span,
+        })
+    }
+
+    pub fn span(&self) -> Option<Span> {
+        match self {
+            Error::Located { span, .. } => Some(*span),
+            _ => None,
+        }
+    }
+}
+
+fn did_you_mean(suggestion: &Option<String>) -> String {
+    suggestion
+        .as_ref()
+        .map(|s| format!(", did you mean `{s}`?"))
+        .unwrap_or_default()
+}
+
+// the name most like `name`, as long as it's close enough to be a typo of it
+fn closest<'a>(name: &str, candidates: impl IntoIterator<Item = &'a str>) -> Option<String> {
+    let name = name.to_lowercase();
+    let max = name.chars().count().div_ceil(3);
+
+    candidates
+        .into_iter()
+        .map(|c| (edit_distance(&name, &c.to_lowercase()), c))
+        .filter(|(d, _)| *d <= max)
+        .min_by_key(|(d, _)| *d)
+        .map(|(_, c)| c.to_owned())
+}
+
+// how many characters have to be inserted, removed or replaced to turn `a`
+// into `b`
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut prev: Vec<usize> = (0..=b.len()).collect();
+
+    for (i, ca) in a.chars().enumerate() {
+        let mut cur = Vec::with_capacity(b.len() + 1);
+        cur.push(i + 1);
+        for (j, cb) in b.iter().enumerate() {
+            let replace = prev[j] + usize::from(ca != *cb);
+            cur.push(replace.min(prev[j + 1] + 1).min(cur[j] + 1));
+        }
+        prev = cur;
+    }
+
+    prev[b.len()]
+}
+
+// sqlparser ends its messages with " at Line: 1, Column 5"
+fn split_location(msg: String) -> (String, Option<Location>) {
+    let Some((head, tail)) = msg.rsplit_once(" at Line: ") else {
+        return (msg, None);
+    };
+    let Some((line, column)) = tail.split_once(", Column ") else {
+        return (msg, None);
+    };
+
+    match (line.parse(), column.parse()) {
+        (Ok(line), Ok(column)) => (head.to_owned(), Some(Location { line, column })),
+        _ => (msg, None),
+    }
 }
 
 impl From<std::io::Error> for Error {
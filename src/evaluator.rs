@@ -1,46 +1,64 @@
-use std::collections::BTreeMap;
-
-use crate::parser::expression::{Expression, Literal};
-use crate::table::{Column, ColumnData, Table};
+use crate::chunked::ChunkedMap;
+use crate::deadline::Deadline;
+use crate::fulltext::FullTextIndex;
+use crate::functions::Functions;
+use crate::kernels::{self, Operand};
+use crate::optimizer;
+use crate::parser::expression::{self, Binary, Expression, Ident, Literal};
+use crate::regexp::{self, Patterns};
+use crate::rowset::RowSet;
+use crate::sequence::{self, Sequences};
+use crate::system::{self, System};
+use crate::table::{Column, ColumnData, RowId, Strictness, Table};
 use crate::{Error, Result};
 
-pub struct Evaluator;
+pub struct Evaluator<'a> {
+    functions: &'a Functions,
+    patterns: &'a Patterns,
+    sequences: &'a Sequences,
+    system: System<'a>,
+    strictness: Strictness,
+    deadline: Deadline,
+}
 
 #[derive(Debug, Clone)]
 pub struct OutColumn {
     pub name: String,
     pub data: ColumnData,
+    // digits shown after the decimal point, see `ColumnData::get_as_string`
+    pub precision: Option<usize>,
 }
 
 impl From<Literal> for OutColumn {
     fn from(value: Literal) -> Self {
         let data = match value {
             Literal::Int(l) => {
-                let mut map = BTreeMap::default();
+                let mut map = ChunkedMap::default();
                 map.insert(0, l);
                 ColumnData::Int(map)
             }
             Literal::Str(s) => {
-                let mut map = BTreeMap::default();
-                map.insert(0, s);
+                let mut map = ChunkedMap::default();
+                map.insert(0, s.into());
                 ColumnData::Str(map)
             }
             Literal::Bool(b) => {
-                let mut map = BTreeMap::default();
+                let mut map = ChunkedMap::default();
                 map.insert(0, b);
                 ColumnData::Bool(map)
             }
             Literal::Float(f) => {
-                let mut map = BTreeMap::default();
+                let mut map = ChunkedMap::default();
                 map.insert(0, f);
                 ColumnData::Float(map)
             }
             Literal::Double(d) => {
-                let mut map = BTreeMap::default();
+                let mut map = ChunkedMap::default();
                 map.insert(0, d);
                 ColumnData::Double(map)
             }
-            Literal::Null => unreachable!(),
+            // null has no rows, so the type doesn't matter
+            Literal::Null => ColumnData::Int(Default::default()),
         };
 
         data.into()
@@ -52,6 +70,7 @@ impl From<&Column> for OutColumn {
         Self {
             name: value.header.name.to_string(),
             data: value.data.to_owned(),
+            precision: value.header.precision,
         }
     }
 }
@@ -61,12 +80,63 @@ impl From<ColumnData> for OutColumn {
         Self {
             name: "?column?".to_string(),
             data: value,
+            precision: None,
         }
     }
 }
 
-impl Evaluator {
-    pub fn eval(table: Option<&Table>, expr: Expression) -> Result<Vec<OutColumn>> {
+// an operand of a binary operator, borrowing table columns rather than
+// copying them out like `eval` does
+enum Evaluated<'t> {
+    Column(&'t Column),
+    Literal(Literal),
+    Computed(OutColumn),
+}
+
+impl Evaluated<'_> {
+    fn name(&self) -> &str {
+        match self {
+            Evaluated::Column(c) => &c.header.name,
+            Evaluated::Literal(_) => "?column?",
+            Evaluated::Computed(c) => &c.name,
+        }
+    }
+
+    fn as_operand(&self) -> Operand<'_> {
+        match self {
+            Evaluated::Column(c) => Operand::Column(&c.data),
+            Evaluated::Literal(l) => Operand::Scalar(l.clone()),
+            Evaluated::Computed(c) => Operand::Column(&c.data),
+        }
+    }
+}
+
+impl<'a> Evaluator<'a> {
+    pub fn new(
+        functions: &'a Functions,
+        patterns: &'a Patterns,
+        sequences: &'a Sequences,
+        system: System<'a>,
+        strictness: Strictness,
+        deadline: Deadline,
+    ) -> Self {
+        Self {
+            functions,
+            patterns,
+            sequences,
+            system,
+            strictness,
+            deadline,
+        }
+    }
+
+    pub fn deadline(&self) -> &Deadline {
+        &self.deadline
+    }
+
+    pub fn eval(&self, table: Option<&Table>, expr: Expression) -> Result<Vec<OutColumn>> {
+        self.deadline.check()?;
+
         match expr {
             Expression::Literal(l) => {
                 let data = match l {
@@ -84,14 +154,16 @@ impl Evaluator {
                 };
 
                 match id {
-                    crate::parser::expression::Ident::Wildcard => {
-                        Ok(table.columns.iter().map(|c| c.into()).collect())
-                    }
+                    crate::parser::expression::Ident::Wildcard => Ok(table
+                        .columns
+                        .iter()
+                        .filter(|c| !c.header.hidden)
+                        .map(|c| c.into())
+                        .collect()),
                     crate::parser::expression::Ident::Named(id) => Ok({
-                        let col = table.col_from_name(&id).ok_or(Error::ColumnNotFound {
-                            col: id,
-                            table: table.name.clone(),
-                        })?;
+                        let col = table
+                            .col_from_name(&id)
+                            .ok_or_else(|| table.column_not_found(&id))?;
                         vec![col.into()]
                     }),
                 }
@@ -105,6 +177,7 @@ impl Evaluator {
                                     .map(|t| {
                                         t.columns
                                             .iter()
+                                            .filter(|c| !c.header.hidden)
                                             .map(|c| c.into())
                                             .collect::<Vec<OutColumn>>()
                                     })
@@ -130,10 +203,7 @@ impl Evaluator {
                             };
 
                             let Some(col) = table.col_from_name(&id) else {
-                                return Err(Error::ColumnNotFound {
-                                    col: id.clone(),
-                                    table: table.name.clone(),
-                                });
+                                return Err(table.column_not_found(&id));
                             };
 
                             let out: OutColumn = match col.data {
@@ -161,6 +231,7 @@ impl Evaluator {
                                     .map(|t| {
                                         t.columns
                                             .iter()
+                                            .filter(|c| !c.header.hidden)
                                             .map(|c| c.into())
                                             .collect::<Vec<OutColumn>>()
                                     })
@@ -186,10 +257,7 @@ impl Evaluator {
                             };
 
                             let Some(col) = table.col_from_name(&id) else {
-                                return Err(Error::ColumnNotFound {
-                                    col: id.clone(),
-                                    table: table.name.clone(),
-                                });
+                                return Err(table.column_not_found(&id));
                             };
 
                             let out: OutColumn = match col.data {
@@ -213,35 +281,32 @@ impl Evaluator {
                 expression,
             } => match operator {
                 crate::parser::expression::Unary::Not => match *expression {
-                    Expression::Literal(l) => match l {
-                        Literal::Bool(b) => Ok(vec![Literal::Bool(!b).into()]),
-                        _ => todo!(),
-                    },
-                    Expression::Ident(id) => match id {
-                        crate::parser::expression::Ident::Named(id) => {
-                            let Some(table) = table else {
+                    Expression::Literal(Literal::Bool(b)) => Ok(vec![Literal::Bool(!b).into()]),
+                    Expression::Literal(Literal::Null) => Ok(vec![]),
+                    Expression::Literal(_) => Err(Error::InvalidOperation(
+                        "not on non boolean literal".to_owned(),
+                    )),
+                    Expression::Ident(Ident::Wildcard) => Err(Error::InvalidOperation(
+                        "not with wildcard (*) operator".to_owned(),
+                    )),
+                    expr => {
+                        let mut out = self.eval(table, expr)?;
+                        for c in out.iter_mut() {
+                            let ColumnData::Bool(tree) = &c.data else {
                                 return Err(Error::Unsupported(
-                                    "identifier without column name to apply unary operator"
-                                        .to_string(),
+                                    "not operator on non boolean column".to_string(),
                                 ));
                             };
-                            let col = table.col_from_name(&id).unwrap();
-                            if let ColumnData::Bool(tree) = &col.data {
-                                let tree = tree.iter().map(|(k, v)| (*k, !*v)).collect();
-                                Ok(vec![ColumnData::Bool(tree).into()])
-                            } else {
-                                Err(Error::Unsupported(
-                                    "not operator on non boolean column".to_string(),
-                                ))
-                            }
+                            c.data =
+                                ColumnData::Bool(tree.iter().map(|(k, v)| (*k, !*v)).collect());
                         }
-                        crate::parser::expression::Ident::Wildcard => todo!(),
-                    },
-                    _ => todo!(),
+
+                        Ok(out)
+                    }
                 },
                 crate::parser::expression::Unary::Plus => match *expression {
-                    Expression::Literal(l) => Ok(vec![l.into()]),
-                    Expression::Ident(ident) => Evaluator::eval(table, Expression::Ident(ident)),
+                    Expression::Literal(l) => self.eval(table, Expression::Literal(l)),
+                    Expression::Ident(ident) => self.eval(table, Expression::Ident(ident)),
                     _ => Err(Error::Unsupported(
                         "unary operator plus on non literal or non column".to_owned(),
                     )),
@@ -249,7 +314,7 @@ impl Evaluator {
                 crate::parser::expression::Unary::Minus => match *expression {
                     Expression::Literal(l) => {
                         let l = match l {
-                            Literal::Int(i) => Literal::Int(-i),
+                            Literal::Int(i) => Literal::Int(i.checked_neg().ok_or_else(negate)?),
                             Literal::Float(f) => Literal::Float(-f),
                             Literal::Double(d) => Literal::Double(-d),
                             Literal::Null => Literal::Null,
@@ -259,17 +324,19 @@ impl Evaluator {
                                 ))
                             }
                         };
-                        Ok(vec![l.into()])
+                        self.eval(table, Expression::Literal(l))
                     }
                     Expression::Ident(ident) => {
-                        let out_col = Evaluator::eval(table, Expression::Ident(ident))?;
+                        let out_col = self.eval(table, Expression::Ident(ident))?;
                         let mut out = vec![];
 
                         for mut c in out_col {
                             c.data = match c.data {
-                                ColumnData::Int(i) => {
-                                    ColumnData::Int(i.into_iter().map(|(k, v)| (k, -v)).collect())
-                                }
+                                ColumnData::Int(i) => ColumnData::Int(
+                                    i.into_iter()
+                                        .map(|(k, v)| Ok((k, v.checked_neg().ok_or_else(negate)?)))
+                                        .collect::<Result<_>>()?,
+                                ),
                                 ColumnData::Float(f) => {
                                     ColumnData::Float(f.into_iter().map(|(k, v)| (k, -v)).collect())
                                 }
@@ -295,451 +362,325 @@ impl Evaluator {
             },
             Expression::Binary {
                 operator,
-                left: left_expr,
-                right: right_expr,
+                left,
+                right,
             } => {
-                // TODO: avoid infinite loop by checking the variant
-                let left = Evaluator::eval(table, *left_expr)?;
-                let right = Evaluator::eval(table, *right_expr.clone())?;
-
-                if left.len() != 1 || right.len() != 1 {
-                    return Err(Error::InvalidQuery(
-                        "binary operator with more than one column".to_owned(),
-                    ));
+                if let (Expression::Tuple(l), Expression::Tuple(r)) = (&*left, &*right) {
+                    let expr = optimizer::compare_rows(operator, l.clone(), r.clone())?;
+                    return self.eval(table, expr);
                 }
-                let left = &left[0];
-                let right = &right[0];
-
-                let out = match operator {
-                    crate::parser::expression::Binary::Plus => match (&left.data, &right.data) {
-                        (ColumnData::Str(left), ColumnData::Str(right)) => ColumnData::Str(
-                            left.iter()
-                                .zip(right)
-                                .filter(|((lk, _), (rk, _))| lk == rk)
-                                .map(|((lk, lv), (_, rv))| (*lk, format!("{lv}{rv}")))
-                                .collect(),
-                        ),
-                        (ColumnData::Int(left), ColumnData::Int(right)) => ColumnData::Int(
-                            left.iter()
-                                .zip(right)
-                                .filter(|((lk, _), (rk, _))| lk == rk)
-                                .map(|((lk, lv), (_, rv))| (*lk, lv + rv))
-                                .collect(),
-                        ),
-                        (ColumnData::Float(left), ColumnData::Float(right)) => ColumnData::Float(
-                            left.iter()
-                                .zip(right)
-                                .filter(|((lk, _), (rk, _))| lk == rk)
-                                .map(|((lk, lv), (_, rv))| (*lk, lv + rv))
-                                .collect(),
-                        ),
-                        (ColumnData::Double(left), ColumnData::Double(right)) => {
-                            ColumnData::Double(
-                                left.iter()
-                                    .zip(right)
-                                    .filter(|((lk, _), (rk, _))| lk == rk)
-                                    .map(|((lk, lv), (_, rv))| (*lk, lv + rv))
-                                    .collect(),
-                            )
-                        }
-                        _ => {
-                            return Err(Error::InvalidQuery(
-                                "binary op add between two different types".to_owned(),
-                            ))
-                        }
-                    },
-
-                    crate::parser::expression::Binary::Minus => match (&left.data, &right.data) {
-                        (ColumnData::Int(left), ColumnData::Int(right)) => ColumnData::Int(
-                            left.iter()
-                                .zip(right)
-                                .filter(|((lk, _), (rk, _))| lk == rk)
-                                .map(|((lk, lv), (_, rv))| (*lk, lv - rv))
-                                .collect(),
-                        ),
-                        (ColumnData::Float(left), ColumnData::Float(right)) => ColumnData::Float(
-                            left.iter()
-                                .zip(right)
-                                .filter(|((lk, _), (rk, _))| lk == rk)
-                                .map(|((lk, lv), (_, rv))| (*lk, lv - rv))
-                                .collect(),
-                        ),
-                        (ColumnData::Double(left), ColumnData::Double(right)) => {
-                            ColumnData::Double(
-                                left.iter()
-                                    .zip(right)
-                                    .filter(|((lk, _), (rk, _))| lk == rk)
-                                    .map(|((lk, lv), (_, rv))| (*lk, lv - rv))
-                                    .collect(),
-                            )
-                        }
-                        _ => {
-                            return Err(Error::InvalidQuery(
-                                "binary op minus on invalid type".to_owned(),
-                            ))
-                        }
-                    },
 
-                    crate::parser::expression::Binary::Mul => match (&left.data, &right.data) {
-                        (ColumnData::Int(left), ColumnData::Int(right)) => ColumnData::Int(
-                            left.iter()
-                                .zip(right)
-                                .filter(|((lk, _), (rk, _))| lk == rk)
-                                .map(|((lk, lv), (_, rv))| (*lk, lv * rv))
-                                .collect(),
-                        ),
-                        (ColumnData::Float(left), ColumnData::Float(right)) => ColumnData::Float(
-                            left.iter()
-                                .zip(right)
-                                .filter(|((lk, _), (rk, _))| lk == rk)
-                                .map(|((lk, lv), (_, rv))| (*lk, lv * rv))
-                                .collect(),
-                        ),
-                        (ColumnData::Double(left), ColumnData::Double(right)) => {
-                            ColumnData::Double(
-                                left.iter()
-                                    .zip(right)
-                                    .filter(|((lk, _), (rk, _))| lk == rk)
-                                    .map(|((lk, lv), (_, rv))| (*lk, lv * rv))
-                                    .collect(),
-                            )
-                        }
-                        _ => {
-                            return Err(Error::InvalidQuery(
-                                "binary op mul on invalid type".to_owned(),
-                            ))
-                        }
-                    },
-
-                    crate::parser::expression::Binary::Div => match (&left.data, &right.data) {
-                        (ColumnData::Int(left), ColumnData::Int(right)) => ColumnData::Int(
-                            left.iter()
-                                .zip(right)
-                                .filter(|((lk, _), (rk, _))| lk == rk)
-                                .map(|((lk, lv), (_, rv))| (*lk, lv / rv))
-                                .collect(),
-                        ),
-                        (ColumnData::Float(left), ColumnData::Float(right)) => ColumnData::Float(
-                            left.iter()
-                                .zip(right)
-                                .filter(|((lk, _), (rk, _))| lk == rk)
-                                .map(|((lk, lv), (_, rv))| (*lk, lv / rv))
-                                .collect(),
-                        ),
-                        (ColumnData::Double(left), ColumnData::Double(right)) => {
-                            ColumnData::Double(
-                                left.iter()
-                                    .zip(right)
-                                    .filter(|((lk, _), (rk, _))| lk == rk)
-                                    .map(|((lk, lv), (_, rv))| (*lk, lv / rv))
-                                    .collect(),
-                            )
-                        }
-                        _ => {
-                            return Err(Error::InvalidQuery(
-                                "binary op div on invalid type".to_owned(),
-                            ))
-                        }
-                    },
-
-                    crate::parser::expression::Binary::Rem => match (&left.data, &right.data) {
-                        (ColumnData::Int(left), ColumnData::Int(right)) => ColumnData::Int(
-                            left.iter()
-                                .zip(right)
-                                .filter(|((lk, _), (rk, _))| lk == rk)
-                                .map(|((lk, lv), (_, rv))| (*lk, lv % rv))
-                                .collect(),
-                        ),
-                        (ColumnData::Float(left), ColumnData::Float(right)) => ColumnData::Float(
-                            left.iter()
-                                .zip(right)
-                                .filter(|((lk, _), (rk, _))| lk == rk)
-                                .map(|((lk, lv), (_, rv))| (*lk, lv % rv))
-                                .collect(),
-                        ),
-                        (ColumnData::Double(left), ColumnData::Double(right)) => {
-                            ColumnData::Double(
-                                left.iter()
-                                    .zip(right)
-                                    .filter(|((lk, _), (rk, _))| lk == rk)
-                                    .map(|((lk, lv), (_, rv))| (*lk, lv % rv))
-                                    .collect(),
-                            )
-                        }
-                        _ => {
-                            return Err(Error::InvalidQuery(
-                                "binary op modulo on invalid type".to_owned(),
-                            ))
-                        }
-                    },
-
-                    crate::parser::expression::Binary::Eq => {
-                        let right_data = if let Expression::Literal(right_lit) = *right_expr {
-                            ColumnData::fill_with_literal(right_lit, left.data.len())?
-                        } else {
-                            right.data.clone()
-                        };
-
-                        let eq = match (&left.data, &right_data) {
-                            (ColumnData::Int(left), ColumnData::Int(right)) => left
-                                .iter()
-                                .zip(right)
-                                .filter(|((lk, _), (rk, _))| lk == rk)
-                                .map(|((lk, lv), (_, rv))| (*lk, lv == rv))
-                                .collect(),
-                            (ColumnData::Float(left), ColumnData::Float(right)) => left
-                                .iter()
-                                .zip(right)
-                                .filter(|((lk, _), (rk, _))| lk == rk)
-                                .map(|((lk, lv), (_, rv))| (*lk, lv == rv))
-                                .collect(),
-                            (ColumnData::Double(left), ColumnData::Double(right)) => left
-                                .iter()
-                                .zip(right)
-                                .filter(|((lk, _), (rk, _))| lk == rk)
-                                .map(|((lk, lv), (_, rv))| (*lk, lv == rv))
-                                .collect(),
-                            (ColumnData::Bool(left), ColumnData::Bool(right)) => left
-                                .iter()
-                                .zip(right)
-                                .filter(|((lk, _), (rk, _))| lk == rk)
-                                .map(|((lk, lv), (_, rv))| (*lk, lv == rv))
-                                .collect(),
-                            (ColumnData::Str(left), ColumnData::Str(right)) => left
-                                .iter()
-                                .zip(right)
-                                .filter(|((lk, _), (rk, _))| lk == rk)
-                                .map(|((lk, lv), (_, rv))| (*lk, lv == rv))
-                                .collect(),
-                            _ => {
-                                return Err(Error::InvalidQuery(
-                                    "binary op equals on invalid type".to_owned(),
-                                ))
-                            }
-                        };
-                        log::debug!("eq: {eq:?}");
-                        ColumnData::Bool(eq)
+                let left = self.operand(table, *left)?;
+                let right = self.operand(table, *right)?;
+
+                let data = match operator {
+                    Binary::Plus | Binary::Minus | Binary::Mul | Binary::Div | Binary::Rem => {
+                        kernels::arithmetic(
+                            operator,
+                            left.as_operand(),
+                            right.as_operand(),
+                            self.strictness,
+                        )?
                     }
-
-                    crate::parser::expression::Binary::Lt => {
-                        let right_data = if let Expression::Literal(right_lit) = *right_expr {
-                            ColumnData::fill_with_literal(right_lit, left.data.len())?
-                        } else {
-                            right.data.clone()
-                        };
-
-                        let lt = match (&left.data, &right_data) {
-                            (ColumnData::Int(left), ColumnData::Int(right)) => left
-                                .iter()
-                                .zip(right)
-                                .filter(|((lk, _), (rk, _))| lk == rk)
-                                .map(|((lk, lv), (_, rv))| (*lk, lv < rv))
-                                .collect(),
-                            (ColumnData::Float(left), ColumnData::Float(right)) => left
-                                .iter()
-                                .zip(right)
-                                .filter(|((lk, _), (rk, _))| lk == rk)
-                                .map(|((lk, lv), (_, rv))| (*lk, lv < rv))
-                                .collect(),
-                            (ColumnData::Double(left), ColumnData::Double(right)) => left
-                                .iter()
-                                .zip(right)
-                                .filter(|((lk, _), (rk, _))| lk == rk)
-                                .map(|((lk, lv), (_, rv))| (*lk, lv < rv))
-                                .collect(),
-                            (ColumnData::Bool(left), ColumnData::Bool(right)) => left
-                                .iter()
-                                .zip(right)
-                                .filter(|((lk, _), (rk, _))| lk == rk)
-                                .map(|((lk, lv), (_, rv))| (*lk, lv < rv))
-                                .collect(),
-                            _ => {
-                                return Err(Error::InvalidQuery(
-                                    "binary op less than on invalid type".to_owned(),
-                                ))
+                    Binary::Eq
+                    | Binary::NotEq
+                    | Binary::Lt
+                    | Binary::LtEq
+                    | Binary::Gt
+                    | Binary::GtEq => kernels::compare(
+                        operator,
+                        left.as_operand(),
+                        right.as_operand(),
+                        self.strictness,
+                    )?,
+                    Binary::And | Binary::Or => {
+                        // a bool literal applies to every row of the table,
+                        // including those that are null on the other side,
+                        // like with `x OR true`
+                        let spread = |side: &Evaluated| match (side, table) {
+                            (Evaluated::Literal(l @ Literal::Bool(_)), Some(table)) => {
+                                let rows = table.row_ids();
+                                ColumnData::from_literals(rows.iter().map(|id| (id, l.clone())))
                             }
+                            _ => Ok(None),
                         };
-                        ColumnData::Bool(lt)
-                    }
-
-                    crate::parser::expression::Binary::Gt => {
-                        let right_data = if let Expression::Literal(right_lit) = *right_expr {
-                            ColumnData::fill_with_literal(right_lit, left.data.len())?
-                        } else {
-                            right.data.clone()
+                        let (l, r) = (spread(&left)?, spread(&right)?);
+                        let l = match &l {
+                            Some(rows) => Operand::Column(rows),
+                            None => left.as_operand(),
                         };
-
-                        let gt = match (&left.data, &right_data) {
-                            (ColumnData::Int(left), ColumnData::Int(right)) => left
-                                .iter()
-                                .zip(right)
-                                .filter(|((lk, _), (rk, _))| lk == rk)
-                                .map(|((lk, lv), (_, rv))| (*lk, lv > rv))
-                                .collect(),
-                            (ColumnData::Float(left), ColumnData::Float(right)) => left
-                                .iter()
-                                .zip(right)
-                                .filter(|((lk, _), (rk, _))| lk == rk)
-                                .map(|((lk, lv), (_, rv))| (*lk, lv > rv))
-                                .collect(),
-                            (ColumnData::Double(left), ColumnData::Double(right)) => left
-                                .iter()
-                                .zip(right)
-                                .filter(|((lk, _), (rk, _))| lk == rk)
-                                .map(|((lk, lv), (_, rv))| (*lk, lv > rv))
-                                .collect(),
-                            (ColumnData::Bool(left), ColumnData::Bool(right)) => left
-                                .iter()
-                                .zip(right)
-                                .filter(|((lk, _), (rk, _))| lk == rk)
-                                .map(|((lk, lv), (_, rv))| (*lk, lv > rv))
-                                .collect(),
-                            _ => {
-                                return Err(Error::InvalidQuery(
-                                    "binary op greater than on invalid type".to_owned(),
-                                ))
-                            }
+                        let r = match &r {
+                            Some(rows) => Operand::Column(rows),
+                            None => right.as_operand(),
                         };
-                        ColumnData::Bool(gt)
+                        kernels::logical(operator, l, r)?
                     }
+                    Binary::Match | Binary::IMatch | Binary::NotMatch | Binary::NotIMatch => self
+                        .patterns
+                        .matches(operator, left.as_operand(), right.as_operand())?,
+                };
 
-                    crate::parser::expression::Binary::LtEq => {
-                        let right_data = if let Expression::Literal(right_lit) = *right_expr {
-                            ColumnData::fill_with_literal(right_lit, left.data.len())?
-                        } else {
-                            right.data.clone()
-                        };
-
-                        let lteq = match (&left.data, &right_data) {
-                            (ColumnData::Int(left), ColumnData::Int(right)) => left
-                                .iter()
-                                .zip(right)
-                                .filter(|((lk, _), (rk, _))| lk == rk)
-                                .map(|((lk, lv), (_, rv))| (*lk, lv <= rv))
-                                .collect(),
-                            (ColumnData::Float(left), ColumnData::Float(right)) => left
-                                .iter()
-                                .zip(right)
-                                .filter(|((lk, _), (rk, _))| lk == rk)
-                                .map(|((lk, lv), (_, rv))| (*lk, lv <= rv))
-                                .collect(),
-                            (ColumnData::Double(left), ColumnData::Double(right)) => left
-                                .iter()
-                                .zip(right)
-                                .filter(|((lk, _), (rk, _))| lk == rk)
-                                .map(|((lk, lv), (_, rv))| (*lk, lv <= rv))
-                                .collect(),
-                            (ColumnData::Bool(left), ColumnData::Bool(right)) => left
-                                .iter()
-                                .zip(right)
-                                .filter(|((lk, _), (rk, _))| lk == rk)
-                                .map(|((lk, lv), (_, rv))| (*lk, lv <= rv))
-                                .collect(),
-                            _ => {
-                                return Err(Error::InvalidQuery(
-                                    "binary op less than eq on invalid type".to_owned(),
-                                ))
-                            }
-                        };
-                        ColumnData::Bool(lteq)
+                Ok(vec![OutColumn {
+                    name: left.name().to_owned(),
+                    data,
+                    precision: None,
+                }])
+            }
+            Expression::Function { name, args, .. } => {
+                // registered functions come first, as they did before there
+                // were any others
+                let patterns = |args: &[Literal]| self.patterns.call(&name, args);
+                let sequences = |args: &[Literal]| self.sequences.call(&name, args);
+                let system = |args: &[Literal]| self.system.call(&name, args);
+                let function: &dyn Fn(&[Literal]) -> Result<Literal> =
+                    match self.functions.get(&name) {
+                        Some(function) => function.as_ref(),
+                        None if regexp::is_function(&name) => &patterns,
+                        None if sequence::is_function(&name) => &sequences,
+                        None if system::is_function(&name) => &system,
+                        None => return Err(Error::Unsupported(format!("function: {name}"))),
+                    };
+
+                // literal arguments hold a single value that applies to every row
+                let mut columns = Vec::with_capacity(args.len());
+                for arg in args {
+                    let constant = matches!(arg, Expression::Literal(_));
+                    let mut out = self.eval(table, arg)?;
+                    if out.len() > 1 {
+                        return Err(Error::InvalidQuery(format!(
+                            "argument to {name} with more than one column"
+                        )));
                     }
+                    columns.push((constant, out.pop()));
+                }
 
-                    crate::parser::expression::Binary::GtEq => {
-                        let right_data = if let Expression::Literal(right_lit) = *right_expr {
-                            ColumnData::fill_with_literal(right_lit, left.data.len())?
-                        } else {
-                            right.data.clone()
-                        };
+                let rows = match table {
+                    Some(table) if columns.iter().any(|(constant, _)| !constant) => table.row_ids(),
+                    _ => [0].into_iter().collect(),
+                };
 
-                        let gteq = match (&left.data, &right_data) {
-                            (ColumnData::Int(left), ColumnData::Int(right)) => left
-                                .iter()
-                                .zip(right)
-                                .filter(|((lk, _), (rk, _))| lk == rk)
-                                .map(|((lk, lv), (_, rv))| (*lk, lv <= rv))
-                                .collect(),
-                            (ColumnData::Float(left), ColumnData::Float(right)) => left
-                                .iter()
-                                .zip(right)
-                                .filter(|((lk, _), (rk, _))| lk == rk)
-                                .map(|((lk, lv), (_, rv))| (*lk, lv <= rv))
-                                .collect(),
-                            (ColumnData::Double(left), ColumnData::Double(right)) => left
-                                .iter()
-                                .zip(right)
-                                .filter(|((lk, _), (rk, _))| lk == rk)
-                                .map(|((lk, lv), (_, rv))| (*lk, lv <= rv))
-                                .collect(),
-                            (ColumnData::Bool(left), ColumnData::Bool(right)) => left
-                                .iter()
-                                .zip(right)
-                                .filter(|((lk, _), (rk, _))| lk == rk)
-                                .map(|((lk, lv), (_, rv))| (*lk, lv <= rv))
-                                .collect(),
-                            _ => {
-                                return Err(Error::InvalidQuery(
-                                    "binary op greater than eq on invalid type".to_owned(),
-                                ))
-                            }
-                        };
-                        ColumnData::Bool(gteq)
-                    }
+                let mut values = Vec::with_capacity(rows.len());
+                for id in rows.iter() {
+                    let args: Vec<Literal> = columns
+                        .iter()
+                        .map(|(constant, col)| {
+                            col.as_ref()
+                                .and_then(|c| c.data.get_literal(if *constant { 0 } else { id }))
+                                .unwrap_or(Literal::Null)
+                        })
+                        .collect();
+                    values.push((id, function(&args)?));
+                }
 
-                    crate::parser::expression::Binary::NotEq => {
-                        let right_data = if let Expression::Literal(right_lit) = *right_expr {
-                            ColumnData::fill_with_literal(right_lit, left.data.len())?
-                        } else {
-                            right.data.clone()
-                        };
+                Ok(ColumnData::from_literals(values)?
+                    .map(|data| OutColumn {
+                        name,
+                        data,
+                        precision: None,
+                    })
+                    .into_iter()
+                    .collect())
+            }
+            Expression::InList {
+                expr,
+                list,
+                negated,
+            } => {
+                // a list of other than values, like columns, is evaluated as
+                // the comparisons it stands for
+                let Some(values) = optimizer::literals(&list) else {
+                    return self.eval(table, expression::any_equal(*expr, list, negated));
+                };
+                let value = self.operand(table, *expr)?;
+                let data = kernels::in_list(value.as_operand(), &values, negated, self.strictness)?;
 
-                        let neq = match (&left.data, &right_data) {
-                            (ColumnData::Int(left), ColumnData::Int(right)) => left
-                                .iter()
-                                .zip(right)
-                                .filter(|((lk, _), (rk, _))| lk == rk)
-                                .map(|((lk, lv), (_, rv))| (*lk, lv != rv))
-                                .collect(),
-                            (ColumnData::Float(left), ColumnData::Float(right)) => left
-                                .iter()
-                                .zip(right)
-                                .filter(|((lk, _), (rk, _))| lk == rk)
-                                .map(|((lk, lv), (_, rv))| (*lk, lv != rv))
-                                .collect(),
-                            (ColumnData::Double(left), ColumnData::Double(right)) => left
-                                .iter()
-                                .zip(right)
-                                .filter(|((lk, _), (rk, _))| lk == rk)
-                                .map(|((lk, lv), (_, rv))| (*lk, lv != rv))
-                                .collect(),
-                            (ColumnData::Bool(left), ColumnData::Bool(right)) => left
-                                .iter()
-                                .zip(right)
-                                .filter(|((lk, _), (rk, _))| lk == rk)
-                                .map(|((lk, lv), (_, rv))| (*lk, lv != rv))
-                                .collect(),
-                            (ColumnData::Str(left), ColumnData::Str(right)) => left
-                                .iter()
-                                .zip(right)
-                                .filter(|((lk, _), (rk, _))| lk == rk)
-                                .map(|((lk, lv), (_, rv))| (*lk, lv != rv))
-                                .collect(),
-                            _ => {
+                Ok(vec![OutColumn {
+                    name: value.name().to_owned(),
+                    data,
+                    precision: None,
+                }])
+            }
+            Expression::MatchAgainst { column, terms } => {
+                // the index is only there for the table itself, not for what
+                // a join makes of it
+                let indexed = match (&*column, table) {
+                    (Expression::Ident(Ident::Named(name)), Some(table)) => {
+                        table.fulltext_index(name)
+                    }
+                    _ => None,
+                };
+                let scores = match indexed {
+                    Some(index) => index.score(&terms),
+                    None => {
+                        let out = self.eval(table, *column)?;
+                        let texts = match out.first().map(|c| &c.data) {
+                            Some(ColumnData::Str(texts)) => texts.iter(),
+                            None => return Ok(vec![]),
+                            Some(_) => {
                                 return Err(Error::InvalidQuery(
-                                    "binary op not equal on invalid type".to_owned(),
+                                    "MATCH of anything other than text".to_owned(),
                                 ))
                             }
                         };
-                        ColumnData::Bool(neq)
+                        FullTextIndex::of(texts.map(|(&id, text)| (id, &**text))).score(&terms)
                     }
                 };
 
                 Ok(vec![OutColumn {
-                    name: left.name.clone(),
-                    data: out,
+                    name: "match".to_owned(),
+                    data: ColumnData::Double(scores),
+                    precision: None,
                 }])
             }
+            Expression::Case {
+                branches,
+                otherwise,
+            } => self.case(table, branches, otherwise.map(|e| *e)),
+            Expression::Subquery(subquery) => Err(Error::Unsupported(format!(
+                "subquery {}, other than as an item of the select list",
+                Expression::Subquery(subquery)
+            ))),
+            Expression::InSubquery { .. } => Err(Error::Unsupported(format!(
+                "{expr}, other than in the select list or WHERE"
+            ))),
+            Expression::Tuple(_) => Err(Error::Unsupported(format!(
+                "row value {expr}, other than compared with another"
+            ))),
             Expression::None => Err(Error::InvalidOperation("none operation".to_owned())),
             _ => Err(Error::Unsupported("unsupported query".to_owned())),
         }
     }
+
+    // each row takes the result of the first branch whose condition is true
+    // for it, or of the `ELSE`, or is null without one. A branch only sees
+    // the rows left to it, so that `WHEN x = 0 THEN 0 ELSE 1 / x` doesn't
+    // divide by zero
+    fn case(
+        &self,
+        table: Option<&Table>,
+        branches: Vec<(Expression, Expression)>,
+        otherwise: Option<Expression>,
+    ) -> Result<Vec<OutColumn>> {
+        let all = match table {
+            Some(table) => table.row_ids(),
+            None => [0].into_iter().collect(),
+        };
+        let mut left = all.clone();
+        let mut values = Vec::with_capacity(all.len());
+
+        let otherwise = otherwise.map(|e| (Expression::Literal(Literal::Bool(true)), e));
+        for (condition, result) in branches.into_iter().chain(otherwise) {
+            if left.is_empty() {
+                break;
+            }
+            let restricted;
+            let scope = match table {
+                Some(table) if left.len() < all.len() => {
+                    restricted = table.restricted(&left);
+                    Some(&restricted)
+                }
+                table => table,
+            };
+
+            let mut taken = RowSet::new();
+            for (id, value) in self.row_values(scope, condition, &left)? {
+                match value {
+                    Literal::Bool(true) => _ = taken.insert(id),
+                    Literal::Bool(false) | Literal::Null => {}
+                    value => {
+                        return Err(Error::InvalidQuery(format!(
+                            "CASE WHEN giving {value}, rather than true or false"
+                        )))
+                    }
+                }
+            }
+            if taken.is_empty() {
+                continue;
+            }
+            left.difference_with(&taken);
+
+            let restricted;
+            let scope = match table {
+                Some(table) if taken.len() < all.len() => {
+                    restricted = table.restricted(&taken);
+                    Some(&restricted)
+                }
+                table => table,
+            };
+            values.extend(self.row_values(scope, result, &taken)?);
+        }
+
+        // the results are all of one type, other than numbers, which are
+        // widened to the widest of them
+        let datatype = kernels::common_type(values.iter().map(|(_, v)| v)).map_err(|(a, b)| {
+            Error::InvalidQuery(format!("CASE with results of both {a:?} and {b:?}"))
+        })?;
+        let Some(datatype) = datatype else {
+            return Ok(vec![]);
+        };
+        let values = values.into_iter().map(|(id, value)| {
+            let value = value.cast(datatype, Strictness::Lenient);
+            (id, value.unwrap_or(Literal::Null))
+        });
+
+        Ok(ColumnData::from_literals(values)?
+            .map(|data| OutColumn {
+                name: "case".to_owned(),
+                data,
+                precision: None,
+            })
+            .into_iter()
+            .collect())
+    }
+
+    // the value of `expr` in each of `rows`, or in every one of them when it
+    // doesn't refer to any column, like a literal
+    fn row_values(
+        &self,
+        table: Option<&Table>,
+        expr: Expression,
+        rows: &RowSet,
+    ) -> Result<Vec<(RowId, Literal)>> {
+        let constant = expr.idents().is_empty();
+        let mut out = self.eval(table, expr)?;
+        if out.len() > 1 {
+            return Err(Error::InvalidQuery(
+                "CASE with more than one column".to_owned(),
+            ));
+        }
+        let column = out.pop();
+
+        Ok(rows
+            .iter()
+            .map(|id| {
+                let value = column
+                    .as_ref()
+                    .and_then(|c| c.data.get_literal(if constant { 0 } else { id }));
+                (id, value.unwrap_or(Literal::Null))
+            })
+            .collect())
+    }
+
+    fn operand<'t>(&self, table: Option<&'t Table>, expr: Expression) -> Result<Evaluated<'t>> {
+        match (expr, table) {
+            (Expression::Literal(l), _) => Ok(Evaluated::Literal(l)),
+            (Expression::Ident(Ident::Named(id)), Some(table)) => table
+                .col_from_name(&id)
+                .map(Evaluated::Column)
+                .ok_or_else(|| table.column_not_found(&id)),
+            (expr, table) => {
+                let mut out = self.eval(table, expr)?;
+                if out.len() != 1 {
+                    return Err(Error::InvalidQuery(
+                        "binary operator with more than one column".to_owned(),
+                    ));
+                }
+                Ok(Evaluated::Computed(out.remove(0)))
+            }
+        }
+    }
+}
+
+fn negate() -> Error {
+    Error::EvaluationError("integer overflow in `-`".to_owned())
 }
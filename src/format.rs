@@ -0,0 +1,109 @@
+// how values are turned into text for views, json output and change events,
+// so that the same value reads the same wherever it's shown
+
+// outside of this range, the shortest form switches to scientific notation
+// rather than spelling out every zero
+const SMALLEST_PLAIN: f64 = 1e-6;
+const LARGEST_PLAIN: f64 = 1e16;
+
+/// `value` with `precision` digits after the decimal point, or otherwise with
+/// the fewest digits that still read back as `value`.
+pub fn double(value: f64, precision: Option<usize>) -> String {
+    if let Some(special) = special(value) {
+        return special;
+    }
+
+    match precision {
+        Some(precision) => fixed(&format!("{:e}", value.abs()), value < 0.0, precision),
+        None if is_plain(value) => format!("{}", value + 0.0),
+        None => format!("{value:e}"),
+    }
+}
+
+/// Like `double`, with the fewest digits that read back as the same `f32`.
+pub fn float(value: f32, precision: Option<usize>) -> String {
+    if let Some(special) = special(value as f64) {
+        return special;
+    }
+
+    match precision {
+        Some(precision) => fixed(&format!("{:e}", value.abs()), value < 0.0, precision),
+        None if is_plain(value as f64) => format!("{}", value + 0.0),
+        None => format!("{value:e}"),
+    }
+}
+
+// the same text whatever platform or formatting rules produced the value
+fn special(value: f64) -> Option<String> {
+    if value.is_nan() {
+        Some("NaN".to_owned())
+    } else if value.is_infinite() {
+        Some(if value > 0.0 { "Infinity" } else { "-Infinity" }.to_owned())
+    } else {
+        None
+    }
+}
+
+fn is_plain(value: f64) -> bool {
+    value == 0.0 || (SMALLEST_PLAIN..LARGEST_PLAIN).contains(&value.abs())
+}
+
+// rounds the shortest form (like `1.005e0`) rather than the binary value, so
+// that 1.005 shows as 1.01 to two places, as it was written
+fn fixed(scientific: &str, negative: bool, precision: usize) -> String {
+    let (mantissa, exponent) = scientific.split_once('e').unwrap_or((scientific, "0"));
+    let mut digits: Vec<u8> = mantissa
+        .bytes()
+        .filter(u8::is_ascii_digit)
+        .map(|b| b - b'0')
+        .collect();
+    // how many of the digits come before the decimal point
+    let mut point = exponent.parse::<i64>().unwrap_or(0) + 1;
+
+    let keep = point + precision as i64;
+    if keep < 0 {
+        digits.clear();
+    } else if (keep as usize) < digits.len() {
+        let round_up = digits[keep as usize] >= 5;
+        digits.truncate(keep as usize);
+        if round_up {
+            carry(&mut digits, &mut point);
+        }
+    }
+
+    // the digit `places` to the left of the decimal point, or to its right
+    // when negative
+    let digit = |place: i64| {
+        usize::try_from(point - 1 - place)
+            .ok()
+            .and_then(|k| digits.get(k))
+            .map_or('0', |d| (b'0' + d) as char)
+    };
+
+    let mut text = String::new();
+    // values that round to zero lose their sign, as -0 itself does
+    if negative && digits.iter().any(|d| *d != 0) {
+        text.push('-');
+    }
+    text.extend((0..point.max(1)).rev().map(digit));
+    if precision > 0 {
+        text.push('.');
+        text.extend((1..=precision as i64).map(|place| digit(-place)));
+    }
+
+    text
+}
+
+fn carry(digits: &mut Vec<u8>, point: &mut i64) {
+    for d in digits.iter_mut().rev() {
+        if *d == 9 {
+            *d = 0;
+        } else {
+            *d += 1;
+            return;
+        }
+    }
+
+    digits.insert(0, 1);
+    *point += 1;
+}
@@ -0,0 +1,157 @@
+use std::collections::{BTreeMap, HashMap};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    chunked::ChunkedMap,
+    parser::expression::{Binary, Expression, Literal, Unary},
+    rowset::RowSet,
+    table::RowId,
+};
+
+/// The words of `text` as they're indexed and searched for: runs of letters
+/// and digits, lowercased.
+pub fn terms(text: &str) -> impl Iterator<Item = String> + '_ {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(str::to_lowercase)
+}
+
+/// An inverted index of a text column, created with `CREATE FULLTEXT INDEX`,
+/// from the words in it to the rows they're in, for `MATCH (<column>)
+/// AGAINST ('<words>')` to rank rows by without going through all the text.
+/// Only its name and column are persisted, the rest is rebuilt from the data
+/// on restore, see `Table::rebuild_fulltext`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FullTextIndex {
+    pub name: String,
+    pub column: String,
+    // how many times each word is in each row
+    #[serde(skip)]
+    postings: HashMap<String, BTreeMap<RowId, u32>>,
+    // how many words each row has, for every row with text
+    #[serde(skip)]
+    lengths: BTreeMap<RowId, u32>,
+}
+
+impl FullTextIndex {
+    pub fn new(name: String, column: String) -> Self {
+        Self {
+            name,
+            column,
+            postings: HashMap::new(),
+            lengths: BTreeMap::new(),
+        }
+    }
+
+    /// An index of `texts` that isn't kept anywhere, for matching a column
+    /// that has none.
+    pub fn of<'a>(texts: impl Iterator<Item = (RowId, &'a str)>) -> Self {
+        let mut index = Self::new(String::new(), String::new());
+        for (row_id, text) in texts {
+            index.add(row_id, text);
+        }
+        index
+    }
+
+    pub fn add(&mut self, row_id: RowId, text: &str) {
+        let mut length = 0;
+        for term in terms(text) {
+            *self
+                .postings
+                .entry(term)
+                .or_default()
+                .entry(row_id)
+                .or_default() += 1;
+            length += 1;
+        }
+        self.lengths.insert(row_id, length);
+    }
+
+    /// Takes out the row `row_id`, which has to still have the `text` it was
+    /// added with.
+    pub fn remove(&mut self, row_id: RowId, text: &str) {
+        if self.lengths.remove(&row_id).is_none() {
+            return;
+        }
+        for term in terms(text) {
+            if let Some(rows) = self.postings.get_mut(&term) {
+                rows.remove(&row_id);
+                if rows.is_empty() {
+                    self.postings.remove(&term);
+                }
+            }
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.postings.clear();
+        self.lengths.clear();
+    }
+
+    /// Leaves out every row that isn't in `rows`.
+    pub fn retain(&mut self, rows: &RowSet) {
+        self.lengths.retain(|id, _| rows.contains(*id));
+        self.postings.retain(|_, ids| {
+            ids.retain(|id, _| rows.contains(*id));
+            !ids.is_empty()
+        });
+    }
+
+    /// How relevant each row is to the words of `query`: for each of them,
+    /// how often it's in the row relative to the length of the row, weighed
+    /// by how rare it is among the rows, added up. Rows with none of the
+    /// words score 0.
+    pub fn score(&self, query: &str) -> ChunkedMap<f64> {
+        let mut words: Vec<String> = terms(query).collect();
+        words.sort();
+        words.dedup();
+
+        let total = self.lengths.len() as f64;
+        let mut scores: BTreeMap<RowId, f64> = self.lengths.keys().map(|&id| (id, 0.0)).collect();
+        for word in words {
+            let Some(rows) = self.postings.get(&word) else {
+                continue;
+            };
+            let rarity = (1.0 + total / rows.len() as f64).ln();
+            for (id, &count) in rows {
+                let length = self.lengths.get(id).copied().unwrap_or(1).max(1);
+                if let Some(score) = scores.get_mut(id) {
+                    *score += f64::from(count) / f64::from(length) * rarity;
+                }
+            }
+        }
+
+        scores.into_iter().collect()
+    }
+}
+
+/// `predicate` with each `MATCH ... AGAINST` on its own, rather than compared
+/// with something, standing for whether the row is relevant at all, as in
+/// `WHERE MATCH (body) AGAINST ('disk full')`.
+pub fn as_predicate(predicate: Expression) -> Expression {
+    match predicate {
+        Expression::MatchAgainst { .. } => Expression::Binary {
+            operator: Binary::Gt,
+            left: Box::new(predicate),
+            right: Box::new(Expression::Literal(Literal::Double(0.0))),
+        },
+        Expression::Binary {
+            operator: operator @ (Binary::And | Binary::Or),
+            left,
+            right,
+        } => Expression::Binary {
+            operator,
+            left: Box::new(as_predicate(*left)),
+            right: Box::new(as_predicate(*right)),
+        },
+        Expression::Unary {
+            operator: Unary::Not,
+            expression,
+        } => Expression::Unary {
+            operator: Unary::Not,
+            expression: Box::new(as_predicate(*expression)),
+        },
+        predicate => predicate,
+    }
+}
@@ -0,0 +1,91 @@
+use std::{collections::HashMap, fmt::Debug, ops::ControlFlow, sync::Arc, time::Duration};
+
+use sqlparser::ast::{visit_expressions_mut, Expr, Value, VisitMut};
+
+use crate::{
+    parser::{expression::Literal, parser::SqlDialect},
+    Error, Result,
+};
+
+/// A scalar function callable from sql. It is called once per row with the
+/// values of its arguments, which are `Literal::Null` where a row has none.
+pub type ScalarFunction = Arc<dyn Fn(&[Literal]) -> Result<Literal> + Send + Sync>;
+
+/// Scalar functions registered by the embedder, by lowercase name.
+#[derive(Default, Clone)]
+pub struct Functions(HashMap<String, ScalarFunction>);
+
+impl Functions {
+    pub fn register(
+        &mut self,
+        name: &str,
+        function: impl Fn(&[Literal]) -> Result<Literal> + Send + Sync + 'static,
+    ) {
+        self.0.insert(name.to_lowercase(), Arc::new(function));
+    }
+
+    pub fn get(&self, name: &str) -> Option<&ScalarFunction> {
+        self.0.get(&name.to_lowercase())
+    }
+}
+
+impl Debug for Functions {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_set().entries(self.0.keys()).finish()
+    }
+}
+
+/// Whom and where the statements being executed come from, as returned by
+/// `current_user()` (or `session_user`), `connection_id()` and `client_addr()`.
+/// Each is null where there's no such thing, like a user for the repl. These
+/// are bound before registered functions are looked up, so they can't be
+/// replaced by them. It also holds what `SET` changed for the statements after
+/// it in the same session, over the settings of the database.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Session {
+    pub user: Option<String>,
+    pub connection_id: Option<u64>,
+    pub client_addr: Option<String>,
+    /// `SET statement_timeout`, which can only make the statement timeout of
+    /// the database shorter
+    pub statement_timeout: Option<Duration>,
+    /// `SET dialect`, in place of the dialect of the database
+    pub dialect: Option<SqlDialect>,
+    /// `SET display_precision`, in place of the display precision of the
+    /// database
+    pub display_precision: Option<usize>,
+}
+
+impl Session {
+    /// Replaces the calls to session functions in `node` with their values,
+    /// as in `WHERE owner = current_user()`.
+    pub fn bind(&self, node: &mut impl VisitMut) -> Result<()> {
+        let res = visit_expressions_mut(node, |e| {
+            let Expr::Function(f) = e else {
+                return ControlFlow::Continue(());
+            };
+            let name = f.name.to_string().to_lowercase();
+            let value = match name.as_str() {
+                "current_user" | "session_user" => self.user.clone().map(Value::SingleQuotedString),
+                "connection_id" => self
+                    .connection_id
+                    .map(|id| Value::Number(id.to_string(), false)),
+                "client_addr" => self.client_addr.clone().map(Value::SingleQuotedString),
+                _ => return ControlFlow::Continue(()),
+            };
+            if !f.args.is_empty() {
+                return ControlFlow::Break(Error::InvalidQuery(format!(
+                    "{name} takes no arguments"
+                )));
+            }
+
+            *e = Expr::Value(value.unwrap_or(Value::Null));
+            ControlFlow::Continue(())
+        });
+
+        match res {
+            ControlFlow::Break(e) => Err(e),
+            ControlFlow::Continue(()) => Ok(()),
+        }
+    }
+}
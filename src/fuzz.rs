@@ -0,0 +1,107 @@
+use std::time::Duration;
+
+use crate::{database::Database, parser::parser::Limits};
+
+// the tables every input starts out with, so that selects reach the
+// evaluator and table code without having to make up a schema first
+const SCHEMA: &str = "
+CREATE TABLE t (id INT PRIMARY KEY, name VARCHAR(16), score DOUBLE, active BOOL);
+INSERT INTO t VALUES (1, 'a', 1.5, true), (2, 'b', 2.25, false), (3, 'c', 0.0, true);
+CREATE TABLE u (id INT PRIMARY KEY, t_id INT, note VARCHAR);
+INSERT INTO u VALUES (10, 1, 'x'), (11, 1, 'y'), (12, 3, 'z');
+";
+
+// of an input, past which it's skipped rather than parsed
+const MAX_INPUT: usize = 4096;
+
+// of each statement, so that inputs like a huge `generate_series` don't stall
+// the fuzzer
+const STATEMENT_TIMEOUT: Duration = Duration::from_millis(200);
+
+/// Executes `bytes`, read as sql with a statement on each line, against a
+/// fresh database holding a couple of small tables, for a fuzzer to drive
+/// parsing, planning and execution with. Errors are expected and ignored, as
+/// is input that isn't utf-8, but panics aren't caught, so that the fuzzer
+/// sees them. Lines are executed one at a time, so that one failing doesn't
+/// stop the ones after it.
+pub fn execute_arbitrary(bytes: &[u8]) {
+    let Ok(sql) = std::str::from_utf8(bytes) else {
+        return;
+    };
+    if sql.len() > MAX_INPUT {
+        return;
+    }
+
+    let mut db = database();
+    for line in sql.lines() {
+        _ = db.query(line);
+    }
+}
+
+// a fresh one holding the tables of `SCHEMA`, for an input to be executed on
+fn database() -> Database {
+    let mut db = Database::new();
+    db.set_limits(Limits {
+        max_size: MAX_INPUT,
+        max_depth: 32,
+    });
+    db.set_statement_timeout(Some(STATEMENT_TIMEOUT));
+    db.query(SCHEMA).expect("the fuzzing schema is valid");
+    db
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::*;
+
+    // put in place of each word of a seed, to reach the error paths around it
+    const REPLACEMENTS: &[&str] = &["NULL", "-1", "2147483648", "''", "*", "(", "t.id", "1e308"];
+
+    // the inputs in `fuzz/corpus/execute`, which the fuzzer starts out from
+    fn seeds() -> Vec<String> {
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("fuzz/corpus/execute");
+        let mut seeds: Vec<String> = std::fs::read_dir(dir)
+            .unwrap()
+            .map(|entry| std::fs::read_to_string(entry.unwrap().path()).unwrap())
+            .collect();
+        seeds.sort();
+        seeds
+    }
+
+    #[test]
+    fn seeds_execute_without_panics() {
+        for seed in seeds() {
+            execute_arbitrary(seed.as_bytes());
+
+            let mut db = database();
+            for line in seed.lines() {
+                _ = db.execute_all(line);
+            }
+        }
+    }
+
+    #[test]
+    fn mutated_seeds_execute_without_panics() {
+        for seed in seeds() {
+            let mut db = database();
+            for line in seed.lines() {
+                // cut short anywhere
+                for (i, _) in line.char_indices() {
+                    _ = db.execute_all(&line[..i]);
+                }
+
+                // with one of its words replaced
+                let words: Vec<&str> = line.split_whitespace().collect();
+                for i in 0..words.len() {
+                    for replacement in REPLACEMENTS {
+                        let mut mutated = words.clone();
+                        mutated[i] = replacement;
+                        _ = db.execute_all(&mutated.join(" "));
+                    }
+                }
+            }
+        }
+    }
+}
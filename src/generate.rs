@@ -0,0 +1,152 @@
+use rand::{seq::SliceRandom, Rng};
+
+use crate::{
+    parser::expression::{Expression, Literal},
+    table::{DataType, PKType, Table},
+    virtual_table::VirtualTable,
+    Error, Result,
+};
+
+// rows a single `generate_series` may give
+const MAX_SERIES: usize = 10_000_000;
+
+// what made up strings are made of
+const WORDS: &[&str] = &[
+    "alpha", "bravo", "charlie", "delta", "echo", "foxtrot", "golf", "hotel", "india", "juliet",
+    "kilo", "lima", "mike", "november", "oscar", "papa", "quebec", "romeo", "sierra", "tango",
+];
+
+/// `generate_series(start, stop[, step])`, the table function of the ints from
+/// `start` to `stop`, both included, in a single `generate_series` column.
+#[derive(Debug, Clone, Copy)]
+pub struct GenerateSeries {
+    start: i32,
+    stop: i32,
+    step: i32,
+}
+
+impl GenerateSeries {
+    pub fn new(args: &[Literal]) -> Result<Self> {
+        let ints = args
+            .iter()
+            .map(|arg| match arg {
+                Literal::Int(i) => Ok(*i),
+                arg => Err(Error::InvalidQuery(format!(
+                    "generate_series takes ints, not {arg}"
+                ))),
+            })
+            .collect::<Result<Vec<i32>>>()?;
+        let series = match ints[..] {
+            [start, stop] => Self {
+                start,
+                stop,
+                step: 1,
+            },
+            [start, stop, step] => Self { start, stop, step },
+            _ => {
+                return Err(Error::InvalidQuery(
+                    "generate_series takes a start, a stop and optionally a step".to_owned(),
+                ))
+            }
+        };
+
+        if series.step == 0 {
+            return Err(Error::InvalidQuery(
+                "the step of generate_series can't be 0".to_owned(),
+            ));
+        }
+        if series.len() > MAX_SERIES {
+            return Err(Error::LimitExceeded(format!(
+                "generate_series of {} rows, where at most {MAX_SERIES} are allowed",
+                series.len()
+            )));
+        }
+
+        Ok(series)
+    }
+
+    fn len(&self) -> usize {
+        let len = (self.stop as i64 - self.start as i64) / self.step as i64 + 1;
+        len.max(0) as usize
+    }
+}
+
+impl VirtualTable for GenerateSeries {
+    fn schema(&self) -> Vec<(String, DataType)> {
+        vec![("generate_series".to_owned(), DataType::Int)]
+    }
+
+    fn scan(&self, _filters: &[Expression]) -> Result<Vec<Vec<Literal>>> {
+        Ok((0..self.len() as i64)
+            .map(|i| {
+                vec![Literal::Int(
+                    (self.start as i64 + i * self.step as i64) as i32,
+                )]
+            })
+            .collect())
+    }
+}
+
+/// `n` rows of made up values for the visible columns of `table`, in their
+/// order. Primary keys continue after the ones the table has, strings fit
+/// their column, and only nullable columns get the odd null.
+pub fn rows(table: &Table, n: usize) -> Result<Vec<Vec<Literal>>> {
+    let mut rng = rand::thread_rng();
+    let headers: Vec<_> = table
+        .columns
+        .iter()
+        .map(|c| &c.header)
+        .filter(|h| !h.hidden)
+        .collect();
+
+    let mut next_int_key = match table.pk_map.left_values().next_back() {
+        Some(PKType::Int(max)) => *max as i64 + 1,
+        _ => 1,
+    };
+    if next_int_key + n as i64 - 1 > i32::MAX as i64 {
+        return Err(Error::LimitExceeded(format!(
+            "{n} more rows, which would run out of int keys for table {}",
+            table.name
+        )));
+    }
+    let mut next_str_key = table.pk_map.len() + 1;
+
+    let mut rows = Vec::with_capacity(n);
+    for _ in 0..n {
+        let row = headers
+            .iter()
+            .map(|h| match (h.is_pk, h.datatype) {
+                (true, DataType::Int) => {
+                    next_int_key += 1;
+                    Literal::Int((next_int_key - 1) as i32)
+                }
+                (true, _) => loop {
+                    let key = next_str_key.to_string();
+                    next_str_key += 1;
+                    if !table.pk_map.contains_left(&PKType::Str(key.clone())) {
+                        break Literal::Str(key);
+                    }
+                },
+                _ if h.nullable && rng.gen_ratio(1, 10) => Literal::Null,
+                (_, DataType::Int) => Literal::Int(rng.gen_range(0..1000)),
+                (_, DataType::Float) => Literal::Float(rng.gen_range(0.0..1000.0)),
+                (_, DataType::Double) => {
+                    let scale = 10f64.powi(h.precision.unwrap_or(6) as i32);
+                    Literal::Double((rng.gen_range(0.0..1000.0) * scale).round() / scale)
+                }
+                (_, DataType::Bool) => Literal::Bool(rng.gen()),
+                (_, DataType::Str | DataType::Invalid) => {
+                    let word = WORDS.choose(&mut rng).expect("words aren't empty");
+                    let text = format!("{word} {}", rng.gen_range(0..1000));
+                    Literal::Str(match h.max_length {
+                        Some(max) => text.chars().take(max).collect(),
+                        None => text,
+                    })
+                }
+            })
+            .collect();
+        rows.push(row);
+    }
+
+    Ok(rows)
+}
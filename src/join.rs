@@ -0,0 +1,388 @@
+use std::collections::HashMap;
+
+use crate::{
+    evaluator::Evaluator,
+    parser::{
+        expression::{Binary, Expression, Ident, Literal},
+        select::{Join, JoinKind},
+    },
+    rowset::RowSet,
+    table::{find_column, Column, ColumnData, ColumnHeader, RowId, Table},
+    Error, Result,
+};
+
+/// One side of a join: the columns of a table under the names they are known
+/// by in the joined result, e.g. `t.a`.
+pub struct Relation<'a> {
+    name: String,
+    columns: Vec<(String, &'a Column)>,
+    rows: RowSet,
+}
+
+impl<'a> Relation<'a> {
+    /// A table as named in the query, qualifying its columns with `alias` or
+    /// else its own name.
+    pub fn base(table: &'a Table, alias: Option<&str>) -> Self {
+        let prefix = alias
+            .map(str::to_owned)
+            .unwrap_or_else(|| table.name.to_lowercase());
+
+        Self {
+            name: table.name.clone(),
+            columns: table
+                .columns
+                .iter()
+                .map(|c| (format!("{prefix}.{}", c.header.name), c))
+                .collect(),
+            rows: table.row_ids(),
+        }
+    }
+
+    /// The result of an earlier join, whose columns are qualified already.
+    pub fn joined(table: &'a Table) -> Self {
+        Self {
+            name: table.name.clone(),
+            columns: table
+                .columns
+                .iter()
+                .map(|c| (c.header.name.clone(), c))
+                .collect(),
+            rows: table.row_ids(),
+        }
+    }
+
+    /// Leaves out the rows that aren't in `rows`, e.g. those filtered out
+    /// below the join.
+    pub fn restrict(&mut self, rows: &RowSet) {
+        self.rows.intersect_with(rows);
+    }
+
+    /// Leaves out the columns that aren't called one of the lowercase
+    /// `names` without their table, other than hidden ones, which every row
+    /// of a table has a value in.
+    pub fn prune(&mut self, names: &[String]) {
+        self.columns.retain(|(name, c)| {
+            c.header.hidden || names.contains(&unqualified(name).to_lowercase())
+        });
+    }
+
+    fn column(&self, name: &str) -> Option<&'a Column> {
+        find_column(self.columns.iter().map(|(n, c)| (n.as_str(), *c)), name)
+    }
+
+    // the names of the columns `SELECT *` gives, without their table
+    fn visible_names(&self) -> impl Iterator<Item = &str> {
+        self.columns
+            .iter()
+            .filter(|(_, c)| !c.header.hidden)
+            .map(|(n, _)| unqualified(n))
+    }
+
+    // the visible column called `name` without its table, to join on
+    fn position(&self, name: &str) -> Result<usize> {
+        let mut found = self
+            .columns
+            .iter()
+            .enumerate()
+            .filter(|(_, (n, c))| !c.header.hidden && unqualified(n).eq_ignore_ascii_case(name))
+            .map(|(i, _)| i);
+
+        match (found.next(), found.next()) {
+            (Some(i), None) => Ok(i),
+            (None, _) => Err(Error::column_not_found(
+                name,
+                &self.name,
+                self.visible_names(),
+            )),
+            (Some(_), Some(_)) => Err(Error::InvalidOperation(format!(
+                "joining on column {name}, which is ambiguous in {}",
+                self.name
+            ))),
+        }
+    }
+}
+
+// a value that equal values hash the same for, within a single type
+#[derive(PartialEq, Eq, Hash)]
+pub(crate) enum HashKey {
+    Int(i32),
+    Str(String),
+    Bool(bool),
+    Float(u32),
+    Double(u64),
+}
+
+impl HashKey {
+    pub(crate) fn new(lit: Literal) -> Option<Self> {
+        match lit {
+            Literal::Int(i) => Some(HashKey::Int(i)),
+            Literal::Str(s) => Some(HashKey::Str(s)),
+            Literal::Bool(b) => Some(HashKey::Bool(b)),
+            // nan equals nothing, and -0.0 has to meet 0.0
+            Literal::Float(f) if f.is_nan() => None,
+            Literal::Float(f) => Some(HashKey::Float((f + 0.0).to_bits())),
+            Literal::Double(d) if d.is_nan() => None,
+            Literal::Double(d) => Some(HashKey::Double((d + 0.0).to_bits())),
+            Literal::Null => None,
+        }
+    }
+}
+
+type Pairs = Vec<(Option<RowId>, Option<RowId>)>;
+
+// a column joined on by name, with `USING` or `NATURAL`: its name in the
+// result and its position on either side
+type UsingColumn = (String, usize, usize);
+
+/// Joins `left` with `right`. Equality between a column of each side is done
+/// with a hash join, anything else by evaluating the condition on every pair
+/// of rows. Columns joined on by name are only in the result once.
+pub fn join(left: Relation, right: Relation, join: &Join, evaluator: &Evaluator) -> Result<Table> {
+    let using = using_columns(&left, &right, join)?;
+    let on = match join.kind {
+        JoinKind::Cross => None,
+        JoinKind::Inner | JoinKind::Left => join.on.as_ref(),
+    };
+
+    let mut pairs = match (join.kind, on) {
+        (JoinKind::Cross, _) => cross(&left, &right),
+        _ if !using.is_empty() => {
+            let l: Vec<&Column> = using.iter().map(|u| left.columns[u.1].1).collect();
+            let r: Vec<&Column> = using.iter().map(|u| right.columns[u.2].1).collect();
+            hash_join(&l, &left.rows, &r, &right.rows)
+        }
+        (_, None) => cross(&left, &right),
+        (_, Some(on)) => match equi_columns(&left, &right, on) {
+            Some((l, r)) if l.header.datatype == r.header.datatype => {
+                hash_join(&[l], &left.rows, &[r], &right.rows)
+            }
+            _ => nested_loop(&left, &right, on, evaluator)?,
+        },
+    };
+
+    evaluator.deadline().check()?;
+
+    if join.kind == JoinKind::Left {
+        let matched: RowSet = pairs.iter().filter_map(|(l, _)| *l).collect();
+        let mut unmatched = left.rows.clone();
+        unmatched.difference_with(&matched);
+        pairs.extend(unmatched.iter().map(|l| (Some(l), None)));
+    }
+
+    pairs.sort_unstable();
+    materialize(&left, &right, &pairs, &using)
+}
+
+// the columns of `USING (...)`, or those both sides have for `NATURAL`
+fn using_columns(left: &Relation, right: &Relation, join: &Join) -> Result<Vec<UsingColumn>> {
+    let names: Vec<&str> = match join.natural {
+        true => {
+            let mut names: Vec<&str> = Vec::new();
+            for name in left.visible_names() {
+                if right.visible_names().any(|n| n.eq_ignore_ascii_case(name))
+                    && !names.iter().any(|n| n.eq_ignore_ascii_case(name))
+                {
+                    names.push(name);
+                }
+            }
+            names
+        }
+        false => join.using.iter().map(String::as_str).collect(),
+    };
+
+    names
+        .into_iter()
+        .map(|name| {
+            let (l, r) = (left.position(name)?, right.position(name)?);
+            let (l_type, r_type) = (
+                left.columns[l].1.header.datatype,
+                right.columns[r].1.header.datatype,
+            );
+            if l_type != r_type {
+                return Err(Error::InvalidOperation(format!(
+                    "joining on column {name}, which is {l_type:?} in {} but {r_type:?} in {}",
+                    left.name, right.name
+                )));
+            }
+            Ok((unqualified(&left.columns[l].0).to_owned(), l, r))
+        })
+        .collect()
+}
+
+// `t.a` without its table
+fn unqualified(name: &str) -> &str {
+    name.rsplit_once('.').map_or(name, |(_, column)| column)
+}
+
+// `l = r` with `l` a column of `left` and `r` one of `right`, either way round
+fn equi_columns<'a>(
+    left: &Relation<'a>,
+    right: &Relation<'a>,
+    on: &Expression,
+) -> Option<(&'a Column, &'a Column)> {
+    let Expression::Binary {
+        operator: Binary::Eq,
+        left: a,
+        right: b,
+    } = on
+    else {
+        return None;
+    };
+
+    let (Expression::Ident(Ident::Named(a)), Expression::Ident(Ident::Named(b))) =
+        (a.as_ref(), b.as_ref())
+    else {
+        return None;
+    };
+
+    match (left.column(a), right.column(b)) {
+        (Some(l), Some(r)) => Some((l, r)),
+        _ => Some((left.column(b)?, right.column(a)?)),
+    }
+}
+
+fn cross(left: &Relation, right: &Relation) -> Pairs {
+    left.rows
+        .iter()
+        .flat_map(|l| right.rows.iter().map(move |r| (Some(l), Some(r))))
+        .collect()
+}
+
+// pairs up the rows of `left` and `right` whose values are equal, for a
+// column or more of each side in the same order
+fn hash_join(
+    left: &[&Column],
+    left_rows: &RowSet,
+    right: &[&Column],
+    right_rows: &RowSet,
+) -> Pairs {
+    // the table is built from whichever side has fewer rows, and the other
+    // one is probed against it
+    let swap = left_rows.len() > right_rows.len();
+    let (build, build_rows, probe, probe_rows) = if swap {
+        (right, right_rows, left, left_rows)
+    } else {
+        (left, left_rows, right, right_rows)
+    };
+
+    let mut table: HashMap<Vec<HashKey>, Vec<RowId>> = HashMap::with_capacity(build_rows.len());
+    for id in build_rows.iter() {
+        if let Some(key) = hash_key(build, id) {
+            table.entry(key).or_default().push(id);
+        }
+    }
+
+    let mut pairs = Vec::new();
+    for id in probe_rows.iter() {
+        let Some(key) = hash_key(probe, id) else {
+            continue;
+        };
+
+        for &other in table.get(&key).into_iter().flatten() {
+            pairs.push(if swap {
+                (Some(id), Some(other))
+            } else {
+                (Some(other), Some(id))
+            });
+        }
+    }
+
+    pairs
+}
+
+// `None` when any of the values is null, since nulls match nothing
+fn hash_key(columns: &[&Column], id: RowId) -> Option<Vec<HashKey>> {
+    columns
+        .iter()
+        .map(|c| c.data.get_literal(id).and_then(HashKey::new))
+        .collect()
+}
+
+// evaluates `on` over the cross product of both sides, which needs memory for
+// every pair of rows
+fn nested_loop(
+    left: &Relation,
+    right: &Relation,
+    on: &Expression,
+    evaluator: &Evaluator,
+) -> Result<Pairs> {
+    let pairs = cross(left, right);
+    evaluator.deadline().check()?;
+    let product = materialize(left, right, &pairs, &[])?;
+
+    let mut out = evaluator.eval(Some(&product), on.clone())?;
+    let matched = match out.len() {
+        0 => RowSet::new(),
+        1 => out.remove(0).data.keys_where_true()?,
+        _ => {
+            return Err(Error::InvalidOperation(
+                "more than one column found in join condition".to_owned(),
+            ))
+        }
+    };
+
+    Ok(matched.iter().map(|id| pairs[id]).collect())
+}
+
+// columns joined on by name come first, once, and are hidden on either side
+// so that `t.a` can still be selected
+fn materialize(
+    left: &Relation,
+    right: &Relation,
+    pairs: &Pairs,
+    using: &[UsingColumn],
+) -> Result<Table> {
+    let column = |name: &str, col: &Column, hidden: bool| -> Result<Column> {
+        Ok(Column {
+            header: ColumnHeader {
+                name: name.to_owned(),
+                hidden: hidden || col.header.hidden,
+                is_pk: false,
+                nullable: true,
+                last_row_id: None,
+                ..col.header.clone()
+            },
+            data: ColumnData::empty(col.header.datatype)?,
+            dictionary: Default::default(),
+        })
+    };
+
+    let mut columns = Vec::new();
+    for (name, l, _) in using {
+        columns.push(column(name, left.columns[*l].1, false)?);
+    }
+    for (i, (name, col)) in left.columns.iter().enumerate() {
+        columns.push(column(name, col, using.iter().any(|u| u.1 == i))?);
+    }
+    for (i, (name, col)) in right.columns.iter().enumerate() {
+        columns.push(column(name, col, using.iter().any(|u| u.2 == i))?);
+    }
+
+    for (out_id, (l, r)) in pairs.iter().enumerate() {
+        let mut out = columns.iter_mut();
+        for ((_, l_col, r_col), out) in using.iter().zip(out.by_ref()) {
+            let value = l
+                .and_then(|id| left.columns[*l_col].1.data.get_literal(id))
+                .or_else(|| r.and_then(|id| right.columns[*r_col].1.data.get_literal(id)));
+            if let Some(value) = value {
+                out.insert(out_id, value)?;
+            }
+        }
+        for (relation, id) in [(left, l), (right, r)] {
+            for ((_, col), out) in relation.columns.iter().zip(out.by_ref()) {
+                if let Some(value) = id.and_then(|id| col.data.get_literal(id)) {
+                    out.insert(out_id, value)?;
+                }
+            }
+        }
+    }
+
+    Ok(Table {
+        name: format!("{} JOIN {}", left.name, right.name),
+        columns,
+        pk_map: Default::default(),
+        triggers: Vec::new(),
+        policies: Vec::new(),
+        fulltext: Vec::new(),
+    })
+}
@@ -0,0 +1,441 @@
+use std::{cmp::Ordering, collections::HashSet, sync::Arc};
+
+use crate::{
+    chunked::ChunkedMap,
+    join::HashKey,
+    parser::expression::{Binary, Literal},
+    rowset::RowSet,
+    table::{ColumnData, DataType, RowId, Strictness},
+    Error, Result,
+};
+
+/// One side of a binary operator: either a column, borrowed from wherever it
+/// lives, or a single value that applies to every row.
+#[derive(Debug)]
+pub enum Operand<'a> {
+    Column(&'a ColumnData),
+    Scalar(Literal),
+}
+
+impl Operand<'_> {
+    fn datatype(&self) -> DataType {
+        match self {
+            Operand::Column(c) => DataType::from(*c),
+            Operand::Scalar(Literal::Int(_)) => DataType::Int,
+            Operand::Scalar(Literal::Str(_)) => DataType::Str,
+            Operand::Scalar(Literal::Float(_)) => DataType::Float,
+            Operand::Scalar(Literal::Double(_)) => DataType::Double,
+            Operand::Scalar(Literal::Bool(_)) => DataType::Bool,
+            Operand::Scalar(Literal::Null) => DataType::Invalid,
+        }
+    }
+}
+
+enum Side<'a, T> {
+    Column(&'a ChunkedMap<T>),
+    Scalar(T),
+}
+
+enum Typed<'a> {
+    Int(Side<'a, i32>),
+    Str(Side<'a, Arc<str>>),
+    Float(Side<'a, f32>),
+    Double(Side<'a, f64>),
+    Bool(Side<'a, bool>),
+    Null,
+}
+
+fn typed<'a>(op: &'a Operand) -> Typed<'a> {
+    match op {
+        Operand::Column(ColumnData::Int(c)) => Typed::Int(Side::Column(c)),
+        Operand::Column(ColumnData::Str(c)) => Typed::Str(Side::Column(c)),
+        Operand::Column(ColumnData::Float(c)) => Typed::Float(Side::Column(c)),
+        Operand::Column(ColumnData::Double(c)) => Typed::Double(Side::Column(c)),
+        Operand::Column(ColumnData::Bool(c)) => Typed::Bool(Side::Column(c)),
+        Operand::Scalar(Literal::Int(v)) => Typed::Int(Side::Scalar(*v)),
+        Operand::Scalar(Literal::Str(v)) => Typed::Str(Side::Scalar(v.as_str().into())),
+        Operand::Scalar(Literal::Float(v)) => Typed::Float(Side::Scalar(*v)),
+        Operand::Scalar(Literal::Double(v)) => Typed::Double(Side::Scalar(*v)),
+        Operand::Scalar(Literal::Bool(v)) => Typed::Bool(Side::Scalar(*v)),
+        Operand::Scalar(Literal::Null) => Typed::Null,
+    }
+}
+
+// literals take the type of the column they are applied to where possible
+fn widen<'a>(
+    left: Operand<'a>,
+    right: Operand<'a>,
+    strictness: Strictness,
+) -> (Operand<'a>, Operand<'a>) {
+    match (left, right) {
+        (Operand::Column(c), Operand::Scalar(l)) => (
+            Operand::Column(c),
+            Operand::Scalar(convert(l, DataType::from(c), strictness)),
+        ),
+        (Operand::Scalar(l), Operand::Column(c)) => (
+            Operand::Scalar(convert(l, DataType::from(c), strictness)),
+            Operand::Column(c),
+        ),
+        sides => sides,
+    }
+}
+
+fn convert(lit: Literal, datatype: DataType, strictness: Strictness) -> Literal {
+    // numbers are never rounded, even when lenient, as that would change what
+    // e.g. `id < 2.5` means
+    let is_number = matches!(
+        lit,
+        Literal::Int(_) | Literal::Float(_) | Literal::Double(_)
+    );
+    let strictness = if is_number && datatype.is_numeric() {
+        Strictness::Strict
+    } else {
+        strictness
+    };
+
+    lit.clone().cast(datatype, strictness).unwrap_or(lit)
+}
+
+/// The wider of two different numeric types, which both sides are promoted
+/// to, like the double of `price * quantity` with an int quantity.
+pub fn common_numeric(left: DataType, right: DataType) -> Option<DataType> {
+    let rank = |datatype| match datatype {
+        DataType::Int => Some(0),
+        DataType::Float => Some(1),
+        DataType::Double => Some(2),
+        _ => None,
+    };
+    let (l, r) = (rank(left)?, rank(right)?);
+    (l != r).then_some(if l > r { left } else { right })
+}
+
+/// The one type of all of `values` other than nulls, numbers being widened
+/// to the widest of them, or the first two types that don't go together.
+pub fn common_type<'a>(
+    values: impl IntoIterator<Item = &'a Literal>,
+) -> std::result::Result<Option<DataType>, (DataType, DataType)> {
+    let mut datatype: Option<DataType> = None;
+    for value in values {
+        let Some(other) = DataType::of(value) else {
+            continue;
+        };
+        datatype = match datatype {
+            Some(current) if current != other => {
+                Some(common_numeric(current, other).ok_or((current, other))?)
+            }
+            _ => Some(other),
+        };
+    }
+    Ok(datatype)
+}
+
+fn promote<'a>(
+    operand: Operand<'a>,
+    datatype: DataType,
+    storage: &'a mut Option<ColumnData>,
+) -> Operand<'a> {
+    let column = match operand {
+        Operand::Scalar(l) => {
+            return Operand::Scalar(convert(l, datatype, Strictness::Lenient));
+        }
+        Operand::Column(c) => c,
+    };
+    let promoted = match (column, datatype) {
+        (ColumnData::Int(c), DataType::Float) => {
+            ColumnData::Float(c.iter().map(|(k, v)| (*k, *v as f32)).collect())
+        }
+        (ColumnData::Int(c), DataType::Double) => {
+            ColumnData::Double(c.iter().map(|(k, v)| (*k, *v as f64)).collect())
+        }
+        (ColumnData::Float(c), DataType::Double) => {
+            ColumnData::Double(c.iter().map(|(k, v)| (*k, *v as f64)).collect())
+        }
+        _ => return Operand::Column(column),
+    };
+    Operand::Column(storage.insert(promoted))
+}
+
+// both sides as the same type when they are numbers of different ones, with
+// the columns that had to be converted kept in `storage`
+fn promote_numeric<'a>(
+    left: Operand<'a>,
+    right: Operand<'a>,
+    storage: &'a mut [Option<ColumnData>; 2],
+) -> (Operand<'a>, Operand<'a>) {
+    match common_numeric(left.datatype(), right.datatype()) {
+        Some(datatype) => {
+            let [l, r] = storage;
+            (promote(left, datatype, l), promote(right, datatype, r))
+        }
+        None => (left, right),
+    }
+}
+
+/// Applies `f` to every row present on both sides. Columns are joined on
+/// their RowIds, and a scalar pairs with every row of the other side. Two
+/// scalars give a single row, at RowId 0.
+fn apply<L, R, O: Clone>(
+    left: Side<L>,
+    right: Side<R>,
+    f: impl Fn(&L, &R) -> Result<O>,
+) -> Result<ChunkedMap<O>> {
+    match (left, right) {
+        (Side::Column(left), Side::Column(right)) => {
+            let mut out = Vec::with_capacity(left.len().min(right.len()));
+            let mut left = left.iter().peekable();
+            let mut right = right.iter().peekable();
+
+            while let (Some(&(lk, lv)), Some(&(rk, rv))) = (left.peek(), right.peek()) {
+                match lk.cmp(rk) {
+                    Ordering::Less => _ = left.next(),
+                    Ordering::Greater => _ = right.next(),
+                    Ordering::Equal => {
+                        out.push((*lk, f(lv, rv)?));
+                        left.next();
+                        right.next();
+                    }
+                }
+            }
+
+            Ok(out.into_iter().collect())
+        }
+        (Side::Column(left), Side::Scalar(right)) => {
+            left.iter().map(|(k, v)| Ok((*k, f(v, &right)?))).collect()
+        }
+        (Side::Scalar(left), Side::Column(right)) => {
+            right.iter().map(|(k, v)| Ok((*k, f(&left, v)?))).collect()
+        }
+        (Side::Scalar(left), Side::Scalar(right)) => {
+            Ok([(0, f(&left, &right)?)].into_iter().collect())
+        }
+    }
+}
+
+fn mismatch(op: Binary, left: &Operand, right: &Operand) -> Error {
+    Error::InvalidQuery(format!(
+        "cannot apply `{op}` to {:?} and {:?}",
+        left.datatype(),
+        right.datatype()
+    ))
+}
+
+fn overflow(op: Binary, rv: i32) -> Error {
+    if rv == 0 && matches!(op, Binary::Div | Binary::Rem) {
+        Error::EvaluationError("division by zero".to_owned())
+    } else {
+        Error::EvaluationError(format!("integer overflow in `{op}`"))
+    }
+}
+
+/// `+`, `-`, `*`, `/` and `%`. A null on either side gives an empty column.
+pub fn arithmetic(
+    op: Binary,
+    left: Operand,
+    right: Operand,
+    strictness: Strictness,
+) -> Result<ColumnData> {
+    let (left, right) = widen(left, right, strictness);
+    let mut storage = [None, None];
+    let (left, right) = promote_numeric(left, right, &mut storage);
+
+    macro_rules! float_op {
+        ($variant:ident, $l:expr, $r:expr) => {
+            ColumnData::$variant(apply($l, $r, |a, b| {
+                Ok(match op {
+                    Binary::Plus => a + b,
+                    Binary::Minus => a - b,
+                    Binary::Mul => a * b,
+                    Binary::Div => a / b,
+                    _ => a % b,
+                })
+            })?)
+        };
+    }
+
+    Ok(match (typed(&left), typed(&right)) {
+        (Typed::Null, _) | (_, Typed::Null) => {
+            let datatype = match left.datatype() {
+                DataType::Invalid => right.datatype(),
+                datatype => datatype,
+            };
+            match datatype {
+                DataType::Invalid => ColumnData::Int(Default::default()),
+                datatype => ColumnData::empty(datatype)?,
+            }
+        }
+        (Typed::Int(l), Typed::Int(r)) => ColumnData::Int(apply(l, r, |a, b| {
+            let out = match op {
+                Binary::Plus => a.checked_add(*b),
+                Binary::Minus => a.checked_sub(*b),
+                Binary::Mul => a.checked_mul(*b),
+                Binary::Div => a.checked_div(*b),
+                _ => a.checked_rem(*b),
+            };
+            out.ok_or_else(|| overflow(op, *b))
+        })?),
+        (Typed::Float(l), Typed::Float(r)) => float_op!(Float, l, r),
+        (Typed::Double(l), Typed::Double(r)) => float_op!(Double, l, r),
+        (Typed::Str(l), Typed::Str(r)) if op == Binary::Plus => {
+            ColumnData::Str(apply(l, r, |a, b| Ok(format!("{a}{b}").into()))?)
+        }
+        _ => return Err(mismatch(op, &left, &right)),
+    })
+}
+
+fn comparator<T: PartialOrd>(op: Binary) -> fn(&T, &T) -> bool {
+    match op {
+        Binary::Eq => T::eq,
+        Binary::NotEq => T::ne,
+        Binary::Lt => T::lt,
+        Binary::LtEq => T::le,
+        Binary::Gt => T::gt,
+        _ => T::ge,
+    }
+}
+
+/// `=`, `<>`, `<`, `<=`, `>` and `>=`, giving a bool column. Rows compared
+/// with a null are left out.
+pub fn compare(
+    op: Binary,
+    left: Operand,
+    right: Operand,
+    strictness: Strictness,
+) -> Result<ColumnData> {
+    let (left, right) = widen(left, right, strictness);
+    let mut storage = [None, None];
+    let (left, right) = promote_numeric(left, right, &mut storage);
+
+    macro_rules! cmp {
+        ($l:expr, $r:expr) => {{
+            let f = comparator(op);
+            apply($l, $r, |a, b| Ok(f(a, b)))?
+        }};
+    }
+
+    Ok(ColumnData::Bool(match (typed(&left), typed(&right)) {
+        (Typed::Null, _) | (_, Typed::Null) => ChunkedMap::new(),
+        (Typed::Int(l), Typed::Int(r)) => cmp!(l, r),
+        (Typed::Str(l), Typed::Str(r)) => cmp!(l, r),
+        (Typed::Float(l), Typed::Float(r)) => cmp!(l, r),
+        (Typed::Double(l), Typed::Double(r)) => cmp!(l, r),
+        (Typed::Bool(l), Typed::Bool(r)) => cmp!(l, r),
+        _ => return Err(mismatch(op, &left, &right)),
+    }))
+}
+
+/// `AND` and `OR` of bool columns, with nulls as sql has them: a row that is
+/// null on one side, by being left out of it, only has a value when the other
+/// side decides it alone, like false does for `AND`. A scalar pairs with the
+/// rows of the other side, and two scalars give a single row, at RowId 0.
+pub fn logical(op: Binary, left: Operand, right: Operand) -> Result<ColumnData> {
+    let (l, r) = match (typed(&left), typed(&right)) {
+        (Typed::Bool(l), Typed::Bool(r)) => (Some(l), Some(r)),
+        (Typed::Bool(l), Typed::Null) => (Some(l), None),
+        (Typed::Null, Typed::Bool(r)) => (None, Some(r)),
+        (Typed::Null, Typed::Null) => (None, None),
+        _ => return Err(mismatch(op, &left, &right)),
+    };
+
+    // true for `OR` and false for `AND`
+    let decisive = op == Binary::Or;
+    let value = |side: &Option<Side<bool>>, id: RowId| match side {
+        Some(Side::Column(c)) => c.get(&id).copied(),
+        Some(Side::Scalar(v)) => Some(*v),
+        None => None,
+    };
+
+    let mut ids: Vec<RowId> = [&l, &r]
+        .into_iter()
+        .filter_map(|side| match side {
+            Some(Side::Column(c)) => Some(c.keys().copied()),
+            _ => None,
+        })
+        .flatten()
+        .collect();
+    ids.sort_unstable();
+    ids.dedup();
+    if !matches!(l, Some(Side::Column(_))) && !matches!(r, Some(Side::Column(_))) {
+        ids.push(0);
+    }
+
+    Ok(ColumnData::Bool(
+        ids.into_iter()
+            .filter_map(|id| {
+                let out = match (value(&l, id), value(&r, id)) {
+                    (Some(a), Some(b)) => match op {
+                        Binary::Or => a || b,
+                        _ => a && b,
+                    },
+                    (Some(v), None) | (None, Some(v)) if v == decisive => v,
+                    _ => return None,
+                };
+                Some((id, out))
+            })
+            .collect(),
+    ))
+}
+
+/// `IN` of a list of values, giving a bool column that is true for the rows
+/// equal to one of them, and false for the others, or the other way around
+/// for `NOT IN`. Like the `OR` of `=` it stands for, a row equal to none of
+/// them is null rather than either when the list has a null in it, and rows
+/// that are null themselves are left out.
+pub fn in_list(
+    value: Operand,
+    list: &[Literal],
+    negated: bool,
+    strictness: Strictness,
+) -> Result<ColumnData> {
+    let rows = match &value {
+        Operand::Column(c) => c.keys(),
+        Operand::Scalar(Literal::Null) => RowSet::new(),
+        Operand::Scalar(_) => [0].into_iter().collect(),
+    };
+
+    let mut found = RowSet::new();
+    let has_null = list.contains(&Literal::Null);
+    // when the values are of the type of the column, each row is looked up
+    // in a set of them rather than the column being compared with every one,
+    // which long lists like those of `IN (SELECT ...)` would make slow
+    if let Operand::Column(column) = &value {
+        let datatype = DataType::from(*column);
+        let other = |l: &Literal| DataType::of(l).is_some_and(|d| d != datatype);
+        if !list.iter().any(other) {
+            let set: HashSet<HashKey> = list.iter().cloned().filter_map(HashKey::new).collect();
+            for id in rows.iter() {
+                let key = column.get_literal(id).and_then(HashKey::new);
+                if key.is_some_and(|k| set.contains(&k)) {
+                    found.insert(id);
+                }
+            }
+            return Ok(membership(rows, found, has_null, negated));
+        }
+    }
+
+    for item in list {
+        if *item == Literal::Null {
+            continue;
+        }
+        let value = match &value {
+            Operand::Column(c) => Operand::Column(c),
+            Operand::Scalar(l) => Operand::Scalar(l.clone()),
+        };
+        let equal = compare(Binary::Eq, value, Operand::Scalar(item.clone()), strictness)?;
+        found.union_with(&equal.keys_where_true()?);
+    }
+
+    Ok(membership(rows, found, has_null, negated))
+}
+
+// of `rows`, those `found` in the list, the others null when it has a null
+fn membership(rows: RowSet, found: RowSet, has_null: bool, negated: bool) -> ColumnData {
+    ColumnData::Bool(
+        rows.iter()
+            .filter_map(|id| match found.contains(id) {
+                true => Some((id, !negated)),
+                false if has_null => None,
+                false => Some((id, negated)),
+            })
+            .collect(),
+    )
+}
@@ -1,9 +1,52 @@
+#[cfg(feature = "server")]
+pub mod acl;
+pub mod aggregate;
+#[cfg(feature = "arrow")]
+pub mod arrow;
+pub mod audit;
+#[cfg(feature = "server")]
+pub mod auth;
+pub mod chunked;
+#[cfg(feature = "client")]
+pub mod client;
+#[cfg(feature = "server")]
+pub mod config;
 pub mod database;
 pub mod dbcommands;
+pub mod deadline;
 pub mod error;
 pub mod evaluator;
+pub mod format;
+pub mod fulltext;
+pub mod functions;
+#[cfg(any(feature = "fuzz", test))]
+pub mod fuzz;
+pub mod generate;
+pub mod join;
+pub mod kernels;
+pub mod load;
 pub mod metacommands;
+pub mod migrations;
+pub mod optimizer;
 pub mod parser;
+pub mod plan;
+pub mod planner;
+pub mod regexp;
+pub mod replay;
+pub mod rowset;
+pub mod sample;
+pub mod schema;
+pub mod sequence;
+pub mod sort;
+pub mod source;
+pub mod sql;
+pub mod system;
 pub mod table;
+#[cfg(feature = "server")]
+pub mod tls;
+pub mod users;
+pub mod virtual_table;
+#[cfg(feature = "xlsx")]
+pub mod xlsx;
 
 pub use error::{Error, Result};
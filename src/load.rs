@@ -0,0 +1,292 @@
+use serde::Serialize;
+use serde_json::{Map, Value};
+
+use crate::{
+    parser::expression::Literal,
+    source,
+    table::{ColumnHeader, DataType, Strictness},
+    Error,
+};
+
+// rows per error kept in a summary, the rest are only counted
+const MAX_ERRORS: usize = 100;
+
+/// What a bulk load is read from: csv with a header of column names, or a
+/// json object per line.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LoadFormat {
+    Csv,
+    Ndjson,
+}
+
+impl LoadFormat {
+    /// The format of a `Content-Type`, ignoring its parameters.
+    pub fn from_content_type(content_type: &str) -> Option<Self> {
+        match content_type.split(';').next().unwrap_or_default().trim() {
+            "text/csv" => Some(LoadFormat::Csv),
+            "application/x-ndjson" | "application/jsonl" | "application/json-lines" => {
+                Some(LoadFormat::Ndjson)
+            }
+            _ => None,
+        }
+    }
+}
+
+/// A row read by a `LoadParser`, by column name, or why it couldn't be. Text
+/// is converted to the type of its column once loaded, see `Database::load`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LoadRecord {
+    // where it starts, counting from 1
+    pub line: usize,
+    pub row: Result<Vec<(String, Literal)>, String>,
+}
+
+/// Splits a body arriving in pieces into rows. Unquoted empty csv values are
+/// null, while `""` is an empty string.
+#[derive(Debug)]
+pub struct LoadParser {
+    format: LoadFormat,
+    buf: Vec<u8>,
+    // how far `buf` was searched for the end of its first record
+    scanned: usize,
+    in_quotes: bool,
+    line: usize,
+    header: Option<Vec<String>>,
+}
+
+impl LoadParser {
+    pub fn new(format: LoadFormat) -> Self {
+        Self {
+            format,
+            buf: Vec::new(),
+            scanned: 0,
+            in_quotes: false,
+            line: 1,
+            header: None,
+        }
+    }
+
+    /// The records completed by `data`.
+    pub fn push(&mut self, data: &[u8]) -> Vec<LoadRecord> {
+        self.buf.extend_from_slice(data);
+
+        let mut records = Vec::new();
+        let mut start = 0;
+        while let Some(end) = self.record_end(start) {
+            let record = self.buf[start..end].to_vec();
+            records.extend(self.record(&record));
+            start = end + 1;
+            self.scanned = start;
+        }
+        self.buf.drain(..start);
+        self.scanned -= start;
+
+        records
+    }
+
+    /// Bytes of the record still being read.
+    pub fn pending(&self) -> usize {
+        self.buf.len()
+    }
+
+    /// The last record, when the body doesn't end with a line break.
+    pub fn finish(&mut self) -> Vec<LoadRecord> {
+        let record = std::mem::take(&mut self.buf);
+        self.scanned = 0;
+        self.record(&record).into_iter().collect()
+    }
+
+    // the index of the line break ending the record at `start`, only outside
+    // of quotes for csv
+    fn record_end(&mut self, start: usize) -> Option<usize> {
+        let from = self.scanned.max(start);
+        for (i, &b) in self.buf[from..].iter().enumerate() {
+            match b {
+                b'"' if self.format == LoadFormat::Csv => self.in_quotes = !self.in_quotes,
+                b'\n' if !self.in_quotes => return Some(from + i),
+                _ => {}
+            }
+        }
+        self.scanned = self.buf.len();
+        None
+    }
+
+    fn record(&mut self, bytes: &[u8]) -> Option<LoadRecord> {
+        let line = self.line;
+        self.line += bytes.iter().filter(|&&b| b == b'\n').count() + 1;
+
+        let text = match std::str::from_utf8(bytes) {
+            Ok(text) => text.strip_suffix('\r').unwrap_or(text),
+            Err(e) => {
+                return Some(LoadRecord {
+                    line,
+                    row: Err(format!("not utf-8: {e}")),
+                })
+            }
+        };
+        if text.trim().is_empty() {
+            return None;
+        }
+
+        let row = match (self.format, &self.header) {
+            (LoadFormat::Ndjson, _) => json_row(text),
+            (LoadFormat::Csv, None) => {
+                let names = csv_fields(text).into_iter();
+                self.header = Some(names.map(Option::unwrap_or_default).collect());
+                return None;
+            }
+            (LoadFormat::Csv, Some(header)) => {
+                let fields = csv_fields(text);
+                if fields.len() == header.len() {
+                    let values = fields
+                        .into_iter()
+                        .map(|f| f.map_or(Literal::Null, Literal::Str));
+                    Ok(header.iter().cloned().zip(values).collect())
+                } else {
+                    Err(format!(
+                        "{} values, expected {} like the header",
+                        fields.len(),
+                        header.len()
+                    ))
+                }
+            }
+        };
+
+        Some(LoadRecord { line, row })
+    }
+}
+
+// the values of a csv record, `None` where they're empty and unquoted
+fn csv_fields(record: &str) -> Vec<Option<String>> {
+    let mut fields = Vec::new();
+    let mut field = String::new();
+    let (mut quoted, mut in_quotes) = (false, false);
+
+    let mut chars = record.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if in_quotes && chars.peek() == Some(&'"') => {
+                field.push('"');
+                chars.next();
+            }
+            '"' => {
+                in_quotes = !in_quotes;
+                quoted = true;
+            }
+            ',' if !in_quotes => {
+                let field = std::mem::take(&mut field);
+                fields.push((quoted || !field.is_empty()).then_some(field));
+                quoted = false;
+            }
+            c => field.push(c),
+        }
+    }
+    fields.push((quoted || !field.is_empty()).then_some(field));
+
+    fields
+}
+
+fn json_row(line: &str) -> Result<Vec<(String, Literal)>, String> {
+    let object: Map<String, Value> = serde_json::from_str(line).map_err(|e| e.to_string())?;
+    object
+        .into_iter()
+        .map(|(column, value)| {
+            let literal = source::literal(&column, value).map_err(|e| e.to_string())?;
+            Ok((column, literal))
+        })
+        .collect()
+}
+
+/// The values of `row` in the order of `headers`, null for the columns it
+/// doesn't have, with text parsed into the type of its column. Columns the
+/// table doesn't have are skipped when lenient.
+pub(crate) fn table_row(
+    headers: &[ColumnHeader],
+    row: Vec<(String, Literal)>,
+    strictness: Strictness,
+) -> Result<Vec<Literal>, String> {
+    let mut values = vec![Literal::Null; headers.len()];
+    for (name, value) in row {
+        match headers
+            .iter()
+            .position(|h| h.name.eq_ignore_ascii_case(&name))
+        {
+            Some(i) => values[i] = parse_text(value, &headers[i])?,
+            None if strictness == Strictness::Lenient => {}
+            None => return Err(format!("column {name}, which the table doesn't have")),
+        }
+    }
+
+    Ok(values)
+}
+
+fn parse_text(value: Literal, header: &ColumnHeader) -> Result<Literal, String> {
+    let Literal::Str(text) = value else {
+        return Ok(value);
+    };
+    let parsed = match header.datatype {
+        DataType::Int => text.trim().parse().ok().map(Literal::Int),
+        DataType::Float => text.trim().parse().ok().map(Literal::Float),
+        DataType::Double => text.trim().parse().ok().map(Literal::Double),
+        DataType::Bool => Literal::Str(text.clone()).cast(DataType::Bool, Strictness::Lenient),
+        DataType::Str | DataType::Invalid => return Ok(Literal::Str(text)),
+    };
+
+    parsed.ok_or_else(|| {
+        format!(
+            "{text:?} for column {}, which isn't of type {:?}",
+            header.name, header.datatype
+        )
+    })
+}
+
+/// What a bulk load did: how many rows were inserted, how many were not, and
+/// why, for the first of them.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct LoadSummary {
+    pub rows: usize,
+    pub rejected: usize,
+    pub chunks: usize,
+    pub errors: Vec<LoadError>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct LoadError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl LoadSummary {
+    pub fn reject(&mut self, line: usize, message: impl Into<String>) {
+        self.rejected += 1;
+        if self.errors.len() < MAX_ERRORS {
+            self.errors.push(LoadError {
+                line,
+                message: message.into(),
+            });
+        }
+    }
+
+    /// Adds up the summary of another chunk of the same load.
+    pub fn merge(&mut self, other: LoadSummary) {
+        self.rows += other.rows;
+        self.chunks += other.chunks;
+        for error in &other.errors {
+            self.reject(error.line, error.message.clone());
+        }
+        // beyond those whose errors were kept
+        self.rejected += other.rejected - other.errors.len();
+    }
+}
+
+/// Whether `e` ends a whole load rather than only rejecting the rows it
+/// happened for.
+pub fn is_fatal(e: &Error) -> bool {
+    matches!(
+        e,
+        Error::PermissionDenied(_)
+            | Error::TableNotFound { .. }
+            | Error::Timeout(_)
+            | Error::Cancelled
+    )
+}
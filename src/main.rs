@@ -1,41 +1,968 @@
-use std::time::{Duration, Instant};
+use std::{
+    any::Any,
+    collections::{HashMap, HashSet, VecDeque},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex, RwLock,
+    },
+    time::{Duration, Instant},
+};
 
-use flume::{Receiver, Sender};
+use flume::{Receiver, Sender, WeakSender};
+use futures_util::future::{self, Either};
+use futures_util::StreamExt;
 
 use actix::{Actor, ActorContext, AsyncContext, StreamHandler};
+use actix_tls::accept::rustls_0_21::TlsStream;
 use actix_web::body::MessageBody;
-use actix_web::http::StatusCode;
-use actix_web::{get, web, App, Error, HttpRequest, HttpResponse, HttpServer};
+use actix_web::dev::{Extensions, Service};
+use actix_web::http::{header, StatusCode};
+use actix_web::rt::net::TcpStream;
+use actix_web::{delete, get, post, put, web, App, Error, HttpRequest, HttpResponse, HttpServer};
 use actix_web_actors::ws;
 use anyhow::Result;
-use serde::Deserialize;
-use socketdb::database::Database;
+use serde::{Deserialize, Serialize};
+use socketdb::acl::Acl;
+use socketdb::arrow::{self, IpcFormat};
+use socketdb::audit::{AuditKind, AuditLog};
+use socketdb::auth::Tokens;
+use socketdb::config::{AuthBackend, Autosave, Config, LimitsConfig, ResponseLimits};
+use socketdb::database::{table_key, Database, OutputMode, RowIter, Subscription, View};
+use socketdb::deadline::CancelToken;
+use socketdb::functions::Session;
+use socketdb::load::{LoadFormat, LoadParser, LoadRecord, LoadSummary};
+use socketdb::parser::parser::{self, Limits, Query};
+use socketdb::parser::user::redact_passwords;
+use socketdb::replay::{self, ReplayLog};
+use socketdb::source::{JsonLines, Source};
+use socketdb::tls;
+use socketdb::users::Users;
+
+// everything the database thread can be asked to do, of the default database
+// unless `database` names another
+enum Request {
+    // a line typed into the repl, `done` is signalled once it has been handled
+    Repl {
+        line: String,
+        done: Sender<()>,
+    },
+    Query {
+        connection: u64,
+        span: tracing::Span,
+        user: Option<String>,
+        client_addr: Option<String>,
+        database: Option<String>,
+        sql: String,
+        timeout: Option<Duration>,
+        respond: Sender<socketdb::Result<Vec<View>>>,
+    },
+    // a single select whose rows are sent back unformatted, to be streamed
+    Rows {
+        connection: u64,
+        span: tracing::Span,
+        user: Option<String>,
+        client_addr: Option<String>,
+        database: Option<String>,
+        sql: String,
+        timeout: Option<Duration>,
+        respond: Sender<socketdb::Result<RowIter>>,
+    },
+    // rows to insert into `table`, as arrow ipc
+    Import {
+        connection: u64,
+        span: tracing::Span,
+        user: Option<String>,
+        client_addr: Option<String>,
+        database: Option<String>,
+        table: String,
+        data: web::Bytes,
+        respond: Sender<socketdb::Result<usize>>,
+    },
+    // a chunk of the rows of a bulk load into `table`
+    Load {
+        connection: u64,
+        span: tracing::Span,
+        user: Option<String>,
+        client_addr: Option<String>,
+        database: Option<String>,
+        table: String,
+        records: Vec<LoadRecord>,
+        respond: Sender<socketdb::Result<LoadSummary>>,
+    },
+    // `LISTEN` or `UNLISTEN`, sent over a websocket subscription
+    Listen {
+        database: Option<String>,
+        query: Query,
+        sender: Sender<String>,
+    },
+    // subscriptions to the default database go straight to its receiver
+    Subscribe {
+        database: String,
+        subscription: Subscription,
+    },
+    CreateDatabase {
+        name: String,
+        respond: Sender<socketdb::Result<()>>,
+    },
+    DeleteDatabase {
+        name: String,
+        respond: Sender<socketdb::Result<()>>,
+    },
+    // publishes a snapshot of the database as it is now, see `Snapshots`
+    Snapshot {
+        database: Option<String>,
+        respond: Sender<socketdb::Result<()>>,
+    },
+    // the config was reloaded, see `reload_on_hangup`
+    Reload(Settings),
+    // saves what autosave hasn't yet, before shutting down
+    Flush {
+        done: Sender<()>,
+    },
+}
+
+// the default database, along with the named ones created through
+// `/admin/databases`, each kept in its own file and sharing the users of the
+// default one
+struct Databases {
+    default: Database,
+    named: HashMap<String, Named>,
+    dir: PathBuf,
+    audit: AuditLog,
+    settings: Settings,
+    // changed since they were last saved, `None` being the default database
+    unsaved: HashSet<Option<String>>,
+    saved_at: Instant,
+    snapshots: Arc<Snapshots>,
+    // what every statement is recorded into, if configured, see `replay_log`
+    replay: Option<ReplayLog>,
+}
+
+// what every database is configured with, changed once the config is reloaded
+#[derive(Debug, Clone, Copy)]
+struct Settings {
+    limits: Limits,
+    statement_timeout: Option<Duration>,
+    statement_cache_size: usize,
+    autosave: Autosave,
+}
+
+impl Settings {
+    fn new(config: &Config) -> Self {
+        Settings {
+            limits: config.limits(),
+            statement_timeout: config.statement_timeout(),
+            statement_cache_size: config.statement_cache_size(),
+            autosave: config.autosave,
+        }
+    }
+
+    fn apply(&self, db: &mut Database) {
+        db.set_limits(self.limits);
+        db.set_statement_timeout(self.statement_timeout);
+        db.set_statement_cache_size(self.statement_cache_size);
+    }
+}
+
+// the rows left out of views sent back to keep them within the limits, see
+// `ResponseLimits`, or to a page, see `Page`, until they're fetched through
+// `/query/cursors` by the user they were sent to. Each is fetched once, and
+// only within the idle timeout of cursors, see `LimitsConfig::cursor_idle`,
+// past which it's swept away.
+#[derive(Default)]
+struct Cursors(Mutex<HashMap<String, Pending>>);
+
+struct Pending {
+    user: Option<String>,
+    rows: View,
+    opened: Instant,
+}
+
+// kept at once, the oldest being dropped for new ones past that
+const MAX_CURSORS: usize = 256;
+
+impl Cursors {
+    fn open(&self, user: Option<&str>, rows: View, ttl: Duration) -> String {
+        let mut open = self.0.lock().unwrap();
+        open.retain(|_, p| p.opened.elapsed() < ttl);
+        if open.len() >= MAX_CURSORS {
+            let oldest = open
+                .iter()
+                .min_by_key(|(_, p)| p.opened)
+                .map(|(id, _)| id.clone());
+            open.remove(&oldest.unwrap_or_default());
+        }
+
+        let id = format!("{:032x}", rand::random::<u128>());
+        let pending = Pending {
+            user: user.map(str::to_owned),
+            rows,
+            opened: Instant::now(),
+        };
+        open.insert(id.clone(), pending);
+        id
+    }
+
+    fn fetch(&self, id: &str, user: Option<&str>, ttl: Duration) -> socketdb::Result<View> {
+        let mut open = self.0.lock().unwrap();
+        let valid = open
+            .get(id)
+            .is_some_and(|p| p.user.as_deref() == user && p.opened.elapsed() < ttl);
+        if !valid {
+            return Err(socketdb::Error::CursorNotFound(id.to_owned()));
+        }
+        Ok(open.remove(id).map(|p| p.rows).unwrap_or_default())
+    }
+
+    // drops the cursors left unfetched for `ttl`, along with their rows
+    fn sweep(&self, ttl: Duration) {
+        let mut open = self.0.lock().unwrap();
+        let before = open.len();
+        open.retain(|_, p| p.opened.elapsed() < ttl);
+        if open.len() < before {
+            log::debug!("dropped {} idle cursors", before - open.len());
+        }
+    }
+}
+
+// how often idle cursors are swept away, see `Cursors::sweep`
+const CURSOR_SWEEP_INTERVAL: Duration = Duration::from_secs(30);
+
+// the latest snapshot of each database, by lowercase name with `None` being
+// the default one, that statements which only read run on, see `read`, so
+// that they don't queue up behind those the database thread executes. A
+// database that's changed drops its snapshot, and the next statement to read
+// it asks the database thread for a new one, which is then shared by all the
+// statements after until the database changes again.
+struct Snapshots {
+    versions: Mutex<HashMap<Option<String>, Version>>,
+    // what the statements on the snapshots record into, like the database
+    audit: AuditLog,
+    replay: Option<ReplayLog>,
+}
+
+struct Version {
+    snapshot: Arc<Database>,
+    // copies of `snapshot` that aren't in use, each statement taking one of
+    // its own to run on
+    idle: Vec<Database>,
+}
+
+// kept of each version at most, more being copied while there are more
+// statements reading it at once
+const MAX_IDLE_COPIES: usize = 8;
+
+impl Snapshots {
+    fn new(audit: AuditLog, replay: Option<ReplayLog>) -> Self {
+        Snapshots {
+            versions: Mutex::new(HashMap::new()),
+            audit,
+            replay,
+        }
+    }
+
+    fn publish(&self, database: Option<&str>, snapshot: Database) {
+        let version = Version {
+            snapshot: Arc::new(snapshot),
+            idle: Vec::new(),
+        };
+        let key = database.map(str::to_ascii_lowercase);
+        self.versions.lock().unwrap().insert(key, version);
+    }
+
+    // after `database` changed, or was deleted
+    fn invalidate(&self, database: Option<&str>) {
+        let key = database.map(str::to_ascii_lowercase);
+        self.versions.lock().unwrap().remove(&key);
+    }
+
+    fn clear(&self) {
+        self.versions.lock().unwrap().clear();
+    }
+
+    fn has(&self, database: Option<&str>) -> bool {
+        let key = database.map(str::to_ascii_lowercase);
+        self.versions.lock().unwrap().contains_key(&key)
+    }
+
+    // a copy of the latest snapshot of `database` to run statements on, along
+    // with the snapshot to give it back for, or `None` when there's none
+    fn take(&self, database: Option<&str>) -> Option<(Arc<Database>, Database)> {
+        let key = database.map(str::to_ascii_lowercase);
+        let (snapshot, idle) = {
+            let mut versions = self.versions.lock().unwrap();
+            let version = versions.get_mut(&key)?;
+            (version.snapshot.clone(), version.idle.pop())
+        };
+
+        let copy = idle.unwrap_or_else(|| {
+            let mut copy = snapshot.snapshot();
+            copy.set_audit_log(self.audit.clone());
+            if let Some(replay) = &self.replay {
+                copy.set_replay_log(replay.for_database(key.as_deref()));
+            }
+            copy
+        });
+        Some((snapshot, copy))
+    }
+
+    // keeps `copy` for the next statement, unless there's a newer snapshot
+    fn give_back(&self, database: Option<&str>, snapshot: &Arc<Database>, copy: Database) {
+        let key = database.map(str::to_ascii_lowercase);
+        let mut versions = self.versions.lock().unwrap();
+        if let Some(version) = versions.get_mut(&key) {
+            if Arc::ptr_eq(&version.snapshot, snapshot) && version.idle.len() < MAX_IDLE_COPIES {
+                version.idle.push(copy);
+            }
+        }
+    }
+}
+
+// not a valid database name, so that it can't be one of the named ones
+const DEFAULT_FILE: &str = ".default.sdb";
+
+struct Named {
+    db: Database,
+    subscriptions: Sender<Subscription>,
+}
+
+impl Named {
+    fn new(mut db: Database) -> Self {
+        let (tx, rx) = flume::unbounded();
+        db.set_receiver(rx);
+        Named {
+            db,
+            subscriptions: tx,
+        }
+    }
+}
+
+impl Databases {
+    // picks up the databases persisted in `dir` by earlier runs, each of them
+    // recording into `audit`, and their statements into `replay`
+    fn open(dir: PathBuf, audit: AuditLog, replay: Option<ReplayLog>, settings: Settings) -> Self {
+        let mut default = match Database::restore(&dir.join(DEFAULT_FILE)) {
+            Ok(db) => db,
+            Err(socketdb::Error::IOError(_)) => Database::new(),
+            Err(e) => {
+                log::error!("could not restore the default database: {e}");
+                Database::new()
+            }
+        };
+
+        let mut named = HashMap::new();
+        let files = std::fs::read_dir(&dir).into_iter().flatten().flatten();
+        for path in files.map(|f| f.path()) {
+            let Some(name) = path
+                .file_stem()
+                .and_then(|n| n.to_str())
+                .filter(|_| path.extension().is_some_and(|e| e == "sdb"))
+                .filter(|n| valid_name(n))
+            else {
+                continue;
+            };
+
+            match Database::restore(&path) {
+                Ok(mut db) => {
+                    db.set_users(default.users());
+                    db.set_audit_log(audit.clone());
+                    if let Some(replay) = &replay {
+                        db.set_replay_log(replay.for_database(Some(name)));
+                    }
+                    settings.apply(&mut db);
+                    log::info!("restored database {name}");
+                    named.insert(name.to_owned(), Named::new(db));
+                }
+                Err(e) => log::error!("could not restore database {name}: {e}"),
+            }
+        }
+
+        default.set_audit_log(audit.clone());
+        if let Some(replay) = &replay {
+            default.set_replay_log(replay.for_database(None));
+        }
+        settings.apply(&mut default);
+        Databases {
+            default,
+            named,
+            dir,
+            snapshots: Arc::new(Snapshots::new(audit.clone(), replay.clone())),
+            audit,
+            settings,
+            unsaved: HashSet::new(),
+            saved_at: Instant::now(),
+            replay,
+        }
+    }
+
+    fn get(&mut self, name: Option<&str>) -> socketdb::Result<&mut Database> {
+        match name {
+            None => Ok(&mut self.default),
+            Some(name) => self
+                .named
+                .get_mut(&name.to_ascii_lowercase())
+                .map(|n| &mut n.db)
+                .ok_or_else(|| socketdb::Error::DatabaseNotFound(name.to_owned())),
+        }
+    }
+
+    fn path(&self, name: &str) -> PathBuf {
+        self.dir.join(format!("{name}.sdb"))
+    }
+
+    // after statements that may have changed a database, which is saved right
+    // away or with the next autosave, depending on its interval
+    fn changed(&mut self, name: Option<&str>) {
+        self.snapshots.invalidate(name);
+        match self.settings.autosave {
+            Autosave { enabled: false, .. } => {}
+            Autosave {
+                interval_secs: 0, ..
+            } => self.persist(name),
+            _ => _ = self.unsaved.insert(name.map(|n| n.to_ascii_lowercase())),
+        }
+    }
+
+    // saves the databases changed since the last autosave, once the interval
+    // has passed or it's `forced`
+    fn autosave(&mut self, forced: bool) {
+        let interval = Duration::from_secs(self.settings.autosave.interval_secs);
+        if self.unsaved.is_empty() || !(forced || self.saved_at.elapsed() >= interval) {
+            return;
+        }
+
+        for name in std::mem::take(&mut self.unsaved) {
+            self.persist(name.as_deref());
+        }
+        self.saved_at = Instant::now();
+    }
+
+    fn reload(&mut self, settings: Settings) {
+        self.settings = settings;
+        self.snapshots.clear();
+        for db in self.all() {
+            settings.apply(db);
+        }
+    }
+
+    fn persist(&self, name: Option<&str>) {
+        let Some(name) = name.map(|n| n.to_ascii_lowercase()) else {
+            let res = std::fs::create_dir_all(&self.dir)
+                .map_err(socketdb::Error::from)
+                .and_then(|()| self.default.persist(&self.dir.join(DEFAULT_FILE)));
+            if let Err(e) = res {
+                log::error!("could not persist the default database: {e}");
+            }
+            return;
+        };
+        if let Some(named) = self.named.get(&name) {
+            if let Err(e) = named.db.persist(&self.path(&name)) {
+                log::error!("could not persist database {name}: {e}");
+            }
+        }
+    }
+
+    fn create(&mut self, name: &str) -> socketdb::Result<()> {
+        if !valid_name(name) {
+            // only letters, digits, `_` and `-`, as it names a file
+            return Err(socketdb::Error::InvalidOperation(format!(
+                "database name {name:?}"
+            )));
+        }
+        let name = name.to_ascii_lowercase();
+        if self.named.contains_key(&name) {
+            return Err(socketdb::Error::DatabaseAlreadyExists(name));
+        }
+
+        let mut db = Database::new();
+        db.set_users(self.default.users());
+        db.set_audit_log(self.audit.clone());
+        if let Some(replay) = &self.replay {
+            db.set_replay_log(replay.for_database(Some(&name)));
+        }
+        self.settings.apply(&mut db);
+        std::fs::create_dir_all(&self.dir)?;
+        db.persist(&self.path(&name))?;
+        self.named.insert(name, Named::new(db));
+
+        Ok(())
+    }
+
+    // subscribers of the database see their subscriptions closed
+    fn delete(&mut self, name: &str) -> socketdb::Result<()> {
+        let name = name.to_ascii_lowercase();
+        if self.named.remove(&name).is_none() {
+            return Err(socketdb::Error::DatabaseNotFound(name));
+        }
+        self.snapshots.invalidate(Some(&name));
+
+        match std::fs::remove_file(self.path(&name)) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.into()),
+            _ => Ok(()),
+        }
+    }
+
+    fn subscribe(&mut self, name: &str, sub: Subscription) {
+        match self.named.get(&name.to_ascii_lowercase()) {
+            Some(named) => _ = named.subscriptions.send(sub),
+            // refused, like a subscription to a table that doesn't exist
+            None => {
+                let error = socketdb::Error::DatabaseNotFound(name.to_owned());
+                _ = sub.sender.send(error.to_json().to_string());
+            }
+        }
+    }
+
+    // applies what attached sources sent, saving the databases they changed
+    fn apply_sources(&mut self) {
+        let mut changed = Vec::new();
+        if self.default.apply_sources() > 0 {
+            changed.push(None);
+        }
+        for (name, named) in &mut self.named {
+            if named.db.apply_sources() > 0 {
+                changed.push(Some(name.clone()));
+            }
+        }
+
+        for name in changed {
+            self.changed(name.as_deref());
+        }
+    }
+
+    fn all(&mut self) -> impl Iterator<Item = &mut Database> {
+        std::iter::once(&mut self.default).chain(self.named.values_mut().map(|n| &mut n.db))
+    }
+}
+
+// what each http request and websocket subscription is doing, for
+// `/admin/connections` and `KILL`
+#[derive(Default)]
+struct Connections {
+    next_id: AtomicU64,
+    open: Mutex<HashMap<u64, Connection>>,
+}
+
+struct Connection {
+    kind: &'static str,
+    database: Option<String>,
+    // who opened it, the only one besides superusers that may kill it
+    user: Option<String>,
+    // along with when it started, once the database thread gets to it
+    running: Option<(String, Instant)>,
+    cancel: CancelToken,
+}
+
+#[derive(Serialize)]
+struct ConnectionInfo {
+    id: u64,
+    kind: &'static str,
+    database: Option<String>,
+    statement: Option<String>,
+    running_ms: Option<u128>,
+}
+
+// forgets the connection once dropped
+struct Registered {
+    id: u64,
+    cancel: CancelToken,
+    connections: Arc<Connections>,
+}
+
+impl Drop for Registered {
+    fn drop(&mut self) {
+        self.connections.open.lock().unwrap().remove(&self.id);
+    }
+}
+
+impl Connections {
+    fn register(
+        self: &Arc<Self>,
+        kind: &'static str,
+        database: Option<String>,
+        user: Option<String>,
+    ) -> Registered {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed) + 1;
+        let cancel = CancelToken::new();
+        self.open.lock().unwrap().insert(
+            id,
+            Connection {
+                kind,
+                database,
+                user,
+                running: None,
+                cancel: cancel.clone(),
+            },
+        );
+
+        Registered {
+            id,
+            cancel,
+            connections: self.clone(),
+        }
+    }
+
+    // the token to run `sql` with, already cancelled when the connection
+    // closed while waiting for its turn
+    fn start(&self, id: u64, sql: &str) -> CancelToken {
+        match self.open.lock().unwrap().get_mut(&id) {
+            Some(conn) => {
+                conn.running = Some((sql.to_owned(), Instant::now()));
+                conn.cancel.clone()
+            }
+            None => {
+                let cancel = CancelToken::new();
+                cancel.cancel();
+                cancel
+            }
+        }
+    }
+
+    fn finish(&self, id: u64) {
+        if let Some(conn) = self.open.lock().unwrap().get_mut(&id) {
+            conn.running = None;
+        }
+    }
+
+    // cancels the statement running for the connection, or closes it when
+    // it's a subscription, if it was opened by `user` or they're a `superuser`
+    fn kill(&self, id: u64, user: Option<&str>, superuser: bool) -> socketdb::Result<()> {
+        match self.open.lock().unwrap().get(&id) {
+            Some(conn) if superuser || conn.user.as_deref() == user => {
+                conn.cancel.cancel();
+                Ok(())
+            }
+            Some(_) => Err(socketdb::Error::PermissionDenied(format!(
+                "killing connection {id}, which isn't yours"
+            ))),
+            None => Err(socketdb::Error::ConnectionNotFound(id)),
+        }
+    }
+
+    fn list(&self) -> Vec<ConnectionInfo> {
+        let mut list: Vec<_> = self
+            .open
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(&id, conn)| ConnectionInfo {
+                id,
+                kind: conn.kind,
+                database: conn.database.clone(),
+                statement: conn.running.as_ref().map(|(sql, _)| redact_passwords(sql)),
+                running_ms: conn
+                    .running
+                    .as_ref()
+                    .map(|(_, at)| at.elapsed().as_millis()),
+            })
+            .collect();
+        list.sort_by_key(|c| c.id);
+        list
+    }
+}
+
+// `Some` with the connections to kill when `sql` is only `KILL` statements,
+// which are handled right away rather than waiting behind the statements
+// they are killing
+fn kill_statements(sql: &str) -> Option<Vec<u64>> {
+    let is_kill = sql
+        .trim_start()
+        .get(..4)
+        .is_some_and(|k| k.eq_ignore_ascii_case("kill"));
+    if !is_kill {
+        return None;
+    }
+
+    parser::parse_all(
+        sql,
+        Default::default(),
+        Default::default(),
+        &Session::default(),
+    )
+    .ok()?
+    .into_iter()
+    .map(|q| match q {
+        Query::Kill(id) => Some(id),
+        _ => None,
+    })
+    .collect()
+}
+
+fn valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && name.len() <= 64
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+}
 
 #[actix_web::main]
 async fn main() -> Result<()> {
-    env_logger::Builder::from_env(
-        env_logger::Env::from("SOCKET_DB_LOG_LEVEL")
-            .default_filter_or("error,rustyline=error,sqlparser=error"),
-    )
-    .init();
+    let config_path = match command()? {
+        Command::Serve { config } => config,
+        Command::Replay(path) => {
+            let entries = replay::read(&path)?;
+            replay::replay(entries, std::io::stdout().lock())?;
+            return Ok(());
+        }
+    };
+    let config = load_config(config_path.as_deref())?;
+    env_logger::Builder::new()
+        .parse_filters(&config.log.level)
+        .init();
 
+    // the spans of statements, see `request_span`, are logged under
+    // `tracing::span` as long as no tracing subscriber is set
     log::info!("logger initialized");
     let (tx, rx) = flume::bounded(2);
+    let (req_tx, req_rx) = flume::unbounded::<Request>();
+    let connections = Arc::new(Connections::default());
+
+    let ttl = config.token_ttl();
+    let tokens = Arc::new(match &config.auth.signing_key {
+        Some(key) => Tokens::new(key.as_bytes(), ttl),
+        None => {
+            log::warn!("there's no signing key, tokens won't be accepted after a restart");
+            Tokens::with_random_key(ttl)
+        }
+    });
+
+    // kept for as long as the server runs, within the configured bounds
+    let audit = AuditLog::new(config.audit_retention());
+
+    let replay_log = config
+        .replay_log
+        .as_deref()
+        .map(ReplayLog::open)
+        .transpose()?;
+    let mut dbs = Databases::open(
+        config.data_dir.clone(),
+        audit.clone(),
+        replay_log,
+        Settings::new(&config),
+    );
+    dbs.default.set_receiver(rx);
+    dbs.default
+        .register_virtual_table("audit_log", audit.clone())?;
+    // users are kept in the default database, whichever one they log in to
+    let users = dbs.default.users();
+    let auth = config.auth.backend;
+    if auth == AuthBackend::Users && users.read().unwrap().is_empty() {
+        match &config.auth.admin_password {
+            Some(password) => users.write().unwrap().create("admin", password, true)?,
+            None => log::warn!(
+                "there are no users, create one from the repl with CREATE USER to be able to log in"
+            ),
+        }
+    }
+
+    for source in &config.sources {
+        let db = dbs.get(source.database.as_deref())?;
+        db.attach_source(&source.table, open_source(&source.url)?)?;
+    }
+
+    let snapshots = dbs.snapshots.clone();
+    let running = connections.clone();
+    std::thread::spawn(move || {
+        loop {
+            match req_rx.recv_timeout(Duration::from_millis(100)) {
+                Ok(Request::Repl { line, done }) => {
+                    let line = line.trim();
+                    if let Err(e) = dbs.default.execute_all(line) {
+                        match e.span().and_then(|span| span.underline(line)) {
+                            Some(underline) => log::error!("{e}\n{underline}"),
+                            None => log::error!("{e}"),
+                        }
+                    }
+                    dbs.changed(None);
+                    _ = done.send(());
+                }
+                Ok(Request::Query {
+                    connection,
+                    span,
+                    user,
+                    client_addr,
+                    database,
+                    sql,
+                    timeout,
+                    respond,
+                }) => {
+                    let _span = span.enter();
+                    let cancel = running.start(connection, &sql);
+                    let session = Session {
+                        user,
+                        connection_id: Some(connection),
+                        client_addr,
+                        ..Default::default()
+                    };
+                    let res = dbs.get(database.as_deref()).and_then(|db| {
+                        db.run_as(session, |db| {
+                            db.run_cancellable(cancel, |db| {
+                                db.run_with_timeout(timeout, |db| db.query(&sql))
+                            })
+                        })
+                    });
+                    running.finish(connection);
+                    dbs.changed(database.as_deref());
+                    _ = respond.send(res);
+                }
+                Ok(Request::Rows {
+                    connection,
+                    span,
+                    user,
+                    client_addr,
+                    database,
+                    sql,
+                    timeout,
+                    respond,
+                }) => {
+                    let _span = span.enter();
+                    let cancel = running.start(connection, &sql);
+                    let session = Session {
+                        user,
+                        connection_id: Some(connection),
+                        client_addr,
+                        ..Default::default()
+                    };
+                    let res = dbs.get(database.as_deref()).and_then(|db| {
+                        db.run_as(session, |db| {
+                            db.run_cancellable(cancel, |db| {
+                                db.run_with_timeout(timeout, |db| db.query_rows(&sql))
+                            })
+                        })
+                    });
+                    running.finish(connection);
+                    // only the selects that change sequences get this far,
+                    // the others running on a snapshot
+                    dbs.changed(database.as_deref());
+                    _ = respond.send(res);
+                }
+                Ok(Request::Import {
+                    connection,
+                    span,
+                    user,
+                    client_addr,
+                    database,
+                    table,
+                    data,
+                    respond,
+                }) => {
+                    let _span = span.enter();
+                    let cancel = running.start(connection, &format!("import into {table}"));
+                    let session = Session {
+                        user,
+                        connection_id: Some(connection),
+                        client_addr,
+                        ..Default::default()
+                    };
+                    let res = dbs.get(database.as_deref()).and_then(|db| {
+                        db.run_as(session, |db| {
+                            db.run_cancellable(cancel, |db| db.import_arrow(&table, &data))
+                        })
+                    });
+                    running.finish(connection);
+                    dbs.changed(database.as_deref());
+                    _ = respond.send(res);
+                }
+                Ok(Request::Load {
+                    connection,
+                    span,
+                    user,
+                    client_addr,
+                    database,
+                    table,
+                    records,
+                    respond,
+                }) => {
+                    let _span = span.enter();
+                    let cancel = running.start(connection, &format!("load into {table}"));
+                    let session = Session {
+                        user,
+                        connection_id: Some(connection),
+                        client_addr,
+                        ..Default::default()
+                    };
+                    let res = dbs.get(database.as_deref()).and_then(|db| {
+                        db.run_as(session, |db| {
+                            db.run_cancellable(cancel, |db| db.load(&table, records))
+                        })
+                    });
+                    running.finish(connection);
+                    dbs.changed(database.as_deref());
+                    _ = respond.send(res);
+                }
+                Ok(Request::Listen {
+                    database,
+                    query,
+                    sender,
+                }) => match (dbs.get(database.as_deref()), query) {
+                    (Ok(db), Query::Listen(channel)) => db.listen(&channel, sender),
+                    (Ok(db), Query::Unlisten(channel)) => db.unlisten(&channel, &sender),
+                    (Err(e), _) => _ = sender.send(e.to_json().to_string()),
+                    _ => {}
+                },
+                Ok(Request::Subscribe {
+                    database,
+                    subscription,
+                }) => dbs.subscribe(&database, subscription),
+                Ok(Request::CreateDatabase { name, respond }) => {
+                    _ = respond.send(dbs.create(&name));
+                }
+                Ok(Request::DeleteDatabase { name, respond }) => {
+                    _ = respond.send(dbs.delete(&name));
+                }
+                Ok(Request::Snapshot { database, respond }) => {
+                    let res = dbs.get(database.as_deref()).map(|db| db.snapshot());
+                    _ = respond.send(
+                        res.map(|snapshot| dbs.snapshots.publish(database.as_deref(), snapshot)),
+                    );
+                }
+                Ok(Request::Reload(settings)) => dbs.reload(settings),
+                Ok(Request::Flush { done }) => {
+                    dbs.autosave(true);
+                    _ = done.send(());
+                }
+                Err(flume::RecvTimeoutError::Timeout) => {}
+                Err(flume::RecvTimeoutError::Disconnected) => break,
+            }
+            dbs.apply_sources();
+            dbs.autosave(false);
+
+            // pick up new subscribers even while no statements are coming in
+            for db in dbs.all() {
+                if let Err(e) = db.recv_senders() {
+                    log::error!("{e}");
+                }
+                db.flush_subscriptions();
+            }
+        }
+    });
 
+    let repl_tx = req_tx.clone();
+    let repl_connections = connections.clone();
     std::thread::spawn(move || {
         let res = move || -> Result<()> {
             let mut rl = rustyline::DefaultEditor::new()?;
 
-            let mut db = Database::new();
-            db.set_receiver(rx);
-
             loop {
                 match rl.readline(">> ") {
                     Ok(line) => {
-                        if let Err(e) = db.execute_all(line.trim()) {
-                            log::error!("{e}");
+                        if let Some(ids) = kill_statements(&line) {
+                            for id in ids {
+                                if let Err(e) = repl_connections.kill(id, None, true) {
+                                    log::error!("{e}");
+                                }
+                            }
                             continue;
                         }
+
+                        let (done_tx, done_rx) = flume::bounded(1);
+                        repl_tx.send(Request::Repl {
+                            line,
+                            done: done_tx,
+                        })?;
+                        done_rx.recv()?;
                     }
                     Err(
                         rustyline::error::ReadlineError::Eof
@@ -64,92 +991,1538 @@ async fn main() -> Result<()> {
         anyhow::Ok(())
     });
 
-    HttpServer::new(move || {
+    // changed later through `/admin/acl`, or by reloading the config
+    let acl = Arc::new(RwLock::new(config.acl.clone()));
+    let limits = Arc::new(RwLock::new(config.limits.clone()));
+    #[cfg(unix)]
+    actix_web::rt::spawn(reload_on_hangup(
+        config_path,
+        acl.clone(),
+        limits.clone(),
+        audit.clone(),
+        req_tx.clone(),
+    ));
+    let cursors = Arc::new(Cursors::default());
+    let (sweeping, idle) = (cursors.clone(), limits.clone());
+    actix_web::rt::spawn(async move {
+        let mut interval = actix_web::rt::time::interval(CURSOR_SWEEP_INTERVAL);
+        loop {
+            interval.tick().await;
+            sweeping.sweep(idle.read().unwrap().cursor_idle());
+        }
+    });
+
+    // requiring clients to present a certificate too once there's a CA to
+    // check theirs with
+    let tls = match &config.tls {
+        Some(tls) => Some(tls::server_config(
+            &tls.cert,
+            &tls.key,
+            tls.client_ca.as_deref(),
+        )?),
+        None => None,
+    };
+
+    let flush = req_tx.clone();
+
+    let server = HttpServer::new(move || {
+        let allowed = acl.clone();
+        let refused = audit.clone();
         App::new()
-            .app_data(web::Data::new(AppState { sender: tx.clone() }))
+            // before authenticating, or even routing
+            .wrap_fn(move |req, srv| {
+                let peer = req.peer_addr().map(|addr| addr.ip());
+                if allowed.read().unwrap().allows(peer) {
+                    Either::Left(srv.call(req))
+                } else {
+                    refused.record(
+                        AuditKind::AddressRefused,
+                        None,
+                        peer.map(|ip| ip.to_string()).as_deref(),
+                        format!("{} {}", req.method(), req.path()),
+                    );
+                    Either::Right(future::ready(Err(actix_web::error::ErrorForbidden(
+                        "address not allowed",
+                    ))))
+                }
+            })
+            .app_data(web::Data::new(AppState {
+                sender: tx.clone(),
+                requests: req_tx.clone(),
+                connections: connections.clone(),
+                tokens: tokens.clone(),
+                users: users.clone(),
+                acl: acl.clone(),
+                limits: limits.clone(),
+                cursors: cursors.clone(),
+                snapshots: snapshots.clone(),
+                audit: audit.clone(),
+                auth,
+            }))
+            .service(ui)
             .service(index)
+            .service(subscribe_to_database)
+            .service(run_query)
+            .service(stream_rows)
+            .service(run_database_query)
+            .service(stream_database_rows)
+            .service(fetch_cursor)
+            .service(import_table)
+            .service(import_database_table)
+            .service(load_table)
+            .service(load_database_table)
+            .service(create_database)
+            .service(delete_database)
+            .service(list_connections)
+            .service(login)
+            .service(logout)
+            .service(get_acl)
+            .service(set_acl)
     })
-    .bind(("127.0.0.1", 8080))?
+    .on_connect(client_certificate);
+
+    let listen = config.listen.as_str();
+    let res = match tls {
+        Some(tls) => server.bind_rustls_021(listen, tls)?,
+        None => server.bind(listen)?,
+    }
     .run()
-    .await
-    .map_err(|e| anyhow::anyhow!(e))
+    .await;
+
+    let (done_tx, done_rx) = flume::bounded(1);
+    if flush.send(Request::Flush { done: done_tx }).is_ok() {
+        _ = done_rx.recv_async().await;
+    }
+    res.map_err(|e| anyhow::anyhow!(e))
 }
 
-#[derive(Debug, Clone)]
-struct AppState {
-    sender: Sender<(String, Sender<String>)>, // table name, and the sender
+enum Command {
+    // with the config at the path, if any
+    Serve { config: Option<PathBuf> },
+    // the statements recorded in the file, see `Config::replay_log`
+    Replay(PathBuf),
 }
 
-struct Ws {
-    receiver: Receiver<String>,
-    start: Instant,
+// serving with `--config <path>`, or else `socketdb.toml` if there is one, or
+// executing a replay file again with `--replay <path>`
+fn command() -> Result<Command> {
+    let mut args = std::env::args_os().skip(1);
+    match (args.next(), args.next(), args.next()) {
+        (None, ..) => Ok(Command::Serve {
+            config: Some(PathBuf::from("socketdb.toml")).filter(|p| p.exists()),
+        }),
+        (Some(flag), Some(path), None) if flag == "--config" => Ok(Command::Serve {
+            config: Some(path.into()),
+        }),
+        (Some(flag), Some(path), None) if flag == "--replay" => Ok(Command::Replay(path.into())),
+        _ => Err(anyhow::anyhow!(
+            "usage: socketdb [--config <path> | --replay <path>]"
+        )),
+    }
 }
 
-impl Actor for Ws {
-    type Context = ws::WebsocketContext<Self>;
+// the feed at `url`, see `SourceConfig`
+fn open_source(url: &str) -> Result<Box<dyn Source>> {
+    if let Some(addr) = url.strip_prefix("tcp://") {
+        let stream = std::net::TcpStream::connect(addr)?;
+        return Ok(Box::new(JsonLines::new(std::io::BufReader::new(stream))));
+    }
+    #[cfg(feature = "client")]
+    if url.starts_with("ws://") {
+        return Ok(Box::new(socketdb::client::JsonWebSocket::connect(url)));
+    }
 
-    fn started(&mut self, ctx: &mut Self::Context) {
-        ctx.run_interval(Duration::from_secs(1), |act, ctx| {
-            if act.start.elapsed() > Duration::from_secs(60 * 60) {
-                ctx.stop();
-                return;
-            }
-            ctx.ping(b"");
+    Err(
+        socketdb::Error::Config(format!("source {url:?}, which isn't a tcp:// or ws:// url"))
+            .into(),
+    )
+}
 
-            if let Ok(r) = act.receiver.try_recv() {
-                ctx.text(r);
-            }
-        });
+fn load_config(path: Option<&Path>) -> socketdb::Result<Config> {
+    match path {
+        Some(path) => Config::load(path),
+        None => {
+            let mut config = Config::default();
+            config.apply_env()?;
+            Ok(config)
+        }
     }
 }
 
-impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for Ws {
-    fn handle(&mut self, item: Result<ws::Message, ws::ProtocolError>, ctx: &mut Self::Context) {
-        if let Ok(ws::Message::Ping(msg)) = item {
-            ctx.pong(&msg)
+// rereads the config on SIGHUP, applying what can change while running: the
+// acl, limits, autosave and audit retention. The rest takes a restart.
+#[cfg(unix)]
+async fn reload_on_hangup(
+    path: Option<PathBuf>,
+    acl: Arc<RwLock<Acl>>,
+    limits: Arc<RwLock<LimitsConfig>>,
+    audit: AuditLog,
+    requests: Sender<Request>,
+) -> std::io::Result<()> {
+    use actix_web::rt::signal::unix::{signal, SignalKind};
+
+    let mut hangup = signal(SignalKind::hangup())?;
+    while hangup.recv().await.is_some() {
+        match load_config(path.as_deref()) {
+            Ok(config) => {
+                *acl.write().unwrap() = config.acl.clone();
+                *limits.write().unwrap() = config.limits.clone();
+                audit.set_retention(config.audit_retention());
+                _ = requests.send(Request::Reload(Settings::new(&config)));
+                log::info!("reloaded the config");
+            }
+            Err(e) => log::error!("{e}, keeping the config as it was"),
         }
     }
-}
 
-#[derive(Deserialize)]
-struct TableName {
-    table: String,
+    Ok(())
 }
 
-#[get("/ws")]
-async fn index(
-    req: HttpRequest,
-    query: web::Query<TableName>,
-    state: web::Data<AppState>,
-    stream: web::Payload,
-) -> Result<HttpResponse, Error> {
-    let username = req
-        .headers()
-        .get("ws-username")
-        .and_then(|v| v.to_str().ok())
-        .unwrap_or_default();
-    let password = req
-        .headers()
-        .get("ws-password")
-        .and_then(|v| v.to_str().ok())
-        .unwrap_or_default();
+// the common name in the certificate a client presented over TLS, as the
+// user it authenticates, see `authenticate`
+struct ClientCertificate(String);
 
-    if username != "abhizer" && password != "passwd" {
-        let resp = HttpResponse::new(StatusCode::UNAUTHORIZED);
-        let resp = resp.set_body("invalid username or password".boxed());
-        return Ok(resp);
+fn client_certificate(conn: &dyn Any, data: &mut Extensions) {
+    let Some(stream) = conn.downcast_ref::<TlsStream<TcpStream>>() else {
+        return;
+    };
+    if let Some(name) = stream
+        .get_ref()
+        .1
+        .peer_certificates()
+        .and_then(tls::common_name)
+    {
+        data.insert(ClientCertificate(name));
     }
+}
 
-    let (tx, rx) = flume::bounded(2);
+#[derive(Clone)]
+struct AppState {
+    sender: Sender<Subscription>,
+    requests: Sender<Request>,
+    connections: Arc<Connections>,
+    tokens: Arc<Tokens>,
+    users: Arc<RwLock<Users>>,
+    acl: Arc<RwLock<Acl>>,
+    limits: Arc<RwLock<LimitsConfig>>,
+    cursors: Arc<Cursors>,
+    snapshots: Arc<Snapshots>,
+    audit: AuditLog,
+    auth: AuthBackend,
+}
 
-    state.sender.send((query.table.clone(), tx)).unwrap();
+impl AppState {
+    // of the responses to `user`, with no more rows than a page of `page`
+    // when it asks for one
+    fn response_limits(&self, user: Option<&str>, page: &Page) -> ResponseLimits {
+        let mut limits = self.limits.read().unwrap().response_limits(user);
+        if let Some(size) = page.page_size.filter(|&n| n > 0) {
+            limits.max_rows = Some(limits.max_rows.map_or(size, |max| max.min(size)));
+        }
+        limits
+    }
 
-    ws::start(
+    // leaves out of `view` what's past the limits on the responses to `user`,
+    // or past its page, keeping it behind a cursor
+    fn limit(&self, view: &mut View, user: Option<&str>, page: &Page) {
+        let limits = self.response_limits(user, page);
+        if let Some(rest) = view.truncate(limits.max_rows, limits.max_bytes) {
+            view.set_cursor(self.open_cursor(user, rest));
+        }
+    }
+
+    // keeps `rows` for `user` to fetch, by the cursor returned
+    fn open_cursor(&self, user: Option<&str>, rows: View) -> String {
+        let ttl = self.limits.read().unwrap().cursor_idle();
+        self.cursors.open(user, rows, ttl)
+    }
+
+    // something `user` did, or tried to do, through `req`
+    // anyone is without authentication, when there's no `user`
+    fn is_superuser(&self, user: Option<&str>) -> bool {
+        let Some(user) = user else {
+            return true;
+        };
+        self.users
+            .read()
+            .unwrap()
+            .get(user)
+            .is_some_and(|u| u.superuser)
+    }
+
+    fn audit(&self, req: &HttpRequest, kind: AuditKind, user: Option<&str>, action: String) {
+        let addr = req.peer_addr().map(|addr| addr.ip().to_string());
+        self.audit.record(kind, user, addr.as_deref(), action);
+    }
+}
+
+struct Ws {
+    receiver: Receiver<String>,
+    // for `LISTEN`, without keeping the subscription open once its table is
+    // dropped
+    sender: WeakSender<String>,
+    requests: Sender<Request>,
+    database: Option<String>,
+    // closes the subscription once killed
+    connection: Registered,
+    start: Instant,
+    // when the client last sent anything, a pong included
+    last_seen: Instant,
+    // see `LimitsConfig::session_idle` and `session_max`
+    idle_timeout: Option<Duration>,
+    max_age: Option<Duration>,
+    // whether the last message was an error, rather than a change event
+    refused: bool,
+    flow: Option<Credits>,
+}
+
+// with `?credits=n`, at most n events are sent that the client hasn't
+// acknowledged yet, by sending `ack` (for all of them) or `ack <count>`
+struct Credits {
+    window: usize,
+    in_flight: usize,
+    queued: VecDeque<String>,
+}
+
+// for subscriptions to tables or columns that don't exist, after the error
+// is sent
+const SUBSCRIPTION_REFUSED: u16 = 4004;
+// for clients that fall this far behind on acknowledging events
+const MAX_QUEUED: usize = 1024;
+const TOO_FAR_BEHIND: u16 = 4008;
+
+impl Ws {
+    fn receive(&mut self, ctx: &mut ws::WebsocketContext<Self>) {
+        loop {
+            match self.receiver.try_recv() {
+                Ok(r) => {
+                    // refusals come from `Error::to_json`, which sorts its
+                    // keys, where change events start with `seq`
+                    self.refused = r.starts_with(r#"{"code""#);
+                    match self.flow.as_mut() {
+                        Some(flow) if !self.refused => flow.queued.push_back(r),
+                        _ => ctx.text(r),
+                    }
+                }
+                Err(flume::TryRecvError::Empty) => break,
+                // the subscription was refused, or the table was dropped
+                Err(flume::TryRecvError::Disconnected) => {
+                    // there's nothing left to wait for acknowledgements of
+                    for r in self.flow.iter_mut().flat_map(|f| f.queued.drain(..)) {
+                        ctx.text(r);
+                    }
+
+                    let reason = match self.refused {
+                        true => ws::CloseReason {
+                            code: ws::CloseCode::Other(SUBSCRIPTION_REFUSED),
+                            description: Some("subscription refused".to_owned()),
+                        },
+                        false => ws::CloseCode::Normal.into(),
+                    };
+                    ctx.close(Some(reason));
+                    ctx.stop();
+                    return;
+                }
+            }
+        }
+
+        self.send_queued(ctx);
+    }
+
+    fn send_queued(&mut self, ctx: &mut ws::WebsocketContext<Self>) {
+        let Some(flow) = self.flow.as_mut() else {
+            return;
+        };
+
+        while flow.in_flight < flow.window {
+            let Some(r) = flow.queued.pop_front() else {
+                break;
+            };
+            flow.in_flight += 1;
+            ctx.text(r);
+        }
+
+        if flow.queued.len() > MAX_QUEUED {
+            ctx.close(Some(ws::CloseReason {
+                code: ws::CloseCode::Other(TOO_FAR_BEHIND),
+                description: Some("too many events left unacknowledged".to_owned()),
+            }));
+            ctx.stop();
+        }
+    }
+}
+
+impl Actor for Ws {
+    type Context = ws::WebsocketContext<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        ctx.run_interval(Duration::from_secs(1), |act, ctx| {
+            if act.max_age.is_some_and(|max| act.start.elapsed() > max) {
+                ctx.stop();
+                return;
+            }
+            // abandoned without closing, like by a client that went away
+            if act
+                .idle_timeout
+                .is_some_and(|idle| act.last_seen.elapsed() > idle)
+            {
+                log::debug!("closing a subscription idle since {:?}", act.last_seen);
+                ctx.close(Some(ws::CloseCode::Away.into()));
+                ctx.stop();
+                return;
+            }
+            if act.connection.cancel.is_cancelled() {
+                ctx.close(Some(ws::CloseCode::Away.into()));
+                ctx.stop();
+                return;
+            }
+            ctx.ping(b"");
+
+            act.receive(ctx);
+        });
+    }
+}
+
+impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for Ws {
+    fn handle(&mut self, item: Result<ws::Message, ws::ProtocolError>, ctx: &mut Self::Context) {
+        if item.is_ok() {
+            self.last_seen = Instant::now();
+        }
+        match item {
+            Ok(ws::Message::Ping(msg)) => ctx.pong(&msg),
+            Ok(ws::Message::Text(text)) => {
+                let text = text.trim();
+                if text == "ack" || text.starts_with("ack ") {
+                    let Some(flow) = self.flow.as_mut() else {
+                        return;
+                    };
+
+                    let acked = match text.split_once(' ') {
+                        Some((_, count)) => count.trim().parse().unwrap_or(0),
+                        None => flow.in_flight,
+                    };
+                    flow.in_flight = flow.in_flight.saturating_sub(acked);
+                    self.receive(ctx);
+                    return;
+                }
+
+                match parser::parse_all(
+                    text,
+                    Default::default(),
+                    Default::default(),
+                    &Session::default(),
+                )
+                .map(|mut q| (q.pop(), q.pop()))
+                {
+                    Ok((Some(query @ (Query::Listen(_) | Query::Unlisten(_))), None)) => {
+                        if let Some(sender) = self.sender.upgrade() {
+                            _ = self.requests.send(Request::Listen {
+                                database: self.database.clone(),
+                                query,
+                                sender,
+                            });
+                        }
+                    }
+                    Ok(_) => ctx.text(
+                        socketdb::Error::Unsupported(format!(
+                            "message {text:?} over a subscription, expected ack, LISTEN or UNLISTEN"
+                        ))
+                        .to_json()
+                        .to_string(),
+                    ),
+                    Err(e) => ctx.text(e.to_json().to_string()),
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct TableName {
+    // in the path instead, for `/ws/{database}/{table}`
+    table: Option<String>,
+    resume: Option<u64>,
+    // subscribe to a table before it is created
+    #[serde(default)]
+    wait: bool,
+    // comma separated, to only receive some of the columns
+    columns: Option<String>,
+    // at most one event per this many milliseconds
+    throttle: Option<u64>,
+    // only every nth event
+    every: Option<u64>,
+    // events sent ahead of acknowledgements, see `Credits`
+    credits: Option<usize>,
+}
+
+// `?page_size=` of a request for rows, which are sent a page at a time,
+// each with a cursor to the next, see `fetch_cursor`
+#[derive(Deserialize, Default)]
+struct Page {
+    page_size: Option<usize>,
+}
+
+// who the statements of a request are executed for, see `Database::run_as`:
+// the user its bearer token (see `bearer` and `websocket_token`) was issued
+// to, the one named by the certificate its connection was made with, or else
+// the one whose credentials are in its `ws-username` and `ws-password`
+// headers. Nobody without authentication.
+async fn authenticate(req: &HttpRequest, state: &AppState) -> Result<Option<String>, HttpResponse> {
+    if state.auth == AuthBackend::None {
+        return Ok(None);
+    }
+    let failed = |user: Option<&str>, reason: &str| {
+        let action = format!("{} {} {reason}", req.method(), req.path());
+        state.audit(req, AuditKind::LoginFailed, user, action);
+    };
+
+    if let Some(token) = bearer(req).or_else(|| websocket_token(req)) {
+        return state.tokens.verify(token).map(Some).map_err(|e| {
+            failed(None, &format!("with an {e}"));
+            error_response(&e)
+        });
+    }
+    if let Some(ClientCertificate(name)) = req.conn_data() {
+        let users = state.users.read().unwrap();
+        return users
+            .get(name)
+            .map(|u| Some(u.name.clone()))
+            .ok_or_else(|| {
+                failed(Some(name), "with a certificate for no user");
+                unauthorized()
+            });
+    }
+
+    let header = |name| {
+        req.headers()
+            .get(name)
+            .and_then(|v| v.to_str().ok())
+            .unwrap_or_default()
+            .to_owned()
+    };
+    let username = header("ws-username");
+    match verify(state, username.clone(), header("ws-password")).await {
+        Some(user) => Ok(Some(user)),
+        None => {
+            failed(Some(&username), "with a wrong username or password");
+            Err(unauthorized())
+        }
+    }
+}
+
+// `authenticate`, for what only superusers may do, like managing databases.
+// Without authentication, anyone may, as in the repl.
+async fn authenticate_superuser(
+    req: &HttpRequest,
+    state: &AppState,
+) -> Result<Option<String>, HttpResponse> {
+    let user = authenticate(req, state).await?;
+    let Some(name) = user.as_deref() else {
+        return Ok(None);
+    };
+    if state.is_superuser(Some(name)) {
+        return Ok(user);
+    }
+
+    let action = format!("{} {} as {name}, not a superuser", req.method(), req.path());
+    state.audit(req, AuditKind::PermissionDenied, Some(name), action.clone());
+    Err(error_response(&socketdb::Error::PermissionDenied(action)))
+}
+
+// the name of the user, as it was created, if `password` is theirs
+async fn verify(state: &AppState, username: String, password: String) -> Option<String> {
+    let users = state.users.clone();
+    // hashing takes long enough to hold up other requests
+    web::block(move || {
+        let users = users.read().unwrap();
+        users.verify(&username, &password).map(|u| u.name.clone())
+    })
+    .await
+    .ok()
+    .flatten()
+}
+
+// the token of an `Authorization: Bearer <token>` header
+fn bearer(req: &HttpRequest) -> Option<&str> {
+    req.headers()
+        .get(header::AUTHORIZATION)?
+        .to_str()
+        .ok()?
+        .strip_prefix("Bearer ")
+        .map(str::trim)
+}
+
+// the `token` query parameter of a websocket upgrade, since browsers can't set
+// the headers of those
+fn websocket_token(req: &HttpRequest) -> Option<&str> {
+    if !req.head().upgrade() {
+        return None;
+    }
+    req.query_string()
+        .split('&')
+        .find_map(|param| param.strip_prefix("token="))
+}
+
+// the `statement-timeout` header, in milliseconds, limits how long each
+// statement of a request may take, though not for longer than the statement
+// timeout of the database
+fn statement_timeout(req: &HttpRequest) -> Result<Option<Duration>, HttpResponse> {
+    let Some(value) = req.headers().get("statement-timeout") else {
+        return Ok(None);
+    };
+
+    match value.to_str().ok().and_then(|v| v.parse().ok()) {
+        Some(ms) => Ok(Some(Duration::from_millis(ms))),
+        None => Err(HttpResponse::BadRequest().json(
+            socketdb::Error::InvalidQuery(
+                "statement-timeout header is expected to be milliseconds".to_owned(),
+            )
+            .to_json(),
+        )),
+    }
+}
+
+// the span of the statements a request runs, carrying on the trace its w3c
+// `traceparent` header is part of, if any
+fn request_span(req: &HttpRequest, database: Option<&str>) -> tracing::Span {
+    let span = tracing::info_span!(
+        "request",
+        path = req.path(),
+        database,
+        trace_id = tracing::field::Empty,
+        parent_id = tracing::field::Empty,
+    );
+
+    let parent = req
+        .headers()
+        .get("traceparent")
+        .and_then(|v| v.to_str().ok())
+        .and_then(traceparent);
+    if let Some((trace_id, parent_id)) = parent {
+        span.record("trace_id", trace_id);
+        span.record("parent_id", parent_id);
+    }
+
+    span
+}
+
+// the trace and parent span ids of `version-trace_id-parent_id-flags`
+fn traceparent(value: &str) -> Option<(&str, &str)> {
+    let mut parts = value.trim().split('-');
+    let (_version, trace_id, parent_id, _flags) =
+        (parts.next()?, parts.next()?, parts.next()?, parts.next()?);
+
+    let hex = |id: &str, len| id.len() == len && id.bytes().all(|b| b.is_ascii_hexdigit());
+    (hex(trace_id, 32) && hex(parent_id, 16)).then_some((trace_id, parent_id))
+}
+
+fn unauthorized() -> HttpResponse {
+    let resp = HttpResponse::new(StatusCode::UNAUTHORIZED);
+    resp.set_body("invalid username or password".boxed())
+}
+
+const UI: &str = include_str!("ui/index.html");
+
+/// A page to log in, pick a table and watch its rows change, over `/query`
+/// and `/ws`.
+#[get("/ui")]
+async fn ui() -> HttpResponse {
+    HttpResponse::Ok()
+        .content_type("text/html; charset=utf-8")
+        .body(UI)
+}
+
+#[get("/ws")]
+async fn index(
+    req: HttpRequest,
+    query: web::Query<TableName>,
+    state: web::Data<AppState>,
+    stream: web::Payload,
+) -> Result<HttpResponse, Error> {
+    let Some(table) = query.table.clone() else {
+        return Ok(HttpResponse::BadRequest().json(
+            socketdb::Error::InvalidQuery("subscription without a table".to_owned()).to_json(),
+        ));
+    };
+
+    subscribe(req, None, &table, &query, &state, stream).await
+}
+
+#[get("/ws/{database}/{table}")]
+async fn subscribe_to_database(
+    req: HttpRequest,
+    path: web::Path<(String, String)>,
+    query: web::Query<TableName>,
+    state: web::Data<AppState>,
+    stream: web::Payload,
+) -> Result<HttpResponse, Error> {
+    let (database, table) = path.into_inner();
+    subscribe(req, Some(database), &table, &query, &state, stream).await
+}
+
+async fn subscribe(
+    req: HttpRequest,
+    database: Option<String>,
+    table: &str,
+    query: &TableName,
+    state: &AppState,
+    stream: web::Payload,
+) -> Result<HttpResponse, Error> {
+    let user = match authenticate(&req, state).await {
+        Ok(user) => user,
+        Err(resp) => return Ok(resp),
+    };
+
+    let (tx, rx) = flume::bounded(2);
+    let weak = tx.downgrade();
+
+    let subscription = Subscription {
+        table: table_key(table),
+        sender: tx,
+        span: request_span(&req, database.as_deref()),
+        user: user.clone(),
+        resume_from: query.resume,
+        wait_for_table: query.wait,
+        columns: query.columns.as_ref().map(|c| {
+            c.split(',')
+                .map(|c| c.trim().to_owned())
+                .filter(|c| !c.is_empty())
+                .collect()
+        }),
+        throttle: query.throttle.map(Duration::from_millis),
+        sample_every: query.every,
+    };
+    match database.clone() {
+        None => state.sender.send(subscription).unwrap(),
+        Some(database) => state
+            .requests
+            .send(Request::Subscribe {
+                database,
+                subscription,
+            })
+            .map_err(actix_web::error::ErrorServiceUnavailable)?,
+    }
+
+    let (idle_timeout, max_age) = {
+        let limits = state.limits.read().unwrap();
+        (limits.session_idle(), limits.session_max())
+    };
+    ws::start(
         Ws {
+            sender: weak,
             receiver: rx,
+            requests: state.requests.clone(),
+            connection: state
+                .connections
+                .register("subscription", database.clone(), user),
+            database,
             start: Instant::now(),
+            last_seen: Instant::now(),
+            idle_timeout,
+            max_age,
+            refused: false,
+            flow: query.credits.filter(|&n| n > 0).map(|window| Credits {
+                window,
+                in_flight: 0,
+                queued: VecDeque::new(),
+            }),
         },
         &req,
         stream,
     )
 }
+
+/// Runs the statements in the body, answering with the views they produced,
+/// as json unless the `Accept` header asks for csv, html, markdown, plain text
+/// tables or MessagePack. With `?page_size=n`, each view has at most `n` rows,
+/// and a cursor to page through the rest with.
+#[post("/query")]
+async fn run_query(
+    req: HttpRequest,
+    page: web::Query<Page>,
+    body: String,
+    state: web::Data<AppState>,
+) -> Result<HttpResponse, Error> {
+    query(req, None, &page, body, &state).await
+}
+
+#[post("/databases/{database}/query")]
+async fn run_database_query(
+    req: HttpRequest,
+    database: web::Path<String>,
+    page: web::Query<Page>,
+    body: String,
+    state: web::Data<AppState>,
+) -> Result<HttpResponse, Error> {
+    query(req, Some(database.into_inner()), &page, body, &state).await
+}
+
+async fn query(
+    req: HttpRequest,
+    database: Option<String>,
+    page: &Page,
+    body: String,
+    state: &AppState,
+) -> Result<HttpResponse, Error> {
+    let user = match authenticate(&req, state).await {
+        Ok(user) => user,
+        Err(resp) => return Ok(resp),
+    };
+    let timeout = match statement_timeout(&req) {
+        Ok(timeout) => timeout,
+        Err(resp) => return Ok(resp),
+    };
+
+    if let Some(ids) = kill_statements(&body) {
+        let superuser = state.is_superuser(user.as_deref());
+        for id in ids {
+            if let Err(e) = state.connections.kill(id, user.as_deref(), superuser) {
+                if let socketdb::Error::PermissionDenied(action) = &e {
+                    let kind = AuditKind::PermissionDenied;
+                    state.audit(&req, kind, user.as_deref(), action.clone());
+                }
+                return Ok(error_response(&e));
+            }
+            state.audit(
+                &req,
+                AuditKind::Admin,
+                user.as_deref(),
+                format!("kill {id}"),
+            );
+        }
+        return Ok(HttpResponse::Ok().json(Vec::<View>::new()));
+    }
+
+    let span = request_span(&req, database.as_deref());
+    let connection = state
+        .connections
+        .register("query", database.clone(), user.clone());
+    let client_addr = req.peer_addr().map(|addr| addr.ip().to_string());
+    let session = Session {
+        user: user.clone(),
+        connection_id: Some(connection.id),
+        client_addr: client_addr.clone(),
+        ..Default::default()
+    };
+    let read = read(
+        state,
+        database.clone(),
+        session,
+        &body,
+        timeout,
+        span.clone(),
+        Database::query,
+    );
+    let res = match read.await? {
+        Some(res) => res,
+        None => {
+            let (tx, rx) = flume::bounded(1);
+            state
+                .requests
+                .send(Request::Query {
+                    connection: connection.id,
+                    span,
+                    user: user.clone(),
+                    client_addr,
+                    database,
+                    sql: body,
+                    timeout,
+                    respond: tx,
+                })
+                .map_err(actix_web::error::ErrorServiceUnavailable)?;
+            rx.recv_async()
+                .await
+                .map_err(actix_web::error::ErrorServiceUnavailable)?
+        }
+    };
+
+    match res {
+        Ok(mut views) => {
+            for view in &mut views {
+                state.limit(view, user.as_deref(), page);
+            }
+            Ok(views_response(&req, &views))
+        }
+        Err(e) => Ok(error_response(&e)),
+    }
+}
+
+// runs `sql` with `run` on a copy of the latest snapshot of `database`, see
+// `Snapshots`, asking the database thread for one if there's none, so that it
+// doesn't wait for the statements before it. Unless every statement of `sql`
+// only reads, it's left for the database thread to run instead, giving back
+// `None`.
+async fn read<T: Send + 'static>(
+    state: &AppState,
+    database: Option<String>,
+    session: Session,
+    sql: &str,
+    timeout: Option<Duration>,
+    span: tracing::Span,
+    run: fn(&mut Database, &str) -> socketdb::Result<T>,
+) -> Result<Option<socketdb::Result<T>>, Error> {
+    // in the default dialect, to tell before there's a snapshot to parse it
+    // with, which checks it again in its own
+    let reads_only = parser::parse_all(sql, Default::default(), Default::default(), &session)
+        .is_ok_and(|queries| queries.iter().all(Query::is_read_only));
+    if !reads_only {
+        return Ok(None);
+    }
+
+    if !state.snapshots.has(database.as_deref()) {
+        let (tx, rx) = flume::bounded(1);
+        state
+            .requests
+            .send(Request::Snapshot {
+                database: database.clone(),
+                respond: tx,
+            })
+            .map_err(actix_web::error::ErrorServiceUnavailable)?;
+        match rx.recv_async().await {
+            Ok(Ok(())) => {}
+            Ok(Err(e)) => return Ok(Some(Err(e))),
+            Err(e) => return Err(actix_web::error::ErrorServiceUnavailable(e)),
+        }
+    }
+
+    let snapshots = state.snapshots.clone();
+    let connections = state.connections.clone();
+    let sql = sql.to_owned();
+    let res = web::block(move || {
+        let _span = span.enter();
+        // changed again since the snapshot was taken
+        let (snapshot, mut db) = snapshots.take(database.as_deref())?;
+        let res = match db.is_read_only(&sql) {
+            Ok(true) => {
+                let connection = session.connection_id.unwrap_or_default();
+                let cancel = connections.start(connection, &sql);
+                let res = db.run_as(session, |db| {
+                    db.run_cancellable(cancel, |db| {
+                        db.run_with_timeout(timeout, |db| run(db, &sql))
+                    })
+                });
+                connections.finish(connection);
+                Some(res)
+            }
+            _ => None,
+        };
+        snapshots.give_back(database.as_deref(), &snapshot, db);
+        res
+    })
+    .await?;
+
+    Ok(res)
+}
+
+/// The rows left out of a view sent back by `/query` or `/query/rows` to keep
+/// it within the limits on responses or its page, by the cursor it came with.
+/// They're a view of their own, as sent by `/query`, which is limited in turn,
+/// to the next page with `?page_size=n`, with a cursor to the rows after. The
+/// rows are those of the select when it ran, however the table has changed
+/// since.
+#[get("/query/cursors/{cursor}")]
+async fn fetch_cursor(
+    req: HttpRequest,
+    cursor: web::Path<String>,
+    page: web::Query<Page>,
+    state: web::Data<AppState>,
+) -> Result<HttpResponse, Error> {
+    let user = match authenticate(&req, &state).await {
+        Ok(user) => user,
+        Err(resp) => return Ok(resp),
+    };
+
+    let ttl = state.limits.read().unwrap().cursor_idle();
+    match state.cursors.fetch(&cursor, user.as_deref(), ttl) {
+        Ok(mut view) => {
+            state.limit(&mut view, user.as_deref(), &page);
+            Ok(views_response(&req, &[view]))
+        }
+        Err(e) => Ok(error_response(&e)),
+    }
+}
+
+const MESSAGEPACK: &str = "application/msgpack";
+
+// `views` as the first media type in the `Accept` header of `req` they can be
+// sent as, see `OutputMode::from_media_type`, or else as json. Those that are
+// text are separated by a blank line. The cursors of those that were
+// truncated are in the `cursor` header too, since only json and MessagePack
+// carry them along with the rows.
+fn views_response(req: &HttpRequest, views: &[View]) -> HttpResponse {
+    let mut resp = HttpResponse::Ok();
+    let cursors: Vec<&str> = views.iter().filter_map(View::cursor).collect();
+    if !cursors.is_empty() {
+        resp.insert_header(("cursor", cursors.join(", ")));
+    }
+
+    let accept = req
+        .headers()
+        .get(header::ACCEPT)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or_default();
+    let media_types = accept
+        .split(',')
+        .filter_map(|m| m.split(';').next())
+        .map(str::trim);
+
+    for media_type in media_types {
+        if media_type == MESSAGEPACK || media_type == "application/x-msgpack" {
+            let body = rmp_serde::to_vec_named(views).expect("views serialize");
+            return resp.content_type(MESSAGEPACK).body(body);
+        }
+        match OutputMode::from_media_type(media_type) {
+            Some(OutputMode::Json) => break,
+            Some(mode) => {
+                let body: Vec<String> = views.iter().map(|v| v.render(mode)).collect();
+                return resp.content_type(mode.media_type()).body(body.join("\n"));
+            }
+            None => {}
+        }
+    }
+
+    resp.json(views)
+}
+
+/// Streams the rows of a select as newline delimited json: the column names
+/// first, then one array per row. With an `Accept` header asking for
+/// `application/vnd.apache.arrow.stream` (or `.file`), they're sent as Arrow
+/// IPC instead. Rows past the limits on responses are left out, ending the
+/// json with `{"truncated": true, "cursor": ...}`, and the cursor to fetch
+/// them through `/query/cursors` is in the `cursor` header either way. So are
+/// the rows past the first `n` with `?page_size=n`.
+#[post("/query/rows")]
+async fn stream_rows(
+    req: HttpRequest,
+    page: web::Query<Page>,
+    body: String,
+    state: web::Data<AppState>,
+) -> Result<HttpResponse, Error> {
+    rows(req, None, &page, body, &state).await
+}
+
+/// Like `/query/rows`, for a database created through `/admin/databases`.
+#[post("/databases/{database}/query/rows")]
+async fn stream_database_rows(
+    req: HttpRequest,
+    database: web::Path<String>,
+    page: web::Query<Page>,
+    body: String,
+    state: web::Data<AppState>,
+) -> Result<HttpResponse, Error> {
+    rows(req, Some(database.into_inner()), &page, body, &state).await
+}
+
+async fn rows(
+    req: HttpRequest,
+    database: Option<String>,
+    page: &Page,
+    body: String,
+    state: &AppState,
+) -> Result<HttpResponse, Error> {
+    let user = match authenticate(&req, state).await {
+        Ok(user) => user,
+        Err(resp) => return Ok(resp),
+    };
+    let timeout = match statement_timeout(&req) {
+        Ok(timeout) => timeout,
+        Err(resp) => return Ok(resp),
+    };
+
+    let span = request_span(&req, database.as_deref());
+    let connection = state
+        .connections
+        .register("rows", database.clone(), user.clone());
+    let client_addr = req.peer_addr().map(|addr| addr.ip().to_string());
+    let session = Session {
+        user: user.clone(),
+        connection_id: Some(connection.id),
+        client_addr: client_addr.clone(),
+        ..Default::default()
+    };
+    let read = read(
+        state,
+        database.clone(),
+        session,
+        &body,
+        timeout,
+        span.clone(),
+        Database::query_rows,
+    );
+    let res = match read.await? {
+        Some(res) => res,
+        None => {
+            let (tx, rx) = flume::bounded(1);
+            state
+                .requests
+                .send(Request::Rows {
+                    connection: connection.id,
+                    span,
+                    user: user.clone(),
+                    client_addr,
+                    database,
+                    sql: body,
+                    timeout,
+                    respond: tx,
+                })
+                .map_err(actix_web::error::ErrorServiceUnavailable)?;
+            rx.recv_async()
+                .await
+                .map_err(actix_web::error::ErrorServiceUnavailable)?
+        }
+    };
+    let mut rows = match res {
+        Ok(rows) => rows,
+        Err(e) => return Ok(error_response(&e)),
+    };
+    let limits = state.response_limits(user.as_deref(), page);
+    let cursor = rows
+        .truncate(limits.max_rows, limits.max_bytes)
+        .map(|rest| state.open_cursor(user.as_deref(), rest));
+    let mut resp = HttpResponse::Ok();
+    if let Some(cursor) = &cursor {
+        resp.insert_header(("cursor", cursor.as_str()));
+    }
+
+    if let Some(format) = accepted_ipc_format(&req) {
+        let ipc = web::block(move || {
+            let mut ipc = Vec::new();
+            arrow::write_rows(rows, &mut ipc, format).map(|()| ipc)
+        })
+        .await?;
+        return Ok(match ipc {
+            Ok(ipc) => resp.content_type(format.content_type()).body(ipc),
+            Err(e) => error_response(&e),
+        });
+    }
+
+    let header = rows.columns().to_vec();
+    let lines = std::iter::once(header)
+        .chain(rows)
+        .map(|line| serde_json::to_vec(&line))
+        .chain(cursor.map(|cursor| {
+            serde_json::to_vec(&serde_json::json!({ "truncated": true, "cursor": cursor }))
+        }))
+        .map(|line| {
+            let mut line = line?;
+            line.push(b'\n');
+            Ok::<_, serde_json::Error>(web::Bytes::from(line))
+        });
+
+    Ok(resp
+        .content_type("application/x-ndjson")
+        .streaming(futures_util::stream::iter(lines)))
+}
+
+// the arrow ipc format the `Accept` header of `req` asks for, if any
+fn accepted_ipc_format(req: &HttpRequest) -> Option<IpcFormat> {
+    let accept = req.headers().get(header::ACCEPT)?.to_str().ok()?;
+    [IpcFormat::Stream, IpcFormat::File]
+        .into_iter()
+        .find(|format| accept.contains(format.content_type()))
+}
+
+// of arrow ipc taken by an import at once
+const MAX_IMPORT_SIZE: usize = 256 * 1024 * 1024;
+
+/// Inserts the rows of the Arrow IPC file or stream in the body into `table`,
+/// by column name, answering with `{"rows": <count>}`.
+#[post("/tables/{table}/import")]
+async fn import_table(
+    req: HttpRequest,
+    table: web::Path<String>,
+    payload: web::Payload,
+    state: web::Data<AppState>,
+) -> Result<HttpResponse, Error> {
+    import(req, None, table.into_inner(), payload, &state).await
+}
+
+/// Like `/tables/{table}/import`, for a database created through
+/// `/admin/databases`.
+#[post("/databases/{database}/tables/{table}/import")]
+async fn import_database_table(
+    req: HttpRequest,
+    path: web::Path<(String, String)>,
+    payload: web::Payload,
+    state: web::Data<AppState>,
+) -> Result<HttpResponse, Error> {
+    let (database, table) = path.into_inner();
+    import(req, Some(database), table, payload, &state).await
+}
+
+async fn import(
+    req: HttpRequest,
+    database: Option<String>,
+    table: String,
+    mut payload: web::Payload,
+    state: &AppState,
+) -> Result<HttpResponse, Error> {
+    let user = match authenticate(&req, state).await {
+        Ok(user) => user,
+        Err(resp) => return Ok(resp),
+    };
+
+    let mut data = web::BytesMut::new();
+    while let Some(chunk) = payload.next().await {
+        let chunk = chunk?;
+        if data.len() + chunk.len() > MAX_IMPORT_SIZE {
+            let e = socketdb::Error::LimitExceeded(format!(
+                "import of more than {MAX_IMPORT_SIZE} bytes"
+            ));
+            return Ok(HttpResponse::PayloadTooLarge().json(e.to_json()));
+        }
+        data.extend_from_slice(&chunk);
+    }
+
+    let span = request_span(&req, database.as_deref());
+    let connection = state
+        .connections
+        .register("import", database.clone(), user.clone());
+    let (tx, rx) = flume::bounded(1);
+    state
+        .requests
+        .send(Request::Import {
+            connection: connection.id,
+            span,
+            user,
+            client_addr: req.peer_addr().map(|addr| addr.ip().to_string()),
+            database,
+            table,
+            data: data.freeze(),
+            respond: tx,
+        })
+        .map_err(actix_web::error::ErrorServiceUnavailable)?;
+
+    match rx.recv_async().await {
+        Ok(Ok(rows)) => Ok(HttpResponse::Ok().json(serde_json::json!({ "rows": rows }))),
+        Ok(Err(e)) => Ok(error_response(&e)),
+        Err(e) => Err(actix_web::error::ErrorServiceUnavailable(e)),
+    }
+}
+
+// rows of a bulk load inserted at once
+const LOAD_CHUNK: usize = 1000;
+
+/// Inserts the rows of a csv body, with a header of column names, or of a
+/// newline delimited json one into `table`, by column name, a chunk of rows
+/// at a time as they arrive. Answers with `{"rows": <inserted>, "rejected":
+/// <count>, "chunks": <count>, "errors": [{"line": ..., "message": ...}]}`. A
+/// denied permission or a cancel stops the load, keeping the chunks before.
+#[post("/tables/{table}/load")]
+async fn load_table(
+    req: HttpRequest,
+    table: web::Path<String>,
+    payload: web::Payload,
+    state: web::Data<AppState>,
+) -> Result<HttpResponse, Error> {
+    load(req, None, table.into_inner(), payload, &state).await
+}
+
+#[post("/databases/{database}/tables/{table}/load")]
+async fn load_database_table(
+    req: HttpRequest,
+    path: web::Path<(String, String)>,
+    payload: web::Payload,
+    state: web::Data<AppState>,
+) -> Result<HttpResponse, Error> {
+    let (database, table) = path.into_inner();
+    load(req, Some(database), table, payload, &state).await
+}
+
+async fn load(
+    req: HttpRequest,
+    database: Option<String>,
+    table: String,
+    mut payload: web::Payload,
+    state: &AppState,
+) -> Result<HttpResponse, Error> {
+    let user = match authenticate(&req, state).await {
+        Ok(user) => user,
+        Err(resp) => return Ok(resp),
+    };
+    let content_type = req
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or_default();
+    let Some(format) = LoadFormat::from_content_type(content_type) else {
+        let e = socketdb::Error::Unsupported(format!(
+            "loading {content_type:?}, expected text/csv or application/x-ndjson"
+        ));
+        return Ok(HttpResponse::UnsupportedMediaType().json(e.to_json()));
+    };
+
+    let span = request_span(&req, database.as_deref());
+    let connection = state
+        .connections
+        .register("load", database.clone(), user.clone());
+    let client_addr = req.peer_addr().map(|addr| addr.ip().to_string());
+    let mut parser = LoadParser::new(format);
+    let mut summary = LoadSummary::default();
+    let mut records = Vec::new();
+    let mut ended = false;
+
+    while !ended {
+        match payload.next().await {
+            Some(chunk) => {
+                records.extend(parser.push(&chunk?));
+                if parser.pending() > MAX_IMPORT_SIZE {
+                    let e = socketdb::Error::LimitExceeded(format!(
+                        "record of more than {MAX_IMPORT_SIZE} bytes"
+                    ));
+                    return Ok(HttpResponse::PayloadTooLarge().json(e.to_json()));
+                }
+            }
+            None => {
+                records.extend(parser.finish());
+                ended = true;
+            }
+        }
+
+        // an empty body still finds out whether the table exists
+        while records.len() >= LOAD_CHUNK || (ended && (!records.is_empty() || summary.chunks == 0))
+        {
+            let rest = records.split_off(LOAD_CHUNK.min(records.len()));
+            let chunk = std::mem::replace(&mut records, rest);
+
+            let (tx, rx) = flume::bounded(1);
+            state
+                .requests
+                .send(Request::Load {
+                    connection: connection.id,
+                    span: span.clone(),
+                    user: user.clone(),
+                    client_addr: client_addr.clone(),
+                    database: database.clone(),
+                    table: table.clone(),
+                    records: chunk,
+                    respond: tx,
+                })
+                .map_err(actix_web::error::ErrorServiceUnavailable)?;
+
+            match rx.recv_async().await {
+                Ok(Ok(chunk)) => summary.merge(chunk),
+                Ok(Err(e)) => return Ok(error_response(&e)),
+                Err(e) => return Err(actix_web::error::ErrorServiceUnavailable(e)),
+            }
+        }
+    }
+
+    Ok(HttpResponse::Ok().json(summary))
+}
+
+/// The addresses allowed to connect: `{"allow": [...], "deny": [...]}`, for
+/// superusers only.
+#[get("/admin/acl")]
+async fn get_acl(req: HttpRequest, state: web::Data<AppState>) -> Result<HttpResponse, Error> {
+    if let Err(resp) = authenticate_superuser(&req, &state).await {
+        return Ok(resp);
+    }
+
+    Ok(HttpResponse::Ok().json(&*state.acl.read().unwrap()))
+}
+
+/// Replaces the addresses allowed to connect, like `{"allow": ["10.0.0.0/8"],
+/// "deny": ["10.0.0.13"]}`, for superusers only. Connections already open
+/// are left alone.
+#[put("/admin/acl")]
+async fn set_acl(
+    req: HttpRequest,
+    acl: web::Json<Acl>,
+    state: web::Data<AppState>,
+) -> Result<HttpResponse, Error> {
+    let user = match authenticate_superuser(&req, &state).await {
+        Ok(user) => user,
+        Err(resp) => return Ok(resp),
+    };
+
+    let acl = acl.into_inner();
+    let action = format!(
+        "set acl {}",
+        serde_json::to_string(&acl).unwrap_or_default()
+    );
+    state.audit(&req, AuditKind::Admin, user.as_deref(), action);
+    let resp = HttpResponse::Ok().json(&acl);
+    *state.acl.write().unwrap() = acl;
+    Ok(resp)
+}
+
+/// Creates an empty database, kept in `{name}.sdb` under `SOCKETDB_DATA_DIR`,
+/// for superusers only.
+#[put("/admin/databases/{database}")]
+async fn create_database(
+    req: HttpRequest,
+    name: web::Path<String>,
+    state: web::Data<AppState>,
+) -> Result<HttpResponse, Error> {
+    let name = name.into_inner();
+    let action = format!("create database {name}");
+    admin(req, &state, action, |respond| Request::CreateDatabase {
+        name,
+        respond,
+    })
+    .await
+    .map(|resp| resp.unwrap_or_else(|| HttpResponse::Created().finish()))
+}
+
+/// Deletes a database along with its file, closing its subscriptions, for
+/// superusers only.
+#[delete("/admin/databases/{database}")]
+async fn delete_database(
+    req: HttpRequest,
+    name: web::Path<String>,
+    state: web::Data<AppState>,
+) -> Result<HttpResponse, Error> {
+    let name = name.into_inner();
+    let action = format!("delete database {name}");
+    admin(req, &state, action, |respond| Request::DeleteDatabase {
+        name,
+        respond,
+    })
+    .await
+    .map(|resp| resp.unwrap_or_else(|| HttpResponse::NoContent().finish()))
+}
+
+// `None` once the request succeeded, which is audited as `action`. Only
+// superusers may make it.
+async fn admin(
+    req: HttpRequest,
+    state: &AppState,
+    action: String,
+    request: impl FnOnce(Sender<socketdb::Result<()>>) -> Request,
+) -> Result<Option<HttpResponse>, Error> {
+    let user = match authenticate_superuser(&req, state).await {
+        Ok(user) => user,
+        Err(resp) => return Ok(Some(resp)),
+    };
+
+    let (tx, rx) = flume::bounded(1);
+    state
+        .requests
+        .send(request(tx))
+        .map_err(actix_web::error::ErrorServiceUnavailable)?;
+
+    match rx.recv_async().await {
+        Ok(Ok(())) => {
+            state.audit(&req, AuditKind::Admin, user.as_deref(), action);
+            Ok(None)
+        }
+        Ok(Err(e)) => Ok(Some(error_response(&e))),
+        Err(e) => Err(actix_web::error::ErrorServiceUnavailable(e)),
+    }
+}
+
+/// What each connection is running: `[{"id", "kind", "database", "statement",
+/// "running_ms"}]`, where the ids are the ones `KILL` takes, for superusers
+/// only. Passwords in the statements are left out.
+#[get("/admin/connections")]
+async fn list_connections(
+    req: HttpRequest,
+    state: web::Data<AppState>,
+) -> Result<HttpResponse, Error> {
+    if let Err(resp) = authenticate_superuser(&req, &state).await {
+        return Ok(resp);
+    }
+
+    Ok(HttpResponse::Ok().json(state.connections.list()))
+}
+
+#[derive(Deserialize)]
+struct Login {
+    username: String,
+    password: String,
+}
+
+/// Exchanges `{"username", "password"}` for `{"token", "expires_at"}`, where
+/// the token is sent as `Authorization: Bearer <token>` in place of them.
+#[post("/login")]
+async fn login(
+    req: HttpRequest,
+    body: web::Json<Login>,
+    state: web::Data<AppState>,
+) -> HttpResponse {
+    let Login { username, password } = body.into_inner();
+    match verify(&state, username.clone(), password).await {
+        Some(user) => HttpResponse::Ok().json(state.tokens.issue(&user)),
+        None => {
+            let action = "POST /login with a wrong username or password".to_owned();
+            state.audit(&req, AuditKind::LoginFailed, Some(&username), action);
+            unauthorized()
+        }
+    }
+}
+
+/// Revokes the bearer token the request is made with.
+#[post("/logout")]
+async fn logout(req: HttpRequest, state: web::Data<AppState>) -> HttpResponse {
+    let Some(token) = bearer(&req) else {
+        return unauthorized();
+    };
+
+    match state.tokens.revoke(token) {
+        Ok(()) => HttpResponse::NoContent().finish(),
+        Err(e) => error_response(&e),
+    }
+}
+
+fn error_response(e: &socketdb::Error) -> HttpResponse {
+    match e {
+        socketdb::Error::DatabaseNotFound(_)
+        | socketdb::Error::ConnectionNotFound(_)
+        | socketdb::Error::CursorNotFound(_) => HttpResponse::NotFound().json(e.to_json()),
+        socketdb::Error::DatabaseAlreadyExists(_) => HttpResponse::Conflict().json(e.to_json()),
+        socketdb::Error::InvalidToken(_) => HttpResponse::Unauthorized().json(e.to_json()),
+        socketdb::Error::PermissionDenied(_) => HttpResponse::Forbidden().json(e.to_json()),
+        _ => HttpResponse::BadRequest().json(e.to_json()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn named_databases_know_the_superusers_of_the_default_one() {
+        let dir = std::env::temp_dir().join(format!("socketdb-databases-{}", std::process::id()));
+        let open = || {
+            Databases::open(
+                dir.clone(),
+                AuditLog::new(Default::default()),
+                None,
+                Settings::new(&Config::default()),
+            )
+        };
+        let admin = || Session {
+            user: Some("admin".to_owned()),
+            ..Default::default()
+        };
+
+        let mut dbs = open();
+        let users = dbs.default.users();
+        users
+            .write()
+            .unwrap()
+            .create("admin", "secret", true)
+            .unwrap();
+        dbs.create("shop").unwrap();
+        let created = dbs
+            .get(Some("shop"))
+            .unwrap()
+            .run_as(admin(), |db| db.query("CREATE USER bob WITH PASSWORD 'pw'"));
+        dbs.persist(None);
+
+        // and once they're restored
+        let mut dbs = open();
+        let dropped = dbs
+            .get(Some("shop"))
+            .unwrap()
+            .run_as(admin(), |db| db.query("DROP USER bob"));
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        created.unwrap();
+        assert!(users.read().unwrap().get("bob").is_some());
+        dropped.unwrap();
+        assert!(dbs.default.users().read().unwrap().get("bob").is_none());
+    }
+}
@@ -1,11 +1,27 @@
 use std::{path::PathBuf, str::FromStr};
 
-use crate::Error;
+use crate::{database::OutputMode, Error};
 
 pub enum MetaCommand {
     ListTables,
+    // of one table, or all of them
+    Check(Option<String>),
     Persist(PathBuf),
     Restore(PathBuf),
+    // as arrow ipc, a stream for `.arrows` files
+    Export { table: String, path: PathBuf },
+    Import { table: String, path: PathBuf },
+    // the result of a select, as an excel spreadsheet
+    ExportXlsx { path: PathBuf, query: String },
+    Mode(OutputMode),
+    // of made up rows, into a table
+    Generate { table: String, rows: usize },
+    // the directory of the migrations to apply
+    Migrate(PathBuf),
+    // of one table, or all of them, as create table statements or json
+    Schema { table: Option<String>, json: bool },
+    // of one table, or all of them, as the sql recreating them with their rows
+    Dump(Option<String>),
     Exit,
 }
 
@@ -19,11 +35,58 @@ impl FromStr for MetaCommand {
         match *first {
             ".exit" => Ok(MetaCommand::Exit),
             ".tables" => Ok(MetaCommand::ListTables),
+            ".check" => Ok(MetaCommand::Check(splitted.get(1).map(|t| t.to_string()))),
+            ".mode" => {
+                let mode = splitted.get(1).ok_or(Error::InvalidMetaCommand(
+                    "mode is expected to be followed by table, markdown, html, csv or json"
+                        .to_owned(),
+                ))?;
+
+                Ok(MetaCommand::Mode(mode.parse()?))
+            }
+            ".schema" => {
+                let json = splitted.contains(&"--json");
+                let table = splitted[1..]
+                    .iter()
+                    .find(|a| **a != "--json" && !a.is_empty())
+                    .map(|t| t.to_string());
+
+                Ok(MetaCommand::Schema { table, json })
+            }
+            ".dump" => Ok(MetaCommand::Dump(
+                splitted
+                    .get(1)
+                    .filter(|t| !t.is_empty())
+                    .map(|t| t.to_string()),
+            )),
+            ".generate" => {
+                let (Some(table), Some(rows)) = (splitted.get(1), splitted.get(2)) else {
+                    return Err(Error::InvalidMetaCommand(
+                        "generate is expected to be followed by a table and a number of rows"
+                            .to_owned(),
+                    ));
+                };
+                let rows = rows.parse().map_err(|_| {
+                    Error::InvalidMetaCommand(format!("{rows} isn't a number of rows"))
+                })?;
+
+                Ok(MetaCommand::Generate {
+                    table: table.to_string(),
+                    rows,
+                })
+            }
+            ".migrate" => {
+                let dir = splitted.get(1).ok_or(Error::InvalidMetaCommand(
+                    "migrate is expected to be followed by a directory".to_owned(),
+                ))?;
+
+                Ok(MetaCommand::Migrate(PathBuf::from(dir)))
+            }
             ".persist" => {
                 let path = splitted.get(1).ok_or(Error::InvalidMetaCommand(
                     "persist is expected to be followed by a path".to_owned(),
                 ))?;
-                let path = PathBuf::from_str(path).unwrap();
+                let path = PathBuf::from(path);
 
                 Ok(MetaCommand::Persist(path))
             }
@@ -31,10 +94,36 @@ impl FromStr for MetaCommand {
                 let path = splitted.get(1).ok_or(Error::InvalidMetaCommand(
                     "restore is expected to be followed by a path".to_owned(),
                 ))?;
-                let path = PathBuf::from_str(path).unwrap();
+                let path = PathBuf::from(path);
 
                 Ok(MetaCommand::Restore(path))
             }
+            ".export" if splitted.get(1) == Some(&"xlsx") => {
+                let mut parts = s.splitn(4, ' ').skip(2);
+                let (Some(path), Some(query)) = (parts.next(), parts.next()) else {
+                    return Err(Error::InvalidMetaCommand(
+                        "export xlsx is expected to be followed by a path and a query".to_owned(),
+                    ));
+                };
+
+                Ok(MetaCommand::ExportXlsx {
+                    path: PathBuf::from(path),
+                    query: query.to_owned(),
+                })
+            }
+            ".export" | ".import" => {
+                let (Some(table), Some(path)) = (splitted.get(1), splitted.get(2)) else {
+                    return Err(Error::InvalidMetaCommand(format!(
+                        "{first} is expected to be followed by a table and a path"
+                    )));
+                };
+                let (table, path) = (table.to_string(), PathBuf::from(path));
+
+                match *first {
+                    ".export" => Ok(MetaCommand::Export { table, path }),
+                    _ => Ok(MetaCommand::Import { table, path }),
+                }
+            }
             _ => Err(Error::InvalidMetaCommand(s.to_owned())),
         }
     }
@@ -0,0 +1,53 @@
+use std::path::{Path, PathBuf};
+
+use crate::{Error, Result};
+
+/// Where applied migrations are recorded, by version.
+pub const MIGRATIONS_TABLE: &str = "__migrations";
+
+/// A `.sql` file of a migrations directory, named like `0003_add_prices.sql`:
+/// its version, then an underscore and its name.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Migration {
+    pub version: i32,
+    pub name: String,
+    pub path: PathBuf,
+}
+
+/// The migrations in `dir`, by version. Files that don't end in `.sql` are
+/// left out.
+pub fn read_dir(dir: &Path) -> Result<Vec<Migration>> {
+    let mut migrations = Vec::new();
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        if !path.is_file() || path.extension().map_or(true, |ext| ext != "sql") {
+            continue;
+        }
+
+        let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+        let (version, name) = stem.split_once('_').unwrap_or((&stem, ""));
+        let Ok(version) = version.parse() else {
+            return Err(Error::Migration(format!(
+                "{} isn't named like 0001_name.sql",
+                path.display()
+            )));
+        };
+
+        migrations.push(Migration {
+            version,
+            name: name.to_owned(),
+            path,
+        });
+    }
+
+    migrations.sort_by_key(|m| m.version);
+    if let Some(pair) = migrations.windows(2).find(|w| w[0].version == w[1].version) {
+        return Err(Error::Migration(format!(
+            "{} and {} have the same version",
+            pair[0].path.display(),
+            pair[1].path.display()
+        )));
+    }
+
+    Ok(migrations)
+}
@@ -0,0 +1,627 @@
+use crate::{
+    kernels::{self, Operand},
+    parser::{
+        expression::{Binary, Expression, Ident, Literal, Unary},
+        select::{JoinKind, OrderBy},
+    },
+    plan::Plan,
+    table::Strictness,
+    Error, Result,
+};
+
+/// `5 < id` is `id > 5`
+pub fn flip(op: Binary) -> Binary {
+    match op {
+        Binary::Lt => Binary::Gt,
+        Binary::LtEq => Binary::GtEq,
+        Binary::Gt => Binary::Lt,
+        Binary::GtEq => Binary::LtEq,
+        op => op,
+    }
+}
+
+/// Rewrites `expr` into an equivalent that is cheaper to evaluate. Whatever
+/// only depends on literals is computed up front, so that e.g. `WHERE 1 = 1`
+/// becomes `WHERE true`, and comparisons between a literal and anything else
+/// are turned around to have the literal on the right.
+///
+/// Expressions that fail to compute, like `1 / 0`, are left for the evaluator
+/// to report.
+pub fn simplify(expr: Expression) -> Expression {
+    match expr {
+        Expression::Binary {
+            operator,
+            left,
+            right,
+        } => binary(operator, simplify(*left), simplify(*right)),
+        Expression::Unary {
+            operator,
+            expression,
+        } => unary(operator, simplify(*expression)),
+        Expression::IsNull(expr) => match simplify(*expr) {
+            Expression::Literal(lit) => Expression::Literal(Literal::Bool(lit == Literal::Null)),
+            expr => Expression::IsNull(Box::new(expr)),
+        },
+        Expression::IsNotNull(expr) => match simplify(*expr) {
+            Expression::Literal(lit) => Expression::Literal(Literal::Bool(lit != Literal::Null)),
+            expr => Expression::IsNotNull(Box::new(expr)),
+        },
+        Expression::IsTrue(expr) => match simplify(*expr) {
+            Expression::Literal(Literal::Bool(b)) => Expression::Literal(Literal::Bool(b)),
+            expr => Expression::IsTrue(Box::new(expr)),
+        },
+        Expression::IsFalse(expr) => match simplify(*expr) {
+            Expression::Literal(Literal::Bool(b)) => Expression::Literal(Literal::Bool(!b)),
+            expr => Expression::IsFalse(Box::new(expr)),
+        },
+        Expression::Tuple(items) => Expression::Tuple(items.into_iter().map(simplify).collect()),
+        Expression::InList {
+            expr,
+            list,
+            negated,
+        } => in_list(
+            simplify(*expr),
+            list.into_iter().map(simplify).collect(),
+            negated,
+        ),
+        Expression::Case {
+            branches,
+            otherwise,
+        } => case(
+            branches
+                .into_iter()
+                .map(|(c, r)| (simplify(c), simplify(r)))
+                .collect(),
+            otherwise.map(|e| simplify(*e)),
+        ),
+        // the function itself could be anything, but its arguments can still
+        // be computed once
+        Expression::Function {
+            name,
+            args,
+            distinct,
+        } => Expression::Function {
+            name,
+            args: args.into_iter().map(simplify).collect(),
+            distinct,
+        },
+        expr => expr,
+    }
+}
+
+fn binary(operator: Binary, left: Expression, right: Expression) -> Expression {
+    match (left, right) {
+        (Expression::Tuple(l), Expression::Tuple(r)) if l.len() == r.len() => {
+            match compare_rows(operator, l.clone(), r.clone()) {
+                Ok(expr) => simplify(expr),
+                Err(_) => Expression::Binary {
+                    operator,
+                    left: Box::new(Expression::Tuple(l)),
+                    right: Box::new(Expression::Tuple(r)),
+                },
+            }
+        }
+        // patterns are compiled for the statement, when it's executed
+        (Expression::Literal(l), Expression::Literal(r)) if !is_pattern_match(operator) => {
+            let operands = (Operand::Scalar(l.clone()), Operand::Scalar(r.clone()));
+            let folded = match operator {
+                Binary::Plus | Binary::Minus | Binary::Mul | Binary::Div | Binary::Rem => {
+                    kernels::arithmetic(operator, operands.0, operands.1, Strictness::Strict)
+                }
+                Binary::And | Binary::Or => kernels::logical(operator, operands.0, operands.1),
+                _ => kernels::compare(operator, operands.0, operands.1, Strictness::Strict),
+            };
+
+            match folded {
+                // two scalars give either a single row, or none for null
+                Ok(data) => Expression::Literal(data.get_literal(0).unwrap_or(Literal::Null)),
+                Err(e) => {
+                    log::debug!("not folding `{operator}`: {e}");
+                    Expression::Binary {
+                        operator,
+                        left: Box::new(Expression::Literal(l)),
+                        right: Box::new(Expression::Literal(r)),
+                    }
+                }
+            }
+        }
+        (left @ Expression::Literal(_), right) if is_comparison(operator) => Expression::Binary {
+            operator: flip(operator),
+            left: Box::new(right),
+            right: Box::new(left),
+        },
+        (left, right) => Expression::Binary {
+            operator,
+            left: Box::new(left),
+            right: Box::new(right),
+        },
+    }
+}
+
+fn in_list(expr: Expression, list: Vec<Expression>, negated: bool) -> Expression {
+    if let (Expression::Literal(value), Some(values)) = (&expr, literals(&list)) {
+        let operand = Operand::Scalar(value.clone());
+        match kernels::in_list(operand, &values, negated, Strictness::Strict) {
+            Ok(data) => return Expression::Literal(data.get_literal(0).unwrap_or(Literal::Null)),
+            Err(e) => log::debug!("not folding `IN`: {e}"),
+        }
+    }
+
+    Expression::InList {
+        expr: Box::new(expr),
+        list,
+        negated,
+    }
+}
+
+// branches that are never taken are left out, and one that always is ends
+// the `CASE`
+fn case(branches: Vec<(Expression, Expression)>, otherwise: Option<Expression>) -> Expression {
+    let mut taken = Vec::with_capacity(branches.len());
+    for (condition, result) in branches {
+        match condition {
+            Expression::Literal(Literal::Bool(true)) if taken.is_empty() => return result,
+            Expression::Literal(Literal::Bool(true)) => {
+                return Expression::Case {
+                    branches: taken,
+                    otherwise: Some(Box::new(result)),
+                }
+            }
+            Expression::Literal(Literal::Bool(false) | Literal::Null) => {}
+            condition => taken.push((condition, result)),
+        }
+    }
+
+    match taken.is_empty() {
+        true => otherwise.unwrap_or(Expression::Literal(Literal::Null)),
+        false => Expression::Case {
+            branches: taken,
+            otherwise: otherwise.map(Box::new),
+        },
+    }
+}
+
+/// The values of `exprs`, if they're all literals.
+pub fn literals(exprs: &[Expression]) -> Option<Vec<Literal>> {
+    exprs
+        .iter()
+        .map(|e| match e {
+            Expression::Literal(l) => Some(l.clone()),
+            _ => None,
+        })
+        .collect()
+}
+
+/// `(a, b) <op> (c, d)` as comparisons of the values the rows are made of:
+/// all of them equal for `=`, any of them different for `<>`, and otherwise
+/// decided by the first values that differ, like `a < c OR (a = c AND b < d)`
+/// for `<`.
+pub fn compare_rows(
+    operator: Binary,
+    left: Vec<Expression>,
+    right: Vec<Expression>,
+) -> Result<Expression> {
+    if left.len() != right.len() || left.is_empty() {
+        return Err(Error::InvalidQuery(format!(
+            "comparison of a row of {} values with one of {}",
+            left.len(),
+            right.len()
+        )));
+    }
+    let op = |operator, left, right| Expression::Binary {
+        operator,
+        left: Box::new(left),
+        right: Box::new(right),
+    };
+
+    let mut pairs: Vec<(Expression, Expression)> = left.into_iter().zip(right).collect();
+    match operator {
+        Binary::Eq | Binary::NotEq => {
+            let join = match operator {
+                Binary::Eq => Binary::And,
+                _ => Binary::Or,
+            };
+            let mut pairs = pairs.into_iter().map(|(l, r)| op(operator, l, r));
+            let first = pairs.next().unwrap_or(Expression::None);
+            Ok(pairs.fold(first, |all, cmp| op(join, all, cmp)))
+        }
+        Binary::Lt | Binary::LtEq | Binary::Gt | Binary::GtEq => {
+            let strict = match operator {
+                Binary::LtEq => Binary::Lt,
+                Binary::GtEq => Binary::Gt,
+                operator => operator,
+            };
+            // from the last values outwards, which are the only ones that
+            // `<=` and `>=` let be equal
+            let Some((l, r)) = pairs.pop() else {
+                return Ok(Expression::None);
+            };
+            let mut out = op(operator, l, r);
+            while let Some((l, r)) = pairs.pop() {
+                let equal = op(Binary::Eq, l.clone(), r.clone());
+                out = op(Binary::Or, op(strict, l, r), op(Binary::And, equal, out));
+            }
+            Ok(out)
+        }
+        operator => Err(Error::InvalidQuery(format!("`{operator}` of rows"))),
+    }
+}
+
+fn is_pattern_match(op: Binary) -> bool {
+    matches!(
+        op,
+        Binary::Match | Binary::IMatch | Binary::NotMatch | Binary::NotIMatch
+    )
+}
+
+fn is_comparison(op: Binary) -> bool {
+    matches!(
+        op,
+        Binary::Eq | Binary::NotEq | Binary::Lt | Binary::LtEq | Binary::Gt | Binary::GtEq
+    )
+}
+
+fn unary(operator: Unary, expr: Expression) -> Expression {
+    let folded = match (operator, &expr) {
+        (Unary::Not, Expression::Literal(Literal::Bool(b))) => Some(Literal::Bool(!b)),
+        (
+            Unary::Plus,
+            Expression::Literal(lit @ (Literal::Int(_) | Literal::Float(_) | Literal::Double(_))),
+        ) => Some(lit.clone()),
+        (Unary::Minus, Expression::Literal(Literal::Int(i))) => i.checked_neg().map(Literal::Int),
+        (Unary::Minus, Expression::Literal(Literal::Float(f))) => Some(Literal::Float(-f)),
+        (Unary::Minus, Expression::Literal(Literal::Double(d))) => Some(Literal::Double(-d)),
+        _ => None,
+    };
+
+    match folded {
+        Some(lit) => Expression::Literal(lit),
+        None => Expression::Unary {
+            operator,
+            expression: Box::new(expr),
+        },
+    }
+}
+
+/// Moves each filter on top of joins that only refers to the columns of one
+/// of the tables joined down to right on top of the scan of that table, so
+/// that fewer rows go into the joins, and the index of the table can answer
+/// it. A filter stays above a left join whose right side it refers to, where
+/// it leaves out the rows that would otherwise be kept with nulls.
+///
+/// `columns` gives the names of the columns of a table, or `None` when they
+/// aren't known without scanning it, like those of a table function.
+pub fn push_down_filters(plan: Plan, columns: &impl Fn(&str) -> Option<Vec<String>>) -> Plan {
+    let push = |plan: Box<Plan>| Box::new(push_down_filters(*plan, columns));
+    match plan {
+        Plan::Filter { input, predicate } => {
+            let input = push_down_filters(*input, columns);
+            let mut scans = Vec::new();
+            collect_scans(&input, &mut scans);
+            match scan_of(&predicate, &scans, columns) {
+                Some(scan) => sink(input, predicate, scan),
+                None => Plan::Filter {
+                    input: Box::new(input),
+                    predicate,
+                },
+            }
+        }
+        Plan::Join { left, right, join } => Plan::Join {
+            left: push(left),
+            right: push(right),
+            join,
+        },
+        Plan::Project { input, items } => Plan::Project {
+            input: push(input),
+            items,
+        },
+        Plan::Aggregate {
+            input,
+            group_by,
+            items,
+        } => Plan::Aggregate {
+            input: push(input),
+            group_by,
+            items,
+        },
+        Plan::Combine {
+            left,
+            right,
+            operator,
+            all,
+        } => Plan::Combine {
+            left: push(left),
+            right: push(right),
+            operator,
+            all,
+        },
+        Plan::Sort { input, keys } => Plan::Sort {
+            input: push(input),
+            keys,
+        },
+        Plan::Limit {
+            input,
+            limit,
+            offset,
+        } => Plan::Limit {
+            input: push(input),
+            limit,
+            offset,
+        },
+        plan @ (Plan::NoTable | Plan::Scan { .. }) => plan,
+    }
+}
+
+// the scans of the tables of `plan` that a filter on top of it can go down
+// to, left to right
+fn collect_scans<'p>(plan: &'p Plan, scans: &mut Vec<&'p Plan>) {
+    match plan {
+        Plan::Scan { .. } => scans.push(plan),
+        Plan::Join { left, right, .. } => {
+            collect_scans(left, scans);
+            collect_scans(right, scans);
+        }
+        Plan::Filter { input, .. } => collect_scans(input, scans),
+        _ => {}
+    }
+}
+
+// the position among `scans` of the only one whose table has every column
+// `predicate` refers to, the same way they would be found among the columns
+// of the joined tables
+fn scan_of(
+    predicate: &Expression,
+    scans: &[&Plan],
+    columns: &impl Fn(&str) -> Option<Vec<String>>,
+) -> Option<usize> {
+    // on top of a single table, there's nowhere to go
+    if scans.len() < 2 {
+        return None;
+    }
+
+    let mut found = None;
+    for name in predicate.idents() {
+        let mut matching = (0..scans.len()).filter(|&i| has_column(scans[i], name, columns));
+        let (Some(scan), None) = (matching.next(), matching.next()) else {
+            return None;
+        };
+        if found.is_some_and(|found| found != scan) {
+            return None;
+        }
+        found = Some(scan);
+    }
+
+    found
+}
+
+// whether `name` could be a column of the table `scan` scans
+fn has_column(scan: &Plan, name: &str, columns: &impl Fn(&str) -> Option<Vec<String>>) -> bool {
+    let Plan::Scan {
+        table, args, alias, ..
+    } = scan
+    else {
+        return false;
+    };
+
+    match name.rsplit_once('.') {
+        // qualified with the alias of the table, or else its name, like the
+        // columns of a join are
+        Some((qualifier, _)) => alias
+            .as_deref()
+            .unwrap_or(table)
+            .eq_ignore_ascii_case(qualifier),
+        None => match args.is_none().then(|| columns(table)).flatten() {
+            Some(columns) => columns.iter().any(|c| c.eq_ignore_ascii_case(name)),
+            // any of its columns could be called that
+            None => true,
+        },
+    }
+}
+
+// `plan` with `predicate` right on top of the scan at `position` of its
+// scans, or as far down towards it as the joins on the way let it go
+fn sink(plan: Plan, predicate: Expression, position: usize) -> Plan {
+    match plan {
+        Plan::Join { left, right, join } => {
+            let mut scans = Vec::new();
+            collect_scans(&left, &mut scans);
+            let on_left = scans.len();
+
+            if position < on_left {
+                Plan::Join {
+                    left: Box::new(sink(*left, predicate, position)),
+                    right,
+                    join,
+                }
+            } else if join.kind != JoinKind::Left {
+                let position = position - on_left;
+                Plan::Join {
+                    left,
+                    right: Box::new(sink(*right, predicate, position)),
+                    join,
+                }
+            } else {
+                Plan::Filter {
+                    input: Box::new(Plan::Join { left, right, join }),
+                    predicate,
+                }
+            }
+        }
+        Plan::Filter {
+            input,
+            predicate: above,
+        } => Plan::Filter {
+            input: Box::new(sink(*input, predicate, position)),
+            predicate: above,
+        },
+        plan => Plan::Filter {
+            input: Box::new(plan),
+            predicate,
+        },
+    }
+}
+
+/// Puts a select without `ORDER BY` in order of the primary keys of its
+/// tables, left to right, or of its groups for a grouped one, so that the
+/// same rows always come out the same way. Tables without a primary key, and
+/// groups whose expressions aren't in the select list, are left in the order
+/// they come in. The selects of a `UNION`, `INTERSECT` or `EXCEPT` are each
+/// put in order, their rows being combined in the order they come in.
+///
+/// `primary_key` gives the name of the primary key column of a table, if it
+/// has one.
+pub fn order_deterministically(plan: Plan, primary_key: &impl Fn(&str) -> Option<String>) -> Plan {
+    if let Plan::Limit {
+        input,
+        limit,
+        offset,
+    } = plan
+    {
+        return Plan::Limit {
+            input: Box::new(order_deterministically(*input, primary_key)),
+            limit,
+            offset,
+        };
+    }
+    if let Plan::Combine {
+        left,
+        right,
+        operator,
+        all,
+    } = plan
+    {
+        return Plan::Combine {
+            left: Box::new(order_deterministically(*left, primary_key)),
+            right: Box::new(order_deterministically(*right, primary_key)),
+            operator,
+            all,
+        };
+    }
+
+    let keys = match &plan {
+        Plan::Aggregate {
+            group_by, items, ..
+        } => group_by
+            .iter()
+            .filter(|g| items.iter().any(|i| i.stands_for(g)))
+            .cloned()
+            .collect(),
+        Plan::Project { input, .. } => {
+            let mut scans = Vec::new();
+            collect_scans(input, &mut scans);
+            scans
+                .into_iter()
+                .filter_map(|scan| match scan {
+                    Plan::Scan {
+                        table,
+                        args: None,
+                        alias,
+                        ..
+                    } => {
+                        let pk = primary_key(table)?;
+                        let qualifier = alias.as_deref().unwrap_or(table);
+                        Some(Expression::Ident(Ident::Named(format!("{qualifier}.{pk}"))))
+                    }
+                    _ => None,
+                })
+                .collect()
+        }
+        _ => Vec::new(),
+    };
+    if keys.is_empty() {
+        return plan;
+    }
+
+    Plan::Sort {
+        input: Box::new(plan),
+        keys: keys
+            .into_iter()
+            .map(|expr| OrderBy {
+                expr,
+                desc: false,
+                nulls_first: false,
+            })
+            .collect(),
+    }
+}
+
+/// Narrows each scan of `plan` down to the columns the rest of it refers to,
+/// so that only those are copied when a table is joined or restricted by
+/// policies. Columns are told apart by name alone, so one of a table is kept
+/// when another table has a column of the same name that is needed. Without
+/// knowing every column needed, like with `*`, a subquery that may refer to
+/// any of them or a natural join joining on whichever have the same name,
+/// scans are left with all of them.
+pub fn prune_columns(mut plan: Plan) -> Plan {
+    let mut names = Vec::new();
+    if !referenced_columns(&plan, &mut names) {
+        return plan;
+    }
+    names.sort();
+    names.dedup();
+
+    set_columns(&mut plan, &names);
+    plan
+}
+
+// adds the lowercase names of the columns `plan` refers to, without their
+// table, to `names`, or gives false when they can't all be told
+fn referenced_columns(plan: &Plan, names: &mut Vec<String>) -> bool {
+    let exprs: Vec<&Expression> = match plan {
+        Plan::NoTable | Plan::Scan { .. } | Plan::Combine { .. } | Plan::Limit { .. } => Vec::new(),
+        Plan::Join { join, .. } => {
+            if join.natural {
+                return false;
+            }
+            names.extend(join.using.iter().map(|u| u.to_lowercase()));
+            join.on.iter().collect()
+        }
+        Plan::Filter { predicate, .. } => vec![predicate],
+        Plan::Project { items, .. } => items.iter().map(|i| &i.expr).collect(),
+        Plan::Aggregate {
+            group_by, items, ..
+        } => group_by
+            .iter()
+            .chain(items.iter().map(|i| &i.expr))
+            .collect(),
+        Plan::Sort { keys, .. } => keys.iter().map(|k| &k.expr).collect(),
+    };
+
+    for expr in exprs {
+        if matches!(expr, Expression::Ident(Ident::Wildcard)) || expr.has_subquery() {
+            return false;
+        }
+        for name in expr.idents() {
+            let column = name.rsplit_once('.').map_or(name, |(_, column)| column);
+            names.push(column.to_lowercase());
+        }
+    }
+
+    match plan {
+        Plan::NoTable | Plan::Scan { .. } => true,
+        Plan::Join { left, right, .. } | Plan::Combine { left, right, .. } => {
+            referenced_columns(left, names) && referenced_columns(right, names)
+        }
+        Plan::Filter { input, .. }
+        | Plan::Project { input, .. }
+        | Plan::Aggregate { input, .. }
+        | Plan::Sort { input, .. }
+        | Plan::Limit { input, .. } => referenced_columns(input, names),
+    }
+}
+
+fn set_columns(plan: &mut Plan, names: &[String]) {
+    match plan {
+        Plan::NoTable => {}
+        Plan::Scan { columns, .. } => *columns = Some(names.to_vec()),
+        Plan::Join { left, right, .. } | Plan::Combine { left, right, .. } => {
+            set_columns(left, names);
+            set_columns(right, names);
+        }
+        Plan::Filter { input, .. }
+        | Plan::Project { input, .. }
+        | Plan::Aggregate { input, .. }
+        | Plan::Sort { input, .. }
+        | Plan::Limit { input, .. } => set_columns(input, names),
+    }
+}
@@ -0,0 +1,292 @@
+use super::{
+    expression::{Binary, Expression, Ident, Literal, Unary},
+    parser::Query,
+    select::{OrderBy, Select, SelectItem},
+};
+
+/// Builds a `SELECT` without going through SQL text, e.g.
+/// `Query::select(["a", "b"]).from("t").filter(col("a").gt(5)).limit(10)`.
+#[derive(Debug)]
+pub struct SelectBuilder {
+    from: Option<String>,
+    projection: Vec<Expression>,
+    selection: Expression,
+    order_by: Vec<OrderBy>,
+    limit: Option<usize>,
+    offset: usize,
+}
+
+impl Query {
+    pub fn select<I, S>(columns: I) -> SelectBuilder
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        let projection = columns
+            .into_iter()
+            .map(|c| match c.as_ref() {
+                "*" => Expression::Ident(Ident::Wildcard),
+                name => col(name),
+            })
+            .collect();
+
+        SelectBuilder {
+            from: None,
+            projection,
+            selection: Expression::None,
+            order_by: Vec::new(),
+            limit: None,
+            offset: 0,
+        }
+    }
+}
+
+impl SelectBuilder {
+    pub fn from(mut self, table: impl Into<String>) -> Self {
+        self.from = Some(table.into());
+        self
+    }
+
+    /// adds an arbitrary expression to the projection
+    pub fn column(mut self, expr: impl Into<Expression>) -> Self {
+        self.projection.push(expr.into());
+        self
+    }
+
+    /// replaces any previously set filter
+    pub fn filter(mut self, expr: impl Into<Expression>) -> Self {
+        self.selection = expr.into();
+        self
+    }
+
+    /// orders by `column` after any previously given ones
+    pub fn order_by(mut self, column: impl Into<String>) -> Self {
+        self.order_by.push(OrderBy {
+            expr: col(column),
+            desc: false,
+            nulls_first: false,
+        });
+        self
+    }
+
+    pub fn order_by_desc(mut self, column: impl Into<String>) -> Self {
+        self.order_by.push(OrderBy {
+            expr: col(column),
+            desc: true,
+            nulls_first: true,
+        });
+        self
+    }
+
+    pub fn limit(mut self, limit: usize) -> Self {
+        self.limit = Some(limit);
+        self
+    }
+
+    /// leaves out the first `offset` rows, before the limit
+    pub fn offset(mut self, offset: usize) -> Self {
+        self.offset = offset;
+        self
+    }
+
+    pub fn build(self) -> Query {
+        Query::Select(Select {
+            from: self.from,
+            from_args: None,
+            alias: None,
+            sample: None,
+            joins: Vec::new(),
+            projection: self
+                .projection
+                .into_iter()
+                .map(|expr| SelectItem { expr, alias: None })
+                .collect(),
+            selection: vec![self.selection],
+            group_by: Vec::new(),
+            order_by: self.order_by,
+            limit: self.limit,
+            offset: self.offset,
+            compound: Vec::new(),
+        })
+    }
+}
+
+impl From<SelectBuilder> for Query {
+    fn from(value: SelectBuilder) -> Self {
+        value.build()
+    }
+}
+
+pub fn col(name: impl Into<String>) -> Expression {
+    Expression::Ident(Ident::Named(name.into()))
+}
+
+pub fn lit(value: impl Into<Literal>) -> Expression {
+    Expression::Literal(value.into())
+}
+
+impl Expression {
+    fn binary(self, operator: Binary, right: impl Into<Expression>) -> Expression {
+        Expression::Binary {
+            operator,
+            left: Box::new(self),
+            right: Box::new(right.into()),
+        }
+    }
+
+    pub fn eq(self, right: impl Into<Expression>) -> Expression {
+        self.binary(Binary::Eq, right)
+    }
+
+    pub fn not_eq(self, right: impl Into<Expression>) -> Expression {
+        self.binary(Binary::NotEq, right)
+    }
+
+    pub fn gt(self, right: impl Into<Expression>) -> Expression {
+        self.binary(Binary::Gt, right)
+    }
+
+    pub fn gt_eq(self, right: impl Into<Expression>) -> Expression {
+        self.binary(Binary::GtEq, right)
+    }
+
+    pub fn lt(self, right: impl Into<Expression>) -> Expression {
+        self.binary(Binary::Lt, right)
+    }
+
+    pub fn lt_eq(self, right: impl Into<Expression>) -> Expression {
+        self.binary(Binary::LtEq, right)
+    }
+
+    pub fn and(self, right: impl Into<Expression>) -> Expression {
+        self.binary(Binary::And, right)
+    }
+
+    pub fn or(self, right: impl Into<Expression>) -> Expression {
+        self.binary(Binary::Or, right)
+    }
+
+    pub fn is_true(self) -> Expression {
+        Expression::IsTrue(Box::new(self))
+    }
+
+    pub fn is_false(self) -> Expression {
+        Expression::IsFalse(Box::new(self))
+    }
+
+    pub fn is_null(self) -> Expression {
+        Expression::IsNull(Box::new(self))
+    }
+
+    pub fn is_not_null(self) -> Expression {
+        Expression::IsNotNull(Box::new(self))
+    }
+}
+
+macro_rules! impl_binary_op {
+    ($trait:ident, $method:ident, $op:expr) => {
+        impl<T: Into<Expression>> std::ops::$trait<T> for Expression {
+            type Output = Expression;
+
+            fn $method(self, right: T) -> Expression {
+                self.binary($op, right)
+            }
+        }
+    };
+}
+
+impl_binary_op!(Add, add, Binary::Plus);
+impl_binary_op!(Sub, sub, Binary::Minus);
+impl_binary_op!(Mul, mul, Binary::Mul);
+impl_binary_op!(Div, div, Binary::Div);
+impl_binary_op!(Rem, rem, Binary::Rem);
+
+impl std::ops::Not for Expression {
+    type Output = Expression;
+
+    fn not(self) -> Expression {
+        Expression::Unary {
+            operator: Unary::Not,
+            expression: Box::new(self),
+        }
+    }
+}
+
+impl std::ops::Neg for Expression {
+    type Output = Expression;
+
+    fn neg(self) -> Expression {
+        Expression::Unary {
+            operator: Unary::Minus,
+            expression: Box::new(self),
+        }
+    }
+}
+
+impl From<Literal> for Expression {
+    fn from(value: Literal) -> Self {
+        Expression::Literal(value)
+    }
+}
+
+impl From<i32> for Literal {
+    fn from(value: i32) -> Self {
+        Literal::Int(value)
+    }
+}
+
+impl From<f32> for Literal {
+    fn from(value: f32) -> Self {
+        Literal::Float(value)
+    }
+}
+
+impl From<f64> for Literal {
+    fn from(value: f64) -> Self {
+        Literal::Double(value)
+    }
+}
+
+impl From<bool> for Literal {
+    fn from(value: bool) -> Self {
+        Literal::Bool(value)
+    }
+}
+
+// unlike `From<String>`, this never tries to parse the value: the builder
+// always means a string literal here
+impl From<&str> for Literal {
+    fn from(value: &str) -> Self {
+        Literal::Str(value.to_owned())
+    }
+}
+
+impl From<i32> for Expression {
+    fn from(value: i32) -> Self {
+        lit(value)
+    }
+}
+
+impl From<f32> for Expression {
+    fn from(value: f32) -> Self {
+        lit(value)
+    }
+}
+
+impl From<f64> for Expression {
+    fn from(value: f64) -> Self {
+        lit(value)
+    }
+}
+
+impl From<bool> for Expression {
+    fn from(value: bool) -> Self {
+        lit(value)
+    }
+}
+
+impl From<&str> for Expression {
+    fn from(value: &str) -> Self {
+        lit(value)
+    }
+}
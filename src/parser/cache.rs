@@ -0,0 +1,180 @@
+use std::{collections::HashMap, ops::ControlFlow};
+
+use sqlparser::{
+    ast::{visit_expressions_mut, Expr, Statement, Value},
+    tokenizer::{Token, TokenWithLocation},
+};
+
+use super::parser::SqlDialect;
+
+/// How many statements a database keeps parsed by default, see
+/// `Database::set_statement_cache_size`.
+pub const DEFAULT_CAPACITY: usize = 256;
+
+/// Selects as sqlparser parsed them, with their numbers and strings taken out
+/// into placeholders, so that the same query with other values in it, like the
+/// ones dashboards send over and over, skips parsing. Keyed by the tokens of
+/// the query with the placeholders in place of the values, so that whitespace
+/// and comments don't matter either.
+#[derive(Debug)]
+pub struct StatementCache {
+    capacity: usize,
+    entries: HashMap<String, Entry>,
+    // goes up with every lookup, for finding the least recently used entry
+    clock: u64,
+}
+
+#[derive(Debug)]
+struct Entry {
+    // `None` for queries that couldn't be parsed with placeholders, e.g.
+    // because sqlparser wants a literal where a value was, so that they're
+    // parsed as they are without trying again
+    statement: Option<Statement>,
+    used: u64,
+}
+
+/// A query with its values taken out, see `normalize`.
+#[derive(Debug)]
+pub struct Normalized {
+    pub key: String,
+    pub tokens: Vec<TokenWithLocation>,
+    pub params: Vec<Value>,
+}
+
+impl Default for StatementCache {
+    fn default() -> Self {
+        Self::new(DEFAULT_CAPACITY)
+    }
+}
+
+impl StatementCache {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            entries: HashMap::new(),
+            clock: 0,
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Keeps no more than `capacity` statements, dropping the least recently
+    /// used ones beyond it. Zero turns the cache off.
+    pub fn set_capacity(&mut self, capacity: usize) {
+        self.capacity = capacity;
+        while self.entries.len() > capacity {
+            self.evict();
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// The statement cached for `key`, with `Some(None)` for a query known not
+    /// to be cacheable.
+    pub fn get(&mut self, key: &str) -> Option<Option<&Statement>> {
+        self.clock += 1;
+        let entry = self.entries.get_mut(key)?;
+        entry.used = self.clock;
+        Some(entry.statement.as_ref())
+    }
+
+    pub fn insert(&mut self, key: String, statement: Option<Statement>) {
+        if self.capacity == 0 {
+            return;
+        }
+        if !self.entries.contains_key(&key) && self.entries.len() >= self.capacity {
+            self.evict();
+        }
+        self.clock += 1;
+        self.entries.insert(
+            key,
+            Entry {
+                statement,
+                used: self.clock,
+            },
+        );
+    }
+
+    /// Forgets every statement, e.g. once tables are created or dropped.
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+
+    fn evict(&mut self) {
+        let oldest = self
+            .entries
+            .iter()
+            .min_by_key(|(_, entry)| entry.used)
+            .map(|(key, _)| key.clone());
+        if let Some(key) = oldest {
+            self.entries.remove(&key);
+        }
+    }
+}
+
+/// `tokens` with their numbers and single quoted strings replaced by the
+/// placeholders `$1`, `$2` and so on, along with the values taken out. `None`
+/// for a query that already has placeholders of its own.
+pub fn normalize(tokens: &[TokenWithLocation], dialect: SqlDialect) -> Option<Normalized> {
+    let mut key = dialect.name().to_owned();
+    let mut params = Vec::new();
+    let mut out = Vec::with_capacity(tokens.len());
+    for t in tokens {
+        let token = match &t.token {
+            Token::Whitespace(_) => continue,
+            Token::Placeholder(_) => return None,
+            Token::Number(n, long) => {
+                params.push(Value::Number(n.clone(), *long));
+                Token::Placeholder(format!("${}", params.len()))
+            }
+            Token::SingleQuotedString(s) => {
+                params.push(Value::SingleQuotedString(s.clone()));
+                Token::Placeholder(format!("${}", params.len()))
+            }
+            token => token.clone(),
+        };
+        key.push(' ');
+        key.push_str(&token.to_string());
+        out.push(TokenWithLocation::new(
+            token,
+            t.location.line,
+            t.location.column,
+        ));
+    }
+
+    Some(Normalized {
+        key,
+        tokens: out,
+        params,
+    })
+}
+
+/// Puts `params` in place of the placeholders `normalize` left in `stmt`.
+/// Gives back whether each of them was used exactly once, which it isn't when
+/// sqlparser kept one somewhere other than an expression.
+pub fn bind_params(stmt: &mut Statement, params: &[Value]) -> bool {
+    let mut used = vec![0; params.len()];
+    _ = visit_expressions_mut(stmt, |expr| {
+        if let Expr::Value(Value::Placeholder(p)) = expr {
+            let param = p
+                .strip_prefix('$')
+                .and_then(|n| n.parse::<usize>().ok())
+                .and_then(|n| n.checked_sub(1))
+                .filter(|&i| i < params.len());
+            if let Some(i) = param {
+                used[i] += 1;
+                *expr = Expr::Value(params[i].clone());
+            }
+        }
+        ControlFlow::<()>::Continue(())
+    });
+    used.iter().all(|&n| n == 1)
+}
@@ -1,6 +1,15 @@
+use std::sync::Arc;
+
 use sqlparser::ast::Expr;
 
-use crate::Error;
+use crate::{
+    aggregate::Aggregate,
+    format, sql,
+    table::{DataType, Strictness},
+    Error,
+};
+
+use super::select::Select;
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub enum Binary {
@@ -15,6 +24,38 @@ pub enum Binary {
     LtEq,
     GtEq,
     NotEq,
+    And,
+    Or,
+    // `~`, `~*`, `!~` and `!~*`, matching text against a regular expression,
+    // see `regexp::Patterns::matches`
+    Match,
+    IMatch,
+    NotMatch,
+    NotIMatch,
+}
+
+impl std::fmt::Display for Binary {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Binary::Plus => "+",
+            Binary::Minus => "-",
+            Binary::Mul => "*",
+            Binary::Div => "/",
+            Binary::Rem => "%",
+            Binary::Eq => "=",
+            Binary::Lt => "<",
+            Binary::Gt => ">",
+            Binary::LtEq => "<=",
+            Binary::GtEq => ">=",
+            Binary::NotEq => "<>",
+            Binary::And => "AND",
+            Binary::Or => "OR",
+            Binary::Match => "~",
+            Binary::IMatch => "~*",
+            Binary::NotMatch => "!~",
+            Binary::NotIMatch => "!~*",
+        })
+    }
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
@@ -24,6 +65,16 @@ pub enum Unary {
     Minus,
 }
 
+impl std::fmt::Display for Unary {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Unary::Not => "NOT ",
+            Unary::Plus => "+",
+            Unary::Minus => "-",
+        })
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, PartialOrd)]
 pub enum Literal {
     Int(i32),
@@ -48,24 +99,114 @@ impl From<String> for Literal {
             return Self::Int(v);
         }
 
-        if let Ok(v) = value.parse::<f32>() {
-            return Self::Float(v);
-        }
-
+        // a float only when that holds the number just as well, like 1.5
         if let Ok(v) = value.parse::<f64>() {
-            return Self::Double(v);
+            return Self::Double(v)
+                .cast(DataType::Float, Strictness::Strict)
+                .unwrap_or(Self::Double(v));
         }
 
         Self::Str(value)
     }
 }
 
+impl std::fmt::Display for Literal {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Literal::Int(i) => write!(f, "{i}"),
+            Literal::Str(s) => f.write_str(&sql::string(s)),
+            Literal::Bool(b) => write!(f, "{b}"),
+            Literal::Float(v) => f.write_str(&format::float(*v, None)),
+            Literal::Double(v) => f.write_str(&format::double(*v, None)),
+            Literal::Null => f.write_str("NULL"),
+        }
+    }
+}
+
+impl Literal {
+    /// Converts to a value of `datatype`, e.g. so that an integer literal can be
+    /// compared with a double column, or `None` if that can't be done under
+    /// `strictness`. Null stays null.
+    pub fn cast(self, datatype: DataType, strictness: Strictness) -> Option<Literal> {
+        let lenient = strictness == Strictness::Lenient;
+        match (self, datatype) {
+            (Literal::Null, _) => Some(Literal::Null),
+            (lit @ Literal::Int(_), DataType::Int)
+            | (lit @ Literal::Str(_), DataType::Str)
+            | (lit @ Literal::Float(_), DataType::Float)
+            | (lit @ Literal::Double(_), DataType::Double)
+            | (lit @ Literal::Bool(_), DataType::Bool) => Some(lit),
+            (Literal::Int(i), DataType::Double) => Some(Literal::Double(i as f64)),
+            (Literal::Int(i), DataType::Float) => {
+                let f = i as f32;
+                (lenient || f as i64 == i as i64).then_some(Literal::Float(f))
+            }
+            // going through the shortest representation keeps 0.3 as 0.3
+            // rather than 0.30000001192092896
+            (Literal::Float(f), DataType::Double) => {
+                Some(Literal::Double(f.to_string().parse().unwrap_or(f as f64)))
+            }
+            (Literal::Double(d), DataType::Float) => {
+                let f = d as f32;
+                let exact = f.to_string().parse::<f64>().is_ok_and(|f| f == d);
+                (lenient || exact).then_some(Literal::Float(f))
+            }
+            (Literal::Float(f), DataType::Int) => to_int(f as f64, lenient),
+            (Literal::Double(d), DataType::Int) => to_int(d, lenient),
+            // everything below either loses something or has to guess
+            _ if !lenient => None,
+            (Literal::Bool(b), DataType::Int) => Some(Literal::Int(b as i32)),
+            (Literal::Int(i), DataType::Bool) => Some(Literal::Bool(i != 0)),
+            (Literal::Str(s), DataType::Bool) => match s.trim().to_lowercase().as_str() {
+                "true" | "t" | "yes" | "on" | "1" => Some(Literal::Bool(true)),
+                "false" | "f" | "no" | "off" | "0" => Some(Literal::Bool(false)),
+                _ => None,
+            },
+            (Literal::Str(s), datatype) if datatype.is_numeric() => {
+                Literal::Double(s.trim().parse().ok()?).cast(datatype, strictness)
+            }
+            (Literal::Int(i), DataType::Str) => Some(Literal::Str(i.to_string())),
+            (Literal::Float(f), DataType::Str) => Some(Literal::Str(f.to_string())),
+            (Literal::Double(d), DataType::Str) => Some(Literal::Str(d.to_string())),
+            (Literal::Bool(b), DataType::Str) => Some(Literal::Str(b.to_string())),
+            _ => None,
+        }
+    }
+}
+
+// whole numbers in range always fit, anything else is rounded when lenient
+fn to_int(v: f64, lenient: bool) -> Option<Literal> {
+    let rounded = v.round();
+    let fits = rounded >= i32::MIN as f64 && rounded <= i32::MAX as f64;
+    (fits && (lenient || rounded == v)).then_some(Literal::Int(rounded as i32))
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub enum Ident {
     Wildcard,
     Named(String),
 }
 
+/// A select inside an expression, kept along with its sql, which is what it
+/// reads as and is compared by.
+#[derive(Debug, Clone)]
+pub struct Subquery {
+    pub sql: String,
+    pub select: Arc<Select>,
+}
+
+impl PartialEq for Subquery {
+    fn eq(&self, other: &Self) -> bool {
+        self.sql == other.sql
+    }
+}
+
+impl PartialOrd for Subquery {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        self.sql.partial_cmp(&other.sql)
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, PartialOrd)]
 pub enum Expression {
     Values(Vec<Literal>),
@@ -84,9 +225,132 @@ pub enum Expression {
         left: Box<Expression>,
         right: Box<Expression>,
     },
+    // a call to a function registered with `Database::register_function`,
+    // or an aggregate, which may only take distinct values
+    Function {
+        name: String,
+        args: Vec<Expression>,
+        distinct: bool,
+    },
+    // a select giving a single value, which may refer to the columns of the
+    // select it's inside of
+    Subquery(Subquery),
+    // a row value like `(a, b)`, only compared with another of as many
+    // values, see `optimizer::compare_rows`
+    Tuple(Vec<Expression>),
+    // `<expr> [NOT] IN (<list>)`, of a single value
+    InList {
+        expr: Box<Expression>,
+        list: Vec<Expression>,
+        negated: bool,
+    },
+    // `<expr> [NOT] IN (<select>)`, in place of which the list of values the
+    // select gives is looked in, see `Database::in_subqueries`
+    InSubquery {
+        expr: Box<Expression>,
+        subquery: Subquery,
+        negated: bool,
+    },
+    // `MATCH (<column>) AGAINST ('<words>')`, how relevant the text in the
+    // column is to the words, see `FullTextIndex::score`
+    MatchAgainst {
+        column: Box<Expression>,
+        terms: String,
+    },
+    // `CASE WHEN <condition> THEN <result> ... [ELSE <result>] END`, with
+    // `CASE <value> WHEN <other> ...` as the conditions `<value> = <other>`
+    Case {
+        branches: Vec<(Expression, Expression)>,
+        otherwise: Option<Box<Expression>>,
+    },
     None,
 }
 
+// sql that reads back as the same expression, e.g. for `EXPLAIN`
+impl std::fmt::Display for Expression {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        // operands that are operations themselves are parenthesized, rather
+        // than working out where precedence makes that unnecessary
+        let operand = |e: &Expression| match e {
+            Expression::Binary { .. } | Expression::Unary { .. } => format!("({e})"),
+            e => e.to_string(),
+        };
+
+        match self {
+            Expression::Values(values) => {
+                let values: Vec<String> = values.iter().map(Literal::to_string).collect();
+                write!(f, "({})", values.join(", "))
+            }
+            Expression::Literal(l) => write!(f, "{l}"),
+            Expression::Ident(Ident::Wildcard) => f.write_str("*"),
+            Expression::Ident(Ident::Named(name)) => f.write_str(&sql::qualified(name)),
+            Expression::IsFalse(e) => write!(f, "{} IS FALSE", operand(e)),
+            Expression::IsTrue(e) => write!(f, "{} IS TRUE", operand(e)),
+            Expression::IsNull(e) => write!(f, "{} IS NULL", operand(e)),
+            Expression::IsNotNull(e) => write!(f, "{} IS NOT NULL", operand(e)),
+            Expression::Unary {
+                operator,
+                expression,
+            } => write!(f, "{operator}{}", operand(expression)),
+            Expression::Binary {
+                operator,
+                left,
+                right,
+            } => write!(f, "{} {operator} {}", operand(left), operand(right)),
+            Expression::Function {
+                name,
+                args,
+                distinct,
+            } => {
+                let args: Vec<String> = args.iter().map(Expression::to_string).collect();
+                let distinct = if *distinct { "DISTINCT " } else { "" };
+                write!(f, "{name}({distinct}{})", args.join(", "))
+            }
+            Expression::Subquery(subquery) => write!(f, "({})", subquery.sql),
+            Expression::Tuple(items) => {
+                let items: Vec<String> = items.iter().map(Expression::to_string).collect();
+                write!(f, "({})", items.join(", "))
+            }
+            Expression::InList {
+                expr,
+                list,
+                negated,
+            } => {
+                let list: Vec<String> = list.iter().map(Expression::to_string).collect();
+                let not = if *negated { "NOT " } else { "" };
+                write!(f, "{} {not}IN ({})", operand(expr), list.join(", "))
+            }
+            Expression::InSubquery {
+                expr,
+                subquery,
+                negated,
+            } => {
+                let not = if *negated { "NOT " } else { "" };
+                write!(f, "{} {not}IN ({})", operand(expr), subquery.sql)
+            }
+            Expression::MatchAgainst { column, terms } => write!(
+                f,
+                "MATCH ({column}) AGAINST ({})",
+                Literal::Str(terms.clone())
+            ),
+            Expression::Case {
+                branches,
+                otherwise,
+            } => {
+                f.write_str("CASE")?;
+                for (condition, result) in branches {
+                    write!(f, " WHEN {condition} THEN {result}")?;
+                }
+                if let Some(otherwise) = otherwise {
+                    write!(f, " ELSE {otherwise}")?;
+                }
+                f.write_str(" END")
+            }
+            Expression::None => Ok(()),
+        }
+    }
+}
+
 impl Expression {
     pub fn from_expr(expr: Expr) -> Result<Expression, Error> {
         match expr {
@@ -103,7 +367,14 @@ impl Expression {
                 sqlparser::ast::Value::Null => Literal::Null,
                 _ => Err(Error::Unsupported(format!("value: {val}")))?,
             })),
-            Expr::Identifier(id) => Ok(Self::Ident(Ident::Named(id.to_string()))),
+            Expr::Identifier(id) => Ok(Self::Ident(Ident::Named(id.value))),
+            // `t.a`, resolved against the columns of joined tables
+            Expr::CompoundIdentifier(ids) => Ok(Self::Ident(Ident::Named(
+                ids.into_iter()
+                    .map(|id| id.value)
+                    .collect::<Vec<_>>()
+                    .join("."),
+            ))),
             Expr::IsFalse(inner) | Expr::IsNotTrue(inner) => Ok(Expression::IsFalse(Box::new(
                 Expression::from_expr(*inner)?,
             ))),
@@ -127,11 +398,18 @@ impl Expression {
                     sqlparser::ast::BinaryOperator::LtEq => Binary::LtEq,
                     sqlparser::ast::BinaryOperator::Eq => Binary::Eq,
                     sqlparser::ast::BinaryOperator::NotEq => Binary::NotEq,
+                    sqlparser::ast::BinaryOperator::And => Binary::And,
+                    sqlparser::ast::BinaryOperator::Or => Binary::Or,
+                    sqlparser::ast::BinaryOperator::PGRegexMatch => Binary::Match,
+                    sqlparser::ast::BinaryOperator::PGRegexIMatch => Binary::IMatch,
+                    sqlparser::ast::BinaryOperator::PGRegexNotMatch => Binary::NotMatch,
+                    sqlparser::ast::BinaryOperator::PGRegexNotIMatch => Binary::NotIMatch,
                     _ => Err(Error::Unsupported(format!("operator: {op}")))?,
                 },
                 left: Box::new(Expression::from_expr(*left)?),
                 right: Box::new(Expression::from_expr(*right)?),
             }),
+            Expr::Nested(expr) => Expression::from_expr(*expr),
             Expr::UnaryOp { op, expr } => Ok(Expression::Unary {
                 operator: match op {
                     sqlparser::ast::UnaryOperator::Plus => Unary::Plus,
@@ -172,10 +450,340 @@ impl Expression {
                         }
                         Ok(Expression::Values(lits))
                     }
-                    _ => Err(Error::Unsupported(format!("function: {fn_name}"))),
+                    _ => {
+                        if function.distinct && Aggregate::from_name(&fn_name).is_none() {
+                            return Err(Error::Unsupported(format!("distinct inside {fn_name}")));
+                        }
+
+                        let mut args = Vec::new();
+                        for arg in function.args.into_iter() {
+                            match arg {
+                                sqlparser::ast::FunctionArg::Named {
+                                    arg: sqlparser::ast::FunctionArgExpr::Expr(expr),
+                                    ..
+                                }
+                                | sqlparser::ast::FunctionArg::Unnamed(
+                                    sqlparser::ast::FunctionArgExpr::Expr(expr),
+                                ) => args.push(Expression::from_expr(expr)?),
+                                // `count(*)`
+                                sqlparser::ast::FunctionArg::Unnamed(
+                                    sqlparser::ast::FunctionArgExpr::Wildcard,
+                                ) if fn_name == "count" => {
+                                    args.push(Expression::Ident(Ident::Wildcard))
+                                }
+                                _ => {
+                                    return Err(Error::Unsupported(format!(
+                                        "wildcard inside {fn_name}"
+                                    )))
+                                }
+                            }
+                        }
+                        Ok(Expression::Function {
+                            name: fn_name,
+                            args,
+                            distinct: function.distinct,
+                        })
+                    }
+                }
+            }
+            Expr::Tuple(exprs) => Ok(Expression::Tuple(
+                exprs
+                    .into_iter()
+                    .map(Expression::from_expr)
+                    .collect::<Result<_, _>>()?,
+            )),
+            Expr::InList {
+                expr,
+                list,
+                negated,
+            } => {
+                let is_row = matches!(*expr, Expr::Tuple(_));
+                let expr = Expression::from_expr(*expr)?;
+                let list = list
+                    .into_iter()
+                    .map(Expression::from_expr)
+                    .collect::<Result<Vec<_>, _>>()?;
+                Ok(match is_row {
+                    // `(a, b) IN ((1, 'x'), (2, 'y'))` is `(a, b) = (1, 'x') OR ...`
+                    true => any_equal(expr, list, negated),
+                    false => Expression::InList {
+                        expr: Box::new(expr),
+                        list,
+                        negated,
+                    },
+                })
+            }
+            Expr::InSubquery {
+                expr,
+                subquery,
+                negated,
+            } => Ok(Expression::InSubquery {
+                expr: Box::new(Expression::from_expr(*expr)?),
+                subquery: Subquery {
+                    sql: subquery.to_string(),
+                    select: Arc::new(Select::new(*subquery)?),
+                },
+                negated,
+            }),
+            // which sqlparser only parses in the mysql and generic dialects
+            Expr::MatchAgainst {
+                mut columns,
+                match_value,
+                opt_search_modifier,
+            } => {
+                if let Some(modifier) = opt_search_modifier {
+                    return Err(Error::Unsupported(format!("MATCH ... AGAINST {modifier}")));
                 }
+                let column = match (columns.pop(), columns.is_empty()) {
+                    (Some(column), true) => column.value,
+                    _ => {
+                        return Err(Error::Unsupported(
+                            "MATCH of other than a single column".to_owned(),
+                        ))
+                    }
+                };
+                let terms = match Expression::from_expr(Expr::Value(match_value))? {
+                    Expression::Literal(Literal::Str(terms)) => terms,
+                    value => {
+                        return Err(Error::InvalidQuery(format!(
+                            "AGAINST {value}, rather than the text to search for"
+                        )))
+                    }
+                };
+                Ok(Expression::MatchAgainst {
+                    column: Box::new(Expression::Ident(Ident::Named(column))),
+                    terms,
+                })
             }
+            Expr::Case {
+                operand,
+                conditions,
+                results,
+                else_result,
+            } => {
+                let operand = operand.map(|e| Expression::from_expr(*e)).transpose()?;
+                let mut branches = Vec::with_capacity(conditions.len());
+                for (condition, result) in conditions.into_iter().zip(results) {
+                    let condition = Expression::from_expr(condition)?;
+                    let condition = match &operand {
+                        Some(operand) => Expression::Binary {
+                            operator: Binary::Eq,
+                            left: Box::new(operand.clone()),
+                            right: Box::new(condition),
+                        },
+                        None => condition,
+                    };
+                    branches.push((condition, Expression::from_expr(result)?));
+                }
+                let otherwise = else_result
+                    .map(|e| Expression::from_expr(*e).map(Box::new))
+                    .transpose()?;
+                Ok(Expression::Case {
+                    branches,
+                    otherwise,
+                })
+            }
+            Expr::Subquery(query) => Ok(Expression::Subquery(Subquery {
+                sql: query.to_string(),
+                select: Arc::new(Select::new(*query)?),
+            })),
             _ => Err(Error::Unsupported(format!("expression: {expr}"))),
         }
     }
+
+    /// The names of the columns the expression refers to, leaving out those
+    /// of its subqueries.
+    pub fn idents(&self) -> Vec<&str> {
+        let mut idents = Vec::new();
+        self.visit(&mut |e| {
+            if let Expression::Ident(Ident::Named(name)) = e {
+                idents.push(name.as_str());
+            }
+        });
+        idents
+    }
+
+    /// Whether there's a select anywhere inside the expression.
+    pub fn has_subquery(&self) -> bool {
+        let mut found = false;
+        self.visit(&mut |e| {
+            found |= matches!(e, Expression::Subquery(_) | Expression::InSubquery { .. })
+        });
+        found
+    }
+
+    /// Whether a function `is_function` is true of is called anywhere inside
+    /// the expression, its subqueries included.
+    pub fn calls(&self, is_function: &impl Fn(&str) -> bool) -> bool {
+        let mut found = false;
+        self.visit(&mut |e| {
+            found |= match e {
+                Expression::Function { name, .. } => is_function(name),
+                Expression::Subquery(s) | Expression::InSubquery { subquery: s, .. } => {
+                    s.select.calls(is_function)
+                }
+                _ => false,
+            }
+        });
+        found
+    }
+
+    /// Replaces the columns `value` gives a value for by that value, other
+    /// than in subqueries.
+    pub fn bind(&mut self, value: &impl Fn(&str) -> Option<Literal>) {
+        match self {
+            Expression::Ident(Ident::Named(name)) => {
+                if let Some(lit) = value(name) {
+                    *self = Expression::Literal(lit);
+                }
+            }
+            Expression::IsFalse(e)
+            | Expression::IsTrue(e)
+            | Expression::IsNull(e)
+            | Expression::IsNotNull(e)
+            | Expression::Unary { expression: e, .. }
+            | Expression::InSubquery { expr: e, .. }
+            | Expression::MatchAgainst { column: e, .. } => e.bind(value),
+            Expression::InList { expr, list, .. } => {
+                expr.bind(value);
+                list.iter_mut().for_each(|i| i.bind(value));
+            }
+            Expression::Case {
+                branches,
+                otherwise,
+            } => {
+                for (condition, result) in branches.iter_mut() {
+                    condition.bind(value);
+                    result.bind(value);
+                }
+                otherwise.iter_mut().for_each(|e| e.bind(value));
+            }
+            Expression::Binary { left, right, .. } => {
+                left.bind(value);
+                right.bind(value);
+            }
+            Expression::Function { args, .. } | Expression::Tuple(args) => {
+                args.iter_mut().for_each(|a| a.bind(value))
+            }
+            Expression::Values(_)
+            | Expression::Literal(_)
+            | Expression::Ident(Ident::Wildcard)
+            | Expression::Subquery(_)
+            | Expression::None => {}
+        }
+    }
+
+    /// Calls `f` on every expression inside this one, innermost first, and
+    /// then on this one itself, other than inside subqueries.
+    pub fn rewrite(
+        &mut self,
+        f: &mut impl FnMut(&mut Expression) -> Result<(), Error>,
+    ) -> Result<(), Error> {
+        match self {
+            Expression::IsFalse(e)
+            | Expression::IsTrue(e)
+            | Expression::IsNull(e)
+            | Expression::IsNotNull(e)
+            | Expression::Unary { expression: e, .. }
+            | Expression::InSubquery { expr: e, .. }
+            | Expression::MatchAgainst { column: e, .. } => e.rewrite(f)?,
+            Expression::InList { expr, list, .. } => {
+                expr.rewrite(f)?;
+                for item in list {
+                    item.rewrite(f)?;
+                }
+            }
+            Expression::Case {
+                branches,
+                otherwise,
+            } => {
+                for (condition, result) in branches {
+                    condition.rewrite(f)?;
+                    result.rewrite(f)?;
+                }
+                if let Some(otherwise) = otherwise {
+                    otherwise.rewrite(f)?;
+                }
+            }
+            Expression::Binary { left, right, .. } => {
+                left.rewrite(f)?;
+                right.rewrite(f)?;
+            }
+            Expression::Function { args, .. } | Expression::Tuple(args) => {
+                for arg in args {
+                    arg.rewrite(f)?;
+                }
+            }
+            Expression::Values(_)
+            | Expression::Literal(_)
+            | Expression::Ident(_)
+            | Expression::Subquery(_)
+            | Expression::None => {}
+        }
+        f(self)
+    }
+
+    fn visit<'e>(&'e self, f: &mut impl FnMut(&'e Expression)) {
+        f(self);
+        match self {
+            Expression::IsFalse(e)
+            | Expression::IsTrue(e)
+            | Expression::IsNull(e)
+            | Expression::IsNotNull(e)
+            | Expression::Unary { expression: e, .. }
+            | Expression::InSubquery { expr: e, .. }
+            | Expression::MatchAgainst { column: e, .. } => e.visit(f),
+            Expression::InList { expr, list, .. } => {
+                expr.visit(f);
+                list.iter().for_each(|i| i.visit(f));
+            }
+            Expression::Case {
+                branches,
+                otherwise,
+            } => {
+                for (condition, result) in branches {
+                    condition.visit(f);
+                    result.visit(f);
+                }
+                otherwise.iter().for_each(|e| e.visit(f));
+            }
+            Expression::Binary { left, right, .. } => {
+                left.visit(f);
+                right.visit(f);
+            }
+            Expression::Function { args, .. } | Expression::Tuple(args) => {
+                args.iter().for_each(|a| a.visit(f))
+            }
+            Expression::Values(_)
+            | Expression::Literal(_)
+            | Expression::Ident(_)
+            | Expression::Subquery(_)
+            | Expression::None => {}
+        }
+    }
+}
+
+/// `<value> IN (<list>)` as what it stands for, `<value> = <item>` for any of
+/// the items of the list, or none of them with `negated`.
+pub fn any_equal(value: Expression, list: Vec<Expression>, negated: bool) -> Expression {
+    let any = list
+        .into_iter()
+        .map(|item| Expression::Binary {
+            operator: Binary::Eq,
+            left: Box::new(value.clone()),
+            right: Box::new(item),
+        })
+        .reduce(|any, m| Expression::Binary {
+            operator: Binary::Or,
+            left: Box::new(any),
+            right: Box::new(m),
+        })
+        .unwrap_or(Expression::Literal(Literal::Bool(false)));
+    match negated {
+        true => Expression::Unary {
+            operator: Unary::Not,
+            expression: Box::new(any),
+        },
+        false => any,
+    }
 }
@@ -0,0 +1,56 @@
+use sqlparser::{
+    keywords::Keyword,
+    parser::{Parser, ParserError},
+    tokenizer::Token,
+};
+
+use super::parser::object_name;
+
+pub(crate) fn is_create_fulltext_index(parser: &Parser) -> bool {
+    matches!(
+        (parser.peek_nth_token(0).token, parser.peek_nth_token(1).token),
+        (Token::Word(c), Token::Word(f)) if c.keyword == Keyword::CREATE && f.value.eq_ignore_ascii_case("FULLTEXT")
+    )
+}
+
+pub(crate) fn is_drop_fulltext_index(parser: &Parser) -> bool {
+    matches!(
+        (parser.peek_nth_token(0).token, parser.peek_nth_token(1).token),
+        (Token::Word(d), Token::Word(f)) if d.keyword == Keyword::DROP && f.value.eq_ignore_ascii_case("FULLTEXT")
+    )
+}
+
+/// Parses `CREATE FULLTEXT INDEX <name> ON <table> (<column>)`, returning the
+/// table, index and column names.
+pub(crate) fn parse_create_fulltext_index(
+    parser: &mut Parser,
+) -> Result<(String, String, String), ParserError> {
+    parser.expect_keyword(Keyword::CREATE)?;
+    // `FULLTEXT`, which sqlparser only has a keyword for in some dialects
+    parser.next_token();
+    parser.expect_keyword(Keyword::INDEX)?;
+    let name = parser.parse_identifier()?.value;
+    parser.expect_keyword(Keyword::ON)?;
+    let table = object_name(parser.parse_object_name()?);
+    parser.expect_token(&Token::LParen)?;
+    let column = parser.parse_identifier()?.value;
+    parser.expect_token(&Token::RParen)?;
+
+    Ok((table, name, column))
+}
+
+/// Parses `DROP FULLTEXT INDEX [IF EXISTS] <name> ON <table>`.
+pub(crate) fn parse_drop_fulltext_index(
+    parser: &mut Parser,
+) -> Result<(String, String, bool), ParserError> {
+    parser.expect_keyword(Keyword::DROP)?;
+    // `FULLTEXT`
+    parser.next_token();
+    parser.expect_keyword(Keyword::INDEX)?;
+    let if_exists = parser.parse_keywords(&[Keyword::IF, Keyword::EXISTS]);
+    let name = parser.parse_identifier()?.value;
+    parser.expect_keyword(Keyword::ON)?;
+    let table = object_name(parser.parse_object_name()?);
+
+    Ok((table, name, if_exists))
+}
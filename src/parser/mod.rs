@@ -1,3 +1,10 @@
+pub mod builder;
+pub mod cache;
 pub mod select;
 pub mod expression;
+pub mod index;
+#[allow(clippy::module_inception)]
 pub mod parser;
+pub mod policy;
+pub mod trigger;
+pub mod user;
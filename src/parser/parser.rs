@@ -1,14 +1,31 @@
-use std::collections::HashMap;
+use std::{collections::HashMap, ops::ControlFlow, str::FromStr};
 
+use serde::{Deserialize, Serialize};
 use sqlparser::{
-    ast::{ColumnDef, Statement},
-    dialect::PostgreSqlDialect,
-    parser::Parser,
+    ast::{ColumnDef, Expr, KillType, ObjectName, Statement, Value, VisitMut, VisitorMut},
+    dialect::{Dialect, GenericDialect, MySqlDialect, PostgreSqlDialect, SQLiteDialect},
+    keywords::{Keyword, RESERVED_FOR_COLUMN_ALIAS, RESERVED_FOR_TABLE_ALIAS},
+    parser::{Parser, ParserError},
+    tokenizer::{Location, Token, TokenWithLocation, Tokenizer, Word},
 };
 
-use crate::{parser::expression::Expression, Error};
+use crate::{
+    functions::Session,
+    optimizer,
+    parser::expression::Expression,
+    sequence::{self, SequenceOptions},
+    sql, Error,
+};
 
-use super::{expression::Literal, select::Select};
+use super::{
+    cache::{self, StatementCache},
+    expression::{Ident, Literal},
+    index,
+    policy::{self, Policy},
+    select::{Select, SelectItem},
+    trigger::{self, Trigger},
+    user::{self, UserOptions},
+};
 
 #[derive(Debug)]
 pub enum Query {
@@ -21,46 +38,847 @@ pub enum Query {
         table: String,
         columns: Vec<String>,
         sources: Vec<Vec<Literal>>,
+        // items of the RETURNING clause, selected from the inserted rows, empty
+        // without one
+        returning: Vec<SelectItem>,
     },
     Update {
         table: String,
-        assignments: HashMap<String, Literal>,
+        // evaluated on each row being updated
+        assignments: HashMap<String, Expression>,
         selection: Option<Expression>,
     },
     Delete {
         table: String,
         selection: Option<Expression>,
+        // items of the RETURNING clause, selected from the deleted rows as they
+        // were before the delete, empty without one
+        returning: Vec<SelectItem>,
     },
     Truncate(String),
     Drop(String),
+    Undrop(String),
+    CreateTrigger {
+        table: String,
+        trigger: Trigger,
+    },
+    DropTrigger {
+        table: String,
+        name: String,
+        if_exists: bool,
+    },
+    Set {
+        variable: String,
+        value: Literal,
+    },
+    Notify {
+        channel: String,
+        payload: String,
+    },
+    Listen(String),
+    // of every channel for `*`
+    Unlisten(String),
+    // the statement running for a connection of the server
+    Kill(u64),
+    CreatePolicy {
+        table: String,
+        policy: Policy,
+    },
+    DropPolicy {
+        table: String,
+        name: String,
+        if_exists: bool,
+    },
+    CreateUser {
+        name: String,
+        password: String,
+        superuser: bool,
+    },
+    AlterUser {
+        name: String,
+        options: UserOptions,
+    },
+    DropUser {
+        name: String,
+        if_exists: bool,
+    },
+    CreateSequence {
+        name: String,
+        options: SequenceOptions,
+        if_not_exists: bool,
+    },
+    DropSequence {
+        name: String,
+        if_exists: bool,
+    },
+    CreateFullTextIndex {
+        table: String,
+        name: String,
+        column: String,
+    },
+    DropFullTextIndex {
+        table: String,
+        name: String,
+        if_exists: bool,
+    },
+    // of a select, executing it with `analyze`
+    Explain {
+        analyze: bool,
+        query: Box<Query>,
+    },
+}
+
+impl Query {
+    /// The kind of statement, e.g. for tracing.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Query::Select(_) => "select",
+            Query::CreateTable { .. } => "create table",
+            Query::Insert { .. } => "insert",
+            Query::Update { .. } => "update",
+            Query::Delete { .. } => "delete",
+            Query::Truncate(_) => "truncate",
+            Query::Drop(_) => "drop table",
+            Query::Undrop(_) => "undrop table",
+            Query::CreateTrigger { .. } => "create trigger",
+            Query::DropTrigger { .. } => "drop trigger",
+            Query::Set { .. } => "set",
+            Query::Notify { .. } => "notify",
+            Query::Listen(_) => "listen",
+            Query::Unlisten(_) => "unlisten",
+            Query::Kill(_) => "kill",
+            Query::Explain { .. } => "explain",
+            Query::CreatePolicy { .. } => "create policy",
+            Query::DropPolicy { .. } => "drop policy",
+            Query::CreateUser { .. } => "create user",
+            Query::AlterUser { .. } => "alter user",
+            Query::DropUser { .. } => "drop user",
+            Query::CreateSequence { .. } => "create sequence",
+            Query::DropSequence { .. } => "drop sequence",
+            Query::CreateFullTextIndex { .. } => "create fulltext index",
+            Query::DropFullTextIndex { .. } => "drop fulltext index",
+        }
+    }
+
+    /// The sql of an insert, update or delete, without its `RETURNING`
+    /// clause, e.g. to record one made through the api rather than from sql.
+    /// `None` for other statements.
+    pub fn write_sql(&self) -> Option<String> {
+        let stmt = match self {
+            Query::Insert {
+                table,
+                columns,
+                sources,
+                ..
+            } => {
+                let columns: Vec<_> = columns.iter().map(|c| sql::ident(c)).collect();
+                let columns = match columns.is_empty() {
+                    true => String::new(),
+                    false => format!(" ({})", columns.join(", ")),
+                };
+                let rows: Vec<String> = sources
+                    .iter()
+                    .map(|row| Expression::Values(row.clone()).to_string())
+                    .collect();
+                format!(
+                    "INSERT INTO {}{columns} VALUES {}",
+                    sql::ident(table),
+                    rows.join(", ")
+                )
+            }
+            Query::Update {
+                table,
+                assignments,
+                selection,
+            } => {
+                let mut assignments: Vec<String> = assignments
+                    .iter()
+                    .map(|(column, expr)| format!("{} = {expr}", sql::ident(column)))
+                    .collect();
+                assignments.sort();
+                let mut stmt = format!(
+                    "UPDATE {} SET {}",
+                    sql::ident(table),
+                    assignments.join(", ")
+                );
+                if let Some(selection) = selection {
+                    stmt += &format!(" WHERE {selection}");
+                }
+                stmt
+            }
+            Query::Delete {
+                table, selection, ..
+            } => {
+                let mut stmt = format!("DELETE FROM {}", sql::ident(table));
+                if let Some(selection) = selection {
+                    stmt += &format!(" WHERE {selection}");
+                }
+                stmt
+            }
+            _ => return None,
+        };
+        Some(stmt)
+    }
+
+    /// Whether the statement leaves the database as it was, so that it can
+    /// be run on a snapshot of it: a select, or the explanation of one, that
+    /// doesn't call the functions of sequences, which change them.
+    pub fn is_read_only(&self) -> bool {
+        match self {
+            Query::Select(select) => !select.calls(&sequence::is_function),
+            Query::Explain { query, .. } => query.is_read_only(),
+            _ => false,
+        }
+    }
+}
+
+/// Bounds on the sql that is accepted, so that hostile input can't exhaust the
+/// memory or stack of the thread executing it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Limits {
+    /// of the sql text given at once, in bytes
+    pub max_size: usize,
+    /// how deeply expressions and subqueries may nest, e.g. `1 + 2 * 3` is 3
+    pub max_depth: usize,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self {
+            max_size: 256 * 1024,
+            max_depth: 64,
+        }
+    }
+}
+
+/// The flavor of sql statements are read as, like `` `quoted` `` identifiers
+/// and `LIMIT <offset>, <count>` for mysql. Changed with `SET dialect`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SqlDialect {
+    #[default]
+    Postgres,
+    MySql,
+    Sqlite,
+    // accepting a bit of everything
+    Generic,
 }
 
-pub fn parse_all(query: &str) -> Result<Vec<Query>, Error> {
+impl SqlDialect {
+    pub(crate) fn dialect(self) -> &'static dyn Dialect {
+        match self {
+            SqlDialect::Postgres => &PostgreSqlDialect {},
+            SqlDialect::MySql => &MySqlDialect {},
+            SqlDialect::Sqlite => &SQLiteDialect {},
+            SqlDialect::Generic => &GenericDialect {},
+        }
+    }
+}
+
+impl FromStr for SqlDialect {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "postgres" | "postgresql" => Ok(SqlDialect::Postgres),
+            "mysql" => Ok(SqlDialect::MySql),
+            "sqlite" => Ok(SqlDialect::Sqlite),
+            "generic" => Ok(SqlDialect::Generic),
+            _ => Err(Error::InvalidQuery(format!(
+                "unknown dialect {s}, expected postgres, mysql, sqlite or generic"
+            ))),
+        }
+    }
+}
+
+/// Parses every statement in `query` as `dialect` has it, with the session
+/// functions in them bound to `session`.
+pub fn parse_all(
+    query: &str,
+    limits: Limits,
+    dialect: SqlDialect,
+    session: &Session,
+) -> Result<Vec<Query>, Error> {
+    let _span = tracing::debug_span!("parse", bytes = query.len()).entered();
+    let tokens = tokenize(query, limits, dialect)?;
+    parse_tokens(query, tokens, limits, dialect, session)
+}
+
+/// Same as `parse_all`, except that a single select is looked up in `cache`
+/// by its sql with the values taken out, to be parsed only the first time it
+/// is seen.
+pub fn parse_all_cached(
+    query: &str,
+    limits: Limits,
+    dialect: SqlDialect,
+    session: &Session,
+    cache: &mut StatementCache,
+) -> Result<Vec<Query>, Error> {
+    let _span = tracing::debug_span!("parse", bytes = query.len()).entered();
+    let tokens = tokenize(query, limits, dialect)?;
+    let normalized = match cache.capacity() > 0 && starts_select(&tokens) {
+        true => cache::normalize(&tokens, dialect),
+        false => None,
+    };
+    let Some(normalized) = normalized else {
+        return parse_tokens(query, tokens, limits, dialect, session);
+    };
+
+    let statement = match cache.get(&normalized.key) {
+        Some(statement) => statement.cloned(),
+        None => {
+            tracing::debug!("statement cache miss");
+            let statement = parse_template(normalized.tokens, &normalized.params, limits, dialect);
+            cache.insert(normalized.key, statement.clone());
+            statement
+        }
+    };
+    let Some(mut stmt) = statement else {
+        return parse_tokens(query, tokens, limits, dialect, session);
+    };
+
+    let start = tokens
+        .iter()
+        .find(|t| !matches!(t.token, Token::Whitespace(_)))
+        .map_or(Location { line: 0, column: 0 }, |t| Location {
+            line: t.location.line,
+            column: t.location.column,
+        });
+    cache::bind_params(&mut stmt, &normalized.params);
+    session
+        .bind(&mut stmt)
+        .and_then(|()| parse(stmt))
+        .map(|query| vec![query])
+        .map_err(|e| e.at(Some(0), start))
+}
+
+fn tokenize(
+    query: &str,
+    limits: Limits,
+    dialect: SqlDialect,
+) -> Result<Vec<TokenWithLocation>, Error> {
+    if query.len() > limits.max_size {
+        return Err(Error::LimitExceeded(format!(
+            "sql of {} bytes, where at most {} are allowed",
+            query.len(),
+            limits.max_size
+        )));
+    }
+
+    let tokens = Tokenizer::new(dialect.dialect(), query)
+        .tokenize_with_location()
+        .map_err(|e| Error::from(ParserError::from(e)).at(None, Location { line: 0, column: 0 }))?;
+    check_prefix_operators(&tokens, limits.max_depth)?;
+    Ok(tablesample_hints(session_function_parens(tokens)))
+}
+
+fn parse_tokens(
+    sql: &str,
+    tokens: Vec<TokenWithLocation>,
+    limits: Limits,
+    dialect: SqlDialect,
+    session: &Session,
+) -> Result<Vec<Query>, Error> {
     let mut res = Vec::new();
+    parse_statements(sql, tokens, limits, dialect, session, &mut res)?;
+    Ok(res)
+}
+
+// the statements of `tokens`, the tokens of `sql`, appended to `res`
+fn parse_statements(
+    sql: &str,
+    tokens: Vec<TokenWithLocation>,
+    limits: Limits,
+    dialect: SqlDialect,
+    session: &Session,
+    res: &mut Vec<Query>,
+) -> Result<(), Error> {
+    let mut parser = Parser::new(dialect.dialect())
+        .with_recursion_limit(limits.max_depth)
+        .with_tokens_with_locations(tokens);
 
-    let ast = Parser::parse_sql(&PostgreSqlDialect {}, query)?;
+    // same as `Parser::parse_statements`, except that it also handles the
+    // statements sqlparser doesn't know about
+    let mut expecting_statement_delimiter = false;
+    loop {
+        while parser.consume_token(&Token::SemiColon) {
+            expecting_statement_delimiter = false;
+        }
+
+        match parser.peek_token().token {
+            Token::EOF => break,
+            Token::Word(word) if expecting_statement_delimiter && word.keyword == Keyword::END => {
+                break
+            }
+            _ => {}
+        }
+
+        let statement = res.len();
+        let start = parser.peek_token().location;
+        if expecting_statement_delimiter {
+            // the previous statement went on for too long
+            return parser
+                .expected("end of statement", parser.peek_token())
+                .map_err(|e| Error::from(e).at(Some(statement - 1), start));
+        }
+
+        // the statements after a `SET dialect` read as that dialect, so
+        // they're tokenized again, with the ones before blanked out so that
+        // errors are still located in `sql`
+        if let Some(next) = res.last().and_then(set_dialect).filter(|d| *d != dialect) {
+            let rest = blank_before(sql, start);
+            let tokens = tokenize(&rest, limits, next)?;
+            return parse_statements(&rest, tokens, limits, next, session, res);
+        }
+
+        let query =
+            parse_one(&mut parser, limits.max_depth, dialect, session).map_err(|e| match e {
+                Error::ParsingError(ParserError::RecursionLimitExceeded) => {
+                    too_deep(limits.max_depth)
+                }
+                e => e.at(Some(statement), start),
+            })?;
 
-    for stmt in ast {
-        let query = parse(stmt)?;
         res.push(query);
+        expecting_statement_delimiter = true;
     }
 
-    Ok(res)
+    Ok(())
+}
+
+// the dialect `query` changes to, if it's a `SET dialect`
+fn set_dialect(query: &Query) -> Option<SqlDialect> {
+    match query {
+        Query::Set {
+            variable,
+            value: Literal::Str(value),
+        } if variable.eq_ignore_ascii_case("dialect") => value.parse().ok(),
+        _ => None,
+    }
+}
+
+// `query` with everything before `location` made spaces, keeping its line
+// breaks so that the rest stays where it was
+fn blank_before(query: &str, location: Location) -> String {
+    let (mut line, mut column) = (1, 1);
+    query
+        .chars()
+        .map(|c| {
+            let before = (line, column) < (location.line, location.column);
+            if c == '\n' {
+                line += 1;
+                column = 1;
+            } else {
+                column += 1;
+            }
+            match c {
+                '\n' => c,
+                _ if before => ' ',
+                _ => c,
+            }
+        })
+        .collect()
+}
+
+fn starts_select(tokens: &[TokenWithLocation]) -> bool {
+    let first = tokens
+        .iter()
+        .find(|t| !matches!(t.token, Token::Whitespace(_)));
+    match first.map(|t| &t.token) {
+        Some(Token::Word(w)) => matches!(w.keyword, Keyword::SELECT | Keyword::WITH),
+        Some(Token::LParen) => true,
+        _ => false,
+    }
+}
+
+// the select in `tokens`, with the placeholders `cache::normalize` put in them,
+// or `None` when it isn't one that can be cached, leaving it to `parse_tokens`
+// to say what's wrong with it, if anything
+fn parse_template(
+    tokens: Vec<TokenWithLocation>,
+    params: &[Value],
+    limits: Limits,
+    dialect: SqlDialect,
+) -> Option<Statement> {
+    let mut parser = Parser::new(dialect.dialect())
+        .with_recursion_limit(limits.max_depth)
+        .with_tokens_with_locations(tokens);
+    let mut stmt = parser.parse_statement().ok()?;
+    while parser.consume_token(&Token::SemiColon) {}
+    if parser.peek_token().token != Token::EOF || !matches!(stmt, Statement::Query(_)) {
+        return None;
+    }
+    check_depth(&mut stmt, limits.max_depth).ok()?;
+
+    // every value has to have ended up where it can be put back
+    let mut bound = stmt.clone();
+    cache::bind_params(&mut bound, params).then_some(stmt)
+}
+
+// trigger and policy bodies are kept unbound, for the sessions they're used in
+fn parse_one(
+    parser: &mut Parser,
+    max_depth: usize,
+    dialect: SqlDialect,
+    session: &Session,
+) -> Result<Query, Error> {
+    if trigger::is_create_trigger(parser) {
+        let (table, trigger) = trigger::parse_create_trigger(parser, max_depth, dialect)?;
+        Ok(Query::CreateTrigger { table, trigger })
+    } else if trigger::is_drop_trigger(parser) {
+        let (table, name, if_exists) = trigger::parse_drop_trigger(parser)?;
+        Ok(Query::DropTrigger {
+            table,
+            name,
+            if_exists,
+        })
+    } else if policy::is_create_policy(parser) {
+        let (table, policy) = policy::parse_create_policy(parser, max_depth, dialect)?;
+        Ok(Query::CreatePolicy { table, policy })
+    } else if policy::is_drop_policy(parser) {
+        let (table, name, if_exists) = policy::parse_drop_policy(parser)?;
+        Ok(Query::DropPolicy {
+            table,
+            name,
+            if_exists,
+        })
+    } else if user::is_create_user(parser) {
+        let (name, options) = user::parse_user(parser)?;
+        Ok(Query::CreateUser {
+            name,
+            password: options.password.unwrap_or_default(),
+            superuser: options.superuser.unwrap_or_default(),
+        })
+    } else if user::is_alter_user(parser) {
+        let (name, options) = user::parse_user(parser)?;
+        Ok(Query::AlterUser { name, options })
+    } else if user::is_drop_user(parser) {
+        let (name, if_exists) = user::parse_drop_user(parser)?;
+        Ok(Query::DropUser { name, if_exists })
+    } else if index::is_create_fulltext_index(parser) {
+        let (table, name, column) = index::parse_create_fulltext_index(parser)?;
+        Ok(Query::CreateFullTextIndex {
+            table,
+            name,
+            column,
+        })
+    } else if index::is_drop_fulltext_index(parser) {
+        let (table, name, if_exists) = index::parse_drop_fulltext_index(parser)?;
+        Ok(Query::DropFullTextIndex {
+            table,
+            name,
+            if_exists,
+        })
+    } else if is_undrop_table(parser) {
+        parser.next_token();
+        parser.expect_keyword(Keyword::TABLE)?;
+        Ok(Query::Undrop(object_name(parser.parse_object_name()?)))
+    } else if let Some(query) = parse_channel_statement(parser)? {
+        Ok(query)
+    } else {
+        let mut stmt = parser.parse_statement()?;
+        check_depth(&mut stmt, max_depth)?;
+        session.bind(&mut stmt)?;
+        parse(stmt)
+    }
+}
+
+// `UNDROP TABLE <name>`, which sqlparser has no keyword for
+fn is_undrop_table(parser: &Parser) -> bool {
+    matches!(
+        (parser.peek_nth_token(0).token, parser.peek_nth_token(1).token),
+        (Token::Word(u), Token::Word(t)) if u.value.eq_ignore_ascii_case("UNDROP") && t.keyword == Keyword::TABLE
+    )
+}
+
+// `NOTIFY <channel> [, '<payload>']`, `LISTEN <channel>` and
+// `UNLISTEN {<channel> | *}`, which sqlparser has no keywords for either
+fn parse_channel_statement(parser: &mut Parser) -> Result<Option<Query>, Error> {
+    let Token::Word(word) = parser.peek_token().token else {
+        return Ok(None);
+    };
+
+    let query = match word.value.to_uppercase().as_str() {
+        "NOTIFY" => {
+            parser.next_token();
+            let channel = parser.parse_identifier()?.value;
+            let payload = match parser.consume_token(&Token::Comma) {
+                true => parser.parse_literal_string()?,
+                false => String::new(),
+            };
+            Query::Notify { channel, payload }
+        }
+        "LISTEN" => {
+            parser.next_token();
+            Query::Listen(parser.parse_identifier()?.value)
+        }
+        "UNLISTEN" => {
+            parser.next_token();
+            match parser.consume_token(&Token::Mul) {
+                true => Query::Unlisten("*".to_owned()),
+                false => Query::Unlisten(parser.parse_identifier()?.value),
+            }
+        }
+        _ => return Ok(None),
+    };
+
+    Ok(Some(query))
+}
+
+// the parser recurses once for every prefix operator, like the minuses in
+// `- - - 1`, without counting them towards its recursion limit
+fn check_prefix_operators(tokens: &[TokenWithLocation], max_depth: usize) -> Result<(), Error> {
+    let mut run = 0;
+    for t in tokens {
+        match &t.token {
+            Token::Whitespace(_) => continue,
+            Token::Minus
+            | Token::Plus
+            | Token::Tilde
+            | Token::AtSign
+            | Token::DoubleExclamationMark
+            | Token::PGSquareRoot
+            | Token::PGCubeRoot => run += 1,
+            Token::Word(w) if w.keyword == Keyword::NOT => run += 1,
+            _ => run = 0,
+        }
+
+        if run > max_depth {
+            return Err(too_deep(max_depth));
+        }
+    }
+
+    Ok(())
+}
+
+// sqlparser only takes `current_user` and `session_user` without
+// parentheses, as the sql standard has them, so drops the `()` after them
+fn session_function_parens(tokens: Vec<TokenWithLocation>) -> Vec<TokenWithLocation> {
+    let significant = |t: &TokenWithLocation| !matches!(t.token, Token::Whitespace(_));
+    let mut out: Vec<TokenWithLocation> = Vec::with_capacity(tokens.len());
+    let mut tokens = tokens.into_iter();
+    while let Some(t) = tokens.next() {
+        let session = matches!(
+            &t.token,
+            Token::Word(w) if w.keyword == Keyword::CURRENT_USER || w.keyword == Keyword::SESSION_USER
+        );
+        out.push(t);
+        if !session {
+            continue;
+        }
+
+        let rest: Vec<_> = tokens.clone().filter(significant).take(2).collect();
+        if matches!(
+            rest.as_slice(),
+            [l, r] if l.token == Token::LParen && r.token == Token::RParen
+        ) {
+            for t in tokens.by_ref() {
+                if t.token == Token::RParen {
+                    break;
+                }
+            }
+        }
+    }
+
+    out
+}
+
+// sqlparser has no `TABLESAMPLE`, so `<table> TABLESAMPLE [BERNOULLI | SYSTEM]
+// (<size> [PERCENT | ROWS]) [REPEATABLE (<seed>)]`, or `SAMPLE` in its place,
+// is turned into the table hint `WITH (tablesample(<size>, '<unit>'
+// [, <seed>]))`, which `Select::new` reads back as a `Sample`
+fn tablesample_hints(tokens: Vec<TokenWithLocation>) -> Vec<TokenWithLocation> {
+    let is_word =
+        |t: &Token, word: &str| matches!(t, Token::Word(w) if w.value.eq_ignore_ascii_case(word));
+    let significant: Vec<(usize, &Token)> = tokens
+        .iter()
+        .enumerate()
+        .filter(|(_, t)| !matches!(t.token, Token::Whitespace(_)))
+        .map(|(i, t)| (i, &t.token))
+        .collect();
+
+    // by the position of `TABLESAMPLE`, the position of the last token of
+    // the clause and the tokens of the hint
+    let mut hints = HashMap::new();
+    for (k, &(i, token)) in significant.iter().enumerate() {
+        // `SAMPLE` only right after the name or alias of a table, so that it
+        // can still be the name of a function
+        let after_table = match k.checked_sub(1).map(|k| significant[k].1) {
+            Some(Token::Word(w)) => w.keyword == Keyword::NoKeyword || w.quote_style.is_some(),
+            _ => false,
+        };
+        if !is_word(token, "TABLESAMPLE") && !(is_word(token, "SAMPLE") && after_table) {
+            continue;
+        }
+
+        let mut rest = &significant[k + 1..];
+        if let [(_, method), after @ ..] = rest {
+            if is_word(method, "BERNOULLI") || is_word(method, "SYSTEM") {
+                rest = after;
+            }
+        }
+        let [(_, Token::LParen), (_, Token::Number(size, _)), rest @ ..] = rest else {
+            continue;
+        };
+        let (unit, rest) = match rest {
+            [(_, unit), rest @ ..] if is_word(unit, "PERCENT") => ("percent", rest),
+            [(_, unit), rest @ ..] if is_word(unit, "ROWS") => ("rows", rest),
+            rest => ("percent", rest),
+        };
+        let [(end, Token::RParen), rest @ ..] = rest else {
+            continue;
+        };
+        let (seed, end) = match rest {
+            [(_, repeatable), (_, Token::LParen), (_, Token::Number(seed, _)), (end, Token::RParen), ..]
+                if is_word(repeatable, "REPEATABLE") =>
+            {
+                (Some(seed), *end)
+            }
+            _ => (None, *end),
+        };
+
+        let function = Token::Word(Word {
+            value: "tablesample".to_owned(),
+            quote_style: None,
+            keyword: Keyword::NoKeyword,
+        });
+        let mut hint = vec![
+            Token::make_keyword("WITH"),
+            Token::LParen,
+            function,
+            Token::LParen,
+            Token::Number(size.clone(), false),
+            Token::Comma,
+            Token::SingleQuotedString(unit.to_owned()),
+        ];
+        if let Some(seed) = seed {
+            hint.extend([Token::Comma, Token::Number(seed.clone(), false)]);
+        }
+        hint.extend([Token::RParen, Token::RParen]);
+        hints.insert(i, (end, hint));
+    }
+
+    let mut out = Vec::with_capacity(tokens.len());
+    let mut skip_to = None;
+    for (i, t) in tokens.into_iter().enumerate() {
+        if skip_to.is_some_and(|end| i <= end) {
+            continue;
+        }
+        let Some((end, hint)) = hints.remove(&i) else {
+            out.push(t);
+            continue;
+        };
+        let (line, column) = (t.location.line, t.location.column);
+        out.extend(
+            hint.into_iter()
+                .map(|h| TokenWithLocation::new(h, line, column)),
+        );
+        skip_to = Some(end);
+    }
+
+    out
+}
+
+fn too_deep(max_depth: usize) -> Error {
+    Error::LimitExceeded(format!(
+        "expression nested more than {max_depth} levels deep"
+    ))
+}
+
+/// Fails for expressions in `stmt` nested deeper than `max_depth`, which the
+/// parser's own limit doesn't catch for operators chained one after another,
+/// like `1 + 1 + 1 + ...`.
+pub(crate) fn check_depth(stmt: &mut impl VisitMut, max_depth: usize) -> Result<(), Error> {
+    let mut limit = DepthLimit {
+        depth: 0,
+        max: max_depth,
+        exceeded: false,
+    };
+    _ = stmt.visit(&mut limit);
+
+    match limit.exceeded {
+        true => Err(too_deep(max_depth)),
+        false => Ok(()),
+    }
+}
+
+struct DepthLimit {
+    depth: usize,
+    max: usize,
+    exceeded: bool,
+}
+
+impl VisitorMut for DepthLimit {
+    type Break = ();
+
+    fn pre_visit_expr(&mut self, expr: &mut Expr) -> ControlFlow<()> {
+        self.depth += 1;
+        if self.depth > self.max {
+            // sqlparser drops (and displays) expressions recursively, which
+            // would overflow the stack for what's below here
+            dismantle(std::mem::replace(expr, Expr::Value(Value::Null)));
+            self.exceeded = true;
+        }
+
+        ControlFlow::Continue(())
+    }
+
+    fn post_visit_expr(&mut self, _: &mut Expr) -> ControlFlow<()> {
+        self.depth -= 1;
+        ControlFlow::Continue(())
+    }
+}
+
+// drops `expr` without recursing through the operators that can be chained
+fn dismantle(expr: Expr) {
+    let mut stack = vec![expr];
+    while let Some(expr) = stack.pop() {
+        match expr {
+            Expr::BinaryOp { left, right, .. } => {
+                stack.push(*left);
+                stack.push(*right);
+            }
+            Expr::UnaryOp { expr, .. }
+            | Expr::Nested(expr)
+            | Expr::Cast { expr, .. }
+            | Expr::Collate { expr, .. }
+            | Expr::IsNull(expr)
+            | Expr::IsNotNull(expr)
+            | Expr::IsTrue(expr)
+            | Expr::IsNotTrue(expr)
+            | Expr::IsFalse(expr)
+            | Expr::IsNotFalse(expr) => stack.push(*expr),
+            _ => {}
+        }
+    }
 }
 
 pub fn parse(stmt: Statement) -> Result<Query, Error> {
     match stmt {
-        Statement::CreateTable { name, columns, .. } => Ok(Query::CreateTable {
-            name: name.to_string(),
-            columns,
-        }),
-        Statement::Truncate { table_name, .. } => Ok(Query::Truncate(table_name.to_string())),
+        Statement::CreateTable { name, columns, .. } => {
+            let [table] = name.0.as_slice() else {
+                return Err(Error::InvalidSchema(format!(
+                    "table name {name} can't be qualified"
+                )));
+            };
+            check_identifier("table", table)?;
+            for c in &columns {
+                check_identifier("column", &c.name)?;
+            }
+
+            Ok(Query::CreateTable {
+                name: object_name(name),
+                columns,
+            })
+        }
+        Statement::Truncate { table_name, .. } => Ok(Query::Truncate(object_name(table_name))),
         Statement::Query(q) => Ok(Query::Select(Select::new(*q)?)),
         Statement::Insert {
             into,
             table_name,
             columns,
             source,
+            returning,
             ..
         } => {
             if !into {
@@ -83,7 +901,7 @@ pub fn parse(stmt: Statement) -> Result<Query, Error> {
                     for outer in &v.rows {
                         let mut source_vec = Vec::new();
                         for e in outer {
-                            match Expression::from_expr(e.clone())? {
+                            match optimizer::simplify(Expression::from_expr(e.clone())?) {
                                 Expression::Literal(l) => {
                                     source_vec.push(l);
                                 }
@@ -105,9 +923,14 @@ pub fn parse(stmt: Statement) -> Result<Query, Error> {
             };
 
             Ok(Query::Insert {
-                table: table_name.to_string(),
-                columns: columns.into_iter().map(|v| v.to_string()).collect(),
+                table: object_name(table_name),
+                columns: columns.into_iter().map(|v| v.value).collect(),
                 sources,
+                returning: returning
+                    .into_iter()
+                    .flatten()
+                    .map(SelectItem::new)
+                    .collect::<Result<_, _>>()?,
             })
         }
         Statement::Update {
@@ -122,7 +945,7 @@ pub fn parse(stmt: Statement) -> Result<Query, Error> {
             }
 
             let tbl_name = match table.relation {
-                sqlparser::ast::TableFactor::Table { name, .. } => name.to_string(),
+                sqlparser::ast::TableFactor::Table { name, .. } => object_name(name),
                 _ => {
                     return Err(Error::Unsupported(
                         "update with complex table relation".to_owned(),
@@ -140,15 +963,8 @@ pub fn parse(stmt: Statement) -> Result<Query, Error> {
                     ));
                 }
                 let col_name = assignment.id[0].value.clone();
-                let value = Expression::from_expr(assignment.value)?;
-
-                if let Expression::Literal(l) = value {
-                    assign_map.insert(col_name, l);
-                } else {
-                    return Err(Error::Unsupported(
-                        "non literal in update query".to_string(),
-                    ));
-                }
+                let value = optimizer::simplify(Expression::from_expr(assignment.value)?);
+                assign_map.insert(col_name, value);
             }
 
             let selection = if let Some(expr) = selection {
@@ -164,7 +980,10 @@ pub fn parse(stmt: Statement) -> Result<Query, Error> {
             })
         }
         Statement::Delete {
-            from, selection, ..
+            from,
+            selection,
+            returning,
+            ..
         } => {
             log::info!("delete: from: {from:?}");
             log::info!("delete: selection: {selection:?}");
@@ -176,7 +995,7 @@ pub fn parse(stmt: Statement) -> Result<Query, Error> {
             }
 
             let tbl_name = match &from[0].relation {
-                sqlparser::ast::TableFactor::Table { name, .. } => name.to_string(),
+                sqlparser::ast::TableFactor::Table { name, .. } => object_name(name.clone()),
                 _ => {
                     return Err(Error::Unsupported(
                         "delete with complex table relation".to_owned(),
@@ -193,10 +1012,28 @@ pub fn parse(stmt: Statement) -> Result<Query, Error> {
             Ok(Query::Delete {
                 table: tbl_name,
                 selection,
+                returning: returning
+                    .into_iter()
+                    .flatten()
+                    .map(SelectItem::new)
+                    .collect::<Result<_, _>>()?,
             })
         }
+        Statement::CreateSequence {
+            if_not_exists,
+            name,
+            sequence_options,
+            ..
+        } => Ok(Query::CreateSequence {
+            name: object_name(name),
+            options: sequence_options(sequence_options)?,
+            if_not_exists,
+        }),
         Statement::Drop {
-            object_type, names, ..
+            object_type,
+            names,
+            if_exists,
+            ..
         } => match object_type {
             sqlparser::ast::ObjectType::Table => {
                 if names.len() != 1 {
@@ -205,14 +1042,129 @@ pub fn parse(stmt: Statement) -> Result<Query, Error> {
                     ));
                 }
 
-                let name = &names[0];
+                let name = names[0].clone();
 
-                Ok(Query::Drop(name.to_string()))
+                Ok(Query::Drop(object_name(name)))
             }
+            sqlparser::ast::ObjectType::Sequence => match <[_; 1]>::try_from(names) {
+                Ok([name]) => Ok(Query::DropSequence {
+                    name: object_name(name),
+                    if_exists,
+                }),
+                Err(_) => Err(Error::InvalidQuery(
+                    "drop sequence must have one sequence name".to_owned(),
+                )),
+            },
             _ => Err(Error::InvalidOperation(
-                "drop only allowed for tables".to_owned(),
+                "drop only allowed for tables and sequences".to_owned(),
             )),
         },
+        Statement::SetVariable {
+            variable, value, ..
+        } => {
+            let [value] = <[_; 1]>::try_from(value).map_err(|_| {
+                Error::InvalidQuery(format!("set {variable} to more than one value"))
+            })?;
+
+            // `set x = lenient` names the value rather than quoting it
+            let value = match optimizer::simplify(Expression::from_expr(value)?) {
+                Expression::Literal(l) => l,
+                Expression::Ident(Ident::Named(name)) => Literal::Str(name),
+                _ => {
+                    return Err(Error::Unsupported(format!(
+                        "non literal value for {variable}"
+                    )))
+                }
+            };
+
+            Ok(Query::Set {
+                variable: variable.to_string(),
+                value,
+            })
+        }
+        Statement::Explain {
+            analyze, statement, ..
+        } => Ok(Query::Explain {
+            analyze,
+            query: Box::new(parse(*statement)?),
+        }),
+        Statement::Kill { modifier, id } => match modifier {
+            None | Some(KillType::Connection | KillType::Query) => Ok(Query::Kill(id)),
+            Some(KillType::Mutation) => Err(Error::Unsupported(format!("{stmt}"))),
+        },
         _ => Err(Error::Unsupported(format!("unsupported statement: {stmt}"))),
     }
 }
+
+/// A possibly qualified name, without the quotes that are only there to get
+/// it past the parser, e.g. `"select"` is just `select`.
+pub fn object_name(name: ObjectName) -> String {
+    name.0
+        .into_iter()
+        .map(|id| id.value)
+        .collect::<Vec<_>>()
+        .join(".")
+}
+
+// the options of `CREATE SEQUENCE`, whose values have to be integers
+fn sequence_options(
+    options: Vec<sqlparser::ast::SequenceOptions>,
+) -> Result<SequenceOptions, Error> {
+    use sqlparser::ast::{MinMaxValue, SequenceOptions as Options};
+
+    let int = |what: &str, expr: Expr| match optimizer::simplify(Expression::from_expr(expr)?) {
+        Expression::Literal(Literal::Int(i)) => Ok(i),
+        e => Err(Error::InvalidQuery(format!(
+            "sequence {what} of {e}, rather than an integer"
+        ))),
+    };
+
+    let mut out = SequenceOptions::default();
+    for option in options {
+        match option {
+            Options::IncrementBy(e, _) => out.increment = Some(int("increment", e)?),
+            Options::StartWith(e, _) => out.start = Some(int("start", e)?),
+            Options::MinValue(MinMaxValue::Some(e)) => out.min = Some(int("minimum", e)?),
+            Options::MaxValue(MinMaxValue::Some(e)) => out.max = Some(int("maximum", e)?),
+            // `NO MINVALUE` and `NO MAXVALUE`, which are the defaults
+            Options::MinValue(_) | Options::MaxValue(_) => {}
+            // how many values are handed out ahead of time, which makes no
+            // difference when they're all handed out by the same process
+            Options::Cache(_) => {}
+            // which is `NO CYCLE` when true
+            Options::Cycle(no) => out.cycle = !no,
+        }
+    }
+
+    Ok(out)
+}
+
+// a name given to a new table or column has to be written the same way again
+// to refer to it, which isn't possible for some of them
+fn check_identifier(kind: &str, id: &sqlparser::ast::Ident) -> Result<(), Error> {
+    let name = &id.value;
+    if name.trim().is_empty() {
+        return Err(Error::InvalidSchema(format!("{kind} name can't be empty")));
+    }
+
+    if name.contains('.') {
+        return Err(Error::InvalidSchema(format!(
+            "{kind} name {name} can't contain `.`, which separates table and column names"
+        )));
+    }
+
+    let reserved = match Token::make_word(name, None) {
+        Token::Word(w) => {
+            RESERVED_FOR_TABLE_ALIAS.contains(&w.keyword)
+                || RESERVED_FOR_COLUMN_ALIAS.contains(&w.keyword)
+        }
+        _ => false,
+    };
+    if reserved && id.quote_style.is_none() {
+        return Err(Error::InvalidSchema(format!(
+            "{kind} name {name} is a reserved word, quote it as \"{name}\" here and wherever it is used"
+        )));
+    }
+
+    Ok(())
+}
@@ -0,0 +1,150 @@
+use serde::{Deserialize, Serialize};
+use sqlparser::{
+    keywords::Keyword,
+    parser::{Parser, ParserError},
+    tokenizer::Token,
+};
+
+use crate::{functions::Session, Error};
+
+use super::{
+    expression::Expression,
+    parser::{check_depth, object_name, SqlDialect},
+};
+
+/// The statements a policy applies to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum PolicyCommand {
+    All,
+    Select,
+    Update,
+    Delete,
+}
+
+/// A row level security policy as stored on its table. Once a table has any,
+/// users only see, update, delete and are notified of the rows that one of
+/// the policies applying to them allows. The predicate is kept as sql, like
+/// trigger bodies are.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Policy {
+    pub name: String,
+    pub command: PolicyCommand,
+    // every user when empty, for `TO PUBLIC`
+    pub roles: Vec<String>,
+    pub using: String,
+    // that the predicate is written in
+    #[serde(default)]
+    pub dialect: SqlDialect,
+}
+
+impl Policy {
+    pub fn applies_to(&self, user: &str, command: PolicyCommand) -> bool {
+        (self.command == PolicyCommand::All || self.command == command)
+            && (self.roles.is_empty() || self.roles.iter().any(|r| r.eq_ignore_ascii_case(user)))
+    }
+
+    /// The predicate, with the session functions in it bound to `session`.
+    pub fn predicate(&self, session: &Session) -> Result<Expression, Error> {
+        let mut expr = Parser::new(self.dialect.dialect())
+            .try_with_sql(&self.using)?
+            .parse_expr()?;
+        session.bind(&mut expr)?;
+
+        Expression::from_expr(expr)
+    }
+}
+
+pub(crate) fn is_create_policy(parser: &Parser) -> bool {
+    matches!(
+        (parser.peek_nth_token(0).token, parser.peek_nth_token(1).token),
+        (Token::Word(c), Token::Word(p)) if c.keyword == Keyword::CREATE && p.value.eq_ignore_ascii_case("POLICY")
+    )
+}
+
+pub(crate) fn is_drop_policy(parser: &Parser) -> bool {
+    matches!(
+        (parser.peek_nth_token(0).token, parser.peek_nth_token(1).token),
+        (Token::Word(d), Token::Word(p)) if d.keyword == Keyword::DROP && p.value.eq_ignore_ascii_case("POLICY")
+    )
+}
+
+/// Parses `CREATE POLICY <name> ON <table> [FOR {ALL | SELECT | UPDATE | DELETE}]
+/// [TO {<user> | PUBLIC} [, ...]] USING (<predicate>)`, returning the table
+/// name along with the policy.
+pub(crate) fn parse_create_policy(
+    parser: &mut Parser,
+    max_depth: usize,
+    dialect: SqlDialect,
+) -> Result<(String, Policy), Error> {
+    parser.expect_keyword(Keyword::CREATE)?;
+    // `POLICY`, which sqlparser has no keyword for
+    parser.next_token();
+    let name = parser.parse_identifier()?.value;
+    parser.expect_keyword(Keyword::ON)?;
+    let table = object_name(parser.parse_object_name()?);
+
+    let mut command = PolicyCommand::All;
+    if parser.parse_keyword(Keyword::FOR) {
+        command = match parser.parse_one_of_keywords(&[
+            Keyword::ALL,
+            Keyword::SELECT,
+            Keyword::UPDATE,
+            Keyword::DELETE,
+        ]) {
+            Some(Keyword::ALL) => PolicyCommand::All,
+            Some(Keyword::SELECT) => PolicyCommand::Select,
+            Some(Keyword::UPDATE) => PolicyCommand::Update,
+            Some(Keyword::DELETE) => PolicyCommand::Delete,
+            _ => {
+                return parser
+                    .expected("ALL, SELECT, UPDATE or DELETE", parser.peek_token())
+                    .map_err(Error::from)
+            }
+        };
+    }
+
+    let mut roles = Vec::new();
+    if parser.parse_keyword(Keyword::TO) {
+        loop {
+            roles.push(parser.parse_identifier()?.value);
+            if !parser.consume_token(&Token::Comma) {
+                break;
+            }
+        }
+    }
+    if roles.iter().any(|r| r.eq_ignore_ascii_case("public")) {
+        roles.clear();
+    }
+
+    parser.expect_keyword(Keyword::USING)?;
+    parser.expect_token(&Token::LParen)?;
+    let mut using = parser.parse_expr()?;
+    parser.expect_token(&Token::RParen)?;
+    check_depth(&mut using, max_depth)?;
+
+    Ok((
+        table,
+        Policy {
+            name,
+            command,
+            roles,
+            using: using.to_string(),
+            dialect,
+        },
+    ))
+}
+
+/// Parses `DROP POLICY [IF EXISTS] <name> ON <table>`.
+pub(crate) fn parse_drop_policy(
+    parser: &mut Parser,
+) -> Result<(String, String, bool), ParserError> {
+    parser.expect_keyword(Keyword::DROP)?;
+    // `POLICY`
+    parser.next_token();
+    let if_exists = parser.parse_keywords(&[Keyword::IF, Keyword::EXISTS]);
+    let name = parser.parse_identifier()?.value;
+    parser.expect_keyword(Keyword::ON)?;
+    let table = object_name(parser.parse_object_name()?);
+
+    Ok((table, name, if_exists))
+}
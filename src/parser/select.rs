@@ -1,60 +1,463 @@
-use crate::Error;
+use crate::{aggregate, sample::Sample, Error};
 
-use super::expression::Expression;
-use sqlparser::ast::Query;
+use super::{
+    expression::{Expression, Ident, Literal},
+    parser::object_name,
+};
+use sqlparser::ast::{Expr, Query, SetExpr, SetQuantifier};
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JoinKind {
+    Inner,
+    Left,
+    Cross,
+}
+
+#[derive(Debug, Clone)]
+pub struct Join {
+    pub table: String,
+    // of a table function, like `generate_series(1, 10)`
+    pub args: Option<Vec<Expression>>,
+    pub alias: Option<String>,
+    pub sample: Option<Sample>,
+    pub kind: JoinKind,
+    pub on: Option<Expression>,
+    // columns of both sides that have to be equal, given by `USING (...)`
+    pub using: Vec<String>,
+    // joins on every column both sides have
+    pub natural: bool,
+}
+
+/// A key of `ORDER BY <expr> [ASC | DESC] [NULLS FIRST | LAST]`. Nulls sort
+/// as if larger than any value unless placed otherwise, and an int is the
+/// position of an expression of the projection, counting from 1.
+#[derive(Debug, Clone)]
+pub struct OrderBy {
+    pub expr: Expression,
+    pub desc: bool,
+    pub nulls_first: bool,
+}
+
+/// An expression of the select list, and what its column is called instead
+/// of the name it would get, given by `AS`.
+#[derive(Debug, Clone)]
+pub struct SelectItem {
+    pub expr: Expression,
+    pub alias: Option<String>,
+}
+
+impl SelectItem {
+    pub fn new(item: sqlparser::ast::SelectItem) -> Result<Self, Error> {
+        match item {
+            sqlparser::ast::SelectItem::UnnamedExpr(exp) => Ok(SelectItem {
+                expr: Expression::from_expr(exp)?,
+                alias: None,
+            }),
+            sqlparser::ast::SelectItem::ExprWithAlias { expr, alias } => Ok(SelectItem {
+                expr: Expression::from_expr(expr)?,
+                alias: Some(alias.value),
+            }),
+            sqlparser::ast::SelectItem::Wildcard(_) => Ok(SelectItem {
+                expr: Expression::Ident(Ident::Wildcard),
+                alias: None,
+            }),
+            item => Err(Error::Unsupported(format!("selection item: {item}"))),
+        }
+    }
+
+    /// Whether `expr` is the expression of the item, or its alias.
+    pub fn stands_for(&self, expr: &Expression) -> bool {
+        match (&self.alias, expr) {
+            (Some(alias), Expression::Ident(Ident::Named(name)))
+                if alias.eq_ignore_ascii_case(name) =>
+            {
+                true
+            }
+            _ => self.expr == *expr,
+        }
+    }
+}
+
+/// How the rows of a select are combined with those before it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SetOperator {
+    Union,
+    Intersect,
+    Except,
+}
+
+impl SetOperator {
+    pub fn name(self) -> &'static str {
+        match self {
+            SetOperator::Union => "union",
+            SetOperator::Intersect => "intersect",
+            SetOperator::Except => "except",
+        }
+    }
+}
+
+/// `UNION`, `INTERSECT` or `EXCEPT` of `select`, with the rows of every one
+/// of the duplicates kept by `ALL`.
+#[derive(Debug, Clone)]
+pub struct Compound {
+    pub operator: SetOperator,
+    pub all: bool,
+    pub select: Select,
+}
+
+#[derive(Debug, Clone)]
 pub struct Select {
     pub from: Option<String>,
-    pub projection: Vec<Expression>,
+    // of a table function, like `generate_series(1, 10)`
+    pub from_args: Option<Vec<Expression>>,
+    pub alias: Option<String>,
+    // of the rows of the table, by `TABLESAMPLE`
+    pub sample: Option<Sample>,
+    pub joins: Vec<Join>,
+    pub projection: Vec<SelectItem>,
     pub selection: Vec<Expression>,
+    // with aliases and positions replaced by the expressions they stand for
+    pub group_by: Vec<Expression>,
+    // keys later in the list break ties of the ones before
+    pub order_by: Vec<OrderBy>,
+    pub limit: Option<usize>,
+    // rows left out before the first, by `OFFSET`, or by mysql's
+    // `LIMIT <offset>, <count>`
+    pub offset: usize,
+    // the selects whose rows are combined with those of this one in turn,
+    // before they're all ordered and limited
+    pub compound: Vec<Compound>,
 }
 
 impl Select {
+    /// Whether the rows are summed up into groups, by `GROUP BY` or by
+    /// aggregates in the select list.
+    pub fn is_grouped(&self) -> bool {
+        !self.group_by.is_empty()
+            || self
+                .projection
+                .iter()
+                .any(|p| aggregate::contains_aggregate(&p.expr))
+    }
+
+    /// Every expression of the select, wherever it appears.
+    pub fn expressions_mut(&mut self) -> impl Iterator<Item = &mut Expression> {
+        let joins = self
+            .joins
+            .iter_mut()
+            .flat_map(|j| j.args.iter_mut().flatten().chain(j.on.as_mut()));
+        self.from_args
+            .iter_mut()
+            .flatten()
+            .chain(joins)
+            .chain(self.projection.iter_mut().map(|p| &mut p.expr))
+            .chain(self.selection.iter_mut())
+            .chain(self.group_by.iter_mut())
+            .chain(self.order_by.iter_mut().map(|o| &mut o.expr))
+    }
+
+    /// Whether a function `is_function` is true of is called anywhere in the
+    /// select, its subqueries and the selects combined with it included.
+    pub fn calls(&self, is_function: &impl Fn(&str) -> bool) -> bool {
+        let joins = self
+            .joins
+            .iter()
+            .flat_map(|j| j.args.iter().flatten().chain(j.on.as_ref()));
+        let mut expressions = self
+            .from_args
+            .iter()
+            .flatten()
+            .chain(joins)
+            .chain(self.projection.iter().map(|p| &p.expr))
+            .chain(&self.selection)
+            .chain(&self.group_by)
+            .chain(self.order_by.iter().map(|o| &o.expr));
+        expressions.any(|e| e.calls(is_function))
+            || self.compound.iter().any(|c| c.select.calls(is_function))
+    }
+
     pub fn new(query: Query) -> Result<Self, Error> {
-        let mut from = None;
-        let mut projection = Vec::new();
-        let mut selection = Vec::new();
+        let limit = match query.limit {
+            Some(sqlparser::ast::Expr::Value(sqlparser::ast::Value::Number(n, _))) => {
+                Some(n.parse::<usize>().map_err(|_| {
+                    Error::InvalidQuery(format!("limit `{n}` is not a valid row count"))
+                })?)
+            }
+            Some(l) => Err(Error::Unsupported(format!("limit: {l}")))?,
+            None => None,
+        };
+        let offset = match query.offset.map(|o| o.value) {
+            Some(sqlparser::ast::Expr::Value(sqlparser::ast::Value::Number(n, _))) => {
+                n.parse::<usize>().map_err(|_| {
+                    Error::InvalidQuery(format!("offset `{n}` is not a valid row count"))
+                })?
+            }
+            Some(o) => Err(Error::Unsupported(format!("offset: {o}")))?,
+            None => 0,
+        };
 
-        match *query.body {
-            sqlparser::ast::SetExpr::Select(select) => {
-                let select = *select;
-                for p in select.projection {
-                    match p {
-                        sqlparser::ast::SelectItem::UnnamedExpr(exp) => {
-                            let exp = Expression::from_expr(exp)?;
-                            projection.push(exp);
-                        }
-                        sqlparser::ast::SelectItem::Wildcard(_) => {
-                            projection.push(Expression::Ident(super::expression::Ident::Wildcard));
-                        }
-                        _ => Err(Error::Unsupported(format!("selection item: {p}")))?,
-                    }
-                }
+        let mut order_by = Vec::new();
+        for o in query.order_by {
+            let desc = o.asc == Some(false);
+            order_by.push(OrderBy {
+                expr: Expression::from_expr(o.expr)?,
+                desc,
+                nulls_first: o.nulls_first.unwrap_or(desc),
+            });
+        }
 
-                if let Some(f) = select.from.into_iter().next() {
-                    match f.relation {
-                        sqlparser::ast::TableFactor::Table { name, .. } => {
-                            from = Some(name.to_string());
-                        }
-                        _ => Err(Error::Unsupported(format!("relation: {}", f.relation)))?,
-                    }
+        let mut select = Self::body(*query.body)?;
+        if !order_by.is_empty() || limit.is_some() || offset > 0 {
+            if select.is_ordered() {
+                return Err(Error::Unsupported(
+                    "ORDER BY or LIMIT of a select in parentheses along with its own".to_owned(),
+                ));
+            }
+            for o in order_by.iter_mut() {
+                if let Expression::Literal(Literal::Int(position)) = o.expr {
+                    o.expr = projected(&select.projection, position, "order by")?;
                 }
+            }
+            select.order_by = order_by;
+            select.limit = limit;
+            select.offset = offset;
+        }
+
+        Ok(select)
+    }
 
-                let sel = match select.selection {
-                    Some(exp) => Expression::from_expr(exp)?,
-                    None => Expression::None,
+    // whether the rows are ordered or limited, by `ORDER BY`, `LIMIT` or
+    // `OFFSET`
+    fn is_ordered(&self) -> bool {
+        !self.order_by.is_empty() || self.limit.is_some() || self.offset > 0
+    }
+
+    // the select of the body of a query, before it's ordered and limited
+    fn body(body: SetExpr) -> Result<Self, Error> {
+        match body {
+            SetExpr::Select(select) => Self::single(*select),
+            // in parentheses, along with its own `ORDER BY` and limits
+            SetExpr::Query(query) => Self::new(*query),
+            SetExpr::SetOperation {
+                op,
+                set_quantifier,
+                left,
+                right,
+            } => {
+                let mut select = Self::body(*left)?;
+                // which would be taken for those of all the rows
+                if select.is_ordered() {
+                    return Err(Error::Unsupported(format!(
+                        "ORDER BY or LIMIT of the first select of {op}"
+                    )));
+                }
+                let operator = match op {
+                    sqlparser::ast::SetOperator::Union => SetOperator::Union,
+                    sqlparser::ast::SetOperator::Intersect => SetOperator::Intersect,
+                    sqlparser::ast::SetOperator::Except => SetOperator::Except,
                 };
+                let all = match set_quantifier {
+                    SetQuantifier::All => true,
+                    SetQuantifier::Distinct | SetQuantifier::None => false,
+                    quantifier => {
+                        return Err(Error::Unsupported(format!("{op} {quantifier}")));
+                    }
+                };
+                select.compound.push(Compound {
+                    operator,
+                    all,
+                    select: Self::body(*right)?,
+                });
+                Ok(select)
+            }
+            body => Err(Error::Unsupported(format!("query body: {body}"))),
+        }
+    }
+
+    fn single(select: sqlparser::ast::Select) -> Result<Self, Error> {
+        let mut from = None;
+        let mut from_args = None;
+        let mut alias = None;
+        let mut sample = None;
+        let mut joins = Vec::new();
+        let mut projection = Vec::new();
+        let mut selection = Vec::new();
+        let mut group_by = Vec::new();
+
+        for p in select.projection {
+            projection.push(SelectItem::new(p)?);
+        }
 
-                selection.push(sel);
+        // `FROM a, b` is a cross join of a and b
+        for (i, f) in select.from.into_iter().enumerate() {
+            let (name, table_alias, args, table_sample) = table_factor(f.relation)?;
+            if i == 0 {
+                from = Some(name);
+                from_args = args;
+                alias = table_alias;
+                sample = table_sample;
+            } else {
+                joins.push(Join {
+                    table: name,
+                    args,
+                    alias: table_alias,
+                    sample: table_sample,
+                    kind: JoinKind::Cross,
+                    on: None,
+                    using: Vec::new(),
+                    natural: false,
+                });
             }
-            _ => Err(Error::Unsupported(format!("query body: {}", query.body)))?,
+
+            for j in f.joins {
+                joins.push(Join::new(j)?);
+            }
+        }
+
+        let sel = match select.selection {
+            Some(exp) => Expression::from_expr(exp)?,
+            None => Expression::None,
+        };
+
+        selection.push(sel);
+
+        if let Some(having) = select.having {
+            Err(Error::Unsupported(format!("having: {having}")))?;
+        }
+        let sqlparser::ast::GroupByExpr::Expressions(exprs) = select.group_by else {
+            return Err(Error::Unsupported("group by all".to_owned()));
+        };
+        for expr in exprs {
+            let expr = match Expression::from_expr(expr)? {
+                Expression::Literal(Literal::Int(position)) => {
+                    projected(&projection, position, "group by")?
+                }
+                // aliases are taken over columns of the same name
+                Expression::Ident(Ident::Named(name)) => projection
+                    .iter()
+                    .find(|p| {
+                        p.alias
+                            .as_ref()
+                            .is_some_and(|a| a.eq_ignore_ascii_case(&name))
+                    })
+                    .map_or(Expression::Ident(Ident::Named(name)), |p| p.expr.clone()),
+                expr => expr,
+            };
+            group_by.push(expr);
         }
 
         Ok(Self {
             from,
+            from_args,
+            alias,
+            sample,
+            joins,
             projection,
             selection,
+            group_by,
+            order_by: Vec::new(),
+            limit: None,
+            offset: 0,
+            compound: Vec::new(),
         })
     }
 }
+
+// the expression at `position` of the select list, counting from 1
+fn projected(projection: &[SelectItem], position: i32, clause: &str) -> Result<Expression, Error> {
+    usize::try_from(position)
+        .ok()
+        .and_then(|p| projection.get(p.checked_sub(1)?))
+        .filter(|p| !matches!(p.expr, Expression::Ident(Ident::Wildcard)))
+        .map(|p| p.expr.clone())
+        .ok_or_else(|| Error::InvalidQuery(format!("{clause} position {position}")))
+}
+
+impl Join {
+    fn new(join: sqlparser::ast::Join) -> Result<Self, Error> {
+        use sqlparser::ast::{JoinConstraint, JoinOperator};
+
+        let (table, alias, args, sample) = table_factor(join.relation)?;
+        let (kind, constraint) = match join.join_operator {
+            JoinOperator::Inner(c) => (JoinKind::Inner, c),
+            JoinOperator::LeftOuter(c) => (JoinKind::Left, c),
+            JoinOperator::CrossJoin => (JoinKind::Cross, JoinConstraint::None),
+            op => Err(Error::Unsupported(format!("join: {op:?}")))?,
+        };
+
+        let (mut on, mut using, mut natural) = (None, Vec::new(), false);
+        match constraint {
+            JoinConstraint::On(expr) => on = Some(Expression::from_expr(expr)?),
+            JoinConstraint::Using(columns) => {
+                using = columns.into_iter().map(|c| c.value).collect();
+            }
+            JoinConstraint::Natural => natural = true,
+            JoinConstraint::None => {}
+        }
+
+        Ok(Self {
+            table,
+            args,
+            alias,
+            sample,
+            kind,
+            on,
+            using,
+            natural,
+        })
+    }
+}
+
+// the name and alias of a table, the arguments when it's a table function,
+// and the sample of its rows
+type TableFactor = (
+    String,
+    Option<String>,
+    Option<Vec<Expression>>,
+    Option<Sample>,
+);
+
+fn table_factor(relation: sqlparser::ast::TableFactor) -> Result<TableFactor, Error> {
+    use sqlparser::ast::{FunctionArg, FunctionArgExpr};
+
+    match relation {
+        sqlparser::ast::TableFactor::Table {
+            name,
+            alias,
+            args,
+            with_hints,
+            ..
+        } => {
+            let args = match args {
+                Some(args) => Some(
+                    args.into_iter()
+                        .map(|arg| match arg {
+                            FunctionArg::Unnamed(FunctionArgExpr::Expr(expr)) => {
+                                Expression::from_expr(expr)
+                            }
+                            arg => Err(Error::Unsupported(format!(
+                                "table function argument: {arg}"
+                            ))),
+                        })
+                        .collect::<Result<Vec<_>, _>>()?,
+                ),
+                None => None,
+            };
+            // other hints, like sql server's `WITH (NOLOCK)`, are ignored
+            let mut sample = None;
+            for hint in with_hints {
+                let Expr::Function(f) = &hint else {
+                    continue;
+                };
+                if f.name.to_string().eq_ignore_ascii_case("tablesample") {
+                    let Expression::Function { args, .. } = Expression::from_expr(hint)? else {
+                        continue;
+                    };
+                    sample = Some(Sample::from_args(&args)?);
+                }
+            }
+            Ok((object_name(name), alias.map(|a| a.name.value), args, sample))
+        }
+        _ => Err(Error::Unsupported(format!("relation: {relation}"))),
+    }
+}
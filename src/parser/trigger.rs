@@ -0,0 +1,308 @@
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+use sqlparser::{
+    ast::{Expr, FunctionArg, FunctionArgExpr, SetExpr, Statement, Value},
+    keywords::Keyword,
+    parser::{Parser, ParserError},
+    tokenizer::Token,
+};
+
+use crate::{database::ChangeKind, Error};
+
+use super::{
+    expression::Literal,
+    parser::{check_depth, object_name, SqlDialect},
+};
+
+/// When a trigger fires, relative to the change to its table. A `Before`
+/// trigger that fails stops the statement before anything is changed. An
+/// `After` one fires once the rows are written and subscribers are told of
+/// them, so a statement whose `After` trigger fails still returns the error,
+/// but stays applied, as there are no transactions to roll it back with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum TriggerTiming {
+    Before,
+    After,
+}
+
+/// A trigger as stored on its table. The body is kept as sql so that it can
+/// be persisted along with the table, and is parsed again every time it fires.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Trigger {
+    pub name: String,
+    pub timing: TriggerTiming,
+    pub event: ChangeKind,
+    pub for_each_row: bool,
+    pub body: String,
+    // that the body is written in
+    #[serde(default)]
+    pub dialect: SqlDialect,
+}
+
+/// The `OLD` and `NEW` values of a single row affected by a statement, by
+/// lowercase column name.
+#[derive(Debug, Default, Clone)]
+pub struct RowImage {
+    pub old: Option<HashMap<String, Literal>>,
+    pub new: Option<HashMap<String, Literal>>,
+}
+
+fn parse_word(parser: &mut Parser, word: &str) -> bool {
+    match parser.peek_token().token {
+        Token::Word(w) if w.value.eq_ignore_ascii_case(word) => {
+            parser.next_token();
+            true
+        }
+        _ => false,
+    }
+}
+
+pub(crate) fn is_create_trigger(parser: &Parser) -> bool {
+    matches!(
+        (parser.peek_nth_token(0).token, parser.peek_nth_token(1).token),
+        (Token::Word(c), Token::Word(t)) if c.keyword == Keyword::CREATE && t.keyword == Keyword::TRIGGER
+    )
+}
+
+pub(crate) fn is_drop_trigger(parser: &Parser) -> bool {
+    matches!(
+        (parser.peek_nth_token(0).token, parser.peek_nth_token(1).token),
+        (Token::Word(d), Token::Word(t)) if d.keyword == Keyword::DROP && t.keyword == Keyword::TRIGGER
+    )
+}
+
+/// Parses `CREATE TRIGGER <name> {BEFORE | AFTER} {INSERT | UPDATE | DELETE | TRUNCATE}
+/// ON <table> [FOR EACH {ROW | STATEMENT}] <statement>`, returning the table
+/// name along with the trigger.
+pub(crate) fn parse_create_trigger(
+    parser: &mut Parser,
+    max_depth: usize,
+    dialect: SqlDialect,
+) -> Result<(String, Trigger), Error> {
+    parser.expect_keyword(Keyword::CREATE)?;
+    parser.expect_keyword(Keyword::TRIGGER)?;
+    let name = parser.parse_identifier()?.value;
+
+    let timing = if parse_word(parser, "BEFORE") {
+        TriggerTiming::Before
+    } else if parse_word(parser, "AFTER") {
+        TriggerTiming::After
+    } else {
+        return parser
+            .expected("BEFORE or AFTER", parser.peek_token())
+            .map_err(Error::from);
+    };
+
+    let event = match parser.parse_one_of_keywords(&[
+        Keyword::INSERT,
+        Keyword::UPDATE,
+        Keyword::DELETE,
+        Keyword::TRUNCATE,
+    ]) {
+        Some(Keyword::INSERT) => ChangeKind::Insert,
+        Some(Keyword::UPDATE) => ChangeKind::Update,
+        Some(Keyword::DELETE) => ChangeKind::Delete,
+        Some(Keyword::TRUNCATE) => ChangeKind::Truncate,
+        _ => {
+            return parser
+                .expected("INSERT, UPDATE, DELETE or TRUNCATE", parser.peek_token())
+                .map_err(Error::from)
+        }
+    };
+
+    parser.expect_keyword(Keyword::ON)?;
+    let table = object_name(parser.parse_object_name()?);
+
+    let mut for_each_row = false;
+    if parser.parse_keyword(Keyword::FOR) {
+        parser.expect_keyword(Keyword::EACH)?;
+        if parser.parse_keyword(Keyword::ROW) {
+            for_each_row = true;
+        } else if !parse_word(parser, "STATEMENT") {
+            return parser
+                .expected("ROW or STATEMENT", parser.peek_token())
+                .map_err(Error::from);
+        }
+    }
+
+    if for_each_row && event == ChangeKind::Truncate {
+        return Err(Error::Unsupported(
+            "row level triggers on truncate".to_owned(),
+        ));
+    }
+
+    let mut body = parser.parse_statement()?;
+    check_depth(&mut body, max_depth)?;
+    match body {
+        Statement::Insert { .. } | Statement::Update { .. } | Statement::Delete { .. } => {}
+        _ => {
+            return Err(Error::Unsupported(format!(
+                "trigger body other than insert, update or delete: {body}"
+            )))
+        }
+    }
+
+    Ok((
+        table,
+        Trigger {
+            name,
+            timing,
+            event,
+            for_each_row,
+            body: body.to_string(),
+            dialect,
+        },
+    ))
+}
+
+/// Parses `DROP TRIGGER [IF EXISTS] <name> ON <table>`.
+pub(crate) fn parse_drop_trigger(
+    parser: &mut Parser,
+) -> Result<(String, String, bool), ParserError> {
+    parser.expect_keyword(Keyword::DROP)?;
+    parser.expect_keyword(Keyword::TRIGGER)?;
+    let if_exists = parser.parse_keywords(&[Keyword::IF, Keyword::EXISTS]);
+    let name = parser.parse_identifier()?.value;
+    parser.expect_keyword(Keyword::ON)?;
+    let table = object_name(parser.parse_object_name()?);
+
+    Ok((table, name, if_exists))
+}
+
+impl Trigger {
+    /// Parses the body, replacing `NEW.<col>` and `OLD.<col>` with the values
+    /// from `row` when given.
+    pub fn bind(&self, row: Option<&RowImage>) -> Result<Statement, Error> {
+        let mut stmts = Parser::parse_sql(self.dialect.dialect(), &self.body)?;
+        if stmts.len() != 1 {
+            return Err(Error::InvalidQuery(format!(
+                "trigger `{}` must have exactly one statement",
+                self.name
+            )));
+        }
+        let mut stmt = stmts.remove(0);
+
+        match &mut stmt {
+            Statement::Insert {
+                source: Some(source),
+                ..
+            } => {
+                if let SetExpr::Values(values) = source.body.as_mut() {
+                    for expr in values.rows.iter_mut().flatten() {
+                        bind_expr(expr, row)?;
+                    }
+                }
+            }
+            Statement::Update {
+                assignments,
+                selection,
+                ..
+            } => {
+                for a in assignments.iter_mut() {
+                    bind_expr(&mut a.value, row)?;
+                }
+                if let Some(selection) = selection {
+                    bind_expr(selection, row)?;
+                }
+            }
+            Statement::Delete {
+                selection: Some(selection),
+                ..
+            } => bind_expr(selection, row)?,
+            _ => {}
+        }
+
+        Ok(stmt)
+    }
+}
+
+fn literal_to_value(lit: &Literal) -> Value {
+    match lit {
+        Literal::Int(i) => Value::Number(i.to_string(), false),
+        Literal::Float(f) => Value::Number(format!("{f:?}"), false),
+        Literal::Double(d) => Value::Number(format!("{d:?}"), false),
+        Literal::Str(s) => Value::SingleQuotedString(s.clone()),
+        Literal::Bool(b) => Value::Boolean(*b),
+        Literal::Null => Value::Null,
+    }
+}
+
+fn bind_expr(expr: &mut Expr, row: Option<&RowImage>) -> Result<(), Error> {
+    match expr {
+        Expr::CompoundIdentifier(idents) if idents.len() == 2 => {
+            let which = idents[0].value.to_lowercase();
+            let values = match (which.as_str(), row) {
+                ("new", Some(row)) => row.new.as_ref(),
+                ("old", Some(row)) => row.old.as_ref(),
+                ("new" | "old", None) => {
+                    return Err(Error::InvalidOperation(format!(
+                        "{which} referenced in a statement level trigger"
+                    )))
+                }
+                _ => return Ok(()),
+            };
+
+            let Some(values) = values else {
+                return Err(Error::InvalidOperation(format!(
+                    "{which} referenced in a trigger on an event without it"
+                )));
+            };
+
+            let col = idents[1].value.to_lowercase();
+            let lit = values.get(&col).ok_or_else(|| {
+                Error::column_not_found(
+                    &col,
+                    &which.to_uppercase(),
+                    values.keys().map(|k| k.as_str()),
+                )
+            })?;
+            *expr = Expr::Value(literal_to_value(lit));
+        }
+        Expr::BinaryOp { left, right, .. } => {
+            bind_expr(left, row)?;
+            bind_expr(right, row)?;
+        }
+        Expr::UnaryOp { expr, .. }
+        | Expr::Nested(expr)
+        | Expr::IsNull(expr)
+        | Expr::IsNotNull(expr)
+        | Expr::IsTrue(expr)
+        | Expr::IsNotTrue(expr)
+        | Expr::IsFalse(expr)
+        | Expr::IsNotFalse(expr)
+        | Expr::InSubquery { expr, .. } => bind_expr(expr, row)?,
+        Expr::InList { expr, list, .. } => {
+            bind_expr(expr, row)?;
+            for e in list {
+                bind_expr(e, row)?;
+            }
+        }
+        Expr::Case {
+            operand,
+            conditions,
+            results,
+            else_result,
+        } => {
+            let boxed = operand.iter_mut().chain(else_result.iter_mut());
+            for e in boxed.map(|e| &mut **e).chain(conditions).chain(results) {
+                bind_expr(e, row)?;
+            }
+        }
+        Expr::Function(function) => {
+            for arg in function.args.iter_mut() {
+                match arg {
+                    FunctionArg::Named {
+                        arg: FunctionArgExpr::Expr(e),
+                        ..
+                    }
+                    | FunctionArg::Unnamed(FunctionArgExpr::Expr(e)) => bind_expr(e, row)?,
+                    _ => {}
+                }
+            }
+        }
+        _ => {}
+    }
+
+    Ok(())
+}
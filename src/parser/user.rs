@@ -0,0 +1,137 @@
+use sqlparser::{
+    dialect::GenericDialect,
+    keywords::Keyword,
+    parser::{Parser, ParserError},
+    tokenizer::{Token, Tokenizer},
+};
+
+/// What `CREATE USER` and `ALTER USER` set, where `None` leaves it as it is.
+#[derive(Debug, Default)]
+pub struct UserOptions {
+    pub password: Option<String>,
+    pub superuser: Option<bool>,
+}
+
+// `<verb> USER`, which sqlparser only knows as part of other statements
+fn is_user_statement(parser: &Parser, verb: Keyword) -> bool {
+    matches!(
+        (parser.peek_nth_token(0).token, parser.peek_nth_token(1).token),
+        (Token::Word(v), Token::Word(u)) if v.keyword == verb && u.keyword == Keyword::USER
+    )
+}
+
+pub(crate) fn is_create_user(parser: &Parser) -> bool {
+    is_user_statement(parser, Keyword::CREATE)
+}
+
+pub(crate) fn is_alter_user(parser: &Parser) -> bool {
+    is_user_statement(parser, Keyword::ALTER)
+}
+
+pub(crate) fn is_drop_user(parser: &Parser) -> bool {
+    is_user_statement(parser, Keyword::DROP)
+}
+
+/// Parses `{CREATE | ALTER} USER <name> [WITH] [PASSWORD '<password>']
+/// [SUPERUSER | NOSUPERUSER]`, returning the name along with the options.
+/// Creating a user takes a password.
+pub(crate) fn parse_user(parser: &mut Parser) -> Result<(String, UserOptions), ParserError> {
+    let create = parser.parse_keyword(Keyword::CREATE);
+    if !create {
+        parser.expect_keyword(Keyword::ALTER)?;
+    }
+    parser.expect_keyword(Keyword::USER)?;
+    let name = parser.parse_identifier()?.value;
+    _ = parser.parse_keyword(Keyword::WITH);
+
+    let mut options = UserOptions::default();
+    loop {
+        match parser.parse_one_of_keywords(&[
+            Keyword::PASSWORD,
+            Keyword::SUPERUSER,
+            Keyword::NOSUPERUSER,
+        ]) {
+            Some(Keyword::PASSWORD) if options.password.is_none() => {
+                options.password = Some(parser.parse_literal_string()?);
+            }
+            Some(Keyword::SUPERUSER) if options.superuser.is_none() => {
+                options.superuser = Some(true);
+            }
+            Some(Keyword::NOSUPERUSER) if options.superuser.is_none() => {
+                options.superuser = Some(false);
+            }
+            Some(_) => {
+                return parser.expected("each option at most once", parser.peek_token());
+            }
+            None => break,
+        }
+    }
+
+    if create && options.password.is_none() {
+        return parser.expected("PASSWORD", parser.peek_token());
+    }
+    if options.password.is_none() && options.superuser.is_none() {
+        return parser.expected("PASSWORD, SUPERUSER or NOSUPERUSER", parser.peek_token());
+    }
+
+    Ok((name, options))
+}
+
+/// Parses `DROP USER [IF EXISTS] <name>`.
+pub(crate) fn parse_drop_user(parser: &mut Parser) -> Result<(String, bool), ParserError> {
+    parser.expect_keyword(Keyword::DROP)?;
+    parser.expect_keyword(Keyword::USER)?;
+    let if_exists = parser.parse_keywords(&[Keyword::IF, Keyword::EXISTS]);
+    let name = parser.parse_identifier()?.value;
+
+    Ok((name, if_exists))
+}
+
+/// `sql` with the string following each `PASSWORD` replaced by `'***'`, so
+/// that `CREATE USER` and `ALTER USER` can be shown to others. Sql that
+/// doesn't tokenize is left out altogether, as its passwords can't be found.
+pub fn redact_passwords(sql: &str) -> String {
+    let Ok(tokens) = Tokenizer::new(&GenericDialect {}, sql).tokenize() else {
+        return "<redacted>".to_owned();
+    };
+
+    let mut out = String::with_capacity(sql.len());
+    let mut after_password = false;
+    for token in tokens {
+        match &token {
+            Token::Whitespace(_) => out.push_str(&token.to_string()),
+            Token::SingleQuotedString(_)
+            | Token::DoubleQuotedString(_)
+            | Token::EscapedStringLiteral(_)
+            | Token::DollarQuotedString(_)
+                if after_password =>
+            {
+                out.push_str("'***'");
+                after_password = false;
+            }
+            _ => {
+                after_password = matches!(&token, Token::Word(w) if w.keyword == Keyword::PASSWORD);
+                out.push_str(&token.to_string());
+            }
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::redact_passwords;
+
+    #[test]
+    fn redacts_passwords() {
+        assert_eq!(
+            redact_passwords("CREATE USER bob WITH PASSWORD 'it''s secret' SUPERUSER"),
+            "CREATE USER bob WITH PASSWORD '***' SUPERUSER"
+        );
+        assert_eq!(
+            redact_passwords("alter user bob password 'a;b'; SELECT 'password'"),
+            "alter user bob password '***'; SELECT 'password'"
+        );
+        assert_eq!(redact_passwords("SELECT 'x"), "<redacted>");
+    }
+}
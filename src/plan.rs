@@ -0,0 +1,218 @@
+use crate::{
+    parser::{
+        expression::{Binary, Expression},
+        select::{Join, OrderBy, Select, SelectItem, SetOperator},
+    },
+    sample::Sample,
+};
+
+/// A select as the operators executing it goes through, each working on the
+/// rows of the one it's built on, from the scan of its table at the bottom up
+/// to the limit at the top. Built from the parsed query by `Plan::new`, and
+/// run by `Database`.
+#[derive(Debug, Clone)]
+pub enum Plan {
+    /// Without `FROM`: a single row of no columns, for e.g. `SELECT 1`.
+    NoTable,
+    /// The rows of a table, virtual table or table function.
+    Scan {
+        table: String,
+        // of a table function, like `generate_series(1, 10)`
+        args: Option<Vec<Expression>>,
+        alias: Option<String>,
+        // lowercase and without their table, the names of the columns the
+        // rest of the plan needs, or `None` for all of them
+        columns: Option<Vec<String>>,
+        // of the rows, by `TABLESAMPLE`, before any filter
+        sample: Option<Sample>,
+    },
+    /// The rows of `left` joined with those of `right`, the table of `join`.
+    Join {
+        left: Box<Plan>,
+        right: Box<Plan>,
+        join: Join,
+    },
+    /// The rows of `input` for which `predicate` holds.
+    Filter {
+        input: Box<Plan>,
+        predicate: Expression,
+    },
+    /// The select list, evaluated for each row of `input`.
+    Project {
+        input: Box<Plan>,
+        items: Vec<SelectItem>,
+    },
+    /// The select list, evaluated for each group of the rows of `input` with
+    /// the same values of `group_by`.
+    Aggregate {
+        input: Box<Plan>,
+        group_by: Vec<Expression>,
+        items: Vec<SelectItem>,
+    },
+    /// The rows of the projections or aggregations `left` and `right`
+    /// combined by `operator`, with the duplicates kept by `all`.
+    Combine {
+        left: Box<Plan>,
+        right: Box<Plan>,
+        operator: SetOperator,
+        all: bool,
+    },
+    /// The rows of a projection, aggregation or combination of them, ordered
+    /// by `keys`.
+    Sort {
+        input: Box<Plan>,
+        keys: Vec<OrderBy>,
+    },
+    /// The rows of `input` after the first `offset`, and no more than `limit`
+    /// of them.
+    Limit {
+        input: Box<Plan>,
+        limit: Option<usize>,
+        offset: usize,
+    },
+}
+
+impl Plan {
+    pub fn new(mut select: Select) -> Self {
+        let order_by = std::mem::take(&mut select.order_by);
+        let (limit, offset) = (select.limit, select.offset);
+        let compound = std::mem::take(&mut select.compound);
+
+        let mut plan = Self::select_list(select);
+        for c in compound {
+            plan = Plan::Combine {
+                left: Box::new(plan),
+                right: Box::new(Plan::new(c.select)),
+                operator: c.operator,
+                all: c.all,
+            };
+        }
+        if !order_by.is_empty() {
+            plan = Plan::Sort {
+                input: Box::new(plan),
+                keys: order_by,
+            };
+        }
+        if limit.is_some() || offset > 0 {
+            plan = Plan::Limit {
+                input: Box::new(plan),
+                limit,
+                offset,
+            };
+        }
+
+        plan
+    }
+
+    // the operators of `select` up to its projection or aggregation
+    fn select_list(select: Select) -> Self {
+        let grouped = select.is_grouped();
+
+        let mut plan = match select.from {
+            Some(table) => Plan::Scan {
+                table,
+                args: select.from_args,
+                alias: select.alias,
+                columns: None,
+                sample: select.sample,
+            },
+            None => Plan::NoTable,
+        };
+        for join in select.joins {
+            let right = Plan::Scan {
+                table: join.table.clone(),
+                args: join.args.clone(),
+                alias: join.alias.clone(),
+                columns: None,
+                sample: join.sample.clone(),
+            };
+            plan = Plan::Join {
+                left: Box::new(plan),
+                right: Box::new(right),
+                join,
+            };
+        }
+        // rows have to satisfy every selection, and each side of an `AND` is
+        // a filter of its own, that can go down to its table on its own
+        let mut predicates = Vec::new();
+        for predicate in select.selection {
+            conjuncts(predicate, &mut predicates);
+        }
+        for predicate in predicates {
+            if matches!(predicate, Expression::None) {
+                continue;
+            }
+            plan = Plan::Filter {
+                input: Box::new(plan),
+                predicate,
+            };
+        }
+
+        match grouped {
+            true => Plan::Aggregate {
+                input: Box::new(plan),
+                group_by: select.group_by,
+                items: select.projection,
+            },
+            false => Plan::Project {
+                input: Box::new(plan),
+                items: select.projection,
+            },
+        }
+    }
+
+    /// What the operator is called, as `EXPLAIN` shows it.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Plan::NoTable => "no table",
+            Plan::Scan { .. } => "scan",
+            Plan::Join { .. } => "join",
+            Plan::Filter { .. } => "filter",
+            Plan::Project { .. } => "project",
+            Plan::Aggregate { .. } => "aggregate",
+            Plan::Combine { operator, .. } => operator.name(),
+            Plan::Sort { .. } => "sort",
+            Plan::Limit { .. } => "limit",
+        }
+    }
+
+    /// The select list the columns of the rows of the plan come from, that of
+    /// the first select of a combination.
+    pub fn items(&self) -> &[SelectItem] {
+        match self {
+            Plan::Project { items, .. } | Plan::Aggregate { items, .. } => items,
+            Plan::Combine { left: input, .. }
+            | Plan::Sort { input, .. }
+            | Plan::Limit { input, .. } => input.items(),
+            _ => &[],
+        }
+    }
+
+    /// The operator below the filters directly on top of each other starting
+    /// at this one, along with their predicates, the lowest first.
+    pub fn filters(&self) -> (&Plan, Vec<Expression>) {
+        let mut predicates = Vec::new();
+        let mut plan = self;
+        while let Plan::Filter { input, predicate } = plan {
+            predicates.push(predicate.clone());
+            plan = input;
+        }
+        predicates.reverse();
+        (plan, predicates)
+    }
+}
+
+// `a AND b AND c` as `a`, `b` and `c`, added to `out`
+fn conjuncts(expr: Expression, out: &mut Vec<Expression>) {
+    match expr {
+        Expression::Binary {
+            operator: Binary::And,
+            left,
+            right,
+        } => {
+            conjuncts(*left, out);
+            conjuncts(*right, out);
+        }
+        expr => out.push(expr),
+    }
+}
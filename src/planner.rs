@@ -0,0 +1,180 @@
+use std::{cmp::Ordering, ops::Bound};
+
+use crate::{
+    parser::expression::{Binary, Expression, Ident, Literal},
+    table::{DataType, PKType, Table},
+};
+
+// following the index to a row it gives costs about this many times as
+// much as evaluating a filter for a row of a full scan, which goes through
+// whole columns at once
+const INDEX_ROW_COST: f64 = 4.0;
+
+/// What the planner knows of the primary key of a table, from its index
+/// rather than its rows. Keys are distinct, so there are as many as rows.
+#[derive(Debug, Clone, PartialEq)]
+pub struct KeyStats {
+    pub rows: usize,
+    pub min: Option<PKType>,
+    pub max: Option<PKType>,
+}
+
+impl KeyStats {
+    pub fn new(table: &Table) -> Self {
+        Self {
+            rows: table.pk_map.len(),
+            min: table.pk_map.left_values().next().cloned(),
+            max: table.pk_map.left_values().next_back().cloned(),
+        }
+    }
+
+    /// How many keys are expected in `range`, going by where its bounds fall
+    /// between the smallest and the largest key.
+    pub fn estimate(&self, (start, end): &(Bound<PKType>, Bound<PKType>)) -> usize {
+        let (Some(min), Some(max)) = (&self.min, &self.max) else {
+            return 0;
+        };
+        if let (Bound::Included(a), Bound::Included(b)) = (start, end) {
+            if a == b {
+                return usize::from(min <= a && a <= max);
+            }
+        }
+
+        let from = match start {
+            Bound::Unbounded => 0.0,
+            Bound::Included(key) | Bound::Excluded(key) => self.fraction_below(key),
+        };
+        let to = match end {
+            Bound::Unbounded => 1.0,
+            Bound::Included(key) | Bound::Excluded(key) => self.fraction_below(key),
+        };
+        ((to - from).max(0.0) * self.rows as f64).round() as usize
+    }
+
+    // the share of keys smaller than `key`, interpolated between the smallest
+    // and the largest
+    fn fraction_below(&self, key: &PKType) -> f64 {
+        match (key, &self.min, &self.max) {
+            (key, Some(min), _) if key <= min => 0.0,
+            (key, _, Some(max)) if key > max => 1.0,
+            (PKType::Int(key), Some(PKType::Int(min)), Some(PKType::Int(max))) => {
+                (*key as f64 - *min as f64) / (*max as f64 - *min as f64 + 1.0)
+            }
+            // strings can't be interpolated between, so either side is as
+            // likely
+            _ => 0.5,
+        }
+    }
+}
+
+/// How a filter finds the rows it selects, and how many it's expected to.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AccessPath {
+    // of the primary key index to look up, or `None` for evaluating the
+    // filter for every row
+    pub range: Option<(Bound<PKType>, Bound<PKType>)>,
+    pub estimate: usize,
+}
+
+/// Chooses between answering `<pk> <op> <literal>` from the primary key index
+/// and evaluating it for every row of `table`, by whichever `KeyStats` make
+/// out to be cheaper. `selection` is expected to have been through
+/// `optimizer::simplify`, which puts the literal on the right. `None` means
+/// that the index can't answer it, so it has to be evaluated.
+pub fn access_path(table: &Table, selection: &Expression) -> Option<AccessPath> {
+    let range = index_range(table, selection)?;
+    let stats = KeyStats::new(table);
+    let estimate = stats.estimate(&range);
+
+    // finding where the range starts goes down the index once
+    let index_cost = (stats.rows as f64).log2().max(1.0) + estimate as f64 * INDEX_ROW_COST;
+    let scan_cost = stats.rows as f64;
+    log::debug!(
+        "{} of {} rows of {} expected, index cost {index_cost:.1}, scan cost {scan_cost:.1}",
+        estimate,
+        stats.rows,
+        table.name
+    );
+
+    Some(AccessPath {
+        range: (index_cost < scan_cost).then_some(range),
+        estimate,
+    })
+}
+
+/// The range of primary keys that `selection` selects, when the index can
+/// answer it. Both sides of an `AND` have to be answerable, for the keys in
+/// both of their ranges.
+pub fn index_range(
+    table: &Table,
+    selection: &Expression,
+) -> Option<(Bound<PKType>, Bound<PKType>)> {
+    if let Expression::Binary {
+        operator: Binary::And,
+        left,
+        right,
+    } = selection
+    {
+        let (left_start, left_end) = index_range(table, left)?;
+        let (right_start, right_end) = index_range(table, right)?;
+        return Some((
+            narrower(left_start, right_start, false),
+            narrower(left_end, right_end, true),
+        ));
+    }
+
+    let Expression::Binary {
+        operator,
+        left,
+        right,
+    } = selection
+    else {
+        return None;
+    };
+
+    let (Expression::Ident(Ident::Named(col)), Expression::Literal(lit)) =
+        (left.as_ref(), right.as_ref())
+    else {
+        return None;
+    };
+
+    let pk = table.col_from_name(col)?;
+    if !pk.header.is_pk {
+        return None;
+    }
+
+    // the index is ordered by key, so it can only stand in for comparisons
+    // that would order the same way
+    match (pk.header.datatype, lit) {
+        (DataType::Int, Literal::Int(_)) | (DataType::Str, Literal::Str(_)) => {}
+        _ => return None,
+    }
+    let key = PKType::from_literal(lit)?;
+
+    match operator {
+        Binary::Eq => Some((Bound::Included(key.clone()), Bound::Included(key))),
+        Binary::Lt => Some((Bound::Unbounded, Bound::Excluded(key))),
+        Binary::LtEq => Some((Bound::Unbounded, Bound::Included(key))),
+        Binary::Gt => Some((Bound::Excluded(key), Bound::Unbounded)),
+        Binary::GtEq => Some((Bound::Included(key), Bound::Unbounded)),
+        _ => None,
+    }
+}
+
+// the one of two bounds on the same end of a range that leaves out more keys,
+// of the upper end when `upper`
+fn narrower(a: Bound<PKType>, b: Bound<PKType>, upper: bool) -> Bound<PKType> {
+    let order = match (&a, &b) {
+        (Bound::Unbounded, _) => return b,
+        (_, Bound::Unbounded) => return a,
+        (Bound::Included(x) | Bound::Excluded(x), Bound::Included(y) | Bound::Excluded(y)) => {
+            x.cmp(y)
+        }
+    };
+    match (order, upper) {
+        (Ordering::Equal, _) if matches!(a, Bound::Excluded(_)) => a,
+        (Ordering::Equal, _) => b,
+        (Ordering::Less, true) | (Ordering::Greater, false) => a,
+        _ => b,
+    }
+}
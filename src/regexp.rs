@@ -0,0 +1,259 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex, PoisonError},
+};
+
+use crate::{
+    chunked::ChunkedMap,
+    kernels::Operand,
+    parser::expression::{Binary, Literal},
+    table::{ColumnData, RowId},
+    Error, Result,
+};
+
+use engine::Regex;
+
+/// Whether `name` is one of the regular expression functions, which are
+/// evaluated by `Patterns::call`.
+pub fn is_function(name: &str) -> bool {
+    matches!(name, "regexp_matches" | "regexp_replace")
+}
+
+/// The regular expressions of the statement being executed, compiled once
+/// each rather than for every row they're matched against.
+#[derive(Debug, Default)]
+pub struct Patterns {
+    // by pattern and whether it ignores case
+    compiled: Mutex<HashMap<(String, bool), Arc<Regex>>>,
+}
+
+impl Patterns {
+    /// Forgets the patterns of the previous statement.
+    pub fn clear(&mut self) {
+        self.compiled
+            .get_mut()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clear();
+    }
+
+    fn compile(&self, pattern: &str, case_insensitive: bool) -> Result<Arc<Regex>> {
+        let mut compiled = self.compiled.lock().unwrap_or_else(PoisonError::into_inner);
+        let key = (pattern.to_owned(), case_insensitive);
+        if let Some(re) = compiled.get(&key) {
+            return Ok(re.clone());
+        }
+        let re = Arc::new(engine::compile(pattern, case_insensitive)?);
+        compiled.insert(key, re.clone());
+        Ok(re)
+    }
+
+    /// `~`, `~*`, `!~` and `!~*` of a text and a pattern, giving a bool
+    /// column. Rows where either is null are left out.
+    pub fn matches(&self, op: Binary, text: Operand, pattern: Operand) -> Result<ColumnData> {
+        let (negated, case_insensitive) = match op {
+            Binary::Match => (false, false),
+            Binary::IMatch => (false, true),
+            Binary::NotMatch => (true, false),
+            _ => (true, true),
+        };
+        let (text, pattern) = match (strings(&text), strings(&pattern)) {
+            (Some(text), Some(pattern)) => (text, pattern),
+            _ => {
+                return Err(Error::InvalidQuery(format!(
+                    "cannot apply `{op}` to anything other than text"
+                )))
+            }
+        };
+
+        let ids: Vec<RowId> = match (&text, &pattern) {
+            (Strings::Column(c), _) | (_, Strings::Column(c)) => c.keys().copied().collect(),
+            _ => vec![0],
+        };
+        let mut out = Vec::with_capacity(ids.len());
+        // a literal pattern is the same for every row
+        let mut last: Option<(&str, Arc<Regex>)> = None;
+        for id in ids {
+            let (Some(t), Some(p)) = (text.get(id), pattern.get(id)) else {
+                continue;
+            };
+            let re = match &last {
+                Some((previous, re)) if *previous == p => re.clone(),
+                _ => {
+                    let re = self.compile(p, case_insensitive)?;
+                    last = Some((p, re.clone()));
+                    re
+                }
+            };
+            out.push((id, engine::is_match(&re, t) != negated));
+        }
+
+        Ok(ColumnData::Bool(out.into_iter().collect()))
+    }
+
+    /// Calls the regular expression function `name`, see `is_function`, with
+    /// the values of `args` for a row:
+    ///
+    /// - `regexp_matches(text, pattern [, flags])`, the part of `text` the
+    ///   first group of `pattern` matches, or all of what it matches when it
+    ///   has no groups, or null when it doesn't match
+    /// - `regexp_replace(text, pattern, replacement [, flags])`, `text` with
+    ///   the first match replaced, or every one with the `g` flag. Groups are
+    ///   referred to as `$1`, `$2` and so on in `replacement`.
+    ///
+    /// The `i` flag ignores case. A null argument gives null.
+    pub fn call(&self, name: &str, args: &[Literal]) -> Result<Literal> {
+        if args.contains(&Literal::Null) {
+            return Ok(Literal::Null);
+        }
+
+        match (name, args) {
+            ("regexp_matches", [s, pattern, flags @ ..]) if flags.len() <= 1 => {
+                let flags = Flags::new(name, flags.first().map(|f| text(name, f)).transpose()?)?;
+                let re = self.compile(text(name, pattern)?, flags.case_insensitive)?;
+                Ok(engine::first_match(&re, text(name, s)?).map_or(Literal::Null, Literal::Str))
+            }
+            ("regexp_replace", [s, pattern, replacement, flags @ ..]) if flags.len() <= 1 => {
+                let flags = Flags::new(name, flags.first().map(|f| text(name, f)).transpose()?)?;
+                let re = self.compile(text(name, pattern)?, flags.case_insensitive)?;
+                Ok(Literal::Str(engine::replace(
+                    &re,
+                    text(name, s)?,
+                    text(name, replacement)?,
+                    flags.global,
+                )))
+            }
+            _ => Err(Error::InvalidQuery(format!(
+                "{name} with {} arguments",
+                args.len()
+            ))),
+        }
+    }
+}
+
+fn text<'a>(function: &str, arg: &'a Literal) -> Result<&'a str> {
+    match arg {
+        Literal::Str(s) => Ok(s),
+        arg => Err(Error::InvalidQuery(format!(
+            "{function} of {arg}, which isn't text"
+        ))),
+    }
+}
+
+// the letters after the arguments of the functions, like `'gi'`
+struct Flags {
+    case_insensitive: bool,
+    global: bool,
+}
+
+impl Flags {
+    fn new(function: &str, flags: Option<&str>) -> Result<Self> {
+        let mut out = Flags {
+            case_insensitive: false,
+            global: false,
+        };
+        for flag in flags.unwrap_or_default().chars() {
+            match flag {
+                'i' => out.case_insensitive = true,
+                'g' if function == "regexp_replace" => out.global = true,
+                flag => {
+                    return Err(Error::InvalidQuery(format!(
+                        "{function} flag {flag:?}, where `i`{} are known",
+                        if function == "regexp_replace" {
+                            " and `g`"
+                        } else {
+                            ""
+                        }
+                    )))
+                }
+            }
+        }
+        Ok(out)
+    }
+}
+
+// a side of a match, as text
+enum Strings<'a> {
+    Column(&'a ChunkedMap<Arc<str>>),
+    Scalar(&'a str),
+    Null,
+}
+
+impl Strings<'_> {
+    fn get(&self, id: RowId) -> Option<&str> {
+        match self {
+            Strings::Column(c) => c.get(&id).map(|s| &**s),
+            Strings::Scalar(s) => Some(s),
+            Strings::Null => None,
+        }
+    }
+}
+
+fn strings<'a>(side: &'a Operand) -> Option<Strings<'a>> {
+    match side {
+        Operand::Column(ColumnData::Str(c)) => Some(Strings::Column(c)),
+        Operand::Scalar(Literal::Str(s)) => Some(Strings::Scalar(s)),
+        Operand::Scalar(Literal::Null) => Some(Strings::Null),
+        _ => None,
+    }
+}
+
+#[cfg(feature = "regex")]
+mod engine {
+    use regex::RegexBuilder;
+
+    use crate::{Error, Result};
+
+    pub type Regex = regex::Regex;
+
+    pub fn compile(pattern: &str, case_insensitive: bool) -> Result<Regex> {
+        RegexBuilder::new(pattern)
+            .case_insensitive(case_insensitive)
+            .build()
+            .map_err(|e| Error::InvalidQuery(format!("regular expression {pattern:?}: {e}")))
+    }
+
+    pub fn is_match(re: &Regex, text: &str) -> bool {
+        re.is_match(text)
+    }
+
+    pub fn first_match(re: &Regex, text: &str) -> Option<String> {
+        let captures = re.captures(text)?;
+        let group = captures.get(1).or_else(|| captures.get(0))?;
+        Some(group.as_str().to_owned())
+    }
+
+    pub fn replace(re: &Regex, text: &str, replacement: &str, global: bool) -> String {
+        match global {
+            true => re.replace_all(text, replacement).into_owned(),
+            false => re.replace(text, replacement).into_owned(),
+        }
+    }
+}
+
+// without the `regex` feature, patterns fail to compile, so there's never one
+// to match with
+#[cfg(not(feature = "regex"))]
+mod engine {
+    use crate::{Error, Result};
+
+    #[derive(Debug)]
+    pub enum Regex {}
+
+    pub fn compile(_: &str, _: bool) -> Result<Regex> {
+        Err(Error::Unsupported(
+            "regular expressions, without the `regex` feature".to_owned(),
+        ))
+    }
+
+    pub fn is_match(re: &Regex, _: &str) -> bool {
+        match *re {}
+    }
+
+    pub fn first_match(re: &Regex, _: &str) -> Option<String> {
+        match *re {}
+    }
+
+    pub fn replace(re: &Regex, _: &str, _: &str, _: bool) -> String {
+        match *re {}
+    }
+}
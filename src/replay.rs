@@ -0,0 +1,120 @@
+use std::{
+    collections::HashMap,
+    fs::{File, OpenOptions},
+    io::{BufRead, BufReader, LineWriter, Write},
+    path::Path,
+    sync::{Arc, Mutex},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    database::{Database, OutputMode},
+    functions::Session,
+    Error, Result,
+};
+
+/// A statement as it was recorded into a replay file, one json object per
+/// line, with when it was executed in seconds since the unix epoch, the
+/// database it was executed on, `None` being the default one, and the user
+/// it was executed for.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ReplayEntry {
+    pub at: f64,
+    pub database: Option<String>,
+    pub user: Option<String>,
+    pub sql: String,
+}
+
+/// Where every statement a database executes is appended to, see
+/// `Database::set_replay_log`, so that what led up to a bug can be run again
+/// with `replay`. Clones append to the same file.
+#[derive(Debug, Clone)]
+pub struct ReplayLog {
+    file: Arc<Mutex<LineWriter<File>>>,
+    // of the statements recorded through this clone
+    database: Option<String>,
+}
+
+impl ReplayLog {
+    /// Appends to the file at `path`, creating it if there's none.
+    pub fn open(path: &Path) -> Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(Self {
+            file: Arc::new(Mutex::new(LineWriter::new(file))),
+            database: None,
+        })
+    }
+
+    /// The same log, recording the statements as executed on `database`.
+    pub fn for_database(&self, database: Option<&str>) -> Self {
+        Self {
+            file: self.file.clone(),
+            database: database.map(str::to_owned),
+        }
+    }
+
+    /// Appends `sql`, executed for `user`. A file that can't be written to
+    /// is logged rather than failing the statement.
+    pub fn record(&self, user: Option<&str>, sql: &str) {
+        let entry = ReplayEntry {
+            at: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs_f64())
+                .unwrap_or_default(),
+            database: self.database.clone(),
+            user: user.map(str::to_owned),
+            sql: sql.to_owned(),
+        };
+        let mut line = serde_json::to_vec(&entry).expect("entries serialize");
+        line.push(b'\n');
+
+        let mut file = self.file.lock().unwrap_or_else(|e| e.into_inner());
+        if let Err(e) = file.write_all(&line) {
+            log::error!("could not record a statement for replay: {e}");
+        }
+    }
+}
+
+/// The entries of the replay file at `path`, in the order they were recorded.
+pub fn read(path: &Path) -> Result<Vec<ReplayEntry>> {
+    let file = BufReader::new(File::open(path)?);
+    let mut entries = Vec::new();
+    for (i, line) in file.lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let entry = serde_json::from_str(&line)
+            .map_err(|e| Error::IOError(format!("line {} of {}: {e}", i + 1, path.display())))?;
+        entries.push(entry);
+    }
+    Ok(entries)
+}
+
+/// Executes `entries` in order, each against an empty database of its own
+/// name, writing to `out` every statement followed by the views it gave, or
+/// its error. They're executed without a user, like the statements of the
+/// repl, as the users they were recorded for don't exist in the new
+/// databases, and each in a session of its own, like the requests of a
+/// server, so that what they `SET` doesn't carry over to the next.
+pub fn replay(entries: impl IntoIterator<Item = ReplayEntry>, mut out: impl Write) -> Result<()> {
+    let mut dbs: HashMap<Option<String>, Database> = HashMap::new();
+    for entry in entries {
+        let db = dbs.entry(entry.database.clone()).or_default();
+        let database = entry.database.as_deref().unwrap_or("default");
+        let user = entry.user.as_deref().unwrap_or("-");
+        writeln!(out, "-- at {} on {database} by {user}", entry.at)?;
+        writeln!(out, "{}", entry.sql)?;
+        match db.run_as(Session::default(), |db| db.query(&entry.sql)) {
+            Ok(views) => {
+                for view in views {
+                    writeln!(out, "{}", view.render(OutputMode::Table))?;
+                }
+            }
+            Err(e) => writeln!(out, "error: {e}")?,
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,135 @@
+use crate::table::RowId;
+
+const BITS: usize = u64::BITS as usize;
+
+/// A set of RowIds. It is kept as a bitmap, as ids are handed out in order
+/// and so stay mostly dense, which makes lookups and set operations cheap.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RowSet {
+    words: Vec<u64>,
+    len: usize,
+}
+
+impl RowSet {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn contains(&self, id: RowId) -> bool {
+        self.words
+            .get(id / BITS)
+            .is_some_and(|w| w & (1 << (id % BITS)) != 0)
+    }
+
+    /// Returns whether `id` was not in the set already.
+    pub fn insert(&mut self, id: RowId) -> bool {
+        let word = id / BITS;
+        if word >= self.words.len() {
+            self.words.resize(word + 1, 0);
+        }
+
+        let bit = 1 << (id % BITS);
+        let added = self.words[word] & bit == 0;
+        self.words[word] |= bit;
+        self.len += added as usize;
+        added
+    }
+
+    /// Returns whether `id` was in the set.
+    pub fn remove(&mut self, id: RowId) -> bool {
+        let Some(word) = self.words.get_mut(id / BITS) else {
+            return false;
+        };
+
+        let bit = 1 << (id % BITS);
+        let removed = *word & bit != 0;
+        *word &= !bit;
+        self.len -= removed as usize;
+        removed
+    }
+
+    /// Keeps only the ids that are also in `other`.
+    pub fn intersect_with(&mut self, other: &RowSet) {
+        self.words.truncate(other.words.len());
+        for (w, o) in self.words.iter_mut().zip(&other.words) {
+            *w &= o;
+        }
+        self.recount();
+    }
+
+    /// Adds every id in `other`.
+    pub fn union_with(&mut self, other: &RowSet) {
+        if other.words.len() > self.words.len() {
+            self.words.resize(other.words.len(), 0);
+        }
+        for (w, o) in self.words.iter_mut().zip(&other.words) {
+            *w |= o;
+        }
+        self.recount();
+    }
+
+    /// Removes every id in `other`.
+    pub fn difference_with(&mut self, other: &RowSet) {
+        for (w, o) in self.words.iter_mut().zip(&other.words) {
+            *w &= !o;
+        }
+        self.recount();
+    }
+
+    /// The smallest id in the set that is at least `from`.
+    pub fn next_from(&self, from: RowId) -> Option<RowId> {
+        let mut word = from / BITS;
+        let mut bits = self.words.get(word)? & (u64::MAX << (from % BITS));
+
+        loop {
+            if bits != 0 {
+                return Some(word * BITS + bits.trailing_zeros() as usize);
+            }
+            word += 1;
+            bits = *self.words.get(word)?;
+        }
+    }
+
+    /// The ids in ascending order.
+    pub fn iter(&self) -> impl Iterator<Item = RowId> + '_ {
+        self.words.iter().enumerate().flat_map(|(i, &word)| {
+            let mut word = word;
+            std::iter::from_fn(move || {
+                if word == 0 {
+                    return None;
+                }
+                let bit = word.trailing_zeros() as usize;
+                word &= word - 1;
+                Some(i * BITS + bit)
+            })
+        })
+    }
+
+    fn recount(&mut self) {
+        self.len = self.words.iter().map(|w| w.count_ones() as usize).sum();
+    }
+}
+
+impl FromIterator<RowId> for RowSet {
+    fn from_iter<T: IntoIterator<Item = RowId>>(iter: T) -> Self {
+        let mut set = RowSet::new();
+        set.extend(iter);
+        set
+    }
+}
+
+impl Extend<RowId> for RowSet {
+    fn extend<T: IntoIterator<Item = RowId>>(&mut self, iter: T) {
+        for id in iter {
+            self.insert(id);
+        }
+    }
+}
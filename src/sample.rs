@@ -0,0 +1,107 @@
+use rand::{rngs::StdRng, seq::IteratorRandom, Rng, SeedableRng};
+
+use crate::{
+    parser::expression::{Expression, Literal},
+    rowset::RowSet,
+    Error, Result,
+};
+
+/// How much of a table `TABLESAMPLE` takes.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SampleSize {
+    // each row with this chance, out of 100
+    Percent(f64),
+    // this many rows, or every row of smaller tables
+    Rows(usize),
+}
+
+/// `TABLESAMPLE (<size> [PERCENT | ROWS]) [REPEATABLE (<seed>)]`, which of the
+/// rows of a table a select goes through, so that large ones can be looked at
+/// without reading every row.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Sample {
+    pub size: SampleSize,
+    // for the same rows every time, as long as the table stays the same
+    pub seed: Option<u64>,
+}
+
+impl Sample {
+    /// The sample given by the arguments of the `tablesample(<size>, <unit>
+    /// [, <seed>])` table hint the parser turns the clause into.
+    pub fn from_args(args: &[Expression]) -> Result<Self> {
+        let (size, unit, seed) = match args {
+            [Expression::Literal(size), Expression::Literal(Literal::Str(unit)), seed @ ..] => {
+                (size, unit, seed)
+            }
+            _ => return Err(invalid(args)),
+        };
+        let size = match (size, unit.as_str()) {
+            (Literal::Int(p), "percent") => f64::from(*p),
+            (Literal::Float(p), "percent") => f64::from(*p),
+            (Literal::Double(p), "percent") => *p,
+            (Literal::Int(n), "rows") => {
+                let n = usize::try_from(*n).map_err(|_| invalid(args))?;
+                return Self::new(SampleSize::Rows(n), seed, args);
+            }
+            _ => return Err(invalid(args)),
+        };
+        if !(0.0..=100.0).contains(&size) {
+            return Err(Error::InvalidQuery(format!(
+                "TABLESAMPLE of {size} percent, rather than 0 to 100"
+            )));
+        }
+        Self::new(SampleSize::Percent(size), seed, args)
+    }
+
+    fn new(size: SampleSize, seed: &[Expression], args: &[Expression]) -> Result<Self> {
+        let seed = match seed {
+            [] => None,
+            [Expression::Literal(Literal::Int(seed))] => Some(i64::from(*seed) as u64),
+            _ => return Err(invalid(args)),
+        };
+        Ok(Self { size, seed })
+    }
+
+    /// The rows of `rows` in the sample. A percentage takes each row or not
+    /// on its own, while a number of rows is chosen by reservoir sampling.
+    pub fn rows(&self, rows: &RowSet) -> RowSet {
+        let mut rng = match self.seed {
+            Some(seed) => StdRng::seed_from_u64(seed),
+            None => StdRng::from_entropy(),
+        };
+
+        match self.size {
+            SampleSize::Percent(percent) => {
+                let chance = percent / 100.0;
+                rows.iter().filter(|_| rng.gen_bool(chance)).collect()
+            }
+            SampleSize::Rows(n) => rows
+                .iter()
+                .choose_multiple(&mut rng, n)
+                .into_iter()
+                .collect(),
+        }
+    }
+}
+
+// sql that reads back as the same clause, e.g. for `EXPLAIN`
+impl std::fmt::Display for Sample {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.size {
+            SampleSize::Percent(percent) => write!(f, "TABLESAMPLE ({percent} PERCENT)")?,
+            SampleSize::Rows(n) => write!(f, "TABLESAMPLE ({n} ROWS)")?,
+        }
+        match self.seed {
+            Some(seed) => write!(f, " REPEATABLE ({seed})"),
+            None => Ok(()),
+        }
+    }
+}
+
+fn invalid(args: &[Expression]) -> Error {
+    let args: Vec<String> = args.iter().map(Expression::to_string).collect();
+    Error::InvalidQuery(format!(
+        "TABLESAMPLE of {}, rather than a percentage or a number of rows",
+        args.join(", ")
+    ))
+}
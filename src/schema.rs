@@ -0,0 +1,226 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    sql,
+    table::{DataType, Table},
+    Error, Result,
+};
+
+/// The tables of a database, their columns, constraints and indexes, as
+/// `Database::schema_to_json` gives them and `Database::apply_schema` takes
+/// them, like:
+///
+/// ```json
+/// {"tables": [{
+///     "name": "PRICES",
+///     "columns": [
+///         {"name": "id", "type": "int", "nullable": false, "primary_key": true},
+///         {"name": "symbol", "type": "varchar", "max_length": 8},
+///         {"name": "price", "type": "numeric", "scale": 2}
+///     ],
+///     "indexes": [{"name": "PRICES_pkey", "columns": ["id"], "unique": true}]
+/// }]}
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Schema {
+    pub tables: Vec<TableSchema>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TableSchema {
+    pub name: String,
+    pub columns: Vec<ColumnSchema>,
+    // only the one of the primary key so far, which is derived from the
+    // columns when applied
+    #[serde(default)]
+    pub indexes: Vec<IndexSchema>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ColumnSchema {
+    pub name: String,
+    #[serde(rename = "type")]
+    pub datatype: ColumnType,
+    #[serde(default = "yes")]
+    pub nullable: bool,
+    #[serde(default)]
+    pub primary_key: bool,
+    // in characters, of varchars
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_length: Option<usize>,
+    // digits after the decimal point, of numerics
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub scale: Option<usize>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ColumnType {
+    Int,
+    Varchar,
+    Float,
+    Double,
+    // a double shown with `scale` digits
+    Numeric,
+    Bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct IndexSchema {
+    pub name: String,
+    pub columns: Vec<String>,
+    pub unique: bool,
+}
+
+fn yes() -> bool {
+    true
+}
+
+impl ColumnSchema {
+    // as the column would be created: primary keys can't be null, and only
+    // numerics have a scale and varchars a length
+    fn normalized(&self) -> ColumnSchema {
+        ColumnSchema {
+            name: self.name.to_lowercase(),
+            datatype: self.datatype,
+            nullable: self.nullable && !self.primary_key,
+            primary_key: self.primary_key,
+            max_length: self
+                .max_length
+                .filter(|_| self.datatype == ColumnType::Varchar),
+            scale: match self.datatype {
+                ColumnType::Numeric => Some(self.scale.unwrap_or_default()),
+                _ => None,
+            },
+        }
+    }
+}
+
+impl Schema {
+    pub fn from_json(json: &str) -> Result<Self> {
+        serde_json::from_str(json).map_err(|e| Error::InvalidSchema(e.to_string()))
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).expect("schemas serialize")
+    }
+}
+
+impl From<&Table> for TableSchema {
+    fn from(table: &Table) -> Self {
+        let columns: Vec<ColumnSchema> = table
+            .columns
+            .iter()
+            .map(|c| &c.header)
+            .filter(|h| !h.hidden)
+            .map(|h| ColumnSchema {
+                name: h.name.clone(),
+                datatype: match (h.datatype, h.precision) {
+                    (DataType::Int, _) => ColumnType::Int,
+                    (DataType::Float, _) => ColumnType::Float,
+                    (DataType::Double, Some(_)) => ColumnType::Numeric,
+                    (DataType::Double, None) => ColumnType::Double,
+                    (DataType::Bool, _) => ColumnType::Bool,
+                    (DataType::Str | DataType::Invalid, _) => ColumnType::Varchar,
+                },
+                nullable: h.nullable,
+                primary_key: h.is_pk,
+                max_length: h.max_length,
+                scale: h.precision,
+            })
+            .collect();
+
+        Self {
+            name: table.name.clone(),
+            indexes: primary_key_index(&table.name, &columns)
+                .into_iter()
+                .collect(),
+            columns,
+        }
+    }
+}
+
+fn primary_key_index(table: &str, columns: &[ColumnSchema]) -> Option<IndexSchema> {
+    let pk = columns.iter().find(|c| c.primary_key)?;
+    Some(IndexSchema {
+        name: format!("{table}_pkey"),
+        columns: vec![pk.name.clone()],
+        unique: true,
+    })
+}
+
+impl TableSchema {
+    /// The `CREATE TABLE` statement of the table.
+    pub fn to_sql(&self) -> String {
+        let columns: Vec<String> = self
+            .columns
+            .iter()
+            .map(|c| {
+                let datatype = match (c.datatype, c.max_length, c.scale) {
+                    (ColumnType::Int, ..) => "INT".to_owned(),
+                    (ColumnType::Varchar, Some(length), _) => format!("VARCHAR({length})"),
+                    (ColumnType::Varchar, None, _) => "VARCHAR".to_owned(),
+                    (ColumnType::Float, ..) => "FLOAT".to_owned(),
+                    (ColumnType::Double, ..) => "DOUBLE".to_owned(),
+                    (ColumnType::Numeric, _, scale) => {
+                        format!("NUMERIC(38, {})", scale.unwrap_or_default())
+                    }
+                    (ColumnType::Bool, ..) => "BOOL".to_owned(),
+                };
+                let constraint = match (c.primary_key, c.nullable) {
+                    (true, _) => " PRIMARY KEY",
+                    (false, false) => " NOT NULL",
+                    (false, true) => "",
+                };
+                format!("{} {datatype}{constraint}", sql::ident(&c.name))
+            })
+            .collect();
+
+        format!(
+            "CREATE TABLE {} ({});",
+            sql::ident(&self.name),
+            columns.join(", ")
+        )
+    }
+
+    /// Indexes other than the one of the primary key, which can't be created.
+    pub fn unsupported_indexes(&self) -> impl Iterator<Item = &IndexSchema> {
+        let pk = primary_key_index(&self.name, &self.columns);
+        self.indexes.iter().filter(move |i| {
+            pk.as_ref()
+                .map_or(true, |pk| pk.columns != i.columns || !i.unique)
+        })
+    }
+
+    /// How `other`, the schema of the table as it is, differs from this one.
+    pub fn differences(&self, other: &TableSchema) -> Vec<String> {
+        let mut differences = Vec::new();
+        for column in &self.columns {
+            match other
+                .columns
+                .iter()
+                .find(|c| c.name.eq_ignore_ascii_case(&column.name))
+            {
+                None => differences.push(format!("column {} is missing", column.name)),
+                Some(c) if c.normalized() != column.normalized() => {
+                    differences.push(format!("column {} is defined differently", column.name))
+                }
+                Some(_) => {}
+            }
+        }
+        for column in &other.columns {
+            if !self
+                .columns
+                .iter()
+                .any(|c| c.name.eq_ignore_ascii_case(&column.name))
+            {
+                differences.push(format!("column {} isn't in the schema", column.name));
+            }
+        }
+        differences
+    }
+}
@@ -0,0 +1,197 @@
+use std::{
+    collections::BTreeMap,
+    sync::{Mutex, PoisonError},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{parser::expression::Literal, Error, Result};
+
+/// Whether `name` is one of the functions of sequences, which are evaluated
+/// by `Sequences::call`.
+pub fn is_function(name: &str) -> bool {
+    matches!(name, "nextval" | "currval" | "setval")
+}
+
+/// What `CREATE SEQUENCE` says about the sequence, with whatever it leaves
+/// out as `None`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SequenceOptions {
+    pub increment: Option<i32>,
+    pub start: Option<i32>,
+    pub min: Option<i32>,
+    pub max: Option<i32>,
+    pub cycle: bool,
+}
+
+/// A counter handing out integers with `nextval`, like the ids of rows,
+/// independent of any table.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Sequence {
+    pub increment: i32,
+    pub min: i32,
+    pub max: i32,
+    // whether it starts over at the other end once it runs out
+    pub cycle: bool,
+    // the value `nextval` last gave, or that it gives next before it's called
+    pub value: i32,
+    pub called: bool,
+}
+
+impl Sequence {
+    /// A sequence of `options`, which otherwise counts up by 1 from 1, or
+    /// down from -1 with a negative increment.
+    pub fn new(name: &str, options: &SequenceOptions) -> Result<Self> {
+        let increment = options.increment.unwrap_or(1);
+        let (min, max) = match increment {
+            0 => {
+                return Err(Error::InvalidQuery(format!(
+                    "sequence {name} with an increment of 0"
+                )))
+            }
+            1.. => (options.min.unwrap_or(1), options.max.unwrap_or(i32::MAX)),
+            _ => (options.min.unwrap_or(i32::MIN), options.max.unwrap_or(-1)),
+        };
+        let start = options
+            .start
+            .unwrap_or(if increment > 0 { min } else { max });
+
+        if min > max {
+            return Err(Error::InvalidQuery(format!(
+                "sequence {name} with a minimum of {min}, above its maximum of {max}"
+            )));
+        }
+        if !(min..=max).contains(&start) {
+            return Err(Error::InvalidQuery(format!(
+                "sequence {name} starting at {start}, outside of {min} to {max}"
+            )));
+        }
+
+        Ok(Self {
+            increment,
+            min,
+            max,
+            cycle: options.cycle,
+            value: start,
+            called: false,
+        })
+    }
+
+    fn next(&mut self, name: &str) -> Result<i32> {
+        if !self.called {
+            self.called = true;
+            return Ok(self.value);
+        }
+
+        let next = self
+            .value
+            .checked_add(self.increment)
+            .filter(|v| (self.min..=self.max).contains(v));
+        self.value = match (next, self.cycle) {
+            (Some(next), _) => next,
+            (None, true) if self.increment > 0 => self.min,
+            (None, true) => self.max,
+            (None, false) => {
+                let (bound, value) = match self.increment > 0 {
+                    true => ("maximum", self.max),
+                    false => ("minimum", self.min),
+                };
+                return Err(Error::LimitExceeded(format!(
+                    "sequence {name} reached its {bound} of {value}"
+                )));
+            }
+        };
+        Ok(self.value)
+    }
+}
+
+/// The sequences of a database, by lowercase name. They're changed by the
+/// statements reading them, through `nextval` and `setval`, so they're kept
+/// behind a lock rather than being borrowed mutably for every select.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Sequences(Mutex<BTreeMap<String, Sequence>>);
+
+impl Clone for Sequences {
+    fn clone(&self) -> Self {
+        Self(Mutex::new(self.lock().clone()))
+    }
+}
+
+impl Sequences {
+    fn lock(&self) -> std::sync::MutexGuard<'_, BTreeMap<String, Sequence>> {
+        self.0.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    pub fn get(&self, name: &str) -> Option<Sequence> {
+        self.lock().get(&name.to_lowercase()).cloned()
+    }
+
+    pub fn create(&self, name: &str, sequence: Sequence) -> Result<()> {
+        let mut sequences = self.lock();
+        if sequences.contains_key(&name.to_lowercase()) {
+            return Err(Error::SequenceAlreadyExists(name.to_owned()));
+        }
+        sequences.insert(name.to_lowercase(), sequence);
+        Ok(())
+    }
+
+    /// Drops the sequence `name`, returning whether there was one.
+    pub fn drop(&self, name: &str) -> bool {
+        self.lock().remove(&name.to_lowercase()).is_some()
+    }
+
+    /// Calls the sequence function `name`, see `is_function`, with `args`:
+    ///
+    /// - `nextval(sequence)`, the next value of the sequence
+    /// - `currval(sequence)`, the value `nextval` last gave
+    /// - `setval(sequence, value [, called])`, sets the value `nextval` last
+    ///   gave, or with `called` false, the one it gives next, and returns it
+    ///
+    /// A null argument gives null.
+    pub fn call(&self, name: &str, args: &[Literal]) -> Result<Literal> {
+        if args.contains(&Literal::Null) {
+            return Ok(Literal::Null);
+        }
+        let Some(Literal::Str(sequence)) = args.first() else {
+            return Err(Error::InvalidQuery(format!(
+                "{name} of other than the name of a sequence"
+            )));
+        };
+
+        let mut sequences = self.lock();
+        let seq = sequences
+            .get_mut(&sequence.to_lowercase())
+            .ok_or_else(|| Error::SequenceNotFound(sequence.clone()))?;
+        match (name, &args[1..]) {
+            ("nextval", []) => seq.next(sequence).map(Literal::Int),
+            ("currval", []) if seq.called => Ok(Literal::Int(seq.value)),
+            ("currval", []) => Err(Error::InvalidOperation(format!(
+                "currval of sequence {sequence} before nextval"
+            ))),
+            ("setval", [Literal::Int(value), called @ ..]) => {
+                let called = match called {
+                    [] => true,
+                    [Literal::Bool(called)] => *called,
+                    _ => {
+                        return Err(Error::InvalidQuery(
+                            "setval with other than whether the value was given".to_owned(),
+                        ))
+                    }
+                };
+                if !(seq.min..=seq.max).contains(value) {
+                    return Err(Error::InvalidQuery(format!(
+                        "setval of sequence {sequence} to {value}, outside of {} to {}",
+                        seq.min, seq.max
+                    )));
+                }
+                seq.value = *value;
+                seq.called = called;
+                Ok(Literal::Int(*value))
+            }
+            _ => Err(Error::InvalidQuery(format!(
+                "{name} with {} arguments",
+                args.len()
+            ))),
+        }
+    }
+}
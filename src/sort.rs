@@ -0,0 +1,92 @@
+use std::{cmp::Ordering, collections::BinaryHeap};
+
+use crate::{
+    parser::expression::Literal,
+    rowset::RowSet,
+    table::{ColumnData, RowId},
+};
+
+/// A column rows are ordered by, and which way.
+pub struct SortKey<'a> {
+    pub data: &'a ColumnData,
+    pub desc: bool,
+    pub nulls_first: bool,
+}
+
+// a row along with the values it is ordered by, where ties keep the rows in
+// the order they were inserted in
+struct Entry<'a> {
+    values: Vec<Option<Literal>>,
+    id: RowId,
+    keys: &'a [SortKey<'a>],
+}
+
+impl Ord for Entry<'_> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        let values = self.values.iter().zip(&other.values);
+        for (key, (a, b)) in self.keys.iter().zip(values) {
+            // where nulls go doesn't depend on the direction
+            let ord = match (a, b) {
+                (Some(a), Some(b)) if key.desc => b.partial_cmp(a).unwrap_or(Ordering::Equal),
+                (Some(a), Some(b)) => a.partial_cmp(b).unwrap_or(Ordering::Equal),
+                (None, None) => Ordering::Equal,
+                (None, Some(_)) if key.nulls_first => Ordering::Less,
+                (None, Some(_)) => Ordering::Greater,
+                (Some(_), None) if key.nulls_first => Ordering::Greater,
+                (Some(_), None) => Ordering::Less,
+            };
+            if ord != Ordering::Equal {
+                return ord;
+            }
+        }
+
+        self.id.cmp(&other.id)
+    }
+}
+
+impl PartialOrd for Entry<'_> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Entry<'_> {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Entry<'_> {}
+
+/// Orders `rows` by their values in `keys`, the first key first. With a
+/// `limit`, only that many rows are kept around while going through them,
+/// rather than sorting all of them.
+pub fn sort(rows: &RowSet, keys: &[SortKey], limit: Option<usize>) -> Vec<RowId> {
+    let entries = rows.iter().map(|id| Entry {
+        values: keys.iter().map(|k| k.data.get_literal(id)).collect(),
+        id,
+        keys,
+    });
+
+    let sorted = match limit {
+        Some(limit) => {
+            // the heap keeps the last of the rows so far on top, to be
+            // dropped once a row that goes before it comes along
+            let mut heap = BinaryHeap::with_capacity(limit + 1);
+            for entry in entries {
+                heap.push(entry);
+                if heap.len() > limit {
+                    heap.pop();
+                }
+            }
+            heap.into_sorted_vec()
+        }
+        None => {
+            let mut entries: Vec<Entry> = entries.collect();
+            entries.sort_unstable();
+            entries
+        }
+    };
+
+    sorted.into_iter().map(|e| e.id).collect()
+}
@@ -0,0 +1,114 @@
+use std::io::BufRead;
+
+use serde::Deserialize;
+use serde_json::{Map, Value};
+
+use crate::{parser::expression::Literal, Error, Result};
+
+/// A change read from an upstream feed, to be applied to the table its source
+/// is attached to, see `Database::attach_source`. As json, like
+/// `{"op": "update", "row": {"id": 1, "price": 9.5}}`, where a bare row is an
+/// insert.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SourceRecord {
+    pub op: SourceOp,
+    // by column name
+    pub row: Vec<(String, Literal)>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SourceOp {
+    Insert,
+    /// Of the columns in the row, for the row with its primary key.
+    Update,
+    /// Of the row with the primary key in the row.
+    Delete,
+}
+
+/// A feed of records from an upstream system, like a Kafka topic or a
+/// websocket, which is read on a thread of its own.
+pub trait Source: Send + 'static {
+    /// Blocks until the next record arrives, giving `None` once the feed ended.
+    fn next_record(&mut self) -> Option<Result<SourceRecord>>;
+}
+
+impl<S: Source + ?Sized> Source for Box<S> {
+    fn next_record(&mut self) -> Option<Result<SourceRecord>> {
+        (**self).next_record()
+    }
+}
+
+/// A source with a json record on every line, e.g. of a tcp connection or a
+/// file. Blank lines are skipped.
+pub struct JsonLines<R> {
+    reader: R,
+    line: String,
+}
+
+impl<R: BufRead> JsonLines<R> {
+    pub fn new(reader: R) -> Self {
+        Self {
+            reader,
+            line: String::new(),
+        }
+    }
+}
+
+impl<R: BufRead + Send + 'static> Source for JsonLines<R> {
+    fn next_record(&mut self) -> Option<Result<SourceRecord>> {
+        loop {
+            self.line.clear();
+            match self.reader.read_line(&mut self.line) {
+                Ok(0) => return None,
+                Ok(_) if self.line.trim().is_empty() => continue,
+                Ok(_) => return Some(SourceRecord::from_json(&self.line)),
+                Err(e) => return Some(Err(e.into())),
+            }
+        }
+    }
+}
+
+impl SourceRecord {
+    pub fn from_json(json: &str) -> Result<Self> {
+        let invalid = |e: &dyn std::fmt::Display| {
+            Error::InvalidOperation(format!("record that isn't valid: {e}"))
+        };
+        let mut object: Map<String, Value> = serde_json::from_str(json).map_err(|e| invalid(&e))?;
+
+        let (op, row) = match object.remove("op") {
+            None => (SourceOp::Insert, object),
+            Some(op) => {
+                let op = serde_json::from_value(op).map_err(|e| invalid(&e))?;
+                match object.remove("row") {
+                    Some(Value::Object(row)) => (op, row),
+                    _ => return Err(invalid(&"without a row object")),
+                }
+            }
+        };
+
+        let row = row
+            .into_iter()
+            .map(|(column, value)| {
+                let literal = literal(&column, value)?;
+                Ok((column, literal))
+            })
+            .collect::<Result<_>>()?;
+        Ok(Self { op, row })
+    }
+}
+
+pub(crate) fn literal(column: &str, value: Value) -> Result<Literal> {
+    match value {
+        Value::Null => Ok(Literal::Null),
+        Value::Bool(b) => Ok(Literal::Bool(b)),
+        Value::String(s) => Ok(Literal::Str(s)),
+        Value::Number(n) => match n.as_i64().and_then(|n| i32::try_from(n).ok()) {
+            Some(n) => Ok(Literal::Int(n)),
+            None => Ok(Literal::Double(n.as_f64().unwrap_or(f64::NAN))),
+        },
+        Value::Array(_) | Value::Object(_) => Err(Error::InvalidOperation(format!(
+            "record with a nested value in column {column}"
+        ))),
+    }
+}
@@ -0,0 +1,217 @@
+use std::borrow::Cow;
+
+use sqlparser::{
+    keywords::{Keyword, RESERVED_FOR_COLUMN_ALIAS, RESERVED_FOR_TABLE_ALIAS},
+    tokenizer::Token,
+};
+
+// keywords that start an expression of their own rather than being taken
+// for a column, on top of the ones sqlparser doesn't take for aliases
+const EXPRESSION_KEYWORDS: &[Keyword] = &[
+    Keyword::NULL,
+    Keyword::TRUE,
+    Keyword::FALSE,
+    Keyword::NOT,
+    Keyword::CASE,
+    Keyword::CAST,
+    Keyword::EXISTS,
+    Keyword::INTERVAL,
+];
+
+/// `name` as an identifier in sql, double quoted unless it reads as itself
+/// without, i.e. it's made of letters, digits and underscores, and isn't a
+/// keyword that could be taken for something other than a name.
+pub fn ident(name: &str) -> Cow<'_, str> {
+    let plain = name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+    let reserved = match Token::make_word(name, None) {
+        Token::Word(w) => [
+            RESERVED_FOR_TABLE_ALIAS,
+            RESERVED_FOR_COLUMN_ALIAS,
+            EXPRESSION_KEYWORDS,
+        ]
+        .iter()
+        .any(|keywords| keywords.contains(&w.keyword)),
+        _ => false,
+    };
+
+    match plain && !reserved {
+        true => Cow::Borrowed(name),
+        false => Cow::Owned(format!("\"{}\"", name.replace('"', "\"\""))),
+    }
+}
+
+/// Like `ident`, for a name that may be qualified with its table, as in
+/// `t.a`, quoting each part on its own.
+pub fn qualified(name: &str) -> String {
+    let parts: Vec<Cow<str>> = name.split('.').map(ident).collect();
+    parts.join(".")
+}
+
+/// `value` as a string literal, in single quotes with those in it doubled.
+/// Line breaks, tabs and backslashes are escaped in a postgres `E'...'`
+/// string instead, so that the literal stays on a single line.
+pub fn string(value: &str) -> String {
+    if !value.contains(['\n', '\r', '\t', '\\']) {
+        return format!("'{}'", value.replace('\'', "''"));
+    }
+
+    let mut out = String::with_capacity(value.len() + 3);
+    out.push_str("E'");
+    for c in value.chars() {
+        match c {
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            '\\' => out.push_str("\\\\"),
+            '\'' => out.push_str("\\'"),
+            c => out.push(c),
+        }
+    }
+    out.push('\'');
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use sqlparser::{
+        ast::{Expr, SelectItem, SetExpr, Statement, TableFactor},
+        dialect::PostgreSqlDialect,
+        parser::Parser,
+    };
+
+    use super::*;
+    use crate::database::Database;
+
+    const NAMES: &[&str] = &[
+        "a",
+        "Mixed",
+        "_x1",
+        "select",
+        "FROM",
+        "null",
+        "true",
+        "order",
+        "user",
+        "1a",
+        "a b",
+        "a\"b",
+        "\"",
+        "it's",
+        "line\nbreak",
+        "back\\slash",
+        "é",
+    ];
+
+    const VALUES: &[&str] = &[
+        "",
+        "plain",
+        "it's",
+        "''",
+        "say \"hi\"",
+        "line\nbreak",
+        "\r\n\t",
+        "back\\slash",
+        "\\n",
+        "E'",
+        "é",
+    ];
+
+    // the column and table `SELECT <name> FROM <name>` reads as
+    fn parse_ident(name: &str) -> (String, String) {
+        let sql = format!("SELECT {name} FROM {name}");
+        let Some(Statement::Query(query)) = Parser::parse_sql(&PostgreSqlDialect {}, &sql)
+            .unwrap()
+            .pop()
+        else {
+            panic!("{sql} isn't a query");
+        };
+        let SetExpr::Select(select) = *query.body else {
+            panic!("{sql} isn't a select");
+        };
+        let SelectItem::UnnamedExpr(Expr::Identifier(column)) = &select.projection[0] else {
+            panic!("{sql} doesn't select a column");
+        };
+        let TableFactor::Table { name: table, .. } = &select.from[0].relation else {
+            panic!("{sql} doesn't select from a table");
+        };
+        (column.value.clone(), table.0[0].value.clone())
+    }
+
+    #[test]
+    fn idents_parse_back() {
+        for name in NAMES {
+            let (column, table) = parse_ident(&ident(name));
+            assert_eq!(column, *name);
+            assert_eq!(table, *name);
+        }
+    }
+
+    #[test]
+    fn qualified_names_parse_back() {
+        let sql = format!("SELECT {}", qualified("order.a b"));
+        let Some(Statement::Query(query)) = Parser::parse_sql(&PostgreSqlDialect {}, &sql)
+            .unwrap()
+            .pop()
+        else {
+            panic!("{sql} isn't a query");
+        };
+        let SetExpr::Select(select) = *query.body else {
+            panic!("{sql} isn't a select");
+        };
+        let SelectItem::UnnamedExpr(Expr::CompoundIdentifier(parts)) = &select.projection[0] else {
+            panic!("{sql} doesn't select a qualified column");
+        };
+        let parts: Vec<&str> = parts.iter().map(|p| p.value.as_str()).collect();
+        assert_eq!(parts, ["order", "a b"]);
+    }
+
+    #[test]
+    fn strings_parse_back() {
+        let mut db = Database::new();
+        for value in VALUES {
+            let literal = string(value);
+            assert!(!literal.contains('\n'), "{literal} spans lines");
+            let row = db
+                .query_rows(&format!("SELECT {literal} AS v"))
+                .unwrap()
+                .next()
+                .unwrap();
+            assert_eq!(row, [*value], "{literal}");
+        }
+    }
+
+    #[test]
+    fn dumps_import_back() {
+        let mut db = Database::new();
+        let table = ident("Order Items");
+        let columns: Vec<_> = ["id", "select", "a\"b", "Mixed"]
+            .into_iter()
+            .map(ident)
+            .collect();
+        db.query(&format!(
+            "CREATE TABLE {table} ({} INT PRIMARY KEY, {} VARCHAR, {} VARCHAR, {} INT)",
+            columns[0], columns[1], columns[2], columns[3]
+        ))
+        .unwrap();
+        for (id, value) in VALUES.iter().enumerate() {
+            db.query(&format!(
+                "INSERT INTO {table} VALUES ({id}, {}, {}, NULL)",
+                string(value),
+                string(&value.to_uppercase())
+            ))
+            .unwrap();
+        }
+
+        let dump = db.dump(None).unwrap();
+        let mut imported = Database::new();
+        imported.query(&dump).unwrap();
+
+        assert_eq!(imported.dump(None).unwrap(), dump);
+        let select = format!("SELECT * FROM {table} ORDER BY {}", columns[0]);
+        assert_eq!(
+            imported.query(&select).unwrap()[0].to_csv(),
+            db.query(&select).unwrap()[0].to_csv()
+        );
+    }
+}
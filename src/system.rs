@@ -0,0 +1,86 @@
+use std::time::Instant;
+
+use crate::{database::table_key, parser::expression::Literal, table::Table, Error, Result};
+
+/// Whether `name` is one of the functions about the database itself, which
+/// are evaluated by `System::call`.
+pub fn is_function(name: &str) -> bool {
+    matches!(name, "version" | "table_rows" | "db_size" | "uptime")
+}
+
+/// When the database was created or opened, which `uptime()` counts from.
+#[derive(Debug, Clone, Copy)]
+pub struct Started(Instant);
+
+impl Default for Started {
+    fn default() -> Self {
+        Self(Instant::now())
+    }
+}
+
+/// What the system functions are about: the tables of the database and how
+/// long it's been up.
+pub struct System<'a> {
+    pub tables: &'a [Table],
+    pub started: Started,
+}
+
+impl System<'_> {
+    /// Calls the system function `name`, see `is_function`, with `args`:
+    ///
+    /// - `version()`, the name and version of the engine
+    /// - `table_rows(table)`, how many rows the table has
+    /// - `db_size([table])`, roughly how many bytes the values of every
+    ///   table, or of only `table`, take up in memory, as a double since
+    ///   that can be more than an int holds
+    /// - `uptime()`, how many seconds it's been since the database was
+    ///   created or opened
+    ///
+    /// A null argument gives null.
+    pub fn call(&self, name: &str, args: &[Literal]) -> Result<Literal> {
+        if args.contains(&Literal::Null) {
+            return Ok(Literal::Null);
+        }
+
+        match (name, args) {
+            ("version", []) => Ok(Literal::Str(format!(
+                "{} {}",
+                env!("CARGO_PKG_NAME"),
+                env!("CARGO_PKG_VERSION")
+            ))),
+            ("table_rows", [table]) => {
+                let rows = self.table(name, table)?.row_ids().len();
+                Ok(Literal::Int(i32::try_from(rows).unwrap_or(i32::MAX)))
+            }
+            ("db_size", []) => Ok(Literal::Double(
+                self.tables.iter().map(|t| t.byte_size() as f64).sum(),
+            )),
+            ("db_size", [table]) => {
+                Ok(Literal::Double(self.table(name, table)?.byte_size() as f64))
+            }
+            ("uptime", []) => {
+                let seconds = self.started.0.elapsed().as_secs();
+                Ok(Literal::Int(i32::try_from(seconds).unwrap_or(i32::MAX)))
+            }
+            _ => Err(Error::InvalidQuery(format!(
+                "{name} with {} arguments",
+                args.len()
+            ))),
+        }
+    }
+
+    fn table(&self, function: &str, name: &Literal) -> Result<&Table> {
+        let Literal::Str(name) = name else {
+            return Err(Error::InvalidQuery(format!(
+                "{function} of other than the name of a table"
+            )));
+        };
+        self.tables
+            .iter()
+            .find(|t| table_key(&t.name) == table_key(name))
+            .ok_or_else(|| {
+                let existing = self.tables.iter().map(|t| t.name.as_str());
+                Error::table_not_found(name, existing)
+            })
+    }
+}
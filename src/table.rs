@@ -1,24 +1,67 @@
-use std::collections::{BTreeMap, HashMap};
+use std::{
+    collections::{HashMap, HashSet},
+    ops::Bound,
+    str::FromStr,
+    sync::Arc,
+    time::{SystemTime, UNIX_EPOCH},
+};
 
 use bimap::BiBTreeMap;
 use serde::{Deserialize, Serialize};
-use sqlparser::ast::ColumnDef;
+use sqlparser::{
+    ast::{CharacterLength, ColumnDef, ExactNumberInfo},
+    dialect::GenericDialect,
+    parser::Parser,
+};
 
-use crate::{parser::expression::Literal, Error};
+use crate::{
+    chunked::ChunkedMap,
+    evaluator::Evaluator,
+    format,
+    fulltext::FullTextIndex,
+    optimizer,
+    parser::{
+        expression::{Expression, Literal},
+        policy::Policy,
+        trigger::Trigger,
+    },
+    rowset::RowSet,
+    Error,
+};
 
 pub type RowId = usize;
 
-#[derive(Debug, Serialize, Deserialize, PartialEq)]
+/// Hidden column holding the time each row was inserted at, as seconds since
+/// the unix epoch. It can still be selected, or filtered on, by name.
+pub const INSERTED_AT: &str = "__inserted_at";
+
+/// The values an update sets, by the name of each column, for each of its
+/// rows in order, as worked out by `Table::assigned`.
+#[derive(Debug, Default)]
+pub struct Assigned(Vec<(String, Vec<(RowId, Literal)>)>);
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub struct Table {
     pub name: String,
     pub columns: Vec<Column>,
+    // rebuilt from the data on restore, see `rebuild_pk_map`
+    #[serde(skip)]
     pub pk_map: BiBTreeMap<PKType, RowId>,
+    #[serde(default)]
+    pub triggers: Vec<Trigger>,
+    #[serde(default)]
+    pub policies: Vec<Policy>,
+    #[serde(default)]
+    pub fulltext: Vec<FullTextIndex>,
 }
 
-#[derive(Debug, Serialize, Deserialize, PartialEq)]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub struct Column {
     pub header: ColumnHeader,
     pub data: ColumnData,
+    // rebuilt from the data on restore, see `rebuild_dictionaries`
+    #[serde(skip)]
+    pub dictionary: Dictionary,
 }
 
 impl Column {
@@ -29,7 +72,7 @@ impl Column {
                 map.insert(row_id, d);
             }
             (ColumnData::Str(map), Literal::Str(d)) => {
-                map.insert(row_id, d);
+                map.insert(row_id, self.dictionary.intern(&d));
             }
             (ColumnData::Float(map), Literal::Float(d)) => {
                 map.insert(row_id, d);
@@ -45,9 +88,98 @@ impl Column {
 
         Ok(())
     }
+
+    pub fn update(&mut self, row_id: RowId, lit: Literal) -> Result<(), Error> {
+        match (&mut self.data, lit) {
+            (ColumnData::Str(map), Literal::Str(value)) => {
+                map.insert(row_id, self.dictionary.intern(&value));
+                Ok(())
+            }
+            (data, lit) => data.update(row_id, lit),
+        }
+    }
+
+    // `value` converted to fit this column, or why it doesn't
+    fn coerce(&self, value: Literal, strictness: Strictness) -> Result<Literal, String> {
+        let header = &self.header;
+        let Some(value) = value.cast(header.datatype, strictness) else {
+            return Err(format!(
+                "a value of the wrong type for column {} ({:?})",
+                header.name, header.datatype
+            ));
+        };
+
+        match (value, header.max_length) {
+            (Literal::Str(s), Some(max)) if s.chars().count() > max => match strictness {
+                Strictness::Strict => Err(format!(
+                    "a value longer than {max} characters for column {}",
+                    header.name
+                )),
+                Strictness::Lenient => Ok(Literal::Str(s.chars().take(max).collect())),
+            },
+            (value, _) => Ok(value),
+        }
+    }
 }
 
-#[derive(Debug, Serialize, Deserialize, PartialEq, Eq)]
+/// How values that don't fit a column are handled. `Strict` only converts
+/// them when nothing is lost, like an int into a double column, and rejects
+/// columns that don't exist. `Lenient` converts wherever it can, rounding
+/// numbers, parsing text and cutting it down to the column's length, and skips
+/// columns that don't exist.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Strictness {
+    #[default]
+    Strict,
+    Lenient,
+}
+
+impl FromStr for Strictness {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "strict" => Ok(Strictness::Strict),
+            "lenient" => Ok(Strictness::Lenient),
+            _ => Err(Error::InvalidQuery(format!(
+                "unknown strictness {s}, expected strict or lenient"
+            ))),
+        }
+    }
+}
+
+/// The distinct values of a text column. Rows holding the same value share a
+/// single allocation of it, which keeps low cardinality columns small.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Dictionary(HashSet<Arc<str>>);
+
+impl Dictionary {
+    pub fn intern(&mut self, value: &str) -> Arc<str> {
+        if let Some(interned) = self.0.get(value) {
+            return interned.clone();
+        }
+
+        let interned: Arc<str> = value.into();
+        self.0.insert(interned.clone());
+        interned
+    }
+
+    // whether `value` is the interned copy, rather than an equal one
+    fn contains(&self, value: &Arc<str>) -> bool {
+        self.0.get(&**value).is_some_and(|v| Arc::ptr_eq(v, value))
+    }
+
+    // forgets the values that no row refers to anymore
+    fn prune(&mut self) {
+        self.0.retain(|v| Arc::strong_count(v) > 1);
+    }
+
+    fn clear(&mut self) {
+        self.0.clear();
+    }
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
 pub enum DataType {
     Int,
     Str,
@@ -57,6 +189,24 @@ pub enum DataType {
     Invalid,
 }
 
+impl DataType {
+    pub fn is_numeric(self) -> bool {
+        matches!(self, DataType::Int | DataType::Float | DataType::Double)
+    }
+
+    /// The type of `lit`, or `None` for null, which could be of any.
+    pub fn of(lit: &Literal) -> Option<Self> {
+        match lit {
+            Literal::Int(_) => Some(DataType::Int),
+            Literal::Str(_) => Some(DataType::Str),
+            Literal::Float(_) => Some(DataType::Float),
+            Literal::Double(_) => Some(DataType::Double),
+            Literal::Bool(_) => Some(DataType::Bool),
+            Literal::Null => None,
+        }
+    }
+}
+
 impl From<&ColumnData> for DataType {
     fn from(value: &ColumnData) -> Self {
         match value {
@@ -69,13 +219,24 @@ impl From<&ColumnData> for DataType {
     }
 }
 
-#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
 pub enum PKType {
     Int(i32),
     Str(String),
 }
 
-#[derive(Debug, Serialize, Deserialize, PartialEq, Eq)]
+impl PKType {
+    /// `None` for values that can't be a primary key.
+    pub fn from_literal(lit: &Literal) -> Option<Self> {
+        match lit {
+            Literal::Int(i) => Some(PKType::Int(*i)),
+            Literal::Str(s) => Some(PKType::Str(s.clone())),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
 pub struct ColumnHeader {
     pub name: String,
     pub hidden: bool,
@@ -83,25 +244,62 @@ pub struct ColumnHeader {
     pub nullable: bool,
     pub is_pk: bool,
     pub last_row_id: Option<RowId>,
+    // in characters, for `varchar(n)`
+    #[serde(default)]
+    pub max_length: Option<usize>,
+    // digits shown after the decimal point, for `numeric(p, s)`
+    #[serde(default)]
+    pub precision: Option<usize>,
+    // the sql of the expression given to `DEFAULT`, see `default_value`
+    #[serde(default)]
+    pub default: Option<String>,
+}
+
+impl ColumnHeader {
+    /// The expression the column is given when an insert leaves it out, like
+    /// `nextval('ids')`, if it has one.
+    pub fn default_value(&self) -> Result<Option<Expression>, Error> {
+        let Some(sql) = &self.default else {
+            return Ok(None);
+        };
+        // as sqlparser wrote it, which any dialect reads back
+        let expr = Parser::new(&GenericDialect {})
+            .try_with_sql(sql)?
+            .parse_expr()?;
+        Expression::from_expr(expr).map(Some)
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub enum ColumnData {
-    Int(BTreeMap<RowId, i32>),
-    Str(BTreeMap<RowId, String>),
-    Float(BTreeMap<RowId, f32>),
-    Double(BTreeMap<RowId, f64>),
-    Bool(BTreeMap<RowId, bool>),
+    Int(ChunkedMap<i32>),
+    Str(ChunkedMap<Arc<str>>),
+    Float(ChunkedMap<f32>),
+    Double(ChunkedMap<f64>),
+    Bool(ChunkedMap<bool>),
 }
 
 impl ColumnData {
+    pub fn empty(datatype: DataType) -> Result<Self, Error> {
+        match datatype {
+            DataType::Int => Ok(ColumnData::Int(Default::default())),
+            DataType::Str => Ok(ColumnData::Str(Default::default())),
+            DataType::Float => Ok(ColumnData::Float(Default::default())),
+            DataType::Double => Ok(ColumnData::Double(Default::default())),
+            DataType::Bool => Ok(ColumnData::Bool(Default::default())),
+            DataType::Invalid => Err(Error::InvalidOperation(
+                "cannot create a column of invalid type".to_owned(),
+            )),
+        }
+    }
+
     pub fn update(&mut self, row_id: RowId, lit: Literal) -> Result<(), Error> {
         match (self, lit) {
             (ColumnData::Int(x), Literal::Int(value)) => {
                 x.insert(row_id, value);
             }
             (ColumnData::Str(x), Literal::Str(value)) => {
-                x.insert(row_id, value);
+                x.insert(row_id, value.into());
             }
             (ColumnData::Float(x), Literal::Float(value)) => {
                 x.insert(row_id, value);
@@ -152,17 +350,17 @@ impl ColumnData {
         }
     }
 
-    pub fn keys(&self) -> Vec<RowId> {
+    pub fn keys(&self) -> RowSet {
         match self {
-            ColumnData::Int(x) => x.keys().cloned().collect(),
-            ColumnData::Str(x) => x.keys().cloned().collect(),
-            ColumnData::Float(x) => x.keys().cloned().collect(),
-            ColumnData::Double(x) => x.keys().cloned().collect(),
-            ColumnData::Bool(x) => x.keys().cloned().collect(),
+            ColumnData::Int(x) => x.keys().copied().collect(),
+            ColumnData::Str(x) => x.keys().copied().collect(),
+            ColumnData::Float(x) => x.keys().copied().collect(),
+            ColumnData::Double(x) => x.keys().copied().collect(),
+            ColumnData::Bool(x) => x.keys().copied().collect(),
         }
     }
 
-    pub fn keys_where_true(&self) -> Result<Vec<RowId>, Error> {
+    pub fn keys_where_true(&self) -> Result<RowSet, Error> {
         match self {
             ColumnData::Bool(map) => Ok(map.iter().filter(|(_, v)| **v).map(|(k, _)| *k).collect()),
             _ => Err(Error::InvalidOperation(
@@ -171,13 +369,13 @@ impl ColumnData {
         }
     }
 
-    pub fn retain_keys(&mut self, keys: &[RowId]) {
+    pub fn retain_keys(&mut self, keys: &RowSet) {
         match self {
-            ColumnData::Int(d) => d.retain(|k, _| keys.contains(k)),
-            ColumnData::Str(d) => d.retain(|k, _| keys.contains(k)),
-            ColumnData::Float(d) => d.retain(|k, _| keys.contains(k)),
-            ColumnData::Double(d) => d.retain(|k, _| keys.contains(k)),
-            ColumnData::Bool(d) => d.retain(|k, _| keys.contains(k)),
+            ColumnData::Int(d) => d.retain(|k, _| keys.contains(*k)),
+            ColumnData::Str(d) => d.retain(|k, _| keys.contains(*k)),
+            ColumnData::Float(d) => d.retain(|k, _| keys.contains(*k)),
+            ColumnData::Double(d) => d.retain(|k, _| keys.contains(*k)),
+            ColumnData::Bool(d) => d.retain(|k, _| keys.contains(*k)),
         }
     }
 
@@ -201,12 +399,40 @@ impl ColumnData {
         }
     }
 
-    pub fn get_as_string(&self, id: RowId) -> Option<String> {
+    /// Roughly how many bytes the values take up: the id and value of every
+    /// row, and the text of strings, counted once for each row holding it.
+    pub fn byte_size(&self) -> usize {
+        fn rows<T>(map: &ChunkedMap<T>) -> usize {
+            map.len() * (std::mem::size_of::<RowId>() + std::mem::size_of::<T>())
+        }
+
+        match self {
+            ColumnData::Int(d) => rows(d),
+            ColumnData::Str(d) => rows(d) + d.values().map(|s| s.len()).sum::<usize>(),
+            ColumnData::Float(d) => rows(d),
+            ColumnData::Double(d) => rows(d),
+            ColumnData::Bool(d) => rows(d),
+        }
+    }
+
+    pub fn get_literal(&self, id: RowId) -> Option<Literal> {
+        match self {
+            ColumnData::Int(d) => d.get(&id).map(|v| Literal::Int(*v)),
+            ColumnData::Str(d) => d.get(&id).map(|v| Literal::Str(v.to_string())),
+            ColumnData::Float(d) => d.get(&id).map(|v| Literal::Float(*v)),
+            ColumnData::Double(d) => d.get(&id).map(|v| Literal::Double(*v)),
+            ColumnData::Bool(d) => d.get(&id).map(|v| Literal::Bool(*v)),
+        }
+    }
+
+    /// The value at `id` as text, with floating point values shown to
+    /// `precision` digits after the decimal point (see `format::double`).
+    pub fn get_as_string(&self, id: RowId, precision: Option<usize>) -> Option<String> {
         match self {
             ColumnData::Int(d) => d.get(&id).map(|v| v.to_string()),
             ColumnData::Str(d) => d.get(&id).map(|v| v.to_string()),
-            ColumnData::Float(d) => d.get(&id).map(|v| v.to_string()),
-            ColumnData::Double(d) => d.get(&id).map(|v| v.to_string()),
+            ColumnData::Float(d) => d.get(&id).map(|v| format::float(*v, precision)),
+            ColumnData::Double(d) => d.get(&id).map(|v| format::double(*v, precision)),
             ColumnData::Bool(d) => d.get(&id).map(|v| v.to_string()),
         }
     }
@@ -214,35 +440,36 @@ impl ColumnData {
     pub fn fill_with_literal(lit: Literal, till: RowId) -> Result<Self, Error> {
         match lit {
             Literal::Int(x) => {
-                let mut map = BTreeMap::default();
+                let mut map = ChunkedMap::default();
                 for i in 0..=till {
                     map.insert(i, x);
                 }
                 Ok(Self::Int(map))
             }
             Literal::Str(x) => {
-                let mut map = BTreeMap::default();
+                let x: Arc<str> = x.into();
+                let mut map = ChunkedMap::default();
                 for i in 0..=till {
                     map.insert(i, x.clone());
                 }
                 Ok(Self::Str(map))
             }
             Literal::Bool(x) => {
-                let mut map = BTreeMap::default();
+                let mut map = ChunkedMap::default();
                 for i in 0..=till {
                     map.insert(i, x);
                 }
                 Ok(Self::Bool(map))
             }
             Literal::Float(x) => {
-                let mut map = BTreeMap::default();
+                let mut map = ChunkedMap::default();
                 for i in 0..=till {
                     map.insert(i, x);
                 }
                 Ok(Self::Float(map))
             }
             Literal::Double(x) => {
-                let mut map = BTreeMap::default();
+                let mut map = ChunkedMap::default();
                 for i in 0..=till {
                     map.insert(i, x);
                 }
@@ -253,10 +480,75 @@ impl ColumnData {
             )),
         }
     }
+
+    /// Collects row wise values into a column, typed after the first non null
+    /// value. Nulls are left out, and `None` is returned if every value is null.
+    pub fn from_literals(
+        values: impl IntoIterator<Item = (RowId, Literal)>,
+    ) -> Result<Option<Self>, Error> {
+        let mut out: Option<Self> = None;
+
+        for (id, lit) in values {
+            match (&mut out, lit) {
+                (_, Literal::Null) => {}
+                (None, Literal::Int(v)) => {
+                    out = Some(ColumnData::Int([(id, v)].into_iter().collect()))
+                }
+                (None, Literal::Str(v)) => {
+                    out = Some(ColumnData::Str([(id, v.into())].into_iter().collect()))
+                }
+                (None, Literal::Float(v)) => {
+                    out = Some(ColumnData::Float([(id, v)].into_iter().collect()))
+                }
+                (None, Literal::Double(v)) => {
+                    out = Some(ColumnData::Double([(id, v)].into_iter().collect()))
+                }
+                (None, Literal::Bool(v)) => {
+                    out = Some(ColumnData::Bool([(id, v)].into_iter().collect()))
+                }
+                (Some(ColumnData::Int(d)), Literal::Int(v)) => _ = d.insert(id, v),
+                (Some(ColumnData::Str(d)), Literal::Str(v)) => _ = d.insert(id, v.into()),
+                (Some(ColumnData::Float(d)), Literal::Float(v)) => _ = d.insert(id, v),
+                (Some(ColumnData::Double(d)), Literal::Double(v)) => _ = d.insert(id, v),
+                (Some(ColumnData::Bool(d)), Literal::Bool(v)) => _ = d.insert(id, v),
+                (Some(_), lit) => {
+                    return Err(Error::InvalidOperation(format!(
+                        "mixed types in a single column: {lit:?}"
+                    )))
+                }
+            }
+        }
+
+        Ok(out)
+    }
+}
+
+/// Finds `name` among `columns`, ignoring case. Either side may be qualified
+/// with a table name: `a` finds `t.a` as long as no other table has an `a`,
+/// and `t.a` finds a plain `a`.
+pub fn find_column<'n, 'c>(
+    columns: impl Iterator<Item = (&'n str, &'c Column)> + Clone,
+    name: &str,
+) -> Option<&'c Column> {
+    if let Some((_, col)) = columns.clone().find(|(n, _)| n.eq_ignore_ascii_case(name)) {
+        return Some(col);
+    }
+
+    let mut candidates = columns.filter(|(n, _)| match name.rsplit_once('.') {
+        Some((_, unqualified)) => !n.contains('.') && n.eq_ignore_ascii_case(unqualified),
+        None => n
+            .rsplit_once('.')
+            .is_some_and(|(_, c)| c.eq_ignore_ascii_case(name)),
+    });
+
+    match (candidates.next(), candidates.next()) {
+        (Some((_, col)), None) => Some(col),
+        _ => None,
+    }
 }
 
 impl Table {
-    pub fn new(name: String, columns: Vec<ColumnDef>) -> Self {
+    pub fn new(name: String, columns: Vec<ColumnDef>) -> Result<Self, Error> {
         let columns: Vec<Column> = columns
             .into_iter()
             .map(|c| {
@@ -271,59 +563,226 @@ impl Table {
                     sqlparser::ast::DataType::Float8
                     | sqlparser::ast::DataType::Float64
                     | sqlparser::ast::DataType::Double
-                    | sqlparser::ast::DataType::DoublePrecision => {
+                    | sqlparser::ast::DataType::DoublePrecision
+                    | sqlparser::ast::DataType::Numeric(_)
+                    | sqlparser::ast::DataType::Decimal(_) => {
                         ColumnData::Double(Default::default())
                     }
                     sqlparser::ast::DataType::Bool | sqlparser::ast::DataType::Boolean => {
                         ColumnData::Bool(Default::default())
                     }
-                    _ => unimplemented!(),
+                    datatype => {
+                        return Err(Error::Unsupported(format!(
+                            "column {} of type {datatype}",
+                            c.name
+                        )))
+                    }
+                };
+
+                let max_length = match c.data_type {
+                    sqlparser::ast::DataType::Varchar(Some(CharacterLength::IntegerLength {
+                        length,
+                        ..
+                    })) => Some(length as usize),
+                    _ => None,
+                };
+
+                // numbers are stored as doubles, so the scale only applies
+                // to how they are shown
+                let precision = match c.data_type {
+                    sqlparser::ast::DataType::Numeric(info)
+                    | sqlparser::ast::DataType::Decimal(info) => match info {
+                        ExactNumberInfo::None => None,
+                        ExactNumberInfo::Precision(_) => Some(0),
+                        ExactNumberInfo::PrecisionAndScale(_, scale) => Some(scale as usize),
+                    },
+                    _ => None,
                 };
 
                 let mut is_pk = false;
                 let mut nullable = true;
-                let mut unique = false;
+                let mut default = None;
 
-                c.options.into_iter().for_each(|c| match c.option {
-                    sqlparser::ast::ColumnOption::Null => {
-                        nullable = true;
+                for option in c.options {
+                    match option.option {
+                        sqlparser::ast::ColumnOption::Null => {
+                            nullable = true;
+                        }
+                        sqlparser::ast::ColumnOption::NotNull => {
+                            nullable = false;
+                        }
+                        sqlparser::ast::ColumnOption::Unique { is_primary } => {
+                            is_pk = is_primary;
+                            nullable = false;
+                        }
+                        sqlparser::ast::ColumnOption::Default(expr) => {
+                            // so that one that can't be evaluated is refused
+                            // now, rather than failing every insert
+                            Expression::from_expr(expr.clone())?;
+                            default = Some(expr.to_string());
+                        }
+                        option => {
+                            return Err(Error::Unsupported(format!(
+                                "column option `{option}` on {}",
+                                c.name
+                            )))
+                        }
                     }
-                    sqlparser::ast::ColumnOption::NotNull => {
-                        nullable = false;
-                    }
-                    sqlparser::ast::ColumnOption::Unique { is_primary } => {
-                        is_pk = is_primary;
-                        unique = true;
-                        nullable = false;
-                    }
-                    _ => unimplemented!(),
-                });
+                }
 
-                Column {
+                Ok(Column {
                     header: ColumnHeader {
-                        name: c.name.to_string(),
+                        name: c.name.value.clone(),
                         nullable,
                         is_pk,
                         datatype: DataType::from(&data),
                         last_row_id: None,
                         hidden: false,
+                        max_length,
+                        precision,
+                        default,
                     },
                     data,
-                }
+                    dictionary: Default::default(),
+                })
             })
-            .collect();
+            .collect::<Result<_, Error>>()?;
+
+        let mut columns = columns;
+        columns.push(Column {
+            header: ColumnHeader {
+                name: INSERTED_AT.to_owned(),
+                hidden: true,
+                datatype: DataType::Double,
+                nullable: false,
+                is_pk: false,
+                last_row_id: None,
+                max_length: None,
+                precision: None,
+                default: None,
+            },
+            data: ColumnData::Double(Default::default()),
+            dictionary: Default::default(),
+        });
 
         log::debug!("creating table {name} with columns: {columns:?}");
 
-        if !columns.iter().any(|c| c.header.is_pk) {
-            log::error!("cannot create table with no primary key");
-            panic!("cannot create table with no primary key");
+        let table = Self {
+            name,
+            columns,
+            pk_map: Default::default(),
+            triggers: Vec::new(),
+            policies: Vec::new(),
+            fulltext: Vec::new(),
+        };
+        table.validate()?;
+
+        Ok(table)
+    }
+
+    /// Checks that the columns make sense together: their names differ, even
+    /// ignoring case as lookups do, and exactly one of them is the primary key.
+    pub fn validate(&self) -> Result<(), Error> {
+        let mut seen = HashSet::new();
+        for col in &self.columns {
+            let name = &col.header.name;
+            if !col.header.hidden && name.eq_ignore_ascii_case(INSERTED_AT) {
+                return Err(Error::InvalidSchema(format!(
+                    "column name {INSERTED_AT} is reserved"
+                )));
+            }
+
+            if !seen.insert(name.to_lowercase()) {
+                return Err(Error::InvalidSchema(format!(
+                    "column {name} appears more than once in table {}",
+                    self.name
+                )));
+            }
+        }
+
+        match self.columns.iter().filter(|c| c.header.is_pk).count() {
+            1 => Ok(()),
+            0 => Err(Error::InvalidSchema(format!(
+                "table {} has no primary key",
+                self.name
+            ))),
+            _ => Err(Error::InvalidSchema(format!(
+                "table {} has more than one primary key",
+                self.name
+            ))),
+        }
+    }
+
+    /// A table with the same name, columns and triggers, but no rows.
+    pub fn clone_empty(&self) -> Self {
+        let mut columns = self.columns.clone();
+        for col in columns.iter_mut() {
+            col.data.truncate();
+            col.dictionary.clear();
+            col.header.last_row_id = None;
         }
 
         Self {
-            name,
+            name: self.name.clone(),
             columns,
             pk_map: Default::default(),
+            triggers: self.triggers.clone(),
+            policies: self.policies.clone(),
+            fulltext: self
+                .fulltext
+                .iter()
+                .map(|i| FullTextIndex::new(i.name.clone(), i.column.clone()))
+                .collect(),
+        }
+    }
+
+    /// A copy holding only the rows in `rows`, e.g. those a user may see.
+    pub fn restricted(&self, rows: &RowSet) -> Self {
+        let mut table = self.clone();
+        table.retain_rows(rows);
+        table
+    }
+
+    /// Leaves out every row that isn't in `rows`.
+    pub fn retain_rows(&mut self, rows: &RowSet) {
+        for col in self.columns.iter_mut() {
+            col.data.retain_keys(rows);
+        }
+        self.pk_map.retain(|_, id| rows.contains(*id));
+        for index in self.fulltext.iter_mut() {
+            index.retain(rows);
+        }
+    }
+
+    /// A copy of only the columns called one of the lowercase `names`, e.g.
+    /// those a query needs, along with the hidden ones, which every row has
+    /// a value in.
+    pub fn pruned(&self, names: &[String]) -> Self {
+        let columns: Vec<Column> = self
+            .columns
+            .iter()
+            .filter(|c| c.header.hidden || names.contains(&c.header.name.to_lowercase()))
+            .cloned()
+            .collect();
+        // the index goes with the key
+        let pk_map = match columns.iter().any(|c| c.header.is_pk) {
+            true => self.pk_map.clone(),
+            false => Default::default(),
+        };
+        let fulltext = self
+            .fulltext
+            .iter()
+            .filter(|i| columns.iter().any(|c| c.header.name == i.column))
+            .cloned()
+            .collect();
+
+        Self {
+            name: self.name.clone(),
+            columns,
+            pk_map,
+            triggers: self.triggers.clone(),
+            policies: self.policies.clone(),
+            fulltext,
         }
     }
 
@@ -340,74 +799,580 @@ impl Table {
     }
 
     pub fn truncate(&mut self) {
-        self.columns.iter_mut().for_each(|c| c.data.truncate());
+        for col in self.columns.iter_mut() {
+            col.data.truncate();
+            col.dictionary.clear();
+        }
+        self.pk_map.clear();
+        for index in self.fulltext.iter_mut() {
+            index.clear();
+        }
+        self.debug_verify();
     }
 
-    pub fn col_from_name(&self, name: &str) -> Option<&Column> {
+    pub fn pk_column(&self) -> Option<&Column> {
+        self.columns.iter().find(|c| c.header.is_pk)
+    }
+
+    // adds `rows` to the primary key index, failing on a key that's already
+    // there, in which case some of the rows may have been indexed
+    fn index_rows(&mut self, rows: impl Iterator<Item = RowId>) -> Result<(), Error> {
+        let Some(col) = self.columns.iter().find(|c| c.header.is_pk) else {
+            return Ok(());
+        };
+
+        for row_id in rows {
+            let Some(key) = col
+                .data
+                .get_literal(row_id)
+                .as_ref()
+                .and_then(PKType::from_literal)
+            else {
+                continue;
+            };
+
+            if self.pk_map.contains_left(&key) {
+                return Err(Error::InvalidOperation(format!(
+                    "duplicate primary key {key:?} in {}",
+                    self.name
+                )));
+            }
+            self.pk_map.insert(key, row_id);
+        }
+
+        Ok(())
+    }
+
+    /// Builds the primary key index again from the data, e.g. for tables
+    /// persisted before it was maintained.
+    pub fn rebuild_pk_map(&mut self) {
+        self.pk_map.clear();
+        let Some(col) = self.pk_column() else {
+            return;
+        };
+
+        let keys: Vec<(PKType, RowId)> = col
+            .data
+            .keys()
+            .iter()
+            .filter_map(|id| {
+                let key = PKType::from_literal(&col.data.get_literal(id)?)?;
+                Some((key, id))
+            })
+            .collect();
+
+        for (key, id) in keys {
+            self.pk_map.insert(key, id);
+        }
+    }
+
+    /// Builds the full text indexes again from the data, which they aren't
+    /// persisted with.
+    pub fn rebuild_fulltext(&mut self) {
+        for index in self.fulltext.iter_mut() {
+            index.clear();
+        }
+        let rows = self.row_ids();
+        self.index_text(&rows, |_| true, true);
+    }
+
+    /// Adds a full text index called `name` on the text column `column`, for
+    /// `MATCH ... AGAINST` to use, indexing the rows already there.
+    pub fn create_fulltext_index(&mut self, name: String, column: &str) -> Result<(), Error> {
+        if self
+            .fulltext
+            .iter()
+            .any(|i| i.name.eq_ignore_ascii_case(&name))
+        {
+            return Err(Error::IndexAlreadyExists(name));
+        }
+        let col = self
+            .col_from_name(column)
+            .ok_or_else(|| self.column_not_found(column))?;
+        let ColumnData::Str(texts) = &col.data else {
+            return Err(Error::InvalidQuery(format!(
+                "full text index on {}, which isn't text",
+                col.header.name
+            )));
+        };
+
+        let mut index = FullTextIndex::new(name, col.header.name.clone());
+        for (&row_id, text) in texts.iter() {
+            index.add(row_id, text);
+        }
+        self.fulltext.push(index);
+
+        Ok(())
+    }
+
+    /// Drops the full text index called `name`, returning whether there was
+    /// one.
+    pub fn drop_fulltext_index(&mut self, name: &str) -> bool {
+        let before = self.fulltext.len();
+        self.fulltext.retain(|i| !i.name.eq_ignore_ascii_case(name));
+        self.fulltext.len() != before
+    }
+
+    /// The full text index on the column called `column`, if it has one.
+    pub fn fulltext_index(&self, column: &str) -> Option<&FullTextIndex> {
+        let col = self.col_from_name(column)?;
+        self.fulltext.iter().find(|i| i.column == col.header.name)
+    }
+
+    // adds `rows` to the full text indexes on the columns `changed` says
+    // yes to, or takes them out of them while they still have the text they
+    // were added with
+    fn index_text(&mut self, rows: &RowSet, changed: impl Fn(&str) -> bool, add: bool) {
+        for index in self.fulltext.iter_mut().filter(|i| changed(&i.column)) {
+            let Some(ColumnData::Str(texts)) = self
+                .columns
+                .iter()
+                .find(|c| c.header.name == index.column)
+                .map(|c| &c.data)
+            else {
+                continue;
+            };
+
+            for row_id in rows.iter() {
+                let Some(text) = texts.get(&row_id) else {
+                    continue;
+                };
+                match add {
+                    true => index.add(row_id, text),
+                    false => index.remove(row_id, text),
+                }
+            }
+        }
+    }
+
+    pub fn rebuild_dictionaries(&mut self) {
+        for col in self.columns.iter_mut() {
+            col.dictionary.clear();
+            if let ColumnData::Str(map) = &mut col.data {
+                for value in map.values_mut() {
+                    *value = col.dictionary.intern(value);
+                }
+            }
+        }
+    }
+
+    /// Checks what the rest of the table relies on: that the primary key index
+    /// matches the primary key column, no row is past the last row id, text
+    /// values are interned, and not null columns have a value in every row.
+    /// Returns what doesn't hold, which is nothing unless there's a bug.
+    pub fn verify(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        let rows = self.row_ids();
+
+        if let Some(max) = rows.iter().last() {
+            let last = self.last_row_id();
+            if last.map_or(true, |last| last < max) {
+                problems.push(format!("row {max} is past the last row id ({last:?})"));
+            }
+        }
+
+        for col in &self.columns {
+            let header = &col.header;
+            if DataType::from(&col.data) != header.datatype {
+                problems.push(format!(
+                    "column {} holds {:?} values, but is declared {:?}",
+                    header.name,
+                    DataType::from(&col.data),
+                    header.datatype
+                ));
+            }
+
+            if !header.nullable {
+                let mut missing = rows.clone();
+                missing.difference_with(&col.data.keys());
+                if let Some(id) = missing.next_from(0) {
+                    problems.push(format!(
+                        "column {} is not null, but {} rows have no value for it, like row {id}",
+                        header.name,
+                        missing.len()
+                    ));
+                }
+            }
+
+            if let ColumnData::Str(map) = &col.data {
+                if let Some((id, _)) = map.iter().find(|(_, v)| !col.dictionary.contains(v)) {
+                    problems.push(format!(
+                        "the value of column {} in row {id} is not interned",
+                        header.name
+                    ));
+                }
+            }
+        }
+
+        let mut indexed = 0;
+        if let Some(col) = self.pk_column() {
+            for id in col.data.keys().iter() {
+                // like `index_rows`, keys of other types aren't indexed
+                let Some(key) = col
+                    .data
+                    .get_literal(id)
+                    .as_ref()
+                    .and_then(PKType::from_literal)
+                else {
+                    continue;
+                };
+
+                match self.pk_map.get_by_left(&key) {
+                    Some(&other) if other == id => indexed += 1,
+                    Some(other) => problems.push(format!(
+                        "primary key {key:?} of row {id} is indexed as row {other}"
+                    )),
+                    None => problems.push(format!(
+                        "primary key {key:?} of row {id} is missing from the index"
+                    )),
+                }
+            }
+        }
+
+        if indexed != self.pk_map.len() {
+            problems.push(format!(
+                "the primary key index has {} entries, for {indexed} rows",
+                self.pk_map.len()
+            ));
+        }
+
+        problems
+    }
+
+    // debug builds check every change against `verify`
+    fn debug_verify(&self) {
+        if cfg!(debug_assertions) {
+            let problems = self.verify();
+            assert!(
+                problems.is_empty(),
+                "table {} is inconsistent: {problems:?}",
+                self.name
+            );
+        }
+    }
+
+    /// The rows whose primary key is within `range`, from the index.
+    pub fn pk_range(&self, range: (Bound<PKType>, Bound<PKType>)) -> RowSet {
+        // which the index would panic over, like `id > 5 AND id < 3` gives
+        let empty = match &range {
+            (Bound::Included(a), Bound::Included(b)) => a > b,
+            (Bound::Included(a) | Bound::Excluded(a), Bound::Included(b) | Bound::Excluded(b)) => {
+                a >= b
+            }
+            _ => false,
+        };
+        if empty {
+            return RowSet::new();
+        }
+        self.pk_map.left_range(range).map(|(_, id)| *id).collect()
+    }
+
+    /// Every RowId that has a value in at least one column.
+    pub fn row_ids(&self) -> RowSet {
+        let mut ids = RowSet::new();
+        for col in &self.columns {
+            ids.union_with(&col.data.keys());
+        }
+        ids
+    }
+
+    /// Roughly how many bytes the values of the table take up, see
+    /// `ColumnData::byte_size`.
+    pub fn byte_size(&self) -> usize {
+        self.columns.iter().map(|c| c.data.byte_size()).sum()
+    }
+
+    /// The values of a row by lowercase column name, missing ones as null.
+    pub fn row_values(&self, row_id: RowId) -> HashMap<String, Literal> {
         self.columns
             .iter()
-            .find(|c| c.header.name.to_lowercase() == name.to_lowercase())
+            .map(|c| {
+                (
+                    c.header.name.to_lowercase(),
+                    c.data.get_literal(row_id).unwrap_or(Literal::Null),
+                )
+            })
+            .collect()
+    }
+
+    pub fn col_from_name(&self, name: &str) -> Option<&Column> {
+        find_column(
+            self.columns.iter().map(|c| (c.header.name.as_str(), c)),
+            name,
+        )
+    }
+
+    /// The error for `name` not being a column, suggesting one that might have
+    /// been meant instead.
+    pub fn column_not_found(&self, name: &str) -> Error {
+        let existing = self
+            .columns
+            .iter()
+            .filter(|c| !c.header.hidden)
+            .map(|c| c.header.name.as_str());
+        Error::column_not_found(name, &self.name, existing)
     }
 
     pub fn insert(
         &mut self,
-        mut columns: Vec<String>,
+        columns: Vec<String>,
         data: Vec<Vec<Literal>>,
+        strictness: Strictness,
     ) -> Result<(), Error> {
-        if columns.is_empty() {
-            columns = self.columns.iter().map(|c| c.header.name.clone()).collect();
+        let mapping = self.insert_mapping(&columns, strictness)?;
+        if let Some((i, row)) = data
+            .iter()
+            .enumerate()
+            .find(|(_, row)| row.len() != mapping.len())
+        {
+            return Err(Error::InvalidQuery(format!(
+                "row {} of insert has {} values, expected {}",
+                i + 1,
+                row.len(),
+                mapping.len()
+            )));
         }
 
-        let mut next_row_id = self.next_row_id();
-        let mut cols: Vec<&mut Column> = self
-            .columns
-            .iter_mut()
-            .filter(|c| columns.contains(&c.header.name))
-            .collect();
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs_f64())
+            .unwrap_or_default();
+
+        let next_row_id = self.next_row_id();
+        let rows = next_row_id..next_row_id + data.len();
 
         log::debug!("insert data: {data:?}");
 
-        for datum in data {
-            log::debug!("insert datum: {datum:?}");
-            for (col, col_data) in cols.iter_mut().zip(datum) {
-                log::debug!("insert col: {col:?}");
-                log::debug!("insert col_data: {col_data:?}");
-                col.insert(next_row_id, col_data)?;
+        if let Err(e) = self.write_rows(&mapping, next_row_id, data, strictness) {
+            // which checks the table
+            self.delete(&rows.collect())?;
+            return Err(e);
+        }
+        if !self.fulltext.is_empty() {
+            self.index_text(&rows.clone().collect(), |_| true, true);
+        }
+
+        // tables restored from before it was added have no such column
+        if let Some(col) = self
+            .columns
+            .iter_mut()
+            .find(|c| c.header.name == INSERTED_AT)
+        {
+            for row_id in rows {
+                col.insert(row_id, Literal::Double(now))?;
             }
-            next_row_id += 1;
         }
 
         log::debug!("column after inserting: {self:?}");
+        self.debug_verify();
 
         Ok(())
     }
 
-    pub fn update(
+    // the index into `columns` of each column named in an insert, in the order
+    // the values are given in. columns that don't exist are `None` when lenient
+    fn insert_mapping(
+        &self,
+        names: &[String],
+        strictness: Strictness,
+    ) -> Result<Vec<Option<usize>>, Error> {
+        if names.is_empty() {
+            return Ok((0..self.columns.len())
+                .filter(|&i| !self.columns[i].header.hidden)
+                .map(Some)
+                .collect());
+        }
+
+        let mut mapping = Vec::with_capacity(names.len());
+        for name in names {
+            let idx = self
+                .columns
+                .iter()
+                .position(|c| !c.header.hidden && c.header.name.eq_ignore_ascii_case(name));
+
+            let idx = match (idx, strictness) {
+                (Some(idx), _) => idx,
+                (None, Strictness::Lenient) => {
+                    log::debug!("skipping column {name}, which {} doesn't have", self.name);
+                    mapping.push(None);
+                    continue;
+                }
+                (None, Strictness::Strict) => return Err(self.column_not_found(name)),
+            };
+
+            if mapping.contains(&Some(idx)) {
+                return Err(Error::InvalidQuery(format!(
+                    "column {name} given more than once in insert"
+                )));
+            }
+            mapping.push(Some(idx));
+        }
+
+        // columns that are left out are null, which not all of them can be
+        if let Some(col) = self.columns.iter().enumerate().find_map(|(i, c)| {
+            (!c.header.hidden && !c.header.nullable && !mapping.contains(&Some(i))).then_some(c)
+        }) {
+            return Err(Error::InvalidQuery(format!(
+                "column {} is not null, but is missing from insert",
+                col.header.name
+            )));
+        }
+
+        Ok(mapping)
+    }
+
+    fn write_rows(
         &mut self,
-        assignments: HashMap<String, Literal>,
-        selected: Vec<RowId>,
+        mapping: &[Option<usize>],
+        first: RowId,
+        data: Vec<Vec<Literal>>,
+        strictness: Strictness,
     ) -> Result<(), Error> {
-        for col in self.columns.iter_mut() {
-            let Some(value) = assignments.get(&col.header.name.to_lowercase()) else {
-                continue;
-            };
+        let rows = first..first + data.len();
+        for (i, (row_id, row)) in rows.clone().zip(data).enumerate() {
+            for (&idx, value) in mapping.iter().zip(row) {
+                let Some(idx) = idx else {
+                    continue;
+                };
+
+                let col = &mut self.columns[idx];
+                let value = col.coerce(value, strictness).map_err(|reason| {
+                    Error::InvalidQuery(format!("row {} of insert has {reason}", i + 1))
+                })?;
+
+                match value {
+                    Literal::Null if col.header.nullable => {}
+                    Literal::Null => {
+                        return Err(Error::InvalidQuery(format!(
+                            "row {} of insert has a null for column {}, which is not null",
+                            i + 1,
+                            col.header.name
+                        )))
+                    }
+                    value => col.insert(row_id, value)?,
+                }
+            }
+        }
 
+        self.index_rows(rows)
+    }
+
+    /// The values the columns of `assignments` take for the `selected` rows,
+    /// their expressions evaluated with `evaluator` on each row as it is, so
+    /// that `SET a = b, b = a` swaps. Nothing is changed until they're given
+    /// to `update`, which is why each expression is evaluated only once.
+    pub fn assigned(
+        &self,
+        assignments: &HashMap<String, Expression>,
+        selected: &RowSet,
+        evaluator: &Evaluator,
+        strictness: Strictness,
+    ) -> Result<Assigned, Error> {
+        // everything is checked before anything is changed
+        let rows = self.restricted(selected);
+        let mut values = Vec::with_capacity(assignments.len());
+        for (name, expr) in assignments {
+            let Some(col) = self.col_from_name(name) else {
+                if strictness == Strictness::Lenient {
+                    log::debug!("skipping column {name}, which {} doesn't have", self.name);
+                    continue;
+                }
+                return Err(self.column_not_found(name));
+            };
             if col.header.is_pk {
                 return Err(Error::Unsupported(
                     "updating the primary is not allowed".to_owned(),
                 ));
             }
 
-            for row_id in &selected {
-                col.data.update(*row_id, value.clone())?;
+            // an expression referring to no column, like a literal or
+            // `nextval('s')`, gives a single value, at row 0, for every row,
+            // anything else a column of the selected rows with nulls left out
+            let expr = optimizer::simplify(expr.clone());
+            let constant = expr.idents().is_empty();
+            let mut out = evaluator.eval(Some(&rows), expr)?;
+            if out.len() > 1 {
+                return Err(Error::InvalidQuery(format!(
+                    "update sets column {name} to more than one column"
+                )));
+            }
+            let computed = out.pop();
+
+            let mut column = Vec::with_capacity(selected.len());
+            for row_id in selected.iter() {
+                let value = computed
+                    .as_ref()
+                    .and_then(|c| c.data.get_literal(if constant { 0 } else { row_id }))
+                    .unwrap_or(Literal::Null);
+                let value = col
+                    .coerce(value, strictness)
+                    .map_err(|reason| Error::InvalidQuery(format!("update sets {reason}")))?;
+                if value == Literal::Null {
+                    return Err(Error::Unsupported(format!(
+                        "setting column {} to null",
+                        col.header.name
+                    )));
+                }
+                column.push((row_id, value));
+            }
+
+            values.push((col.header.name.clone(), column));
+        }
+
+        Ok(Assigned(values))
+    }
+
+    /// The values of `row_id`, like `row_values`, once `assigned` is written.
+    pub fn assigned_row_values(
+        &self,
+        assigned: &Assigned,
+        row_id: RowId,
+    ) -> HashMap<String, Literal> {
+        let mut values = self.row_values(row_id);
+        for (name, column) in &assigned.0 {
+            // in the order of the row ids, as `RowSet` gives them
+            if let Ok(i) = column.binary_search_by_key(&row_id, |(id, _)| *id) {
+                values.insert(name.to_lowercase(), column[i].1.clone());
+            }
+        }
+        values
+    }
+
+    /// Writes the values worked out by `assigned` for the `selected` rows.
+    pub fn update(&mut self, assigned: Assigned, selected: &RowSet) -> Result<(), Error> {
+        let mut values = Vec::with_capacity(assigned.0.len());
+        for (name, column) in assigned.0 {
+            let idx = self
+                .columns
+                .iter()
+                .position(|c| c.header.name == name)
+                .ok_or_else(|| self.column_not_found(&name))?;
+            values.push((idx, column));
+        }
+
+        // the text indexed is about to change
+        let changed: Vec<String> = values
+            .iter()
+            .map(|(idx, _)| self.columns[*idx].header.name.clone())
+            .collect();
+        self.index_text(selected, |c| changed.iter().any(|n| n == c), false);
+
+        for (idx, column) in values {
+            let col = &mut self.columns[idx];
+            for (row_id, value) in column {
+                col.update(row_id, value)?;
             }
+            col.dictionary.prune();
         }
+        self.index_text(selected, |c| changed.iter().any(|n| n == c), true);
+        self.debug_verify();
 
         Ok(())
     }
 
-    pub fn delete(&mut self, selected: Vec<RowId>) -> Result<(), Error> {
-        for row_id in selected {
+    pub fn delete(&mut self, selected: &RowSet) -> Result<(), Error> {
+        self.index_text(selected, |_| true, false);
+        for row_id in selected.iter() {
             for col in self.columns.iter_mut() {
                 col.data.delete(row_id);
             }
@@ -415,6 +1380,11 @@ impl Table {
             self.pk_map.remove_by_right(&row_id);
         }
 
+        for col in self.columns.iter_mut() {
+            col.dictionary.prune();
+        }
+        self.debug_verify();
+
         Ok(())
     }
 }
@@ -0,0 +1,68 @@
+use std::{fs::File, io::BufReader, path::Path};
+
+use rustls::{server::AllowAnyAuthenticatedClient, Certificate, PrivateKey, RootCertStore};
+
+use crate::{Error, Result};
+
+pub use rustls::ServerConfig;
+
+/// The config to serve over TLS with the certificate chain and private key in
+/// the pem files at `cert` and `key`. With `client_ca`, clients have to
+/// present a certificate signed by one of the certificates in it.
+pub fn server_config(cert: &Path, key: &Path, client_ca: Option<&Path>) -> Result<ServerConfig> {
+    let chain = certificates(cert)?;
+    let key = private_key(key)?;
+
+    let builder = ServerConfig::builder().with_safe_defaults();
+    let builder = match client_ca {
+        Some(ca) => {
+            let mut roots = RootCertStore::empty();
+            for cert in certificates(ca)? {
+                roots.add(&cert).map_err(|e| tls_error(ca, e))?;
+            }
+            builder.with_client_cert_verifier(AllowAnyAuthenticatedClient::new(roots).boxed())
+        }
+        None => builder.with_no_client_auth(),
+    };
+
+    builder
+        .with_single_cert(chain, key)
+        .map_err(|e| Error::Tls(e.to_string()))
+}
+
+/// The common name in the subject of the certificate a client presented,
+/// which is the first one of its chain.
+pub fn common_name(chain: &[Certificate]) -> Option<String> {
+    let (_, cert) = x509_parser::parse_x509_certificate(&chain.first()?.0).ok()?;
+    let name = cert.subject().iter_common_name().next()?;
+    name.as_str().ok().map(str::to_owned)
+}
+
+fn certificates(path: &Path) -> Result<Vec<Certificate>> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let certs = rustls_pemfile::certs(&mut reader).map_err(|e| tls_error(path, e))?;
+    if certs.is_empty() {
+        return Err(tls_error(path, "no certificates"));
+    }
+
+    Ok(certs.into_iter().map(Certificate).collect())
+}
+
+fn private_key(path: &Path) -> Result<PrivateKey> {
+    let mut reader = BufReader::new(File::open(path)?);
+    loop {
+        match rustls_pemfile::read_one(&mut reader).map_err(|e| tls_error(path, e))? {
+            Some(
+                rustls_pemfile::Item::PKCS8Key(key)
+                | rustls_pemfile::Item::RSAKey(key)
+                | rustls_pemfile::Item::ECKey(key),
+            ) => return Ok(PrivateKey(key)),
+            Some(_) => continue,
+            None => return Err(tls_error(path, "no private key")),
+        }
+    }
+}
+
+fn tls_error(path: &Path, e: impl std::fmt::Display) -> Error {
+    Error::Tls(format!("{}: {e}", path.display()))
+}
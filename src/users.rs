@@ -0,0 +1,93 @@
+use argon2::{
+    password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString},
+    Argon2,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{Error, Result};
+
+/// The users that can log in to a server, see `CREATE USER`. Only the argon2
+/// hashes of their passwords are kept.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct Users(Vec<User>);
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct User {
+    pub name: String,
+    // which may create, alter and drop other users
+    pub superuser: bool,
+    password_hash: String,
+}
+
+impl Users {
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn get(&self, name: &str) -> Option<&User> {
+        self.0.iter().find(|u| u.name.eq_ignore_ascii_case(name))
+    }
+
+    pub fn create(&mut self, name: &str, password: &str, superuser: bool) -> Result<()> {
+        if self.get(name).is_some() {
+            return Err(Error::UserAlreadyExists(name.to_owned()));
+        }
+
+        self.0.push(User {
+            name: name.to_owned(),
+            superuser,
+            password_hash: hash(password)?,
+        });
+        Ok(())
+    }
+
+    pub fn alter(
+        &mut self,
+        name: &str,
+        password: Option<&str>,
+        superuser: Option<bool>,
+    ) -> Result<()> {
+        let user = self
+            .0
+            .iter_mut()
+            .find(|u| u.name.eq_ignore_ascii_case(name))
+            .ok_or_else(|| Error::UserNotFound(name.to_owned()))?;
+
+        if let Some(password) = password {
+            user.password_hash = hash(password)?;
+        }
+        if let Some(superuser) = superuser {
+            user.superuser = superuser;
+        }
+        Ok(())
+    }
+
+    pub fn remove(&mut self, name: &str, if_exists: bool) -> Result<()> {
+        let before = self.0.len();
+        self.0.retain(|u| !u.name.eq_ignore_ascii_case(name));
+
+        if self.0.len() == before && !if_exists {
+            return Err(Error::UserNotFound(name.to_owned()));
+        }
+        Ok(())
+    }
+
+    /// The user named `name`, if `password` is theirs. Hashing is slow on
+    /// purpose, so this takes a while either way.
+    pub fn verify(&self, name: &str, password: &str) -> Option<&User> {
+        let user = self.get(name)?;
+        let hash = PasswordHash::new(&user.password_hash).ok()?;
+        Argon2::default()
+            .verify_password(password.as_bytes(), &hash)
+            .ok()
+            .map(|()| user)
+    }
+}
+
+fn hash(password: &str) -> Result<String> {
+    let salt = SaltString::generate(&mut rand::rngs::OsRng);
+    Argon2::default()
+        .hash_password(password.as_bytes(), &salt)
+        .map(|hash| hash.to_string())
+        .map_err(|e| Error::InvalidOperation(format!("hashing the password: {e}")))
+}
@@ -0,0 +1,106 @@
+use std::{collections::HashMap, fmt::Debug, sync::Arc};
+
+use crate::{
+    parser::expression::{Expression, Literal},
+    table::{Column, ColumnData, ColumnHeader, DataType, Table},
+    Error, Result,
+};
+
+/// A read only table whose rows are produced by user code whenever it is
+/// selected from, e.g. a metrics ring buffer or a config map.
+pub trait VirtualTable: Send + Sync {
+    /// Column names and types, in the order `scan` returns values in.
+    fn schema(&self) -> Vec<(String, DataType)>;
+
+    /// Returns every row, with `Literal::Null` for missing values. `filters`
+    /// are the parts of the `WHERE` clause accepted by `supports_filter`.
+    fn scan(&self, filters: &[Expression]) -> Result<Vec<Vec<Literal>>>;
+
+    /// Whether `scan` can use `filter` to skip rows itself. The filter is
+    /// applied to the scanned rows regardless, so rows that don't match may
+    /// still be returned.
+    fn supports_filter(&self, _filter: &Expression) -> bool {
+        false
+    }
+}
+
+/// Virtual tables registered by the embedder, by lowercase name.
+#[derive(Default, Clone)]
+pub struct VirtualTables(HashMap<String, Arc<dyn VirtualTable>>);
+
+impl VirtualTables {
+    pub fn register(&mut self, name: &str, table: impl VirtualTable + 'static) {
+        self.0.insert(name.to_lowercase(), Arc::new(table));
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Arc<dyn VirtualTable>> {
+        self.0.get(&name.to_lowercase())
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&String, &Arc<dyn VirtualTable>)> {
+        self.0.iter()
+    }
+}
+
+impl Debug for VirtualTables {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_set().entries(self.0.keys()).finish()
+    }
+}
+
+/// Scans `vt` into a regular table, so that it can go through the same
+/// evaluation as every other table.
+pub fn materialize(name: &str, vt: &dyn VirtualTable, selection: &[Expression]) -> Result<Table> {
+    let mut columns = vt
+        .schema()
+        .into_iter()
+        .map(|(name, datatype)| {
+            Ok(Column {
+                data: ColumnData::empty(datatype)?,
+                header: ColumnHeader {
+                    name,
+                    hidden: false,
+                    datatype,
+                    nullable: true,
+                    is_pk: false,
+                    last_row_id: None,
+                    max_length: None,
+                    precision: None,
+                    default: None,
+                },
+                dictionary: Default::default(),
+            })
+        })
+        .collect::<Result<Vec<Column>>>()?;
+
+    let filters: Vec<Expression> = selection
+        .iter()
+        .filter(|s| !matches!(s, Expression::None) && vt.supports_filter(s))
+        .cloned()
+        .collect();
+
+    for (row_id, row) in vt.scan(&filters)?.into_iter().enumerate() {
+        if row.len() != columns.len() {
+            return Err(Error::InvalidOperation(format!(
+                "virtual table {name} returned a row with {} values, expected {}",
+                row.len(),
+                columns.len()
+            )));
+        }
+
+        for (col, value) in columns.iter_mut().zip(row) {
+            if value != Literal::Null {
+                col.insert(row_id, value)?;
+            }
+        }
+    }
+
+    Ok(Table {
+        name: name.to_uppercase(),
+        columns,
+        pk_map: Default::default(),
+        triggers: Vec::new(),
+        policies: Vec::new(),
+        fulltext: Vec::new(),
+    })
+}
@@ -0,0 +1,43 @@
+use std::io::Write;
+
+use rust_xlsxwriter::{Format, Workbook, XlsxError};
+
+use crate::{database::RowIter, table::ColumnData, Error, Result};
+
+/// Writes `rows` to `writer` as an Excel workbook of a single sheet, with the
+/// column names in a bold first row. Cells keep their type: ints and floats
+/// are numbers, bools are booleans and nulls are left empty.
+pub fn write_rows(rows: RowIter, writer: impl Write + Send) -> Result<()> {
+    let (names, columns, ids) = rows.into_parts();
+    let mut workbook = Workbook::new();
+    let sheet = workbook.add_worksheet();
+    let bold = Format::new().set_bold();
+    for (col, name) in names.iter().enumerate() {
+        sheet
+            .write_string_with_format(0, col as u16, name, &bold)
+            .map_err(xlsx_error)?;
+    }
+
+    for (col, data) in columns.iter().enumerate() {
+        let col = col as u16;
+        for (row, id) in ids.iter().enumerate() {
+            let row = row as u32 + 1;
+            let written = match data {
+                ColumnData::Int(map) => map.get(id).map(|&v| sheet.write_number(row, col, v)),
+                ColumnData::Str(map) => map.get(id).map(|v| sheet.write_string(row, col, &**v)),
+                ColumnData::Float(map) => map.get(id).map(|&v| sheet.write_number(row, col, v)),
+                ColumnData::Double(map) => map.get(id).map(|&v| sheet.write_number(row, col, v)),
+                ColumnData::Bool(map) => map.get(id).map(|&v| sheet.write_boolean(row, col, v)),
+            };
+            written.transpose().map_err(xlsx_error)?;
+        }
+    }
+
+    sheet.set_freeze_panes(1, 0).map_err(xlsx_error)?;
+    sheet.autofit();
+    workbook.save_to_writer(writer).map_err(xlsx_error)
+}
+
+fn xlsx_error(e: XlsxError) -> Error {
+    Error::Xlsx(e.to_string())
+}